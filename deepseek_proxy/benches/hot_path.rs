@@ -0,0 +1,161 @@
+//! 热路径组件的基准测试：全局令牌桶、登录会话表、输入 token 估算、SSE 计数变换
+//!
+//! 运行：`cargo bench --bench hot_path`（可加过滤，如 `cargo bench -- login_limiter`）。
+//! 改动锁结构或数据结构（如换成 DashMap）前后各跑一次，对比 target/criterion/ 下的结果
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use deepseek_proxy::deepseek::ChatRequest;
+use deepseek_proxy::error::AppError;
+use deepseek_proxy::proxy::stream_transform::{CountingTransform, StreamTransform};
+use deepseek_proxy::proxy::{estimate_input_tokens, GlobalRateLimiter, LoginLimiter};
+use serde_json::json;
+use std::sync::Arc;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap()
+}
+
+fn bench_global_rate_limiter(c: &mut Criterion) {
+    let rt = runtime();
+    // 容量足够大，测的是加锁与补充令牌的开销而不是拒绝路径
+    let limiter = Arc::new(GlobalRateLimiter::new(usize::MAX / 4));
+
+    c.bench_function("global_rate_limiter/acquire", |b| {
+        b.iter(|| rt.block_on(limiter.acquire()).unwrap())
+    });
+
+    // 8 个任务同时获取，观察锁竞争
+    c.bench_function("global_rate_limiter/acquire_contended_8x100", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let tasks: Vec<_> = (0..8)
+                    .map(|_| {
+                        let limiter = limiter.clone();
+                        tokio::spawn(async move {
+                            for _ in 0..100 {
+                                limiter.acquire().await.unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+        })
+    });
+}
+
+fn bench_login_limiter(c: &mut Criterion) {
+    let rt = runtime();
+    let limiter = LoginLimiter::new(3600);
+    // 预先放入 1000 个用户的会话
+    let tokens: Vec<String> = rt.block_on(async {
+        let mut tokens = Vec::new();
+        for i in 0..1000 {
+            let token = limiter
+                .get_or_generate(&format!("user{}", i), Some("10.0.0.1"), None, || Ok::<_, AppError>(format!("token-{}", i)))
+                .await
+                .unwrap();
+            tokens.push(token);
+        }
+        tokens
+    });
+
+    c.bench_function("login_limiter/get_or_generate_cached", |b| {
+        b.iter(|| {
+            let token = rt
+                .block_on(limiter.get_or_generate("user500", Some("10.0.0.1"), None, || {
+                    Err(AppError::InternalError("应命中缓存".to_string()))
+                }))
+                .unwrap();
+            black_box(token)
+        })
+    });
+
+    c.bench_function("login_limiter/acquire_permit", |b| {
+        b.iter(|| {
+            let permit = rt.block_on(limiter.acquire_permit("user500", &tokens[500])).unwrap();
+            drop(black_box(permit));
+        })
+    });
+}
+
+fn chat_request() -> ChatRequest {
+    let mut messages = vec![json!({"role": "system", "content": "You are a helpful assistant. 你是一个乐于助人的助手。"})];
+    for i in 0..20 {
+        messages.push(json!({
+            "role": if i % 2 == 0 { "user" } else { "assistant" },
+            "content": "请解释一下 Rust 的所有权与借用规则，并给出 a short example with lifetimes. ".repeat(8),
+        }));
+    }
+    messages.push(json!({
+        "role": "user",
+        "content": [
+            {"type": "text", "text": "这张图里有什么？"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png", "detail": "low"}},
+        ],
+    }));
+    serde_json::from_value(json!({
+        "model": "deepseek-chat",
+        "stream": true,
+        "messages": messages,
+        "tools": [{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}}}],
+    }))
+    .unwrap()
+}
+
+fn bench_estimate_input_tokens(c: &mut Criterion) {
+    let request = chat_request();
+    c.bench_function("estimate_input_tokens/22_messages", |b| {
+        b.iter(|| estimate_input_tokens(black_box(&request)))
+    });
+}
+
+/// 典型的上游 SSE 流：每个事件一个数据块，最后一块带 usage
+fn sse_chunks() -> Vec<Bytes> {
+    let mut chunks: Vec<Bytes> = (0..200)
+        .map(|i| {
+            let event = json!({
+                "id": "chatcmpl-bench",
+                "object": "chat.completion.chunk",
+                "choices": [{"index": 0, "delta": {"content": format!("词{} ", i)}, "finish_reason": null}],
+            });
+            Bytes::from(format!("data: {}\n\n", event))
+        })
+        .collect();
+    let usage = json!({
+        "id": "chatcmpl-bench",
+        "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 120, "completion_tokens": 200, "total_tokens": 320},
+    });
+    chunks.push(Bytes::from(format!("data: {}\n\ndata: [DONE]\n\n", usage)));
+    chunks
+}
+
+fn bench_counting_transform(c: &mut Criterion) {
+    let chunks = sse_chunks();
+    c.bench_function("counting_transform/201_sse_chunks", |b| {
+        b.iter_batched(
+            || chunks.clone(),
+            |chunks| {
+                let mut transform = CountingTransform::new("bench".to_string(), Some(1024 * 1024));
+                for chunk in chunks {
+                    black_box(transform.on_chunk(chunk));
+                }
+                transform.on_end();
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_global_rate_limiter,
+    bench_login_limiter,
+    bench_estimate_input_tokens,
+    bench_counting_transform
+);
+criterion_main!(benches);
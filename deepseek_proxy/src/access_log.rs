@@ -0,0 +1,131 @@
+use crate::config::AccessLogConfig;
+use axum::http::{Request, Response};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::trace::{MakeSpan, OnRequest, OnResponse};
+use tracing::Span;
+
+/// 请求日志（TraceLayer）的过滤：排除的路径不创建请求 span、不输出请求 / 响应日志（探针流量），
+/// 记录请求头时跳过排除的头（Authorization 等凭据）
+///
+/// 请求出错（5xx）仍由 TraceLayer 的 on_failure 记录，不受路径排除影响
+#[derive(Debug, Clone)]
+pub struct AccessLogFilter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    exact: HashSet<String>,
+    prefixes: Vec<String>,
+    log_headers: bool,
+    excluded_headers: HashSet<String>,
+}
+
+impl AccessLogFilter {
+    pub fn new(cfg: &AccessLogConfig) -> Self {
+        let mut exact = HashSet::new();
+        let mut prefixes = Vec::new();
+        for path in &cfg.exclude_paths {
+            match path.strip_suffix('*') {
+                Some(prefix) => prefixes.push(prefix.to_string()),
+                None => {
+                    exact.insert(path.clone());
+                }
+            }
+        }
+        Self {
+            inner: Arc::new(Inner {
+                exact,
+                prefixes,
+                log_headers: cfg.log_headers,
+                excluded_headers: cfg.exclude_headers.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            }),
+        }
+    }
+
+    /// 该路径的请求是否不记录日志；`exclude_paths` 中以 `*` 结尾的项按前缀匹配
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.inner.exact.contains(path) || self.inner.prefixes.iter().any(|p| path.starts_with(p.as_str()))
+    }
+
+    /// 去掉排除的请求头后用于日志输出的请求头
+    fn loggable_headers<B>(&self, request: &Request<B>) -> Vec<(String, String)> {
+        request
+            .headers()
+            .iter()
+            .filter(|(name, _)| !self.inner.excluded_headers.contains(name.as_str()))
+            .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect()
+    }
+}
+
+impl<B> MakeSpan<B> for AccessLogFilter {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        if self.is_excluded(request.uri().path()) {
+            return Span::none();
+        }
+        if self.inner.log_headers {
+            tracing::debug_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                version = ?request.version(),
+                headers = ?self.loggable_headers(request),
+            )
+        } else {
+            tracing::debug_span!("request", method = %request.method(), uri = %request.uri(), version = ?request.version())
+        }
+    }
+}
+
+/// 与 DefaultOnRequest 相同（debug 级别），排除的路径（span 为空）不输出
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilteredOnRequest;
+
+impl<B> OnRequest<B> for FilteredOnRequest {
+    fn on_request(&mut self, _: &Request<B>, span: &Span) {
+        if !span.is_none() {
+            tracing::debug!("started processing request");
+        }
+    }
+}
+
+/// 与 DefaultOnResponse 相同（debug 级别），排除的路径（span 为空）不输出
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilteredOnResponse;
+
+impl<B> OnResponse<B> for FilteredOnResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        if !span.is_none() {
+            tracing::debug!(latency = %format_args!("{} ms", latency.as_millis()), status = response.status().as_u16(), "finished processing request");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excluded_paths_and_headers() {
+        let filter = AccessLogFilter::new(&AccessLogConfig {
+            exclude_paths: vec!["/metrics".into(), "/probe/*".into()],
+            log_headers: true,
+            exclude_headers: vec!["Authorization".into()],
+        });
+        assert!(filter.is_excluded("/metrics"));
+        assert!(filter.is_excluded("/probe/chat"));
+        assert!(!filter.is_excluded("/metrics/extra"));
+        assert!(!filter.is_excluded("/chat/completions"));
+
+        let request = Request::builder()
+            .header("authorization", "Bearer secret-token")
+            .header("user-agent", "curl/8.0")
+            .body(())
+            .unwrap();
+        let headers = filter.loggable_headers(&request);
+        assert_eq!(headers, vec![("user-agent".to_string(), "curl/8.0".to_string())]);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::user_activity::UserActivityLog;
+use serde_json::Value;
+use std::path::Path;
+
+/// 当前写入的用户行为日志 schema 版本
+///
+/// 版本历史：
+/// - 1：早期版本，记录中没有 schema_version 字段
+/// - 2：增加 schema_version 字段
+/// - 3：chat_request 增加可选的 metadata 字段
+/// - 4：新增 audio_request 行为（语音识别 / 语音合成）
+///
+/// 新增字段时递增版本，并在 `migrate` 中补上对应的迁移步骤
+pub const CURRENT_SCHEMA_VERSION: u32 = 4;
+
+/// 没有 schema_version 字段的记录视为版本 1
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ActivitySchemaError {
+    #[error("日志行不是合法 JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("日志记录无法解析（schema 版本 {version}）: {message}")]
+    InvalidRecord { version: u32, message: String },
+}
+
+/// 解析一行日志，必要时先迁移到当前版本
+///
+/// 比当前版本更新的记录（由更新的代理写入）按当前结构尽力解析，未知字段忽略
+pub fn parse_line(line: &str) -> Result<UserActivityLog, ActivitySchemaError> {
+    let value: Value = serde_json::from_str(line).map_err(|e| ActivitySchemaError::InvalidJson(e.to_string()))?;
+    let version = schema_version(&value);
+    let value = migrate(value, version);
+    serde_json::from_value(value).map_err(|e| ActivitySchemaError::InvalidRecord {
+        version,
+        message: e.to_string(),
+    })
+}
+
+/// 读取日志记录的 schema 版本
+pub fn schema_version(value: &Value) -> u32 {
+    value
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(LEGACY_SCHEMA_VERSION)
+}
+
+/// 逐版本迁移到当前版本
+///
+/// 1 -> 2：结构不变，仅补充版本号
+/// 2 -> 3：metadata 为可选字段，缺省即可。以后新增字段时在此按 `from < N` 依次补默认值
+/// 3 -> 4：只新增行为类型，旧记录无需修改
+fn migrate(mut value: Value, from: u32) -> Value {
+    if from < CURRENT_SCHEMA_VERSION {
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("schema_version".to_string(), Value::from(CURRENT_SCHEMA_VERSION));
+        }
+    }
+    value
+}
+
+/// 读取单个日志文件，返回解析出的记录与跳过的行数
+pub async fn read_log_file(path: &Path) -> anyhow::Result<(Vec<UserActivityLog>, usize)> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(parse_lines(&content))
+}
+
+/// 逐行解析日志内容，无法解析的行计入跳过数
+pub fn parse_lines(content: &str) -> (Vec<UserActivityLog>, usize) {
+    let mut logs = Vec::new();
+    let mut skipped = 0;
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        match parse_line(line) {
+            Ok(log) => logs.push(log),
+            Err(e) => {
+                tracing::debug!(error = %e, "跳过无法解析的行为日志");
+                skipped += 1;
+            }
+        }
+    }
+    (logs, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_activity::UserAction;
+
+    #[test]
+    fn test_legacy_line_is_migrated() {
+        let line = r#"{"timestamp":"2025-11-01T00:00:00Z","username":"alice","action":"login"}"#;
+        let log = parse_line(line).unwrap();
+        assert_eq!(log.schema_version, CURRENT_SCHEMA_VERSION);
+        assert!(matches!(log.action, UserAction::Login));
+    }
+
+    #[test]
+    fn test_newer_version_parsed_leniently() {
+        let line = r#"{"schema_version":9,"timestamp":"t","username":"alice","action":"logout","cost":0.1}"#;
+        let log = parse_line(line).unwrap();
+        assert_eq!(log.schema_version, 9);
+        assert!(matches!(parse_line("oops"), Err(ActivitySchemaError::InvalidJson(_))));
+        assert!(matches!(
+            parse_line(r#"{"timestamp":"t","username":"a","action":"teleport"}"#),
+            Err(ActivitySchemaError::InvalidRecord { version: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use super::AdminAccess;
+use crate::{error::AppError, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// 确认令牌有效期（秒）
+const CONFIRMATION_TTL_SECONDS: i64 = 300;
+/// 匿名化假名前缀（完整性检查据此识别有意保留、没有对应用户的配额文件）
+pub const PSEUDONYM_PREFIX: &str = "erased-";
+
+/// 擦除方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErasureMode {
+    /// 删除用户记录、配额文件与行为日志
+    #[default]
+    Delete,
+    /// 删除用户记录，配额与行为日志改用假名保留（去掉 IP 等身份信息）
+    Anonymize,
+}
+
+impl ErasureMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErasureMode::Delete => "delete",
+            ErasureMode::Anonymize => "anonymize",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EraseUserDataQuery {
+    #[serde(default)]
+    pub mode: ErasureMode,
+    /// 第一次调用返回的确认令牌
+    pub confirm: Option<String>,
+}
+
+/// 第一次调用的响应：返回确认令牌
+#[derive(Debug, Serialize)]
+pub struct ErasureConfirmation {
+    pub username: String,
+    pub mode: ErasureMode,
+    pub confirmation_token: String,
+    pub expires_in_seconds: i64,
+    pub message: String,
+}
+
+/// 擦除报告
+#[derive(Debug, Serialize)]
+pub struct ErasureReport {
+    pub username: String,
+    pub mode: ErasureMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pseudonym: Option<String>,
+    pub user_record_deleted: bool,
+    pub quota_file_erased: bool,
+    /// 涉及的配额归档月份数
+    pub quota_archive_months: usize,
+    pub activity_log_files: usize,
+    /// 新 IP 登录通知使用的登录 IP 记录（匿名化模式同样删除）
+    pub known_ips_erased: bool,
+    /// 本服务不保存对话内容，固定为 false
+    pub transcripts_stored: bool,
+    pub completed_at: String,
+}
+
+/// 管理接口：擦除用户数据（隐私合规请求）
+///
+/// 用户须先被停用（软删除）。不带 `confirm` 调用时返回 202 与确认令牌，
+/// 携带 `?confirm=<token>` 再次调用才会真正擦除并返回擦除报告
+pub async fn erase_user_data(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(query): Query<EraseUserDataQuery>,
+) -> Result<Response, AppError> {
+    crate::read_only::ensure_writable()?;
+    let user = state.user_manager
+        .get_user(&username)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?;
+    if user.is_active {
+        return Err(AppError::Conflict(format!("用户 {} 仍处于启用状态，请先停用再擦除数据", username)));
+    }
+
+    let secret = state.config.auth.jwt_secret.as_bytes();
+    let now = chrono::Utc::now().timestamp();
+
+    let Some(token) = query.confirm else {
+        let token = confirmation_token(secret, &username, query.mode, now + CONFIRMATION_TTL_SECONDS);
+        let body = ErasureConfirmation {
+            message: format!(
+                "将擦除用户 {} 的全部数据，请在 {} 秒内携带 ?confirm=<token> 再次调用以确认",
+                username, CONFIRMATION_TTL_SECONDS
+            ),
+            username,
+            mode: query.mode,
+            confirmation_token: token,
+            expires_in_seconds: CONFIRMATION_TTL_SECONDS,
+        };
+        return Ok((StatusCode::ACCEPTED, Json(body)).into_response());
+    };
+    if !verify_confirmation_token(secret, &username, query.mode, &token, now) {
+        return Err(AppError::BadRequest("确认令牌无效或已过期".to_string()));
+    }
+
+    let pseudonym = (query.mode == ErasureMode::Anonymize).then(|| pseudonym(secret, &username));
+
+    // 先擦除数据，最后删除用户记录：中途失败时用户仍在，可直接重试
+    state.login_limiter.revoke(&username).await;
+    let activity_log_files = state.activity_logger
+        .erase_user(&username, pseudonym.as_deref())
+        .await
+        .map_err(|e| AppError::InternalError(format!("擦除行为日志失败: {}", e)))?;
+    let quota_file_erased = state.quota_manager.erase_user(&username, pseudonym.as_deref()).await?;
+    let quota_archive_months = state.quota_manager.archive().erase_user(&username, pseudonym.as_deref()).await?;
+    let known_ips_erased = state.known_ips.erase_user(&username).await?;
+    let user_record_deleted = state.user_manager.purge_user(&username).await?;
+
+    tracing::warn!(
+        username = %username,
+        mode = query.mode.as_str(),
+        activity_log_files,
+        quota_file_erased,
+        "用户数据已擦除"
+    );
+
+    Ok(Json(ErasureReport {
+        username,
+        mode: query.mode,
+        pseudonym,
+        user_record_deleted,
+        quota_file_erased,
+        quota_archive_months,
+        activity_log_files,
+        known_ips_erased,
+        transcripts_stored: false,
+        completed_at: crate::utils::now_beijing_rfc3339(),
+    })
+    .into_response())
+}
+
+fn hmac_hex(secret: &[u8], message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC 接受任意长度密钥");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 确认令牌："{过期时间戳}.{HMAC}"，绑定用户名与擦除方式，无需服务端保存
+fn confirmation_token(secret: &[u8], username: &str, mode: ErasureMode, expires_at: i64) -> String {
+    let signature = hmac_hex(secret, &format!("erase\n{}\n{}\n{}", username, mode.as_str(), expires_at));
+    format!("{}.{}", expires_at, signature)
+}
+
+fn verify_confirmation_token(secret: &[u8], username: &str, mode: ErasureMode, token: &str, now: i64) -> bool {
+    let Some(expires_at) = token.split_once('.').and_then(|(ts, _)| ts.parse::<i64>().ok()) else {
+        return false;
+    };
+    expires_at >= now && confirmation_token(secret, username, mode, expires_at) == token
+}
+
+/// 匿名化使用的假名：由密钥派生，无法从假名反推用户名
+fn pseudonym(secret: &[u8], username: &str) -> String {
+    format!("{}{}", PSEUDONYM_PREFIX, &hmac_hex(secret, &format!("pseudonym\n{}", username))[..12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmation_token_binds_user_mode_and_expiry() {
+        let token = confirmation_token(b"secret", "alice", ErasureMode::Delete, 1_000);
+        assert!(verify_confirmation_token(b"secret", "alice", ErasureMode::Delete, &token, 999));
+        assert!(!verify_confirmation_token(b"secret", "alice", ErasureMode::Delete, &token, 1_001));
+        assert!(!verify_confirmation_token(b"secret", "bob", ErasureMode::Delete, &token, 999));
+        assert!(!verify_confirmation_token(b"secret", "alice", ErasureMode::Anonymize, &token, 999));
+        assert!(!verify_confirmation_token(b"other", "alice", ErasureMode::Delete, &token, 999));
+    }
+
+    #[test]
+    fn test_pseudonym_is_stable_and_opaque() {
+        let p = pseudonym(b"secret", "alice");
+        assert_eq!(p, pseudonym(b"secret", "alice"));
+        assert!(p.starts_with("erased-") && !p.contains("alice"));
+        // 假名需能通过用户名校验规则（作为文件名使用）
+        assert!(p.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    }
+}
@@ -0,0 +1,89 @@
+use super::AdminAccess;
+use crate::{error::AppError, quota::QuotaState, user_activity::UserActivityLog, AppState};
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+/// 导出的用户记录（不含密码）
+#[derive(Debug, Serialize)]
+pub struct ExportedUser {
+    pub username: String,
+    pub quota_tier: String,
+    pub is_active: bool,
+    pub unlimited: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+/// 用户数据导出包
+#[derive(Debug, Serialize)]
+pub struct UserDataExport {
+    pub exported_at: String,
+    pub user: ExportedUser,
+    pub quota: QuotaState,
+    pub activity_logs: Vec<UserActivityLog>,
+    /// 新 IP 登录通知记录的登录 IP
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub known_ips: Vec<crate::auth::known_ips::KnownIp>,
+    /// 无法解析而跳过的日志行数
+    pub skipped_log_lines: usize,
+}
+
+/// 管理接口：导出用户数据（数据访问请求、账户迁移）
+///
+/// 导出文件包含用户记录（不含密码）、当前配额状态与全部行为日志，响应只返回其签名下载链接
+pub async fn export_user_data(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Response, AppError> {
+    crate::read_only::ensure_writable()?;
+    let user = state.user_manager
+        .get_user(&username)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?;
+
+    let quota = state.quota_manager.get_quota(&username).await?;
+    let (activity_logs, skipped_log_lines) = state.activity_logger
+        .read_user_logs(&username)
+        .await
+        .map_err(|e| AppError::InternalError(format!("读取行为日志失败: {}", e)))?;
+
+    tracing::info!(username = %username, logs = activity_logs.len(), "导出用户数据");
+
+    let export = UserDataExport {
+        exported_at: crate::utils::now_beijing_rfc3339(),
+        user: ExportedUser {
+            username: user.username,
+            quota_tier: user.quota_tier,
+            is_active: user.is_active,
+            unlimited: user.unlimited,
+            allowed_ips: user.allowed_ips,
+            notify_webhook: user.notify_webhook,
+            notify_email: user.notify_email,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        },
+        quota,
+        activity_logs,
+        known_ips: state.known_ips.get(&username).await,
+        skipped_log_lines,
+    };
+
+    let content = serde_json::to_vec_pretty(&export)
+        .map_err(|e| AppError::InternalError(format!("序列化导出数据失败: {}", e)))?;
+    let link = state.downloads.publish(&format!("{}-export.json", username), content).await?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(link)).into_response())
+}
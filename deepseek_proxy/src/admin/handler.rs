@@ -1,7 +1,10 @@
-use crate::{error::AppError, AppState};
+use super::AdminAccess;
+use crate::{error::AppError, tls::ClientCertIdentity, AppState};
 use axum::{
-    extract::{Path, State},
-    Json,
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 
@@ -22,10 +25,12 @@ pub struct SetUserActiveResponse {
 /// 管理接口：设置用户的 is_active 状态
 /// 只能从 localhost 访问（由中间件控制）
 pub async fn set_user_active(
+    _: AdminAccess,
     State(state): State<AppState>,
     Path(username): Path<String>,
     Json(req): Json<SetUserActiveRequest>,
 ) -> Result<Json<SetUserActiveResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
     // 设置用户状态（会同时更新内存和配置文件）
     state.user_manager
         .set_user_active(&username, req.is_active)
@@ -50,10 +55,25 @@ pub struct GetUserResponse {
     pub username: String,
     pub quota_tier: String,
     pub is_active: bool,
+    pub unlimited: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_email: Option<String>,
+    /// 是否已开启 TOTP 两步验证
+    pub totp_enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned_model: Option<String>,
+    pub model_override: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_login_at: Option<String>,
 }
 
 /// 管理接口：获取用户信息
 pub async fn get_user(
+    _: AdminAccess,
     State(state): State<AppState>,
     Path(username): Path<String>,
 ) -> Result<Json<GetUserResponse>, AppError> {
@@ -66,21 +86,275 @@ pub async fn get_user(
         username: user.username,
         quota_tier: user.quota_tier,
         is_active: user.is_active,
+        unlimited: user.unlimited,
+        allowed_ips: user.allowed_ips,
+        notify_webhook: user.notify_webhook,
+        notify_email: user.notify_email,
+        totp_enabled: user.totp_secret.is_some(),
+        pinned_model: user.pinned_model,
+        model_override: user.model_override,
+        last_login_at: user.last_login_at,
     }))
 }
 
-/// 管理接口：列出所有用户
+/// 设置允许来源 IP 的请求
+#[derive(Debug, Deserialize)]
+pub struct SetAllowedIpsRequest {
+    /// IP 或 CIDR 列表，空列表表示不限制
+    pub allowed_ips: Vec<String>,
+}
+
+/// 设置新 IP 登录通知的请求（字段省略或为 null 表示取消该渠道）
+#[derive(Debug, Deserialize)]
+pub struct SetLoginNotifyRequest {
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// 管理接口：设置账户所有者的新 IP 登录通知渠道
+pub async fn set_login_notify(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<SetLoginNotifyRequest>,
+) -> Result<Json<GetUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.user_manager.set_login_notify(&username, req.webhook, req.email).await?;
+    get_user(admin, State(state), Path(username)).await
+}
+
+/// 设置固定模型的请求（pinned_model 省略或为 null 表示取消固定）
+#[derive(Debug, Deserialize)]
+pub struct SetPinnedModelRequest {
+    #[serde(default)]
+    pub pinned_model: Option<String>,
+    #[serde(default)]
+    pub model_override: bool,
+}
+
+/// 管理接口：固定账户使用的聊天模型（机构部署保证账户实际调用的后端模型）
+pub async fn set_pinned_model(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<SetPinnedModelRequest>,
+) -> Result<Json<GetUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.user_manager.set_pinned_model(&username, req.pinned_model, req.model_override).await?;
+    get_user(admin, State(state), Path(username)).await
+}
+
+/// 开启两步验证的响应：密钥只在这里返回一次，之后无法再查询
+#[derive(Debug, Serialize)]
+pub struct ProvisionTotpResponse {
+    pub username: String,
+    /// base32 密钥，供无法扫码时手工输入
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// otpauth_uri 的二维码（SVG），交给用户用验证器 App 扫描
+    pub qr_svg: String,
+}
+
+/// 管理接口：为用户生成新的 TOTP 密钥并开启两步验证（已开启时替换旧密钥）
+pub async fn provision_totp(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Response, AppError> {
+    crate::read_only::ensure_writable()?;
+    let secret = crate::auth::totp::generate_secret();
+    let issuer = state.config.branding.service_name.as_deref().unwrap_or("DeepSeek Proxy");
+    let otpauth_uri = crate::auth::totp::provisioning_uri(issuer, &username, &secret);
+    let qr = crate::qr::QrCode::encode(otpauth_uri.as_bytes())
+        .ok_or_else(|| AppError::BadRequest("用户名或服务名称过长，无法生成二维码".to_string()))?;
+    state.user_manager.set_totp_secret(&username, Some(secret.clone())).await?;
+
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(ProvisionTotpResponse { username, secret, otpauth_uri, qr_svg: qr.to_svg() }),
+    )
+        .into_response())
+}
+
+/// 管理接口：关闭用户的两步验证（如用户丢失了验证器）
+pub async fn disable_totp(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<GetUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.user_manager.set_totp_secret(&username, None).await?;
+    get_user(admin, State(state), Path(username)).await
+}
+
+/// 重置密码请求
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub password: String,
+    /// 操作人，未填时使用管理员证书 CN 或来源地址
+    #[serde(default)]
+    pub operator: Option<String>,
+}
+
+/// 管理接口：重置用户密码（用户忘记密码时），该用户已签发的 token 全部失效，并写入审计记录
+pub async fn reset_password(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    identity: Option<Extension<ClientCertIdentity>>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<Json<GetUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.user_manager.set_password(&username, &req.password).await?;
+    crate::auth::end_sessions(&state, &username).await?;
+
+    let actor = admin.actor(req.operator, identity.as_ref().map(|Extension(identity)| identity));
+    tracing::warn!(user = %username, actor = %actor, "管理员重置用户密码");
+    state
+        .activity_logger
+        .log_password_reset(&username, &actor, Some(admin.peer.ip().to_string()))
+        .await;
+    get_user(admin, State(state), Path(username)).await
+}
+
+/// 强制下线请求体（可省略）
+#[derive(Debug, Default, Deserialize)]
+pub struct ForceLogoutRequest {
+    /// 操作人，未填时使用管理员证书 CN 或来源地址
+    #[serde(default)]
+    pub operator: Option<String>,
+}
+
+/// 强制下线的响应
 #[derive(Debug, Serialize)]
-pub struct ListUsersResponse {
-    pub users: Vec<crate::auth::UserInfo>,
+pub struct ForceLogoutResponse {
+    pub username: String,
+    /// 注销的访问 token 数
+    pub revoked_tokens: usize,
+}
+
+/// 管理接口：强制用户下线（账户行为异常时立即切断访问），已签发的 token 与 refresh token 全部失效，并写入审计记录
+///
+/// 账户仍可重新登录；需要阻止登录时再停用账户
+pub async fn force_logout(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    identity: Option<Extension<ClientCertIdentity>>,
+    body: Option<Json<ForceLogoutRequest>>,
+) -> Result<Json<ForceLogoutResponse>, AppError> {
+    if state.user_manager.get_user(&username).await.is_none() {
+        return Err(AppError::NotFound(format!("用户 {} 不存在", username)));
+    }
+    let revoked_tokens = crate::auth::end_sessions(&state, &username).await?;
+
+    let request = body.map(|Json(b)| b).unwrap_or_default();
+    let actor = admin.actor(request.operator, identity.as_ref().map(|Extension(identity)| identity));
+    tracing::warn!(user = %username, actor = %actor, revoked_tokens, "管理员强制用户下线");
+    state
+        .activity_logger
+        .log_forced_logout(&username, &actor, revoked_tokens, Some(admin.peer.ip().to_string()))
+        .await;
+    Ok(Json(ForceLogoutResponse { username, revoked_tokens }))
+}
+
+/// 管理接口：限制用户只能从指定 IP / CIDR 调用接口
+pub async fn set_allowed_ips(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<SetAllowedIpsRequest>,
+) -> Result<Json<GetUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.user_manager.set_allowed_ips(&username, req.allowed_ips).await?;
+    get_user(admin, State(state), Path(username)).await
 }
 
+/// 管理接口：按条件分页列出用户（默认按用户名排序，内容不变时 ETag 也不变）
 pub async fn list_users(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Query(query): Query<crate::auth::UserQuery>,
+) -> Result<Json<crate::auth::UserPage>, AppError> {
+    Ok(Json(state.user_manager.query_users(&query).await?))
+}
+
+/// 一个用户的配额用量
+#[derive(Debug, Serialize)]
+pub struct QuotaSummary {
+    pub username: String,
+    pub tier: String,
+    pub used: u32,
+    pub limit: u32,
+    pub credits: u32,
+    pub reset_at: String,
+}
+
+/// 管理接口：列出用户的配额用量（筛选、排序与分页参数同用户列表）
+#[derive(Debug, Serialize)]
+pub struct ListQuotasResponse {
+    pub quotas: Vec<QuotaSummary>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+pub async fn list_quotas(
+    _: AdminAccess,
     State(state): State<AppState>,
-) -> Result<Json<ListUsersResponse>, AppError> {
-    let users = state.user_manager.list_users().await;
+    Query(query): Query<crate::auth::UserQuery>,
+) -> Result<Json<ListQuotasResponse>, AppError> {
+    let page = state.user_manager.query_users(&query).await?;
+    let mut quotas = Vec::with_capacity(page.users.len());
+    for user in page.users {
+        match state.quota_manager.get_quota(&user.username).await {
+            Ok(quota) => quotas.push(QuotaSummary {
+                username: quota.username,
+                tier: quota.tier,
+                used: quota.used_count,
+                limit: quota.monthly_limit,
+                credits: quota.credits,
+                reset_at: quota.reset_at,
+            }),
+            Err(e) => tracing::warn!(user = %user.username, error = %e, "读取配额失败，列表中跳过该用户"),
+        }
+    }
+    Ok(Json(ListQuotasResponse { quotas, total: page.total, page: page.page, per_page: page.per_page }))
+}
+
+/// 已有配额归档的月份
+#[derive(Debug, Serialize)]
+pub struct QuotaArchiveMonthsResponse {
+    pub months: Vec<String>,
+}
+
+/// 管理接口：列出已有配额归档的月份
+pub async fn quota_archive_months(_: AdminAccess, State(state): State<AppState>) -> Json<QuotaArchiveMonthsResponse> {
+    Json(QuotaArchiveMonthsResponse { months: state.quota_manager.archive().months().await })
+}
 
-    Ok(Json(ListUsersResponse { users }))
+/// 某月的配额归档
+#[derive(Debug, Serialize)]
+pub struct QuotaArchiveResponse {
+    pub month: String,
+    pub users: Vec<crate::quota::ArchivedUser>,
+}
+
+/// 管理接口：查询某月（YYYY-MM）结束的配额周期
+pub async fn quota_archive(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Path(month): Path<String>,
+) -> Result<Json<QuotaArchiveResponse>, AppError> {
+    let users = state
+        .quota_manager
+        .archive()
+        .month(&month)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("没有 {} 的配额归档", month)))?;
+    Ok(Json(QuotaArchiveResponse { month, users }))
 }
 
 /// 创建用户请求
@@ -90,6 +364,10 @@ pub struct CreateUserRequest {
     pub password: String,
     #[serde(default = "default_quota_tier")]
     pub quota_tier: String,
+    #[serde(default)]
+    pub unlimited: bool,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
 }
 
 fn default_quota_tier() -> String {
@@ -105,11 +383,20 @@ pub struct CreateUserResponse {
 
 /// 管理接口：创建新用户
 pub async fn create_user(
+    _: AdminAccess,
     State(state): State<AppState>,
     Json(req): Json<CreateUserRequest>,
 ) -> Result<Json<CreateUserResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    if !state.config.quota.tiers.contains(&req.quota_tier) {
+        return Err(AppError::BadRequest(format!(
+            "配额档次 {} 未在 [quota.tiers] 中定义，可选: {}",
+            req.quota_tier,
+            state.config.quota.tiers.names().join(", ")
+        )));
+    }
     state.user_manager
-        .create_user(req.username.clone(), req.password, req.quota_tier)
+        .create_user(req.username.clone(), req.password, req.quota_tier, req.unlimited, req.allowed_ips)
         .await?;
 
     Ok(Json(CreateUserResponse {
@@ -118,5 +405,160 @@ pub async fn create_user(
     }))
 }
 
-// 注意：不提供物理删除功能
-// 要"删除"用户，请使用 POST /admin/users/:username/active 并设置 is_active = false
+/// 发放预付费额度请求
+#[derive(Debug, Deserialize)]
+pub struct GrantCreditsRequest {
+    pub amount: u32,
+}
+
+/// 发放预付费额度响应
+#[derive(Debug, Serialize)]
+pub struct GrantCreditsResponse {
+    pub username: String,
+    pub credits: u32,
+    pub message: String,
+}
+
+/// 管理接口：为用户发放预付费额度（不随周期重置，周期配额耗尽后扣减）
+pub async fn grant_credits(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<GrantCreditsRequest>,
+) -> Result<Json<GrantCreditsResponse>, AppError> {
+    crate::read_only::ensure_writable()?;
+    if req.amount == 0 {
+        return Err(AppError::BadRequest("amount 必须大于 0".to_string()));
+    }
+    if state.user_manager.get_user(&username).await.is_none() {
+        return Err(AppError::NotFound(format!("用户 {} 不存在", username)));
+    }
+
+    let credits = state.quota_manager.grant_credits(&username, req.amount).await?;
+
+    Ok(Json(GrantCreditsResponse {
+        message: format!("已为用户 {} 发放 {} 次额度", username, req.amount),
+        username,
+        credits,
+    }))
+}
+
+/// 数据目录检查结果
+#[derive(Debug, Serialize)]
+pub struct IntegrityResponse {
+    /// 启动时的检查结果（含隔离情况）
+    pub startup: crate::integrity::IntegrityReport,
+    /// 当前重新扫描的结果（只报告，不隔离）
+    pub current: crate::integrity::IntegrityReport,
+}
+
+/// 管理接口：查看数据目录完整性检查结果
+pub async fn integrity(_: AdminAccess, State(state): State<AppState>) -> Json<IntegrityResponse> {
+    let known_users = state.user_manager.list_users().await.into_iter().map(|u| u.username).collect();
+    let current = crate::integrity::scan(std::path::Path::new("data"), &known_users).await;
+    Json(IntegrityResponse {
+        startup: (*state.integrity_report).clone(),
+        current,
+    })
+}
+
+/// 管理接口：立即备份 data/ 目录，返回备份文件信息
+pub async fn backup(_: AdminAccess, State(state): State<AppState>) -> Result<Json<crate::backup::BackupInfo>, AppError> {
+    Ok(Json(state.backup.run().await?))
+}
+
+/// 管理接口：最近 24 小时 / 7 天的请求成功率、上游 p95 延迟与剩余错误预算
+pub async fn slo(_: AdminAccess, State(state): State<AppState>) -> Result<Json<crate::slo::SloReport>, AppError> {
+    crate::slo::report(&state.config.slo)
+        .await
+        .map(Json)
+        .map_err(|e| AppError::InternalError(format!("读取 SLO 采样失败: {}", e)))
+}
+
+/// 管理接口：查看实验性功能开关的当前状态
+pub async fn flags(_: AdminAccess, State(state): State<AppState>) -> Json<crate::flags::FlagsReport> {
+    Json(state.flags.report())
+}
+
+/// 管理接口：上游前缀缓存命中情况与重复 system 提示统计（估算费用按 [reconciliation] 单价）
+pub async fn cache_stats(_: AdminAccess, State(state): State<AppState>) -> Json<crate::proxy::prompt_cache::CacheStatsReport> {
+    let prices = &state.config.reconciliation;
+    Json(state.prompt_cache.report(prices.input_cache_hit_price, prices.input_cache_miss_price, &prices.currency))
+}
+
+/// 当前被阻断的登录来源
+#[derive(Debug, Serialize)]
+pub struct LockoutsResponse {
+    pub lockouts: Vec<crate::auth::bruteforce::Lockout>,
+}
+
+/// 管理接口：列出因登录失败次数过多而被阻断的 `用户名:IP`
+pub async fn list_lockouts(_: AdminAccess, State(state): State<AppState>) -> Json<LockoutsResponse> {
+    Json(LockoutsResponse { lockouts: state.brute_force_guard.lockouts() })
+}
+
+/// 管理接口：解除阻断（`key` 为列表中的 `用户名:IP`）
+pub async fn unban_lockout(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if !state.brute_force_guard.unban(&key) {
+        return Err(AppError::NotFound(format!("没有 {} 的登录失败记录", key)));
+    }
+    state.brute_force_guard.persist().await;
+    tracing::warn!(key = %key, operator = %admin.peer.ip(), "管理员解除了登录阻断");
+    Ok(Json(serde_json::json!({ "key": key, "unbanned": true })))
+}
+
+/// 管理接口：查看当前生效的全局 IP 规则
+pub async fn ip_rules(_: AdminAccess, State(state): State<AppState>) -> Json<crate::config::IpRulesConfig> {
+    Json(state.ip_rules.rules())
+}
+
+/// 管理接口：替换全局 IP 规则（`{"allow": [...], "deny": [...]}`），立即生效并保存到 data/ip_rules.json
+pub async fn set_ip_rules(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Json(req): Json<crate::config::IpRulesConfig>,
+) -> Result<Json<crate::config::IpRulesConfig>, AppError> {
+    crate::read_only::ensure_writable()?;
+    state.ip_rules.update(req).await?;
+    tracing::warn!(operator = %admin.peer.ip(), "管理员修改了全局 IP 规则");
+    Ok(Json(state.ip_rules.rules()))
+}
+
+/// 管理接口：查看合并后的生效配置及各项来源（敏感字段已脱敏）
+pub async fn effective_config(_: AdminAccess, State(state): State<AppState>) -> Json<crate::effective_config::EffectiveConfig> {
+    Json(crate::effective_config::report(&state.config))
+}
+
+/// 配额重置预演的查询参数
+#[derive(Debug, Deserialize)]
+pub struct PreviewResetQuery {
+    /// 预演日期（YYYY-MM-DD，北京时间），缺省为今天
+    pub date: Option<String>,
+}
+
+/// 管理接口：预演指定日期的配额重置，列出会重置的用户、重置后的上限与异常（不修改数据）
+pub async fn preview_quota_reset(
+    _: AdminAccess,
+    State(state): State<AppState>,
+    Query(query): Query<PreviewResetQuery>,
+) -> Result<Json<crate::quota::ResetPreviewReport>, AppError> {
+    let now = crate::utils::now_beijing();
+    let date = match query.date.as_deref() {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| AppError::BadRequest(format!("无效的日期 {}，格式应为 YYYY-MM-DD", date)))?,
+        None => now.date_naive(),
+    };
+    // 模拟到当天结束：当天任意时刻到期的周期都计入
+    let at = date
+        .and_hms_opt(23, 59, 59)
+        .and_then(|end| end.and_local_timezone(*now.offset()).single())
+        .ok_or_else(|| AppError::BadRequest(format!("无效的日期 {}", date)))?;
+    Ok(Json(state.quota_manager.preview_reset(at).await?))
+}
+
+// 注意：日常"删除"用户请使用 POST /admin/users/:username/active 并设置 is_active = false；
+// 物理删除仅在隐私擦除请求中使用（DELETE /admin/users/:username/data，见 erasure.rs）
@@ -0,0 +1,72 @@
+use super::AdminAccess;
+use crate::{error::AppError, tls::ClientCertIdentity, AppState};
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+/// 代管请求
+#[derive(Debug, Deserialize)]
+pub struct ImpersonateRequest {
+    /// 代管原因（必填，写入审计记录）
+    pub reason: String,
+    /// 操作人，未填时使用管理员证书 CN 或来源地址
+    #[serde(default)]
+    pub operator: Option<String>,
+}
+
+/// 代管响应
+#[derive(Debug, Serialize)]
+pub struct ImpersonateResponse {
+    pub token: String,
+    pub username: String,
+    pub impersonated_by: String,
+    pub expires_in: u64,
+}
+
+/// 管理接口：签发以指定用户身份访问的短期代管 token，用于复现"只有某个用户出错"的问题
+///
+/// token 的 claims 带 `impersonated_by`，每次使用都会记录日志并在响应头 `X-Impersonated-By` 中标明；
+/// 请求与普通 token 一样占用该用户的并发许可并扣减其配额，但不会复用或挤出用户自己的会话
+pub async fn impersonate_user(
+    admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    identity: Option<Extension<ClientCertIdentity>>,
+    Json(req): Json<ImpersonateRequest>,
+) -> Result<Json<ImpersonateResponse>, AppError> {
+    let reason = req.reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::BadRequest("reason 不能为空".to_string()));
+    }
+    let user = state
+        .user_manager
+        .get_user(&username)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?;
+    if !user.is_active {
+        return Err(AppError::BadRequest(format!("用户 {} 已停用，无法代管", username)));
+    }
+
+    let actor = admin.actor(req.operator, identity.as_ref().map(|Extension(identity)| identity));
+    let ttl = state.config.admin.impersonation_ttl_seconds.max(1);
+    let token = state
+        .jwt_service
+        .generate_impersonation_token(&username, &actor, ttl)
+        .map_err(|e| AppError::InternalError(format!("Token生成失败: {}", e)))?;
+    state.login_limiter.add_impersonation_session(&username, &token, ttl).await;
+
+    tracing::warn!(user = %username, actor = %actor, reason = %reason, expires_in = ttl, "签发代管 token");
+    state
+        .activity_logger
+        .log_impersonation(&username, &actor, reason, ttl, Some(admin.peer.ip().to_string()))
+        .await;
+
+    Ok(Json(ImpersonateResponse {
+        token,
+        username,
+        impersonated_by: actor,
+        expires_in: ttl,
+    }))
+}
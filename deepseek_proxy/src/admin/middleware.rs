@@ -1,29 +1,279 @@
+use crate::{listener::ListenerInfo, tls::ClientCertIdentity, AppState};
 use axum::{
-    extract::{ConnectInfo, Request},
-    http::StatusCode,
+    async_trait,
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
 use std::net::SocketAddr;
 
-/// 中间件：只允许 localhost 访问
-pub async fn localhost_only(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    request: Request,
-    next: Next,
-) -> Result<Response, Response> {
-    // 检查是否是 localhost
-    let is_localhost = addr.ip().is_loopback();
+/// 签名请求头
+pub const ADMIN_TIMESTAMP_HEADER: &str = "x-admin-timestamp";
+pub const ADMIN_SIGNATURE_HEADER: &str = "x-admin-signature";
+/// 签名请求体的最大长度
+const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+
+/// 已使用的签名 -> 时间戳，用于在允许的时间窗口内拒绝重放
+static SEEN_SIGNATURES: Lazy<DashMap<String, i64>> = Lazy::new(DashMap::new);
+
+/// 管理请求通过校验的凭证：只由本模块的中间件在放行时写入请求扩展
+///
+/// 管理接口的 handler 都以它为参数，路由漏挂中间件（或中间件顺序调整后未生效）时请求返回 403 而不是被执行
+#[derive(Debug, Clone)]
+pub struct AdminAccess {
+    /// 请求来源（经受信任代理解析后的客户端地址）
+    pub peer: SocketAddr,
+    /// 放行依据：localhost / internal_socket / signature / client_cert
+    via: &'static str,
+}
+
+impl AdminAccess {
+    fn grant(request: &mut Request, peer: SocketAddr, via: &'static str) {
+        request.extensions_mut().insert(Self { peer, via });
+    }
 
-    if !is_localhost {
+    pub fn via(&self) -> &'static str {
+        self.via
+    }
+
+    /// 写入审计记录的操作人：请求中填写的 operator，其次是管理员证书 CN，最后是来源地址
+    pub fn actor(&self, operator: Option<String>, identity: Option<&ClientCertIdentity>) -> String {
+        operator
+            .map(|o| o.trim().to_string())
+            .filter(|o| !o.is_empty())
+            .or_else(|| identity.map(|identity| identity.cn.clone()))
+            .unwrap_or_else(|| format!("admin@{}", self.peer.ip()))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminAccess {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Self>().cloned().ok_or_else(|| {
+            tracing::error!("管理接口 {} 未经管理中间件校验，拒绝请求（检查路由是否经 admin::router 注册）", parts.uri.path());
+            (StatusCode::FORBIDDEN, "Admin API access not verified").into_response()
+        })
+    }
+}
+
+/// 请求的对端地址；监听器未注入 `ConnectInfo` 时返回 None，调用方按非 localhost 拒绝
+fn peer_addr(request: &Request) -> Option<SocketAddr> {
+    let addr = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+    if addr.is_none() {
+        tracing::warn!("管理请求缺少来源地址，拒绝: {}", request.uri().path());
+    }
+    addr
+}
+
+/// 按连接来源放行：TCP 回环地址，或内部监听的 Unix socket（由文件权限控制访问）
+///
+/// 公开的 Unix socket 通常由本机 nginx 转发公网请求，不视为 localhost
+fn local_access(request: &Request, addr: SocketAddr) -> Option<&'static str> {
+    match request.extensions().get::<ListenerInfo>() {
+        Some(listener) if listener.unix => listener.internal.then_some("internal_socket"),
+        _ => addr.ip().is_loopback().then_some("localhost"),
+    }
+}
+
+/// 中间件：只允许 localhost 访问
+pub async fn localhost_only(mut request: Request, next: Next) -> Result<Response, Response> {
+    let Some(addr) = peer_addr(&request) else {
+        return Err((StatusCode::FORBIDDEN, "Admin API only accessible from localhost").into_response());
+    };
+    let Some(via) = local_access(&request, addr) else {
         tracing::warn!("拒绝非 localhost 的管理请求，来源: {}", addr);
         return Err((
             StatusCode::FORBIDDEN,
             "Admin API only accessible from localhost",
         )
             .into_response());
-    }
+    };
 
     tracing::debug!("允许来自 localhost 的管理请求: {}", addr);
+    AdminAccess::grant(&mut request, addr, via);
+    Ok(next.run(request).await)
+}
+
+/// 中间件：允许 localhost，或配置了 `admin.signing_secret` 时携带有效 HMAC 签名的请求
+///
+/// 经开启 mTLS（`server.tls.client_ca`）的监听到达时改为只接受 admin 角色的客户端证书；
+/// 内部监听（`server.internal_listen`）不做 TLS，仍按 localhost / 签名放行
+pub async fn localhost_or_signed(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    let Some(addr) = peer_addr(&request) else {
+        return Err((StatusCode::FORBIDDEN, "Admin API only accessible from localhost").into_response());
+    };
+    let mtls = match request.extensions().get::<ListenerInfo>() {
+        Some(listener) => listener.mtls,
+        None => state.config.server.tls.as_ref().is_some_and(|tls| tls.client_ca.is_some()),
+    };
+    if mtls {
+        return match request.extensions().get::<ClientCertIdentity>() {
+            Some(identity) if identity.is_admin() => {
+                tracing::info!("允许客户端证书 {} 的管理请求，来源: {}", identity.cn, addr);
+                AdminAccess::grant(&mut request, addr, "client_cert");
+                Ok(next.run(request).await)
+            }
+            identity => {
+                tracing::warn!(
+                    "拒绝管理请求，来源: {}，客户端证书: {}",
+                    addr,
+                    identity.map(|i| i.cn.as_str()).unwrap_or("无")
+                );
+                Err((StatusCode::FORBIDDEN, "Admin API requires an admin client certificate").into_response())
+            }
+        };
+    }
+
+    if let Some(via) = local_access(&request, addr) {
+        tracing::debug!("允许来自 localhost 的管理请求: {}", addr);
+        AdminAccess::grant(&mut request, addr, via);
+        return Ok(next.run(request).await);
+    }
+
+    let Some(secret) = state.config.admin.signing_secret.as_deref() else {
+        tracing::warn!("拒绝非 localhost 的管理请求，来源: {}", addr);
+        return Err((StatusCode::FORBIDDEN, "Admin API only accessible from localhost").into_response());
+    };
+
+    // 签名覆盖请求体，需先完整读取再放回
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES)
+        .await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Admin request body too large").into_response())?;
+
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    if let Err(reason) = verify_signature(
+        secret,
+        header(ADMIN_TIMESTAMP_HEADER),
+        header(ADMIN_SIGNATURE_HEADER),
+        parts.method.as_str(),
+        path,
+        &body,
+        chrono::Utc::now().timestamp(),
+        state.config.admin.signature_max_skew_seconds as i64,
+    ) {
+        tracing::warn!("拒绝管理请求，来源: {}，签名校验失败: {}", addr, reason);
+        return Err((StatusCode::FORBIDDEN, "Invalid admin request signature").into_response());
+    }
+
+    tracing::info!("允许来自 {} 的签名管理请求: {} {}", addr, parts.method, path);
+    let mut request = Request::from_parts(parts, Body::from(body));
+    AdminAccess::grant(&mut request, addr, "signature");
     Ok(next.run(request).await)
 }
+
+/// 签名内容："{timestamp}\n{METHOD}\n{path?query}\n{body}"，算法 HMAC-SHA256
+fn admin_mac(secret: &str, timestamp: i64, method: &str, path: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC 接受任意长度的密钥");
+    mac.update(format!("{}\n{}\n{}\n", timestamp, method, path).as_bytes());
+    mac.update(body);
+    mac
+}
+
+#[allow(clippy::too_many_arguments)]
+fn verify_signature(
+    secret: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    now: i64,
+    max_skew: i64,
+) -> Result<(), &'static str> {
+    let timestamp: i64 = timestamp.ok_or("缺少时间戳")?.parse().map_err(|_| "时间戳格式错误")?;
+    if (now - timestamp).abs() > max_skew {
+        return Err("时间戳超出允许范围");
+    }
+    let signature = signature.ok_or("缺少签名")?;
+    let expected = hex::decode(signature).map_err(|_| "签名格式错误")?;
+    // 常量时间比较
+    admin_mac(secret, timestamp, method, path, body)
+        .verify_slice(&expected)
+        .map_err(|_| "签名不匹配")?;
+
+    // 时间窗口内同一签名只能使用一次
+    SEEN_SIGNATURES.retain(|_, ts| (now - *ts).abs() <= max_skew);
+    if SEEN_SIGNATURES.insert(signature.to_lowercase(), timestamp).is_some() {
+        return Err("签名已被使用");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign_admin_request(secret: &str, timestamp: i64, method: &str, path: &str, body: &[u8]) -> String {
+        hex::encode(admin_mac(secret, timestamp, method, path, body).finalize().into_bytes())
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let now = 1_700_000_000;
+        let sig = sign_admin_request("secret", now, "POST", "/admin/users", b"{\"a\":1}");
+        let verify = |secret: &str, ts: i64, sig: &str, body: &[u8]| {
+            verify_signature(secret, Some(&ts.to_string()), Some(sig), "POST", "/admin/users", body, now, 300)
+        };
+
+        assert_eq!(verify("wrong", now, &sig, b"{\"a\":1}"), Err("签名不匹配"));
+        assert_eq!(verify("secret", now, &sig, b"{\"a\":2}"), Err("签名不匹配"));
+        assert_eq!(verify("secret", now - 301, &sig, b"{\"a\":1}"), Err("时间戳超出允许范围"));
+        assert_eq!(verify("secret", now, &sig, b"{\"a\":1}"), Ok(()));
+        // 重放被拒绝
+        assert_eq!(verify("secret", now, &sig, b"{\"a\":1}"), Err("签名已被使用"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_connect_info_or_guard_is_forbidden() {
+        use tower::ServiceExt;
+        let request = |peer: Option<[u8; 4]>| {
+            let mut request = Request::builder().uri("/admin/x").body(Body::empty()).unwrap();
+            if let Some(ip) = peer {
+                request.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            }
+            request
+        };
+        let handler = |access: AdminAccess| async move { access.peer.to_string() };
+
+        // 监听器未注入 ConnectInfo 时 403（而不是提取失败的 500）
+        let guarded = axum::Router::new()
+            .route("/admin/x", axum::routing::get(handler))
+            .layer(axum::middleware::from_fn(localhost_only));
+        let status = |r: Response| r.status();
+        assert_eq!(status(guarded.clone().oneshot(request(None)).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(guarded.clone().oneshot(request(Some([10, 0, 0, 1]))).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(guarded.clone().oneshot(request(Some([127, 0, 0, 1]))).await.unwrap()), StatusCode::OK);
+
+        // Unix socket：公开 socket 不视为 localhost（nginx 转发的公网请求），内部 socket 放行
+        let unix = |internal: bool| {
+            let mut request = Request::builder().uri("/admin/x").body(Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(crate::listener::UNIX_PEER));
+            request.extensions_mut().insert(ListenerInfo { internal, unix: true, mtls: false });
+            request
+        };
+        assert_eq!(status(guarded.clone().oneshot(unix(false)).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(guarded.clone().oneshot(unix(true)).await.unwrap()), StatusCode::OK);
+        // 经受信任代理解析出的回环地址同样不能让公开 socket 上的请求当作 localhost
+        let mut forwarded = unix(false);
+        forwarded.extensions_mut().insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))));
+        assert_eq!(status(guarded.clone().oneshot(forwarded).await.unwrap()), StatusCode::FORBIDDEN);
+
+        // 漏挂中间件的管理路由即使来自 localhost 也不会执行
+        let unguarded = axum::Router::new().route("/admin/x", axum::routing::get(handler));
+        assert_eq!(status(unguarded.oneshot(request(Some([127, 0, 0, 1]))).await.unwrap()), StatusCode::FORBIDDEN);
+    }
+}
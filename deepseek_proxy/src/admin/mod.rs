@@ -1,5 +1,15 @@
+pub mod erasure;
+pub mod export;
 pub mod handler;
+pub mod impersonate;
 pub mod middleware;
+pub mod probe;
+pub mod routes;
 
+pub use erasure::*;
+pub use export::*;
 pub use handler::*;
+pub use impersonate::*;
 pub use middleware::*;
+pub use probe::*;
+pub use routes::{registry, AdminRoute};
@@ -0,0 +1,155 @@
+use crate::{
+    deepseek::{ChatRequest, Message, MessageContent},
+    quota::QuotaStatus,
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use serde::Serialize;
+use std::time::Instant;
+
+/// 单个探测步骤结果
+#[derive(Debug, Serialize)]
+pub struct ProbeStep {
+    pub name: &'static str,
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 探测结果
+#[derive(Debug, Serialize)]
+pub struct ProbeResponse {
+    pub success: bool,
+    pub latency_ms: u64,
+    pub steps: Vec<ProbeStep>,
+}
+
+/// 探测接口：端到端执行一次极小的上游请求（仅 localhost）
+///
+/// 配置了 `probe.username` 时依次验证 认证 -> 配额 -> 上游，并为该用户记录用量；
+/// 建议为探针用户设置 `unlimited = true`，避免占用真实配额。
+/// 成功返回 200，任一步骤失败返回 503。
+pub async fn probe_chat(State(state): State<AppState>) -> Response {
+    let started = Instant::now();
+    let mut steps = Vec::new();
+    let success = run_probe(&state, &mut steps).await;
+
+    crate::metrics::METRICS
+        .probe_runs
+        .with_label_values(&[if success { "success" } else { "failure" }])
+        .inc();
+
+    let status = if success { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = ProbeResponse {
+        success,
+        latency_ms: started.elapsed().as_millis() as u64,
+        steps,
+    };
+    (status, Json(body)).into_response()
+}
+
+/// 依次执行探测步骤，遇到失败立即停止
+async fn run_probe(state: &AppState, steps: &mut Vec<ProbeStep>) -> bool {
+    let probe = &state.config.probe;
+
+    if let Some(username) = &probe.username {
+        // 1. 认证：用户存在且激活，签发并校验 token
+        let step = Instant::now();
+        let result = match state.user_manager.get_user(username).await {
+            None => Err(format!("探针用户 {} 不存在", username)),
+            Some(u) if !u.is_active => Err(format!("探针用户 {} 已停用", username)),
+            Some(_) => state
+                .jwt_service
+                .generate_token(username)
+                .and_then(|t| state.jwt_service.validate_token(&t))
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+        if !record(steps, "auth", step, result) {
+            return false;
+        }
+
+        // 2. 配额检查
+        let step = Instant::now();
+        let result = match state.quota_manager.check_quota(username).await {
+            Ok(QuotaStatus::Ok { .. }) => Ok(()),
+            Ok(QuotaStatus::Exceeded { used, limit, .. }) => Err(format!("配额已耗尽: {}/{}", used, limit)),
+            Err(e) => Err(e.to_string()),
+        };
+        if !record(steps, "quota", step, result) {
+            return false;
+        }
+    }
+
+    // 3. 上游：发送极小请求并读完整个流
+    let step = Instant::now();
+    let result = probe_upstream(state).await;
+    if !record(steps, "upstream", step, result) {
+        return false;
+    }
+
+    // 4. 记录探针用户用量
+    if let Some(username) = &probe.username {
+        let step = Instant::now();
+        let result = state.quota_manager.increment_quota(username).await.map(|_| ()).map_err(|e| e.to_string());
+        if !record(steps, "record_usage", step, result) {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn probe_upstream(state: &AppState) -> Result<(), String> {
+    let request = ChatRequest {
+        model: state.config.probe.model.clone(),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(MessageContent::Text(state.config.probe.prompt.clone())),
+            extra: serde_json::Map::new(),
+        }],
+        temperature: None,
+        top_p: None,
+        max_tokens: Some(1),
+        n: None,
+        logprobs: None,
+        top_logprobs: None,
+        stream: true,
+        metadata: None,
+        extra: serde_json::json!({}),
+    };
+
+    let stream = state.deepseek_client.chat_stream(request).await.map_err(|e| e.to_string())?;
+    futures::pin_mut!(stream);
+
+    let mut bytes = 0usize;
+    while let Some(chunk) = stream.next().await {
+        bytes += chunk.map_err(|e| format!("读取上游流失败: {}", e))?.len();
+    }
+    if bytes == 0 {
+        return Err("上游返回空响应".to_string());
+    }
+    Ok(())
+}
+
+/// 记录步骤结果，返回是否成功
+fn record(steps: &mut Vec<ProbeStep>, name: &'static str, started: Instant, result: Result<(), String>) -> bool {
+    let ok = result.is_ok();
+    if let Err(e) = &result {
+        tracing::warn!(step = name, error = %e, "探测步骤失败");
+    }
+    steps.push(ProbeStep {
+        name,
+        ok,
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: result.err(),
+    });
+    ok
+}
@@ -0,0 +1,62 @@
+use super::*;
+use crate::{conditional::conditional_get, AppState};
+use axum::{
+    http::Method,
+    middleware::from_fn,
+    routing::{delete, get, post, MethodRouter},
+};
+
+/// 一条管理接口路由
+pub struct AdminRoute {
+    pub method: Method,
+    /// axum 路径模板，如 `/admin/users/:username`
+    pub path: &'static str,
+    handler: MethodRouter<AppState>,
+}
+
+impl AdminRoute {
+    fn new(method: Method, path: &'static str, handler: MethodRouter<AppState>) -> Self {
+        Self { method, path, handler }
+    }
+
+    pub(crate) fn into_handler(self) -> MethodRouter<AppState> {
+        self.handler
+    }
+}
+
+/// 全部 `/admin/*` 路由（新增管理接口只在这里登记）
+///
+/// 由 [`crate::routes::registry`] 统一按 admin 策略挂上访问控制中间件；端到端测试遍历这张表，确认每条路由都拒绝非 localhost 请求
+pub fn registry() -> Vec<AdminRoute> {
+    vec![
+        AdminRoute::new(Method::POST, "/admin/users/:username/active", post(set_user_active)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/credits", post(grant_credits)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/allowed_ips", post(set_allowed_ips)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/password", post(reset_password)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/logout", post(force_logout)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/login_notify", post(set_login_notify)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/model", post(set_pinned_model)),
+        AdminRoute::new(Method::POST, "/admin/users/:username/totp", post(provision_totp)),
+        AdminRoute::new(Method::DELETE, "/admin/users/:username/totp", delete(disable_totp)),
+        AdminRoute::new(Method::DELETE, "/admin/users/:username/data", delete(erase_user_data)),
+        AdminRoute::new(Method::GET, "/admin/users/:username/export", get(export_user_data)),
+        AdminRoute::new(Method::GET, "/admin/users/:username", get(get_user)),
+        AdminRoute::new(Method::POST, "/admin/impersonate/:username", post(impersonate_user)),
+        AdminRoute::new(Method::GET, "/admin/integrity", get(integrity)),
+        AdminRoute::new(Method::POST, "/admin/backup", post(backup)),
+        AdminRoute::new(Method::GET, "/admin/slo", get(slo)),
+        AdminRoute::new(Method::GET, "/admin/flags", get(flags)),
+        AdminRoute::new(Method::GET, "/admin/cache-stats", get(cache_stats)),
+        AdminRoute::new(Method::GET, "/admin/security/lockouts", get(list_lockouts)),
+        AdminRoute::new(Method::DELETE, "/admin/security/lockouts/:key", delete(unban_lockout)),
+        AdminRoute::new(Method::GET, "/admin/ip_rules", get(ip_rules)),
+        AdminRoute::new(Method::POST, "/admin/ip_rules", post(set_ip_rules)),
+        AdminRoute::new(Method::GET, "/admin/config/effective", get(effective_config)),
+        AdminRoute::new(Method::GET, "/admin/quotas/preview-reset", get(preview_quota_reset)),
+        AdminRoute::new(Method::GET, "/admin/quotas", get(list_quotas).layer(from_fn(conditional_get))),
+        AdminRoute::new(Method::GET, "/admin/quotas/archive", get(quota_archive_months)),
+        AdminRoute::new(Method::GET, "/admin/quotas/archive/:month", get(quota_archive)),
+        AdminRoute::new(Method::GET, "/admin/users", get(list_users).layer(from_fn(conditional_get))),
+        AdminRoute::new(Method::POST, "/admin/users", post(create_user)),
+    ]
+}
@@ -0,0 +1,385 @@
+//! 应用组装：由配置初始化 [`AppState`] 并构建路由
+//!
+//! `main.rs` 只负责日志、加载配置与监听；测试或其他二进制可以用 [`build_app`] / [`AppBuilder`] 嵌入整个服务
+
+use crate::{
+    access_log,
+    auth::{self, bruteforce::BruteForceGuard, JwtService},
+    backup, branding, client_ip,
+    config::Config,
+    deepseek::DeepSeekClient,
+    disk_health, downloads, error_report, flags, integrity, ip_rules, load_shed, metrics, notify, object_storage, panic_guard,
+    proxy::{self, GlobalRateLimiter, LoginLimiter},
+    quota::{self, QuotaManager},
+    read_only, reconcile, routes, shutdown_report, slo, statsd,
+    user_activity::UserActivityLogger,
+};
+use axum::{middleware, Router};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+// 统一的应用状态
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub jwt_service: Arc<JwtService>,
+    pub deepseek_client: Arc<DeepSeekClient>,
+    pub upstreams: Arc<crate::deepseek::upstreams::UpstreamRegistry>, // 可经 X-Upstream 选用的其他上游
+    pub login_limiter: Arc<LoginLimiter>, // 现在统一管理Token生命周期和并发控制
+    pub quota_manager: Arc<QuotaManager>,
+    pub charge_journal: Arc<quota::ChargeJournal>, // 流式响应的在途扣费记录
+    pub user_manager: Arc<auth::UserManager>, // 用户管理器（内存+持久化）
+    pub global_rate_limiter: Arc<GlobalRateLimiter>, // 全局速率限制器
+    pub activity_logger: Arc<UserActivityLogger>, // 用户行为日志记录器
+    pub brute_force_guard: Arc<BruteForceGuard>, // 登录失败检测
+    pub notifier: Arc<notify::Notifier>, // 告警通知
+    pub load_shedder: Arc<load_shed::LoadShedder>, // 系统压力降级
+    pub integrity_report: Arc<integrity::IntegrityReport>, // 启动时数据目录检查结果
+    pub backup: Arc<backup::BackupService>, // data/ 目录备份
+    pub spam_guard: Arc<proxy::spam::SpamGuard>, // 重复提问检测
+    pub prompt_cache: Arc<proxy::prompt_cache::PromptCacheAdvisor>, // 重复 system 提示检测（缓存对齐建议）
+    pub known_ips: Arc<auth::known_ips::KnownIpStore>, // 登录 IP 记录（新 IP 登录通知）
+    pub refresh_tokens: Arc<auth::refresh::RefreshTokenStore>, // 已签发未使用的 refresh token
+    pub revoked_tokens: Arc<auth::revocation::RevocationList>, // 已注销的访问 token
+    pub flags: Arc<flags::FeatureFlags>, // 实验性功能开关（随 config.toml 热更新）
+    pub ip_rules: Arc<ip_rules::IpRules>, // 全局 IP 允许 / 拒绝名单（可经管理接口修改）
+    pub downloads: Arc<downloads::DownloadStore>, // 导出文件的签名下载链接
+}
+
+impl AppState {
+    /// 由配置初始化全部组件：加载 data/ 下的用户与配额、检查数据目录，并启动各组件的后台任务
+    /// （上游预热、指标推送、SLO 采样、用量对账、磁盘检查、定时备份、负载监控、不活跃账户过期）
+    pub async fn new(config: Config) -> anyhow::Result<Self> {
+        if config.server.read_only {
+            read_only::enable();
+            tracing::warn!("只读模式：不写 data/ 目录，配额用量只保存在内存中（重启后丢失），修改数据的管理接口与数据导出已停用");
+        }
+        tracing::info!("DeepSeek API: {}", config.deepseek.base_url);
+        tracing::info!("限流: 每个 token 同时只允许1个请求");
+    
+        // 安全限制：登录缓存和 JWT TTL 最多 60 秒，防止 token 长时间有效
+        let effective_ttl = config.auth.token_ttl_seconds.min(60);
+        if config.auth.token_ttl_seconds > 60 {
+            tracing::warn!(
+                "配置的 token_ttl_seconds ({}) 超过安全限制，已强制限制为 60 秒",
+                config.auth.token_ttl_seconds
+            );
+        }
+        tracing::info!("登录缓存: 每个用户 {} 秒内复用同一 token", effective_ttl);
+        tracing::info!("JWT有效期: {} 秒", effective_ttl);
+    
+        tracing::info!("HTTP客户端: 连接池={}个, 保活={}秒, 连接超时={}秒", 
+            config.deepseek.http_client.pool_max_idle_per_host,
+            config.deepseek.http_client.pool_idle_timeout_seconds,
+            config.deepseek.http_client.connect_timeout_seconds
+        );
+
+        // 初始化组件
+        // 加载今日指标快照（如果存在）
+        if let Err(e) = metrics::METRICS.load_today() {
+            tracing::warn!("加载今日指标快照失败: {}", e);
+        } else {
+            tracing::info!("今日指标快照加载完成");
+        }
+        // 清理超过 90 天的历史指标文件
+        if let Err(e) = metrics::METRICS.cleanup_old_days(90) {
+            tracing::warn!("清理指标历史文件失败: {}", e);
+        }
+        let jwt_service = JwtService::new(
+            config.auth.jwt_secret.clone(),
+            effective_ttl,  // 使用安全限制后的 TTL
+        ).map_err(|e| anyhow::anyhow!("JWT服务初始化失败: {}", e))?
+            .with_refresh_ttl(config.auth.refresh_token_ttl_seconds);
+        let jwt_service = Arc::new(match &config.auth.jwt_private_key_path {
+            Some(path) if config.auth.jwt_algorithm.is_asymmetric() => {
+                let pem = std::fs::read(path).map_err(|e| anyhow::anyhow!("读取 JWT 私钥 {} 失败: {}", path, e))?;
+                jwt_service
+                    .with_signing_key(config.auth.jwt_algorithm, &pem)
+                    .map_err(|e| anyhow::anyhow!("JWT 私钥 {} 无效: {}", path, e))?
+            }
+            _ => jwt_service,
+        });
+
+        let notifier = Arc::new(notify::Notifier::from_config(&config.security, &config.notifications)?);
+
+        let deepseek_client = Arc::new(DeepSeekClient::new(
+            config.deepseek.api_key.clone(),
+            config.deepseek.base_url.clone(),
+            config.deepseek.timeout_seconds,
+            &config.deepseek.http_client,
+        ).map_err(|e| anyhow::anyhow!("DeepSeek客户端初始化失败: {}", e))?
+            .with_metadata_cache_ttl(std::time::Duration::from_secs(config.deepseek.metadata_cache_ttl_seconds))
+            .with_request_transforms(config.deepseek.request_transforms.clone())
+            .with_failure_alerts(notifier.clone(), config.notifications.upstream_failure_threshold)
+            .with_fault_injector(crate::chaos::FaultInjector::from_config(&config.chaos)?)
+            .with_outbound_limit(crate::deepseek::outbound::OutboundLimiter::from_config(&config.deepseek.outbound_rate_limit)));
+
+        let upstreams = Arc::new(crate::deepseek::upstreams::UpstreamRegistry::from_config(&config.deepseek)?);
+
+        if config.deepseek.http_client.warmup {
+            deepseek_client.clone().spawn_warmup_task(std::time::Duration::from_secs(
+                config.deepseek.http_client.pool_idle_timeout_seconds,
+            ));
+        }
+
+        branding::init(&config.branding);
+
+        if error_report::init(&config.observability)? {
+            tracing::info!("错误上报已启用（500 错误与 panic）");
+        }
+
+        if config.observability.statsd.enabled {
+            statsd::spawn_exporter(config.observability.statsd.clone());
+            tracing::info!("StatsD 指标推送: {}", config.observability.statsd.addr);
+        }
+
+        slo::spawn_recorder(config.slo.clone());
+        reconcile::spawn(config.reconciliation.clone(), deepseek_client.clone())?;
+        disk_health::spawn_monitor(
+            config.disk_health.clone(),
+            vec![("data", PathBuf::from("data")), ("logs", PathBuf::from("logs"))],
+            notifier.clone(),
+        );
+
+        let refresh_tokens = Arc::new(auth::refresh::RefreshTokenStore::load(PathBuf::from("data/refresh_tokens.json")).await);
+        let revoked_tokens = Arc::new(auth::revocation::RevocationList::load(PathBuf::from("data/revoked_tokens.json")).await);
+        let login_limiter = Arc::new(LoginLimiter::new(effective_ttl).with_refresh_tokens(refresh_tokens.clone()));  // 使用安全限制后的 TTL
+
+        // 初始化用户管理器（基于文件存储）- 必须在配额管理器之前
+        let users_dir = PathBuf::from("data/users");
+        let hash_params = auth::password::params(config.auth.password_hash_memory_kib, config.auth.password_hash_time_cost)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let user_manager = Arc::new(
+            auth::UserManager::new(users_dir, config.auth.users.clone(), hash_params)
+                .await
+                .map_err(|e| anyhow::anyhow!("用户管理器初始化失败: {}", e))?
+                .with_password_policy(config.security.password_policy.clone())
+        );
+        tracing::info!("用户管理器初始化完成，用户数据存储在 data/users/");
+
+        // 数据目录完整性检查（无法解析、孤立的配额文件、写入残留）
+        let users = user_manager.list_users().await;
+        for user in users.iter().filter(|u| !config.quota.tiers.contains(&u.quota_tier)) {
+            tracing::warn!(
+                user = %user.username,
+                tier = %user.quota_tier,
+                fallback = ?config.quota.fallback_tier,
+                "用户档次未在 [quota.tiers] 中定义"
+            );
+        }
+        let known_users = users.into_iter().map(|u| u.username).collect();
+        let mut integrity_report = integrity::scan(Path::new("data"), &known_users).await;
+        if config.data_integrity.quarantine && !read_only::is_enabled() {
+            integrity::quarantine(Path::new("data"), &mut integrity_report).await;
+        }
+        integrity::log_summary(&integrity_report);
+
+        // 初始化配额管理器（需要 user_manager 来查询动态用户）
+        let data_dir = PathBuf::from("data/quotas");
+        if !read_only::is_enabled() {
+            tokio::fs::create_dir_all(&data_dir).await?;
+        }
+        let config_arc = Arc::new(config.clone());
+        let quota_manager = Arc::new(QuotaManager::new(
+            config_arc,
+            user_manager.clone(),
+            data_dir,
+            config.quota.save_interval,
+        ));
+
+        tracing::info!("配额: 每 {} 次请求写一次磁盘", config.quota.save_interval);
+        // 处理上次运行遗留的未完成响应扣费（需在接受请求前完成）
+        let charge_journal = Arc::new(quota::ChargeJournal::load(PathBuf::from("data/charge_journal.json"), config.quota.crash_recovery).await);
+        charge_journal.recover(&quota_manager).await;
+        charge_journal.clone().spawn_flusher();
+        quota_manager.clone().spawn_topup_scheduler(PathBuf::from("data/topups/runs.json"));
+        quota_manager.clone().spawn_compactor();
+
+        let object_storage = object_storage::ObjectStorage::from_config(config.object_storage.as_ref());
+        if let Some(cfg) = &config.object_storage {
+            tracing::info!("对象存储: {} bucket={}，备份与滚动后的行为日志将转存", cfg.endpoint, cfg.bucket);
+        }
+
+        let backup = Arc::new(backup::BackupService::new(
+            config.backup.clone(),
+            "data",
+            quota_manager.clone(),
+            object_storage.clone(),
+        ));
+        backup.spawn_schedule();
+        if config.backup.enabled && !read_only::is_enabled() {
+            tracing::info!(
+                "定时备份: 每 {} 小时打包 data/ 到 {}/，保留 {} 份",
+                config.backup.interval_hours, config.backup.dir, config.backup.retention
+            );
+        }
+
+        // 初始化全局速率限制器
+        let global_rate_limiter = Arc::new(GlobalRateLimiter::from_config(&config.rate_limit));
+        tracing::info!("全局速率限制: {}", global_rate_limiter.info());
+
+        // 初始化用户行为日志记录器
+        let activity_logger = Arc::new(UserActivityLogger::new("logs/users", &config.activity_log, object_storage));
+        tracing::info!("用户行为日志: logs/users/");
+        let brute_force_guard = Arc::new(BruteForceGuard::load(config.security.clone(), PathBuf::from("data/login_attempts.json")).await);
+        brute_force_guard.clone().spawn_snapshotter();
+        let load_shedder = Arc::new(load_shed::LoadShedder::new(config.load_shedding.clone()));
+        load_shedder.spawn_monitor();
+        if config.load_shedding.enabled {
+            tracing::info!("负载降级: 过载时拒绝档次 {:?}", config.load_shedding.shed_tiers);
+        }
+        let spam_guard = Arc::new(proxy::spam::SpamGuard::new(config.spam.clone()));
+        if config.spam.enabled {
+            tracing::info!(
+                "重复提问检测: {} 秒内相同内容超过 {} 次即限流",
+                config.spam.window_seconds, config.spam.max_duplicates
+            );
+        }
+        let prompt_cache = Arc::new(proxy::prompt_cache::PromptCacheAdvisor::new(config.prompt_cache.clone()));
+
+        let known_ips = Arc::new(auth::known_ips::KnownIpStore::new(PathBuf::from("data/known_ips")));
+        auth::expiry::UserExpiry::new(
+            config.user_expiry.clone(),
+            PathBuf::from("data/archive"),
+            user_manager.clone(),
+            quota_manager.clone(),
+            known_ips.clone(),
+            login_limiter.clone(),
+            activity_logger.clone(),
+        )
+        .spawn();
+
+        let ip_rules = Arc::new(ip_rules::IpRules::load(&config.security.ip_rules, PathBuf::from("data/ip_rules.json")).await?);
+
+        let downloads = Arc::new(
+            downloads::DownloadStore::new(
+                PathBuf::from("downloads"),
+                config.admin.download_ttl_seconds,
+                config.admin.download_base_url.clone(),
+            )
+            .await,
+        );
+        downloads.clone().spawn_cleaner();
+
+        let flags = Arc::new(flags::FeatureFlags::new(&config.flags));
+        flags.clone().spawn_watcher(PathBuf::from("config.toml"));
+
+        let config = Arc::new(config);
+
+        // 创建统一的应用状态
+        Ok(Self {
+            config: config.clone(),
+            jwt_service,
+            deepseek_client,
+            upstreams,
+            login_limiter, // 统一管理Token生命周期和并发控制
+            quota_manager: quota_manager.clone(),
+            charge_journal,
+            user_manager,
+            global_rate_limiter,
+            activity_logger,
+            brute_force_guard,
+            notifier,
+            load_shedder,
+            integrity_report: Arc::new(integrity_report),
+            backup,
+            spam_guard,
+            prompt_cache,
+            known_ips,
+            refresh_tokens,
+            revoked_tokens,
+            flags,
+            ip_rules,
+            downloads,
+        })
+    }
+}
+
+/// 构建好的路由
+pub struct Routers {
+    /// 公开接口；未分离管理面时也包含 /admin、/probe、/metrics
+    pub public: Router,
+    /// 分离管理面时的内部接口（/admin、/probe、/metrics 与 /readyz）
+    pub internal: Option<Router>,
+}
+
+/// 路由构建器
+///
+/// ```ignore
+/// let state = AppState::new(config).await?;
+/// let routers = AppBuilder::new(state).separate_internal(true).build()?;
+/// ```
+pub struct AppBuilder {
+    state: AppState,
+    separate_internal: bool,
+}
+
+impl AppBuilder {
+    pub fn new(state: AppState) -> Self {
+        Self { state, separate_internal: false }
+    }
+
+    /// 把管理面放到单独的内部路由（对应 `server.internal_listen`）
+    pub fn separate_internal(mut self, separate: bool) -> Self {
+        self.separate_internal = separate;
+        self
+    }
+
+    /// 构建路由；受信任代理配置无效时返回错误
+    pub fn build(self) -> anyhow::Result<Routers> {
+        let state = self.state;
+        let trusted_proxies = Arc::new(client_ip::TrustedProxies::from_config(&state.config.server.trusted_proxies)?);
+
+        // 所有接口登记在 routes::registry，按各自的策略挂载鉴权与限制中间件
+        let routes = routes::registry(&state.config);
+        routes::validate(&routes).map_err(|e| anyhow::anyhow!("路由策略无效: {}", e))?;
+        let (public_routes, internal_routes, shared_routes) = routes::build(routes, &state);
+
+        // 请求日志按 [observability.access_log] 排除探针路径与敏感请求头
+        let access_log = access_log::AccessLogFilter::new(&state.config.observability.access_log);
+
+        // 公共中间件：handler panic 转为标准 500 JSON，再由错误上报中间件带上下文上报
+        let finish = |routes: Router<AppState>| {
+            routes
+                .layer(CatchPanicLayer::custom(panic_guard::panic_response))
+                .layer(middleware::from_fn_with_state(state.clone(), error_report::report_server_errors))
+                .with_state(state.clone())
+                .layer(middleware::from_fn_with_state(state.ip_rules.clone(), ip_rules::enforce))
+                .layer(middleware::from_fn_with_state(trusted_proxies.clone(), client_ip::resolve_client_ip))
+                .layer(middleware::from_fn(panic_guard::request_id_scope))
+                .layer(middleware::from_fn(shutdown_report::count_requests))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(access_log.clone())
+                        .on_request(access_log::FilteredOnRequest)
+                        .on_response(access_log::FilteredOnResponse),
+                )
+        };
+
+        // 配置内部地址时，管理面（/admin、/probe、/metrics）只绑定在内部地址；否则与公开接口共用一个监听
+        let public_routes = public_routes.merge(shared_routes.clone());
+        Ok(if self.separate_internal {
+            Routers {
+                public: finish(public_routes),
+                internal: Some(finish(internal_routes.merge(shared_routes))),
+            }
+        } else {
+            Routers { public: finish(public_routes.merge(internal_routes)), internal: None }
+        })
+    }
+}
+
+/// 由配置构建完整应用（所有接口在同一个路由中），用于测试或嵌入其他服务
+///
+/// data/、logs/ 等目录相对于当前工作目录
+pub async fn build_app(config: Config) -> anyhow::Result<Router> {
+    let state = AppState::new(config).await?;
+    Ok(AppBuilder::new(state).build()?.public)
+}
@@ -0,0 +1,152 @@
+use crate::config::{Config, User};
+use crate::error::{AppError, AuthError};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// 允许使用服务的时段（北京时间），如教室账户只在工作日 08:00-18:00 可用
+///
+/// 各条件同时满足才允许；未配置的条件不限制
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize, JsonSchema)]
+pub struct AccessSchedule {
+    /// 允许的星期（mon / tue / ... / sun），为空表示每天
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+    /// 每天允许的时段，如 "08:00-18:00"；结束早于开始时跨零点（如 "22:00-06:00"）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub hours: Option<HoursRange>,
+    /// 生效日期（含），如学期开始日
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    /// 截止日期（含），如学期结束日
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<NaiveDate>,
+}
+
+/// "HH:MM-HH:MM" 形式的每日时段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HoursRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TryFrom<String> for HoursRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |s: &str| NaiveTime::parse_from_str(s.trim(), "%H:%M");
+        match value.split_once('-').map(|(a, b)| (parse(a), parse(b))) {
+            Some((Ok(start), Ok(end))) if start != end => Ok(Self { start, end }),
+            _ => Err(format!("时段 \"{}\" 格式应为 HH:MM-HH:MM", value)),
+        }
+    }
+}
+
+impl From<HoursRange> for String {
+    fn from(range: HoursRange) -> Self {
+        format!("{}-{}", range.start.format("%H:%M"), range.end.format("%H:%M"))
+    }
+}
+
+impl HoursRange {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl AccessSchedule {
+    /// 该时刻（北京时间）是否允许访问
+    pub fn allows(&self, now: NaiveDateTime) -> bool {
+        let date = now.date();
+        self.from.is_none_or(|from| date >= from)
+            && self.until.is_none_or(|until| date <= until)
+            && (self.days.is_empty() || self.days.contains(&now.weekday()))
+            && self.hours.is_none_or(|hours| hours.contains(now.time()))
+    }
+
+    /// 用于错误提示的可读描述
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(from) = self.from {
+            parts.push(format!("{} 起", from));
+        }
+        if let Some(until) = self.until {
+            parts.push(format!("至 {}", until));
+        }
+        if !self.days.is_empty() {
+            parts.push(self.days.iter().map(|d| weekday_name(*d)).collect::<Vec<_>>().join("、"));
+        }
+        if let Some(hours) = self.hours {
+            parts.push(String::from(hours));
+        }
+        format!("{}（北京时间）", parts.join(" "))
+    }
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "周一",
+        Weekday::Tue => "周二",
+        Weekday::Wed => "周三",
+        Weekday::Thu => "周四",
+        Weekday::Fri => "周五",
+        Weekday::Sat => "周六",
+        Weekday::Sun => "周日",
+    }
+}
+
+/// 用户的访问时段：用户记录中的配置优先，其次是档次配置
+pub fn effective<'a>(config: &'a Config, user: &'a User) -> Option<&'a AccessSchedule> {
+    user.access_schedule.as_ref().or_else(|| config.access_schedules.for_tier(&user.quota_tier))
+}
+
+/// 当前不在允许时段内时返回 403 outside_access_hours
+pub fn check(config: &Config, user: &User) -> Result<(), AppError> {
+    let Some(schedule) = effective(config, user) else {
+        return Ok(());
+    };
+    if schedule.allows(crate::utils::now_beijing().naive_local()) {
+        return Ok(());
+    }
+    tracing::info!(user = %user.username, tier = %user.quota_tier, "不在账户允许的访问时段内，拒绝请求");
+    Err(AppError::Auth(AuthError::OutsideAccessHours(schedule.describe())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_weekday_office_hours() {
+        let schedule: AccessSchedule = toml::from_str(
+            "days = [\"mon\", \"tue\", \"wed\", \"thu\", \"fri\"]\nhours = \"08:00-18:00\"\nuntil = \"2027-01-15\"\n",
+        )
+        .unwrap();
+        assert!(schedule.allows(at("2026-10-16", "08:00"))); // 周五
+        assert!(!schedule.allows(at("2026-10-16", "18:00")));
+        assert!(!schedule.allows(at("2026-10-17", "10:00"))); // 周六
+        assert!(!schedule.allows(at("2027-01-18", "10:00"))); // 学期结束后
+        assert_eq!(schedule.describe(), "至 2027-01-15 周一、周二、周三、周四、周五 08:00-18:00（北京时间）");
+    }
+
+    #[test]
+    fn test_overnight_hours_and_invalid_range() {
+        let schedule: AccessSchedule = toml::from_str("hours = \"22:00-06:00\"\n").unwrap();
+        assert!(schedule.allows(at("2026-10-16", "23:30")));
+        assert!(schedule.allows(at("2026-10-17", "05:59")));
+        assert!(!schedule.allows(at("2026-10-17", "12:00")));
+
+        assert!(toml::from_str::<AccessSchedule>("hours = \"8-18\"\n").is_err());
+        assert!(toml::from_str::<AccessSchedule>("days = [\"someday\"]\n").is_err());
+    }
+}
@@ -1,38 +1,291 @@
-use dashmap::DashMap;
-use std::time::{Duration, Instant};
+//! 登录失败检测：同一 `用户名:IP` 在窗口内失败次数达到阈值即阻断登录
+//!
+//! 阻断时长逐级递增（`[security] login_lockout_steps_seconds`，默认 1 分钟、5 分钟、30 分钟、24 小时），
+//! 登录成功、管理员解除阻断，或上次阻断结束后超过最长一级时长没有再被阻断时回到第一级。
+//!
+//! 失败记录有变化时定期保存到 data/login_attempts.json，关闭时再保存一次，重启后阻断仍然有效；
+//! 管理员可通过 `/admin/security/lockouts` 查看与解除阻断
+
 use crate::config::SecurityConfig;
+use chrono::{FixedOffset, TimeZone};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 有变化时保存失败记录的间隔
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 当前被阻断的 `用户名:IP`
+#[derive(Debug, Clone, Serialize)]
+pub struct Lockout {
+    /// 解除阻断时使用的键（`用户名:IP`）
+    pub key: String,
+    pub username: String,
+    pub ip: String,
+    /// 第几级阻断（从 1 开始）
+    pub level: usize,
+    /// 解除阻断的时间
+    pub blocked_until: String,
+    /// 剩余阻断秒数
+    pub retry_after_seconds: u64,
+}
 
-// 记录失败尝试 (username:ip -> Vec<Instant>) 简易实现
+/// 单个 `用户名:IP` 的失败记录（时间均为 Unix 毫秒）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AttemptRecord {
+    /// 当前窗口内的失败时间（进入阻断时清空）
+    #[serde(default)]
+    failures: Vec<i64>,
+    /// 已经历的阻断次数，决定下一次阻断的时长
+    #[serde(default)]
+    level: usize,
+    /// 阻断结束时间
+    #[serde(default)]
+    locked_until: Option<i64>,
+}
+
+// 记录失败尝试 (username:ip -> 失败记录)
 pub struct BruteForceGuard {
-    attempts: DashMap<String, Vec<Instant>>,
+    attempts: DashMap<String, AttemptRecord>,
     cfg: SecurityConfig,
+    path: PathBuf,
+    /// 上次保存后是否有变化
+    dirty: AtomicBool,
 }
 
 impl BruteForceGuard {
-    pub fn new(cfg: SecurityConfig) -> Self { Self { attempts: DashMap::new(), cfg } }
+    /// 加载上次保存的失败记录（已失效的丢弃）；文件不存在或无法解析时从空表开始
+    pub async fn load(cfg: SecurityConfig, path: PathBuf) -> Self {
+        let saved: HashMap<String, AttemptRecord> = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "登录失败记录无法解析，已忽略");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let guard = Self { attempts: DashMap::new(), cfg, path, dirty: AtomicBool::new(false) };
+        let now = now_millis();
+        for (key, mut record) in saved {
+            if guard.prune(&mut record, now) {
+                guard.attempts.insert(key, record);
+            }
+        }
+        let blocked = guard.lockouts().len();
+        if blocked > 0 {
+            tracing::warn!(blocked, "恢复了上次运行中被阻断的登录来源");
+        }
+        guard
+    }
 
     fn key(username: &str, ip: &str) -> String { format!("{}:{}", username, ip) }
 
+    fn window_millis(&self) -> i64 {
+        self.cfg.login_fail_window_seconds as i64 * 1000
+    }
+
+    /// 第 `level` 级（从 0 开始）阻断的时长，超过配置的级数时沿用最后一级
+    fn step_millis(&self, level: usize) -> i64 {
+        let steps = &self.cfg.login_lockout_steps_seconds;
+        let seconds = steps.get(level).or(steps.last()).copied().unwrap_or(self.cfg.login_fail_window_seconds);
+        seconds as i64 * 1000
+    }
+
+    /// 阻断结束后经过这么久没有再被阻断，阻断级别回到第一级
+    fn decay_millis(&self) -> i64 {
+        self.step_millis(usize::MAX)
+    }
+
+    /// 丢弃窗口外的失败、已结束的阻断与已过期的阻断级别，返回记录是否仍需保留
+    fn prune(&self, record: &mut AttemptRecord, now: i64) -> bool {
+        let window = self.window_millis();
+        record.failures.retain(|t| now - *t <= window);
+        if let Some(until) = record.locked_until {
+            if now - until > self.decay_millis() {
+                record.locked_until = None;
+                record.level = 0;
+            }
+        }
+        !record.failures.is_empty() || record.locked_until.is_some()
+    }
+
     pub fn record_failure(&self, username: &str, ip: &str) -> usize {
-        let now = Instant::now();
-        let window = Duration::from_secs(self.cfg.login_fail_window_seconds);
-        let key = Self::key(username, ip);
-        let mut vec = self.attempts.entry(key).or_insert_with(Vec::new);
+        self.record_failure_at(&Self::key(username, ip), now_millis())
+    }
+
+    /// 记录一次失败，窗口内的失败次数达到阈值时进入下一级阻断；返回窗口内的失败次数
+    fn record_failure_at(&self, key: &str, now: i64) -> usize {
+        let mut record = self.attempts.entry(key.to_string()).or_default();
         // 清理过期
-        vec.retain(|t| now.duration_since(*t) <= window);
-        vec.push(now);
-        vec.len()
+        self.prune(&mut record, now);
+        record.failures.push(now);
+        let fails = record.failures.len();
+        if fails >= self.cfg.login_fail_threshold.max(1) {
+            let lock = self.step_millis(record.level);
+            record.locked_until = Some(now + lock);
+            record.level += 1;
+            record.failures.clear();
+            tracing::warn!(key = %key, level = record.level, lock_seconds = lock / 1000, "登录失败次数达到阈值，阻断该来源");
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+        fails
     }
 
-    pub fn should_block(&self, username: &str, ip: &str) -> bool {
-        let key = Self::key(username, ip);
-        if let Some(vec) = self.attempts.get(&key) {
-            vec.len() >= self.cfg.login_fail_threshold
-        } else { false }
+    /// 仍在阻断中时返回剩余秒数（向上取整）
+    pub fn blocked_for(&self, username: &str, ip: &str) -> Option<u64> {
+        self.blocked_for_at(&Self::key(username, ip), now_millis())
+    }
+
+    fn blocked_for_at(&self, key: &str, now: i64) -> Option<u64> {
+        let until = self.attempts.get(key)?.locked_until?;
+        (until > now).then(|| ((until - now + 999) / 1000) as u64)
     }
 
     pub fn reset_on_success(&self, username: &str, ip: &str) {
         let key = Self::key(username, ip);
-        self.attempts.remove(&key);
+        if self.attempts.remove(&key).is_some() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// 当前被阻断的来源，按用户名排序
+    pub fn lockouts(&self) -> Vec<Lockout> {
+        let now = now_millis();
+        let beijing = FixedOffset::east_opt(8 * 3600).expect("Invalid timezone offset");
+        let mut lockouts: Vec<Lockout> = self
+            .attempts
+            .iter()
+            .filter_map(|entry| {
+                let until = entry.value().locked_until.filter(|until| *until > now)?;
+                let (username, ip) = entry.key().split_once(':')?;
+                Some(Lockout {
+                    key: entry.key().clone(),
+                    username: username.to_string(),
+                    ip: ip.to_string(),
+                    level: entry.value().level,
+                    blocked_until: beijing.timestamp_millis_opt(until).single()?.to_rfc3339(),
+                    retry_after_seconds: ((until - now + 999) / 1000) as u64,
+                })
+            })
+            .collect();
+        lockouts.sort_by(|a, b| a.key.cmp(&b.key));
+        lockouts
+    }
+
+    /// 解除阻断（清空该来源的失败记录与阻断级别），返回是否存在记录
+    pub fn unban(&self, key: &str) -> bool {
+        let removed = self.attempts.remove(key).is_some();
+        if removed {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// 保存失败记录（只读模式下只保留在内存中）
+    pub async fn persist(&self) {
+        if crate::read_only::is_enabled() {
+            return;
+        }
+        self.dirty.store(false, Ordering::Relaxed);
+        let now = now_millis();
+        self.attempts.retain(|_, record| self.prune(record, now));
+        let snapshot: HashMap<String, AttemptRecord> =
+            self.attempts.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect();
+        if let Err(e) = crate::utils::write_json_atomic(&self.path, &snapshot).await {
+            self.dirty.store(true, Ordering::Relaxed);
+            crate::metrics::METRICS.record_persist_failure("login_attempts");
+            tracing::warn!(error = %e, "写入登录失败记录失败");
+        }
+    }
+
+    /// 后台任务：失败记录有变化时定期保存
+    pub fn spawn_snapshotter(self: Arc<Self>) {
+        crate::supervisor::spawn("login_attempts_snapshotter", move || {
+            let guard = self.clone();
+            async move {
+                let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if guard.dirty.load(Ordering::Relaxed) {
+                        guard.persist().await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SecurityConfig {
+        SecurityConfig {
+            login_fail_window_seconds: 60,
+            login_fail_threshold: 2,
+            login_lockout_steps_seconds: vec![60, 300],
+            ..SecurityConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lockout_survives_reload_and_unban() {
+        let path = std::env::temp_dir().join("test_login_attempts.json");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let guard = BruteForceGuard::load(config(), path.clone()).await;
+        guard.record_failure("alice", "2001:db8::1");
+        assert_eq!(guard.blocked_for("alice", "2001:db8::1"), None);
+        guard.record_failure("alice", "2001:db8::1");
+        guard.record_failure("bob", "203.0.113.1");
+        assert!(guard.blocked_for("alice", "2001:db8::1").is_some_and(|secs| secs <= 60));
+        guard.persist().await;
+
+        let guard = BruteForceGuard::load(config(), path.clone()).await;
+        let lockouts = guard.lockouts();
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!((lockouts[0].username.as_str(), lockouts[0].ip.as_str(), lockouts[0].level), ("alice", "2001:db8::1", 1));
+        assert!(guard.unban("alice:2001:db8::1"));
+        assert!(!guard.unban("alice:2001:db8::1"));
+        assert_eq!(guard.blocked_for("alice", "2001:db8::1"), None);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_lockout_escalates_and_decays() {
+        let guard = BruteForceGuard::load(config(), std::env::temp_dir().join("test_login_attempts_escalate.json")).await;
+        let key = "carol:192.0.2.1";
+        let minute = 60_000;
+
+        // 窗口外的失败不计入
+        guard.record_failure_at(key, 0);
+        guard.record_failure_at(key, 2 * minute);
+        assert_eq!(guard.blocked_for_at(key, 2 * minute), None);
+
+        // 第一级 60 秒
+        guard.record_failure_at(key, 2 * minute + 1);
+        assert_eq!(guard.blocked_for_at(key, 2 * minute + 1), Some(60));
+        assert_eq!(guard.blocked_for_at(key, 3 * minute + 1), None);
+
+        // 第二级 300 秒，之后沿用最后一级
+        guard.record_failure_at(key, 4 * minute);
+        guard.record_failure_at(key, 4 * minute);
+        assert_eq!(guard.blocked_for_at(key, 4 * minute), Some(300));
+        guard.record_failure_at(key, 10 * minute);
+        guard.record_failure_at(key, 10 * minute);
+        assert_eq!(guard.blocked_for_at(key, 10 * minute), Some(300));
+
+        // 阻断结束后超过最长一级没有再被阻断，回到第一级
+        guard.record_failure_at(key, 30 * minute);
+        guard.record_failure_at(key, 30 * minute);
+        assert_eq!(guard.blocked_for_at(key, 30 * minute), Some(60));
     }
 }
@@ -0,0 +1,119 @@
+use crate::{
+    auth::{known_ips::KnownIpStore, UserInfo, UserManager},
+    config::UserExpiryConfig,
+    error::AppError,
+    proxy::LoginLimiter,
+    quota::QuotaManager,
+    user_activity::UserActivityLogger,
+};
+use chrono::{DateTime, FixedOffset};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 不活跃账户自动过期：定期找出超过 `inactive_days` 天没有登录的用户，停用后把用户文件、配额文件与
+/// 登录 IP 记录移到 `data/archive/{username}.{时间}/`，并在该用户的行为日志中记录审计事件
+///
+/// 归档后的用户不再出现在用户列表中；行为日志留在 logs/users/ 原处
+pub struct UserExpiry {
+    cfg: UserExpiryConfig,
+    archive_root: PathBuf,
+    user_manager: Arc<UserManager>,
+    quota_manager: Arc<QuotaManager>,
+    known_ips: Arc<KnownIpStore>,
+    login_limiter: Arc<LoginLimiter>,
+    activity_logger: Arc<UserActivityLogger>,
+}
+
+impl UserExpiry {
+    pub fn new(
+        cfg: UserExpiryConfig,
+        archive_root: PathBuf,
+        user_manager: Arc<UserManager>,
+        quota_manager: Arc<QuotaManager>,
+        known_ips: Arc<KnownIpStore>,
+        login_limiter: Arc<LoginLimiter>,
+        activity_logger: Arc<UserActivityLogger>,
+    ) -> Self {
+        Self { cfg, archive_root, user_manager, quota_manager, known_ips, login_limiter, activity_logger }
+    }
+
+    /// 启动定期检查（inactive_days 为 0 或只读模式下不启动）
+    pub fn spawn(self) {
+        if self.cfg.inactive_days == 0 || crate::read_only::is_enabled() {
+            return;
+        }
+        tracing::info!(
+            "不活跃账户过期: 超过 {} 天未登录的用户停用并归档到 {}/，每 {} 小时检查一次",
+            self.cfg.inactive_days,
+            self.archive_root.display(),
+            self.cfg.check_interval_hours
+        );
+        let expiry = Arc::new(self);
+        crate::supervisor::spawn("user_expiry", move || {
+            let expiry = expiry.clone();
+            async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(expiry.cfg.check_interval_hours.max(1) * 3600));
+                loop {
+                    ticker.tick().await;
+                    let expired = expiry.run_once(crate::utils::now_beijing()).await;
+                    if expired > 0 {
+                        tracing::warn!(expired, "不活跃账户已停用并归档");
+                    }
+                }
+            }
+        });
+    }
+
+    /// 检查一次，返回本次归档的用户数
+    pub async fn run_once(&self, now: DateTime<FixedOffset>) -> usize {
+        let mut expired = 0;
+        for user in self.user_manager.list_users().await {
+            if !user.is_active || user.unlimited || self.cfg.exempt_users.contains(&user.username) {
+                continue;
+            }
+            let Some(days) = user.last_login_at.as_deref().and_then(|last| inactive_days(last, now)) else {
+                // 没有登录记录（包括启用本功能前的老用户）：从这次检查开始计时
+                if let Err(e) = self.user_manager.record_login(&user.username).await {
+                    tracing::warn!(user = %user.username, error = %e, "记录不活跃计时起点失败");
+                }
+                continue;
+            };
+            if days < i64::from(self.cfg.inactive_days) {
+                continue;
+            }
+            match self.expire(&user, days, now).await {
+                Ok(dir) => {
+                    expired += 1;
+                    crate::metrics::METRICS.users_expired.inc();
+                    tracing::warn!(user = %user.username, inactive_days = days, archive = %dir.display(), "账户长期未登录，已停用并归档");
+                }
+                Err(e) => tracing::warn!(user = %user.username, error = %e, "归档不活跃账户失败，下次检查时重试"),
+            }
+        }
+        expired
+    }
+
+    /// 先移走配额与登录 IP 记录，最后停用并移走用户文件：中途失败时用户仍在，下次检查重试
+    async fn expire(&self, user: &UserInfo, days: i64, now: DateTime<FixedOffset>) -> Result<PathBuf, AppError> {
+        let dir = self.archive_root.join(format!("{}.{}", user.username, now.format("%Y%m%d%H%M%S")));
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| AppError::InternalError(format!("创建归档目录失败: {}", e)))?;
+        self.quota_manager.archive_user(&user.username, &dir).await?;
+        self.known_ips.archive_user(&user.username, &dir).await?;
+        self.user_manager.archive_user(&user.username, &dir).await?;
+        self.login_limiter.revoke(&user.username).await;
+        self.activity_logger
+            .log_account_expired(&user.username, days, user.last_login_at.clone(), &dir.display().to_string())
+            .await;
+        Ok(dir)
+    }
+}
+
+/// 距最近一次登录的整天数；时间无法解析时返回 None
+fn inactive_days(last_login_at: &str, now: DateTime<FixedOffset>) -> Option<i64> {
+    let last = DateTime::parse_from_rfc3339(last_login_at).ok()?;
+    Some((now - last).num_days())
+}
+
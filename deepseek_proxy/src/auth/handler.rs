@@ -1,43 +1,67 @@
-use crate::{error::AppError, AppState};
-use axum::{extract::{State, ConnectInfo}, Json};
-use std::net::SocketAddr;
+use crate::{
+    auth::{known_ips::LoginSource, Claims},
+    client_ip::ClientIp,
+    error::{AppError, AuthError},
+    notify::AlertEvent,
+    AppState,
+};
+use axum::{extract::State, http::StatusCode, Extension, Json};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+    /// 已开启两步验证的账户需要提供验证器 App 上的 6 位验证码
+    #[serde(default)]
+    pub totp_code: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
     pub expires_in: u64,
+    /// 用于 POST /auth/refresh 换取新 token（`auth.refresh_token_ttl_seconds` 为 0 时不返回）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// 注销请求体（可省略）：同时提交 refresh token 时一并作废
+#[derive(Debug, Default, Deserialize)]
+pub struct LogoutRequest {
+    #[serde(default)]
+    pub refresh_token: Option<String>,
 }
 
 pub async fn login(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ClientIp(ip): ClientIp,
     State(state): State<AppState>,
     Json(req): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, AppError> {
     // 0. 全局速率限制检查（防止登录接口被暴力破解）
     if let Err(wait_time) = state.global_rate_limiter.acquire().await {
         tracing::warn!("全局速率限制：拒绝登录请求，建议等待 {:.2} 秒", wait_time);
-        return Err(AppError::TooManyRequests);
+        crate::metrics::METRICS.record_rate_limit_rejection("global_bucket");
+        return Err(AppError::TooManyRequests(state.global_rate_limiter.rate_limit(wait_time)));
     }
 
     // 验证用户名密码（从内存中的用户管理器获取）
-    let client_ip = addr.ip().to_string();
+    let client_ip = ip.to_string();
 
     // 暴力破解阻断检查（在真正验证前先看是否已被阻断）
-    if state.brute_force_guard.should_block(&req.username, &client_ip) {
+    if let Some(retry_after) = state.brute_force_guard.blocked_for(&req.username, &client_ip) {
         crate::metrics::METRICS.login_bruteforce_blocked.inc();
-        tracing::warn!(user=%req.username, ip=%client_ip, "登录被暴力破解策略阻断");
+        tracing::warn!(user=%req.username, ip=%client_ip, retry_after, "登录被暴力破解策略阻断");
         // 可选 webhook 通知
-        if let Some(url) = &state.config.security.webhook_url {
-            spawn_webhook_notify(url.clone(), "login_bruteforce_blocked", &req.username, &client_ip, None);
-        }
-        return Err(AppError::TooManyRequests);
+        state.notifier.notify(AlertEvent::new("login_bruteforce_blocked", &req.username).with_ip(&client_ip));
+        return Err(AuthError::LoginLocked { retry_after }.into());
     }
 
     let user = match state
@@ -46,19 +70,7 @@ pub async fn login(
         .await
     {
         Some(u) => u,
-        None => {
-            let fails = state.brute_force_guard.record_failure(&req.username, &client_ip);
-            crate::metrics::METRICS.login_attempts.with_label_values(&["failure"]).inc();
-            tracing::warn!(user=%req.username, ip=%client_ip, fails=fails, "登录失败");
-            if state.brute_force_guard.should_block(&req.username, &client_ip) {
-                crate::metrics::METRICS.login_bruteforce_blocked.inc();
-                if let Some(url) = &state.config.security.webhook_url {
-                    spawn_webhook_notify(url.clone(), "login_bruteforce_blocked", &req.username, &client_ip, Some(fails));
-                }
-                return Err(AppError::TooManyRequests);
-            }
-            return Err(AppError::Unauthorized("用户名或密码错误".to_string()));
-        }
+        None => return Err(login_failed(&state, &req.username, &client_ip)),
     };
 
     // 检查账户是否已激活
@@ -66,10 +78,187 @@ pub async fn login(
         tracing::warn!("用户 {} 尝试登录，但账户已被停用", user.username);
         return Err(AppError::Unauthorized("账户已被停用".to_string()));
     }
+    // 来源 IP 不在账户允许范围内时与密码错误一样返回 401 并计入暴力破解，不向外部网络泄露密码是否正确
+    if crate::auth::middleware::check_allowed_ips(&state, &user, Some(ip)).await.is_err() {
+        return Err(login_failed(&state, &req.username, &client_ip));
+    }
+    crate::auth::access_schedule::check(&state.config, &user)?;
+
+    // 两步验证：密码正确后再要求验证码，错误的验证码与错误密码一样计入暴力破解
+    if let Some(secret) = &user.totp_secret {
+        let Some(code) = req.totp_code.as_deref().filter(|c| !c.trim().is_empty()) else {
+            return Err(AuthError::TotpRequired.into());
+        };
+        if !crate::auth::totp::verify(&user.username, secret, code, chrono::Utc::now().timestamp()) {
+            let fails = state.brute_force_guard.record_failure(&req.username, &client_ip);
+            crate::metrics::METRICS.login_attempts.with_label_values(&["failure"]).inc();
+            tracing::warn!(user=%req.username, ip=%client_ip, fails=fails, "两步验证码错误");
+            return Err(AuthError::InvalidTotp.into());
+        }
+    }
+
+    let response = issue_tokens(&state, &user, &client_ip).await?;
+
+    // 记录登录行为
+    state.activity_logger.log_login(&user.username, None).await;
+    if let Err(e) = state.user_manager.record_login(&user.username).await {
+        tracing::warn!(user = %user.username, error = %e, "记录最近登录时间失败");
+    }
+    tracing::info!("用户 {} 登录成功", user.username);
+    crate::metrics::METRICS.login_attempts.with_label_values(&["success"]).inc();
+    state.brute_force_guard.reset_on_success(&user.username, &client_ip);
+    notify_if_new_ip(&state, &user, &client_ip).await;
+
+    Ok(Json(response))
+}
+
+/// 登录失败：计入暴力破解，达到阈值时返回阻断，否则返回与密码错误相同的 401
+fn login_failed(state: &AppState, username: &str, client_ip: &str) -> AppError {
+    let fails = state.brute_force_guard.record_failure(username, client_ip);
+    crate::metrics::METRICS.login_attempts.with_label_values(&["failure"]).inc();
+    tracing::warn!(user=%username, ip=%client_ip, fails=fails, "登录失败");
+    if let Some(retry_after) = state.brute_force_guard.blocked_for(username, client_ip) {
+        crate::metrics::METRICS.login_bruteforce_blocked.inc();
+        state.notifier.notify(
+            AlertEvent::new("login_bruteforce_blocked", username)
+                .with_ip(client_ip)
+                .with_fail_count(Some(fails)),
+        );
+        return AuthError::LoginLocked { retry_after }.into();
+    }
+    AppError::Unauthorized("用户名或密码错误".to_string())
+}
+
+/// 用 refresh token 换取新的访问 token；refresh token 同时轮换，旧的立即失效
+///
+/// 已经用过的 refresh token 再次出现说明可能已泄露，吊销该用户的全部 refresh token，需重新登录
+pub async fn refresh(
+    ClientIp(ip): ClientIp,
+    State(state): State<AppState>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    if let Err(wait_time) = state.global_rate_limiter.acquire().await {
+        tracing::warn!("全局速率限制：拒绝刷新 token 请求，建议等待 {:.2} 秒", wait_time);
+        crate::metrics::METRICS.record_rate_limit_rejection("global_bucket");
+        return Err(AppError::TooManyRequests(state.global_rate_limiter.rate_limit(wait_time)));
+    }
+
+    let claims = state
+        .jwt_service
+        .validate_refresh_token(&req.refresh_token)
+        .map_err(|e| AppError::Unauthorized(format!("refresh token 无效: {}", e)))?;
+    if !state.refresh_tokens.consume(&claims.jti, &claims.sub).await? {
+        let revoked = state.refresh_tokens.revoke_user(&claims.sub).await?;
+        tracing::warn!(user = %claims.sub, ip = %ip, revoked, "已使用或已吊销的 refresh token 被重复使用，吊销该用户全部 refresh token");
+        return Err(AppError::Unauthorized("refresh token 已失效，请重新登录".to_string()));
+    }
+
+    let user = state
+        .user_manager
+        .get_user(&claims.sub)
+        .await
+        .ok_or_else(|| AppError::Unauthorized("用户不存在".to_string()))?;
+    if !user.is_active {
+        return Err(AppError::Unauthorized("账户已被停用".to_string()));
+    }
+    crate::auth::access_schedule::check(&state.config, &user)?;
+
+    let response = issue_tokens(&state, &user, &ip.to_string()).await?;
+    tracing::info!("用户 {} 刷新 token", user.username);
+    Ok(Json(response))
+}
+
+/// 注销当前 token：在其过期前拒绝访问，并移除对应会话（再次登录签发新 token）
+pub async fn logout(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Extension(token): Extension<String>,
+    body: Option<Json<LogoutRequest>>,
+) -> Result<StatusCode, AppError> {
+    state.revoked_tokens.revoke(&claims.jti, claims.exp as i64).await?;
+    state.login_limiter.remove_session(&claims.sub, &token).await;
+
+    let request = body.map(|Json(b)| b).unwrap_or_default();
+    if let Some(refresh_token) = request.refresh_token {
+        // 只作废属于同一用户的 refresh token；无效的 refresh token 忽略
+        if let Ok(refresh) = state.jwt_service.validate_refresh_token(&refresh_token) {
+            if refresh.sub == claims.sub {
+                state.refresh_tokens.consume(&refresh.jti, &refresh.sub).await?;
+            }
+        }
+    }
 
-    // 使用登录限流器：在有效期内返回同一个 token（最多 60 秒）
+    tracing::info!(user = %claims.sub, jti = %claims.jti, "用户注销 token");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 修改密码请求体
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// 用户自助修改密码（POST /me/password）
+///
+/// 旧密码错误计入登录失败次数；修改成功后作废该用户已签发的 token 与 refresh token，需用新密码重新登录
+pub async fn change_password(
+    ClientIp(ip): ClientIp,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    crate::read_only::ensure_writable()?;
+    let client_ip = ip.to_string();
+    if let Some(retry_after) = state.brute_force_guard.blocked_for(&claims.sub, &client_ip) {
+        return Err(AuthError::LoginLocked { retry_after }.into());
+    }
+
+    match state.user_manager.change_password(&claims.sub, &req.old_password, &req.new_password).await {
+        Err(AppError::Auth(AuthError::InvalidCredentials)) => {
+            state.brute_force_guard.record_failure(&claims.sub, &client_ip);
+            tracing::warn!(user = %claims.sub, ip = %client_ip, "修改密码失败：旧密码错误");
+            return Err(AuthError::InvalidCredentials.into());
+        }
+        result => result?,
+    }
+
+    end_sessions(&state, &claims.sub).await?;
+    state.revoked_tokens.revoke(&claims.jti, claims.exp as i64).await?;
+    state.activity_logger.log_password_changed(&claims.sub, Some(client_ip)).await;
+
+    Ok(Json(serde_json::json!({
+        "message": "密码已修改，请使用新密码重新登录"
+    })))
+}
+
+/// 结束用户的全部会话：移出登录缓存、吊销 refresh token，并注销其中尚未过期的访问 token，返回注销的数量
+pub async fn end_sessions(state: &AppState, username: &str) -> Result<usize, AppError> {
+    let mut revoked = 0;
+    for token in state.login_limiter.revoke(username).await {
+        // 已过期的 token 无法通过验签，无需注销
+        if let Ok(claims) = state.jwt_service.validate_token(&token) {
+            state.revoked_tokens.revoke(&claims.jti, claims.exp as i64).await?;
+            revoked += 1;
+        }
+    }
+    Ok(revoked)
+}
+
+/// 发布 token 验签公钥（GET /.well-known/jwks.json，仅 RS256 / EdDSA 时登记）
+pub async fn jwks(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    (
+        [(axum::http::header::CACHE_CONTROL, "public, max-age=300")],
+        Json(state.jwt_service.jwks()),
+    )
+}
+
+/// 签发（或复用）访问 token，并签发一个新的 refresh token
+async fn issue_tokens(state: &AppState, user: &crate::config::User, client_ip: &str) -> Result<LoginResponse, AppError> {
+    // 使用登录限流器：在有效期内返回同一个 token（最多 60 秒）；按档次限制同时持有 token 的来源 IP 数
+    let max_sessions = state.config.auth.max_sessions.for_tier(&user.quota_tier).map(|v| v as usize);
     let token = state.login_limiter
-        .get_or_generate(&user.username, || {
+        .get_or_generate(&user.username, Some(client_ip), max_sessions, || {
             state
                 .jwt_service
                 .generate_token(&user.username)
@@ -77,32 +266,70 @@ pub async fn login(
         })
         .await?;
 
-    // 记录登录行为
-    state.activity_logger.log_login(&user.username, None).await;
-    tracing::info!("用户 {} 登录成功", user.username);
-    crate::metrics::METRICS.login_attempts.with_label_values(&["success"]).inc();
-    state.brute_force_guard.reset_on_success(&user.username, &client_ip);
+    let refresh = state
+        .jwt_service
+        .generate_refresh_token(&user.username)
+        .map_err(|e| AppError::InternalError(format!("refresh token 生成失败: {}", e)))?;
+    let refresh_token = match refresh {
+        Some((refresh_token, claims)) => {
+            state.refresh_tokens.insert(&claims.jti, &user.username, claims.exp as i64).await?;
+            Some(refresh_token)
+        }
+        None => None,
+    };
 
-    Ok(Json(LoginResponse {
+    Ok(LoginResponse {
         token,
         expires_in: state.jwt_service.get_ttl_seconds(),  // 返回实际的 TTL（已被限制为最多 60 秒）
-    }))
+        refresh_expires_in: refresh_token.as_ref().map(|_| state.jwt_service.get_refresh_ttl_seconds()),
+        refresh_token,
+    })
 }
 
-fn spawn_webhook_notify(url: String, event: &str, username: &str, ip: &str, fail_count: Option<usize>) {
-    let event = event.to_string();
-    let username = username.to_string();
-    let ip = ip.to_string();
-    tokio::spawn(async move {
-        let payload = serde_json::json!({
-            "event": event,
-            "username": username,
-            "ip": ip,
-            "fail_count": fail_count,
-            "timestamp": chrono::Utc::now().to_rfc3339(),
-        });
-        if let Err(e) = reqwest::Client::new().post(&url).json(&payload).send().await {
-            tracing::warn!(error=%e, "Webhook 通知发送失败");
+/// 配置了个人通知渠道的用户从未出现过的 IP 登录时通知账户所有者（首次记录不通知）
+async fn notify_if_new_ip(state: &AppState, user: &crate::config::User, client_ip: &str) {
+    if !state.notifier.has_user_channel(user) {
+        return;
+    }
+    match state.known_ips.record_login(&user.username, client_ip).await {
+        Ok(LoginSource::New { first_login: false }) => {
+            tracing::info!(user = %user.username, ip = %client_ip, "账户在新的 IP 登录，通知账户所有者");
+            state.notifier.notify_user(
+                user,
+                AlertEvent::new("new_ip_login", &user.username)
+                    .with_ip(client_ip)
+                    .with_detail("如果不是您本人操作，请尽快联系管理员修改密码"),
+            );
         }
-    });
+        Ok(_) => {}
+        Err(e) => tracing::warn!(user = %user.username, error = %e, "记录登录 IP 失败"),
+    }
+}
+
+/// 当前用户信息（含配额与预付费额度）
+#[derive(Debug, Serialize)]
+pub struct MeResponse {
+    pub username: String,
+    pub quota_tier: String,
+    pub used: u32,
+    pub limit: u32,
+    pub credits: u32,
+    pub reset_at: String,
+}
+
+/// 查询当前登录用户的配额与预付费额度
+pub async fn me(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<MeResponse>, AppError> {
+    let quota = state.quota_manager.get_quota(&claims.sub).await?;
+
+    Ok(Json(MeResponse {
+        username: claims.sub,
+        quota_tier: quota.tier,
+        used: quota.used_count,
+        limit: quota.monthly_limit,
+        credits: quota.credits,
+        reset_at: quota.reset_at,
+    }))
 }
@@ -0,0 +1,132 @@
+use crate::error::AppError;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// 每个用户最多记住的登录 IP 数，超出时淘汰最早出现的
+const MAX_KNOWN_IPS: usize = 50;
+
+/// 用户曾经登录过的 IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownIp {
+    pub ip: String,
+    pub first_seen: String,
+}
+
+/// 一次成功登录的来源判断
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoginSource {
+    /// 之前登录过的 IP
+    Known,
+    /// 新 IP；first_login 为该用户第一次被记录（没有可比较的历史，不通知）
+    New { first_login: bool },
+}
+
+/// 登录 IP 记录（data/known_ips/{username}.json），用于新 IP 登录通知
+///
+/// 只为配置了登录通知的用户记录，避免无谓地保存 IP
+pub struct KnownIpStore {
+    dir: PathBuf,
+    cache: DashMap<String, Vec<KnownIp>>,
+}
+
+impl KnownIpStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, cache: DashMap::new() }
+    }
+
+    fn file_path(&self, username: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", username))
+    }
+
+    async fn load(&self, username: &str) -> Vec<KnownIp> {
+        if let Some(ips) = self.cache.get(username) {
+            return ips.clone();
+        }
+        let ips = match tokio::fs::read_to_string(self.file_path(username)).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(username, error = %e, "登录 IP 记录无法解析，重新开始记录");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        self.cache.insert(username.to_string(), ips.clone());
+        ips
+    }
+
+    /// 记录一次成功登录，返回该 IP 是否为新来源
+    pub async fn record_login(&self, username: &str, ip: &str) -> Result<LoginSource, AppError> {
+        let mut ips = self.load(username).await;
+        if ips.iter().any(|known| known.ip == ip) {
+            return Ok(LoginSource::Known);
+        }
+        let first_login = ips.is_empty();
+        ips.push(KnownIp {
+            ip: ip.to_string(),
+            first_seen: crate::utils::now_beijing_rfc3339(),
+        });
+        if ips.len() > MAX_KNOWN_IPS {
+            ips.drain(..ips.len() - MAX_KNOWN_IPS);
+        }
+
+        if crate::read_only::is_enabled() {
+            self.cache.insert(username.to_string(), ips);
+            return Ok(LoginSource::New { first_login });
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let json = serde_json::to_string_pretty(&ips)
+            .map_err(|e| AppError::InternalError(format!("序列化登录 IP 记录失败: {}", e)))?;
+        tokio::fs::write(self.file_path(username), json).await?;
+        self.cache.insert(username.to_string(), ips);
+        Ok(LoginSource::New { first_login })
+    }
+
+    /// 读取用户的登录 IP 记录（数据导出用）
+    pub async fn get(&self, username: &str) -> Vec<KnownIp> {
+        self.load(username).await
+    }
+
+    /// 把登录 IP 记录移到 archive_dir，返回记录是否存在
+    pub async fn archive_user(&self, username: &str, archive_dir: &std::path::Path) -> Result<bool, AppError> {
+        self.cache.remove(username);
+        match tokio::fs::rename(self.file_path(username), archive_dir.join("known_ips.json")).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 删除用户的登录 IP 记录（数据擦除用），返回文件是否存在
+    pub async fn erase_user(&self, username: &str) -> Result<bool, AppError> {
+        self.cache.remove(username);
+        match tokio::fs::remove_file(self.file_path(username)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_login_detects_new_ip() {
+        let dir = std::env::temp_dir().join("test_known_ips");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let store = KnownIpStore::new(dir.clone());
+
+        assert_eq!(store.record_login("alice", "10.0.0.1").await.unwrap(), LoginSource::New { first_login: true });
+        assert_eq!(store.record_login("alice", "10.0.0.1").await.unwrap(), LoginSource::Known);
+        assert_eq!(store.record_login("alice", "10.0.0.2").await.unwrap(), LoginSource::New { first_login: false });
+
+        // 重启后从文件恢复
+        let reloaded = KnownIpStore::new(dir.clone());
+        assert_eq!(reloaded.record_login("alice", "10.0.0.2").await.unwrap(), LoginSource::Known);
+        assert!(reloaded.erase_user("alice").await.unwrap());
+        assert!(reloaded.get("alice").await.is_empty());
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
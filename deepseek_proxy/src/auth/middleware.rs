@@ -1,39 +1,124 @@
-use crate::{error::AppError, AppState};
+use crate::{
+    config::User,
+    error::{AppError, AuthError},
+    tls::ClientCertIdentity,
+    AppState,
+};
 use axum::{
     extract::{Request, State},
-    http::header::AUTHORIZATION,
+    http::{header::AUTHORIZATION, HeaderValue},
     middleware::Next,
     response::Response,
 };
+use std::net::IpAddr;
+
+/// 代管 token 的响应头，值为操作人，便于客户端与日志区分
+pub const IMPERSONATED_BY_HEADER: &str = "x-impersonated-by";
 
 /// Token 验证中间件
+///
+/// mTLS 下证书 CN 映射了用户名时可免 Bearer token；同时携带 token 时两者必须一致。
+/// 用户配置了 allowed_ips 时，来源 IP（经受信任代理解析后）必须在列表内；
+/// 配置了访问时段时，当前时间必须在时段内（代管 token 均除外）
 pub async fn auth_middleware(
     State(state): State<AppState>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, AppError> {
+    let cert_username = request
+        .extensions()
+        .get::<ClientCertIdentity>()
+        .and_then(|identity| identity.username.clone());
+
     // 提取 Authorization header
     let auth_header = request
         .headers()
         .get(AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::Unauthorized("缺少 Authorization header".to_string()))?;
+        .and_then(|h| h.to_str().ok());
 
-    // 提取 Bearer token
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::Unauthorized("Authorization 格式错误".to_string()))?
-        .to_string(); // 先克隆 token
+    let token = match (auth_header, &cert_username) {
+        (None, Some(username)) => {
+            let peer = crate::client_ip::peer_ip(request.extensions()).map(|ip| ip.to_string());
+            certificate_token(&state, username, peer.as_deref()).await?
+        }
+        (auth_header, _) => {
+            let auth_header = auth_header
+                .ok_or_else(|| AppError::Unauthorized("缺少 Authorization header".to_string()))?;
+            // 提取 Bearer token
+            auth_header
+                .strip_prefix("Bearer ")
+                .ok_or_else(|| AppError::Unauthorized("Authorization 格式错误".to_string()))?
+                .to_string() // 先克隆 token
+        }
+    };
 
     // 验证 token
     let claims = state
         .jwt_service
         .validate_token(&token)
         .map_err(|e| AppError::Unauthorized(format!("Token 无效: {}", e)))?;
+    if state.revoked_tokens.is_revoked(&claims.jti).await {
+        return Err(AppError::Unauthorized("Token 已注销，请重新登录".to_string()));
+    }
+
+    if let Some(username) = &cert_username {
+        if &claims.sub != username {
+            tracing::warn!(cert_user = %username, token_user = %claims.sub, "客户端证书与 token 用户不一致");
+            return Err(AppError::Unauthorized("客户端证书与 token 用户不一致".to_string()));
+        }
+    }
+
+    // 代管 token 逐请求留痕；管理员不在用户的允许 IP 范围内，跳过来源 IP 与访问时段限制
+    let impersonated_by = claims.impersonated_by.clone();
+    if let Some(actor) = &impersonated_by {
+        tracing::warn!(user = %claims.sub, actor = %actor, method = %request.method(), path = %request.uri().path(), "代管 token 访问");
+    } else if let Some(user) = state.user_manager.get_user(&claims.sub).await {
+        check_allowed_ips(&state, &user, crate::client_ip::peer_ip(request.extensions())).await?;
+        crate::auth::access_schedule::check(&state.config, &user)?;
+    }
 
     // 将用户信息和 token 存入 request extensions
     request.extensions_mut().insert(claims);
     request.extensions_mut().insert(token);
 
-    Ok(next.run(request).await)
+    let mut response = next.run(request).await;
+    if let Some(value) = impersonated_by.and_then(|actor| HeaderValue::from_str(&actor).ok()) {
+        response.headers_mut().insert(IMPERSONATED_BY_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// 用户配置了 allowed_ips 时，来源 IP 必须在列表内；拒绝时记录用户行为日志
+///
+/// Unix socket 连接没有来源 IP（`peer` 为 None），按不允许处理
+pub(crate) async fn check_allowed_ips(state: &AppState, user: &User, peer: Option<IpAddr>) -> Result<(), AppError> {
+    if user.allowed_ips.is_empty() || peer.is_some_and(|ip| crate::client_ip::matches_any(&user.allowed_ips, ip)) {
+        return Ok(());
+    }
+    tracing::warn!(user = %user.username, ip = ?peer, "来源 IP 不在账户允许范围内，拒绝请求");
+    state.activity_logger.log_ip_restricted(&user.username, peer.map(|ip| ip.to_string())).await;
+    Err(AppError::Auth(AuthError::AccountIpRestricted))
+}
+
+/// 为证书映射的用户签发（或复用）token，等同于一次免密登录
+async fn certificate_token(state: &AppState, username: &str, peer: Option<&str>) -> Result<String, AppError> {
+    let user = state
+        .user_manager
+        .get_user(username)
+        .await
+        .ok_or_else(|| AppError::Unauthorized(format!("证书映射的用户 {} 不存在", username)))?;
+    if !user.is_active {
+        return Err(AppError::Unauthorized("账户已被停用".to_string()));
+    }
+
+    let max_sessions = state.config.auth.max_sessions.for_tier(&user.quota_tier).map(|v| v as usize);
+    state
+        .login_limiter
+        .get_or_generate(username, peer, max_sessions, || {
+            state
+                .jwt_service
+                .generate_token(username)
+                .map_err(|e| AppError::InternalError(format!("Token生成失败: {}", e)))
+        })
+        .await
 }
@@ -1,6 +1,14 @@
+pub mod access_schedule;
+pub mod expiry;
 pub mod handler;
 pub mod jwt;
+pub mod known_ips;
 pub mod middleware;
+pub mod password;
+pub mod password_policy;
+pub mod refresh;
+pub mod revocation;
+pub mod totp;
 pub mod user_manager;
 pub mod bruteforce;
 
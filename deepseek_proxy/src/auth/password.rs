@@ -0,0 +1,59 @@
+//! 用户密码哈希：Argon2id，随机盐，存储为标准 PHC 字符串
+//!
+//! 格式：`$argon2id$v=19$m=19456,t=2,p=1$<盐>$<哈希>`。参数随哈希保存，
+//! 调整 `[auth] password_hash_memory_kib` / `password_hash_time_cost` 不影响已有哈希的校验
+
+use crate::error::AppError;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::rand::{SecureRandom, SystemRandom};
+
+const SALT_LEN: usize = 16;
+
+/// 新哈希的 Argon2id 参数；参数无效（如内存低于 8 KiB）时返回错误
+pub fn params(memory_kib: u32, time_cost: u32) -> Result<Params, AppError> {
+    Params::new(memory_kib, time_cost, 1, None)
+        .map_err(|e| AppError::InternalError(format!("密码哈希参数无效: {}", e)))
+}
+
+/// 计算密码哈希
+pub fn hash(password: &str, params: &Params) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new().fill(&mut salt).expect("系统随机数生成失败");
+    let salt = SaltString::encode_b64(&salt).expect("16 字节盐长度合法");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 参数已校验，哈希不会失败")
+        .to_string()
+}
+
+/// 常量时间校验密码（按哈希中记录的参数）；哈希格式无法识别时返回 false
+pub fn verify(password: &str, encoded: &str) -> bool {
+    PasswordHash::new(encoded).is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+/// 是否为有效的 Argon2 哈希
+pub fn is_valid_hash(encoded: &str) -> bool {
+    PasswordHash::new(encoded).is_ok_and(|parsed| Algorithm::new(parsed.algorithm.as_str()).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let params = params(1024, 1).unwrap();
+        let encoded = hash("pass123", &params);
+        assert!(encoded.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(is_valid_hash(&encoded));
+        assert!(verify("pass123", &encoded));
+        assert!(!verify("pass124", &encoded));
+        // 盐随机，相同密码的哈希不同
+        assert_ne!(encoded, hash("pass123", &params));
+        assert!(!verify("pass123", "pass123"));
+        assert!(!is_valid_hash("pass123"));
+        assert!(!verify("pass123", "$argon2id$v=19$m=1024,t=1,p=1$AAAA"));
+        assert!(super::params(1, 1).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! 密码策略（`[security.password_policy]`）：最短长度、字符类别、常见弱密码
+//!
+//! 创建用户与修改密码时校验，不满足时一次返回全部未满足的条目（`400 weak_password`）
+
+use crate::config::PasswordPolicyConfig;
+use crate::error::AppError;
+use serde::Serialize;
+
+/// 内置的常见弱密码（不区分大小写）
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "password1", "password123", "passw0rd", "p@ssw0rd", "12345678", "123456789", "1234567890",
+    "11111111", "00000000", "88888888", "66666666", "87654321", "abc12345", "abcd1234", "a1234567",
+    "qwerty123", "qwertyuiop", "1q2w3e4r", "1qaz2wsx", "iloveyou", "admin123", "admin888", "root1234",
+    "welcome1", "letmein1", "changeme", "zxcvbnm123", "woaini1314", "5201314520",
+];
+
+/// 一条未满足的要求
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyViolation {
+    /// 机器可读的条目：too_short / missing_lowercase / missing_uppercase / missing_digit / missing_symbol / common_password
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// 检查密码，返回全部未满足的要求
+pub fn violations(policy: &PasswordPolicyConfig, password: &str) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+    if password.chars().count() < policy.min_length {
+        violations.push(PolicyViolation::new("too_short", format!("密码至少 {} 个字符", policy.min_length)));
+    }
+    let classes = [
+        (policy.require_lowercase, password.chars().any(|c| c.is_lowercase()), "missing_lowercase", "密码需包含小写字母"),
+        (policy.require_uppercase, password.chars().any(|c| c.is_uppercase()), "missing_uppercase", "密码需包含大写字母"),
+        (policy.require_digit, password.chars().any(|c| c.is_ascii_digit()), "missing_digit", "密码需包含数字"),
+        (policy.require_symbol, password.chars().any(|c| !c.is_alphanumeric()), "missing_symbol", "密码需包含符号"),
+    ];
+    for (required, present, code, message) in classes {
+        if required && !present {
+            violations.push(PolicyViolation::new(code, message));
+        }
+    }
+    let lowered = password.to_lowercase();
+    let common = policy.deny_common && COMMON_PASSWORDS.contains(&lowered.as_str());
+    if common || policy.denylist.iter().any(|p| p.to_lowercase() == lowered) {
+        violations.push(PolicyViolation::new("common_password", "密码过于常见，请换一个"));
+    }
+    violations
+}
+
+/// 不满足策略时返回 `AppError::WeakPassword`
+pub fn check(policy: &PasswordPolicyConfig, password: &str) -> Result<(), AppError> {
+    let violations = violations(policy, password);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::WeakPassword(violations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(policy: &PasswordPolicyConfig, password: &str) -> Vec<&'static str> {
+        violations(policy, password).into_iter().map(|v| v.code).collect()
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let default = PasswordPolicyConfig::default();
+        assert!(codes(&default, "secret123").is_empty());
+        assert_eq!(codes(&default, "pass123"), ["too_short"]);
+        assert_eq!(codes(&default, "PassWord1"), ["common_password"]);
+        // 按字符而非字节计长度
+        assert_eq!(codes(&default, "密码密码密码密"), ["too_short"]);
+
+        let strict = PasswordPolicyConfig {
+            min_length: 10,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            deny_common: false,
+            denylist: vec!["Company2026!".to_string()],
+        };
+        assert_eq!(codes(&strict, "abc"), ["too_short", "missing_uppercase", "missing_digit", "missing_symbol"]);
+        assert_eq!(codes(&strict, "company2026!"), ["missing_uppercase", "common_password"]);
+        assert!(check(&strict, "Correct-Horse-9").is_ok());
+        assert!(matches!(check(&strict, "x"), Err(AppError::WeakPassword(v)) if v.len() == 4));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+/// 一个仍然有效的 refresh token（以 jti 为键，不保存 token 本身）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RefreshRecord {
+    username: String,
+    /// 过期时间（Unix 秒）
+    expires_at: i64,
+}
+
+/// 已签发且未使用的 refresh token（data/refresh_tokens.json）
+///
+/// refresh token 每用一次就轮换：旧的从这里删除、新的登记进来；签名有效但不在表中的 token
+/// 视为已被使用过，可能已经泄露，调用方据此吊销该用户的全部 refresh token
+pub struct RefreshTokenStore {
+    path: PathBuf,
+    tokens: Mutex<HashMap<String, RefreshRecord>>,
+}
+
+impl RefreshTokenStore {
+    /// 加载已登记的 refresh token；文件不存在或无法解析时从空表开始（用户需重新登录）
+    pub async fn load(path: PathBuf) -> Self {
+        let tokens = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "refresh token 记录无法解析，已有的 refresh token 全部失效");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self { path, tokens: Mutex::new(tokens) }
+    }
+
+    /// 登记新签发的 refresh token
+    pub async fn insert(&self, jti: &str, username: &str, expires_at: i64) -> Result<(), AppError> {
+        let mut tokens = self.tokens.lock().await;
+        tokens.insert(jti.to_string(), RefreshRecord { username: username.to_string(), expires_at });
+        self.persist(&mut tokens).await
+    }
+
+    /// 使用一次 refresh token：属于该用户且未过期时删除并返回 true
+    pub async fn consume(&self, jti: &str, username: &str) -> Result<bool, AppError> {
+        let mut tokens = self.tokens.lock().await;
+        let valid = tokens
+            .get(jti)
+            .is_some_and(|r| r.username == username && r.expires_at > chrono::Utc::now().timestamp());
+        if tokens.remove(jti).is_some() {
+            self.persist(&mut tokens).await?;
+        }
+        Ok(valid)
+    }
+
+    /// 吊销用户的全部 refresh token，返回吊销的数量
+    pub async fn revoke_user(&self, username: &str) -> Result<usize, AppError> {
+        let mut tokens = self.tokens.lock().await;
+        let before = tokens.len();
+        tokens.retain(|_, r| r.username != username);
+        let revoked = before - tokens.len();
+        if revoked > 0 {
+            self.persist(&mut tokens).await?;
+        }
+        Ok(revoked)
+    }
+
+    /// 清理过期记录后原子写入（只读模式下只保留在内存中）
+    async fn persist(&self, tokens: &mut HashMap<String, RefreshRecord>) -> Result<(), AppError> {
+        let now = chrono::Utc::now().timestamp();
+        tokens.retain(|_, r| r.expires_at > now);
+        if crate::read_only::is_enabled() {
+            return Ok(());
+        }
+        crate::utils::write_json_atomic(&self.path, tokens).await.map_err(|e| {
+            crate::metrics::METRICS.record_persist_failure("refresh_token");
+            AppError::InternalError(format!("写入 refresh token 记录失败: {}", e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consume_rotates_and_revoke_persists() {
+        let path = std::env::temp_dir().join("test_refresh_tokens.json");
+        let _ = tokio::fs::remove_file(&path).await;
+        let exp = chrono::Utc::now().timestamp() + 60;
+
+        let store = RefreshTokenStore::load(path.clone()).await;
+        store.insert("a", "alice", exp).await.unwrap();
+        store.insert("b", "alice", exp).await.unwrap();
+        store.insert("c", "bob", exp).await.unwrap();
+        store.insert("old", "bob", 0).await.unwrap();
+
+        assert!(!store.consume("a", "bob").await.unwrap()); // 用户不符也会作废
+        assert!(!store.consume("a", "alice").await.unwrap());
+        assert!(!store.consume("old", "bob").await.unwrap());
+        assert_eq!(store.revoke_user("alice").await.unwrap(), 1);
+
+        // 重新加载后只剩 bob 的有效记录
+        let store = RefreshTokenStore::load(path.clone()).await;
+        assert!(store.consume("c", "bob").await.unwrap());
+        assert!(!store.consume("c", "bob").await.unwrap());
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::AppError;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+/// 已注销的访问 token（data/revoked_tokens.json，jti -> 过期时间 Unix 秒）
+///
+/// 鉴权中间件对每个请求检查 jti；记录在 token 过期后失去意义，写入时顺带清理
+pub struct RevocationList {
+    path: PathBuf,
+    revoked: RwLock<HashMap<String, i64>>,
+}
+
+impl RevocationList {
+    /// 加载注销记录；文件不存在时从空表开始，无法解析时告警并从空表开始
+    pub async fn load(path: PathBuf) -> Self {
+        let revoked = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "token 注销记录无法解析，已忽略");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self { path, revoked: RwLock::new(revoked) }
+    }
+
+    /// 注销一个 token，在其过期前一直拒绝
+    pub async fn revoke(&self, jti: &str, expires_at: i64) -> Result<(), AppError> {
+        let mut revoked = self.revoked.write().await;
+        revoked.insert(jti.to_string(), expires_at);
+        let now = chrono::Utc::now().timestamp();
+        revoked.retain(|_, exp| *exp > now);
+        if crate::read_only::is_enabled() {
+            return Ok(());
+        }
+        crate::utils::write_json_atomic(&self.path, &*revoked).await.map_err(|e| {
+            crate::metrics::METRICS.record_persist_failure("token_revocation");
+            AppError::InternalError(format!("写入 token 注销记录失败: {}", e))
+        })
+    }
+
+    pub async fn is_revoked(&self, jti: &str) -> bool {
+        !jti.is_empty() && self.revoked.read().await.contains_key(jti)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revocation_survives_reload() {
+        let path = std::env::temp_dir().join("test_revoked_tokens.json");
+        let _ = tokio::fs::remove_file(&path).await;
+        let exp = chrono::Utc::now().timestamp() + 60;
+
+        let list = RevocationList::load(path.clone()).await;
+        list.revoke("a", exp).await.unwrap();
+        list.revoke("expired", 0).await.unwrap();
+        assert!(list.is_revoked("a").await);
+        assert!(!list.is_revoked("b").await);
+        assert!(!list.is_revoked("").await);
+
+        let list = RevocationList::load(path.clone()).await;
+        assert!(list.is_revoked("a").await);
+        assert!(!list.is_revoked("expired").await);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
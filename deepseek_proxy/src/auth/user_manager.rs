@@ -1,5 +1,6 @@
-use crate::config::User;
-use crate::error::AppError;
+use crate::auth::password;
+use crate::config::{PasswordPolicyConfig, User};
+use crate::error::{AppError, AuthError};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::path::PathBuf;
@@ -12,6 +13,12 @@ pub struct UserManager {
     users: Arc<RwLock<HashMap<String, User>>>,
     /// 用户文件存储目录
     users_dir: PathBuf,
+    /// 新密码哈希的 Argon2 参数
+    hash_params: argon2::Params,
+    /// 用户不存在时用来校验的哈希，使其耗时与真实校验一致，避免通过响应时间探测用户名
+    dummy_hash: Arc<String>,
+    /// 新密码的强度要求
+    password_policy: Arc<PasswordPolicyConfig>,
 }
 
 impl UserManager {
@@ -20,15 +27,25 @@ impl UserManager {
     /// 初始化逻辑：
     /// 1. 如果 users_dir 为空，从 initial_users 导入
     /// 2. 如果 users_dir 有文件，从文件加载（忽略 initial_users）
-    pub async fn new(users_dir: PathBuf, initial_users: Vec<User>) -> Result<Self, AppError> {
+    /// 3. 明文密码一律迁移为 password_hash 后写回文件
+    pub async fn new(users_dir: PathBuf, initial_users: Vec<User>, hash_params: argon2::Params) -> Result<Self, AppError> {
         // 确保目录存在
-        tokio::fs::create_dir_all(&users_dir)
-            .await
-            .map_err(|e| AppError::InternalError(format!("创建用户目录失败: {}", e)))?;
+        if !crate::read_only::is_enabled() {
+            tokio::fs::create_dir_all(&users_dir)
+                .await
+                .map_err(|e| AppError::InternalError(format!("创建用户目录失败: {}", e)))?;
+        }
 
+        let params = hash_params.clone();
+        let dummy_hash = tokio::task::spawn_blocking(move || password::hash("dummy-password", &params))
+            .await
+            .map_err(|e| AppError::InternalError(format!("计算密码哈希失败: {}", e)))?;
         let manager = Self {
             users: Arc::new(RwLock::new(HashMap::new())),
             users_dir,
+            hash_params,
+            dummy_hash: Arc::new(dummy_hash),
+            password_policy: Arc::new(PasswordPolicyConfig::default()),
         };
 
         // 加载现有用户文件
@@ -43,18 +60,60 @@ impl UserManager {
         } else {
             tracing::info!("从文件加载了 {} 个用户", loaded_count);
         }
+        manager.migrate_plaintext_passwords().await?;
 
         Ok(manager)
     }
 
+    /// 设置新密码的强度要求（默认为 `PasswordPolicyConfig::default()`）
+    pub fn with_password_policy(mut self, policy: PasswordPolicyConfig) -> Self {
+        self.password_policy = Arc::new(policy);
+        self
+    }
+
+    /// 按密码策略校验新密码（创建用户与修改密码时调用）
+    pub fn check_password_policy(&self, password: &str) -> Result<(), AppError> {
+        crate::auth::password_policy::check(&self.password_policy, password)
+    }
+
+    /// 把明文密码替换为哈希并写回用户文件（只读模式下只更新内存）
+    async fn migrate_plaintext_passwords(&self) -> Result<(), AppError> {
+        let pending: Vec<User> = self.users.read().await.values().filter(|u| !u.password.is_empty()).cloned().collect();
+        for mut user in pending {
+            if user.password_hash.is_none() {
+                user.password_hash = Some(self.hash_password(std::mem::take(&mut user.password)).await?);
+            } else {
+                // 已有哈希时以哈希为准，丢弃残留的明文
+                user.password.clear();
+            }
+            self.save_user(&user).await?;
+            tracing::info!(username = %user.username, "明文密码已迁移为哈希");
+        }
+        for user in self.users.read().await.values().filter(|u| u.password_hash.as_deref().is_none_or(|h| !password::is_valid_hash(h))) {
+            tracing::warn!(username = %user.username, "用户没有有效的密码哈希，将无法登录");
+        }
+        Ok(())
+    }
+
+    /// 计算密码哈希（Argon2 开销较大，放到阻塞线程池）
+    async fn hash_password(&self, plain: String) -> Result<String, AppError> {
+        let params = self.hash_params.clone();
+        tokio::task::spawn_blocking(move || password::hash(&plain, &params))
+            .await
+            .map_err(|e| AppError::InternalError(format!("计算密码哈希失败: {}", e)))
+    }
+
     /// 从目录加载所有用户文件
     async fn load_all_users(&self) -> Result<usize, AppError> {
         let mut users = self.users.write().await;
         let mut count = 0;
 
-        let mut entries = tokio::fs::read_dir(&self.users_dir)
-            .await
-            .map_err(|e| AppError::InternalError(format!("读取用户目录失败: {}", e)))?;
+        let mut entries = match tokio::fs::read_dir(&self.users_dir).await {
+            Ok(entries) => entries,
+            // 只读模式下不创建目录，目录不存在时视为没有用户文件
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && crate::read_only::is_enabled() => return Ok(0),
+            Err(e) => return Err(AppError::InternalError(format!("读取用户目录失败: {}", e))),
+        };
 
         while let Some(entry) = entries.next_entry().await
             .map_err(|e| AppError::InternalError(format!("读取目录条目失败: {}", e)))?
@@ -88,8 +147,12 @@ impl UserManager {
         Ok(user)
     }
 
-    /// 保存用户到文件
+    /// 保存用户到文件（只读模式下只更新内存）
     async fn save_user(&self, user: &User) -> Result<(), AppError> {
+        if crate::read_only::is_enabled() {
+            self.users.write().await.insert(user.username.clone(), user.clone());
+            return Ok(());
+        }
         let file_path = self.users_dir.join(format!("{}.toml", user.username));
 
         let content = toml::to_string_pretty(user)
@@ -97,7 +160,10 @@ impl UserManager {
 
         tokio::fs::write(&file_path, content)
             .await
-            .map_err(|e| AppError::InternalError(format!("写入用户文件失败: {}", e)))?;
+            .map_err(|e| {
+                crate::metrics::METRICS.record_persist_failure("user");
+                AppError::InternalError(format!("写入用户文件失败: {}", e))
+            })?;
 
         // 同时更新内存
         let mut users = self.users.write().await;
@@ -107,12 +173,44 @@ impl UserManager {
         Ok(())
     }
 
-    /// 查找用户（用于登录验证）
+    /// 查找用户（用于登录验证）：常量时间比对密码哈希，用户不存在时同样做一次哈希校验
     pub async fn find_user(&self, username: &str, password: &str) -> Option<User> {
-        let users = self.users.read().await;
-        users.get(username)
-            .filter(|u| u.password == password)
-            .cloned()
+        let user = self.users.read().await.get(username).cloned();
+        let encoded = match user.as_ref().and_then(|u| u.password_hash.clone()) {
+            Some(hash) => hash,
+            None => self.dummy_hash.to_string(),
+        };
+        let plain = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || password::verify(&plain, &encoded))
+            .await
+            .unwrap_or(false);
+        user.filter(|u| matched && u.password_hash.is_some())
+    }
+
+    /// 用户自助修改密码：旧密码错误返回 `InvalidCredentials`，新密码须满足密码策略且与旧密码不同
+    pub async fn change_password(&self, username: &str, old_password: &str, new_password: &str) -> Result<(), AppError> {
+        if self.find_user(username, old_password).await.is_none() {
+            return Err(AuthError::InvalidCredentials.into());
+        }
+        if old_password == new_password {
+            return Err(AppError::BadRequest("新密码不能与旧密码相同".to_string()));
+        }
+        self.set_password(username, new_password).await
+    }
+
+    /// 设置新密码（按密码策略校验后保存哈希）
+    pub async fn set_password(&self, username: &str, new_password: &str) -> Result<(), AppError> {
+        self.check_password_policy(new_password)?;
+        let mut user = self
+            .get_user(username)
+            .await
+            .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?;
+        user.password.clear();
+        user.password_hash = Some(self.hash_password(new_password.to_string()).await?);
+        user.updated_at = Some(crate::utils::now_beijing_rfc3339());
+        self.save_user(&user).await?;
+        tracing::info!("用户 {} 的密码已更新", username);
+        Ok(())
     }
 
     /// 设置用户的 is_active 状态
@@ -134,6 +232,121 @@ impl UserManager {
         Ok(())
     }
 
+    /// 设置允许的来源 IP / CIDR（空列表表示不限制）
+    pub async fn set_allowed_ips(&self, username: &str, allowed_ips: Vec<String>) -> Result<(), AppError> {
+        Self::validate_allowed_ips(&allowed_ips)?;
+        let users = self.users.read().await;
+        let mut user = users.get(username)
+            .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?
+            .clone();
+        drop(users);
+
+        user.allowed_ips = allowed_ips;
+        user.updated_at = Some(crate::utils::now_beijing_rfc3339());
+        self.save_user(&user).await?;
+
+        tracing::info!(username, allowed_ips = ?user.allowed_ips, "用户允许的来源 IP 已更新");
+        Ok(())
+    }
+
+    /// 设置新 IP 登录通知的 webhook 与邮箱（None 表示取消）
+    pub async fn set_login_notify(&self, username: &str, webhook: Option<String>, email: Option<String>) -> Result<(), AppError> {
+        if let Some(url) = &webhook {
+            reqwest::Url::parse(url).map_err(|_| AppError::BadRequest(format!("无效的 webhook 地址: {}", url)))?;
+        }
+        if email.as_ref().is_some_and(|email| !email.contains('@')) {
+            return Err(AppError::BadRequest("无效的邮箱地址".to_string()));
+        }
+        let users = self.users.read().await;
+        let mut user = users.get(username)
+            .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?
+            .clone();
+        drop(users);
+
+        user.notify_webhook = webhook;
+        user.notify_email = email;
+        user.updated_at = Some(crate::utils::now_beijing_rfc3339());
+        self.save_user(&user).await?;
+
+        tracing::info!(username, "用户登录通知设置已更新");
+        Ok(())
+    }
+
+    /// 设置固定使用的聊天模型（None 表示不固定）及是否允许调用方自选模型
+    pub async fn set_pinned_model(&self, username: &str, pinned_model: Option<String>, model_override: bool) -> Result<(), AppError> {
+        let pinned_model = pinned_model.map(|model| model.trim().to_string());
+        if pinned_model.as_ref().is_some_and(|model| model.is_empty()) {
+            return Err(AppError::BadRequest("pinned_model 不能为空字符串".to_string()));
+        }
+        let users = self.users.read().await;
+        let mut user = users.get(username)
+            .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?
+            .clone();
+        drop(users);
+
+        user.pinned_model = pinned_model;
+        user.model_override = model_override;
+        user.updated_at = Some(crate::utils::now_beijing_rfc3339());
+        self.save_user(&user).await?;
+
+        tracing::info!(username, pinned_model = ?user.pinned_model, model_override, "用户固定模型设置已更新");
+        Ok(())
+    }
+
+    /// 设置 TOTP 两步验证密钥（None 表示关闭两步验证）
+    pub async fn set_totp_secret(&self, username: &str, secret: Option<String>) -> Result<(), AppError> {
+        let users = self.users.read().await;
+        let mut user = users.get(username)
+            .ok_or_else(|| AppError::NotFound(format!("用户 {} 不存在", username)))?
+            .clone();
+        drop(users);
+
+        let enabled = secret.is_some();
+        user.totp_secret = secret;
+        user.updated_at = Some(crate::utils::now_beijing_rfc3339());
+        self.save_user(&user).await?;
+
+        tracing::info!(username, enabled, "用户两步验证设置已更新");
+        Ok(())
+    }
+
+    /// 记录一次成功登录：同一天内只写一次用户文件
+    pub async fn record_login(&self, username: &str) -> Result<(), AppError> {
+        let now = crate::utils::now_beijing_rfc3339();
+        let users = self.users.read().await;
+        let Some(user) = users.get(username) else {
+            return Ok(());
+        };
+        if user.last_login_at.as_deref().is_some_and(|last| last.get(..10) == now.get(..10)) {
+            return Ok(());
+        }
+        let mut user = user.clone();
+        drop(users);
+
+        user.last_login_at = Some(now);
+        self.save_user(&user).await
+    }
+
+    /// 停用用户并把用户文件移到 archive_dir，同时从内存中移除（不再出现在用户列表中）
+    ///
+    /// 恢复时把归档的 user.toml 移回 data/users/{username}.toml 并重启服务
+    pub async fn archive_user(&self, username: &str, archive_dir: &std::path::Path) -> Result<(), AppError> {
+        self.set_user_active(username, false).await?;
+        let file_path = self.users_dir.join(format!("{}.toml", username));
+        tokio::fs::rename(&file_path, archive_dir.join("user.toml"))
+            .await
+            .map_err(|e| AppError::InternalError(format!("归档用户文件失败: {}", e)))?;
+        self.users.write().await.remove(username);
+        Ok(())
+    }
+
+    fn validate_allowed_ips(allowed_ips: &[String]) -> Result<(), AppError> {
+        match allowed_ips.iter().find(|cidr| crate::client_ip::parse_net(cidr).is_none()) {
+            Some(invalid) => Err(AppError::BadRequest(format!("无效的 IP 或 CIDR: {}", invalid))),
+            None => Ok(()),
+        }
+    }
+
     /// 获取用户信息
     pub async fn get_user(&self, username: &str) -> Option<User> {
         let users = self.users.read().await;
@@ -148,10 +361,38 @@ impl UserManager {
                 username: u.username.clone(),
                 quota_tier: u.quota_tier.clone(),
                 is_active: u.is_active,
+                unlimited: u.unlimited,
+                allowed_ips: u.allowed_ips.clone(),
+                last_login_at: u.last_login_at.clone(),
             })
             .collect()
     }
 
+    /// 按条件筛选、排序并分页列出用户
+    pub async fn query_users(&self, query: &UserQuery) -> Result<UserPage, AppError> {
+        let sort = UserSort::parse(query.sort.as_deref().unwrap_or("username"))?;
+        let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+        let page = query.page.unwrap_or(1).max(1);
+
+        let mut users: Vec<UserInfo> = self
+            .list_users()
+            .await
+            .into_iter()
+            .filter(|u| match query.filter {
+                Some(UserFilter::Active) => u.is_active,
+                Some(UserFilter::Inactive) => !u.is_active,
+                None => true,
+            })
+            .filter(|u| query.tier.as_deref().is_none_or(|tier| u.quota_tier == tier))
+            .filter(|u| query.search.as_deref().is_none_or(|prefix| u.username.starts_with(prefix)))
+            .collect();
+        sort.apply(&mut users);
+
+        let total = users.len();
+        let users = users.into_iter().skip((page - 1).saturating_mul(per_page)).take(per_page).collect();
+        Ok(UserPage { users, total, page, per_page })
+    }
+
     /// 校验用户名是否合法
     /// 
     /// 规则：
@@ -206,9 +447,11 @@ impl UserManager {
     }
 
     /// 创建新用户
-    pub async fn create_user(&self, username: String, password: String, quota_tier: String) -> Result<(), AppError> {
+    pub async fn create_user(&self, username: String, password: String, quota_tier: String, unlimited: bool, allowed_ips: Vec<String>) -> Result<(), AppError> {
         // 校验用户名合法性
         Self::validate_username(&username)?;
+        Self::validate_allowed_ips(&allowed_ips)?;
+        self.check_password_policy(&password)?;
 
         // 检查用户是否已存在
         {
@@ -218,12 +461,23 @@ impl UserManager {
             }
         }
 
+        let password_hash = self.hash_password(password).await?;
         let now = crate::utils::now_beijing_rfc3339();
         let user = User {
             username: username.clone(),
-            password,
+            password: String::new(),
+            password_hash: Some(password_hash),
             quota_tier,
             is_active: true,
+            unlimited,
+            allowed_ips,
+            notify_webhook: None,
+            notify_email: None,
+            totp_secret: None,
+            pinned_model: None,
+            model_override: false,
+            access_schedule: None,
+            last_login_at: None,
             created_at: Some(now.clone()),
             updated_at: Some(now),
         };
@@ -233,7 +487,19 @@ impl UserManager {
         Ok(())
     }
 
-    // 注意：不提供物理删除功能，只能通过 set_user_active(username, false) 进行逻辑删除
+    /// 物理删除用户记录（文件与内存），仅供数据擦除使用；返回记录是否存在
+    ///
+    /// 日常"删除"请使用 set_user_active(username, false) 进行逻辑删除
+    pub async fn purge_user(&self, username: &str) -> Result<bool, AppError> {
+        let removed = self.users.write().await.remove(username).is_some();
+
+        let file_path = self.users_dir.join(format!("{}.toml", username));
+        match tokio::fs::remove_file(&file_path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(removed),
+            Err(e) => Err(AppError::InternalError(format!("删除用户文件失败: {}", e))),
+        }
+    }
 }
 
 /// 用户信息（不含密码）
@@ -242,4 +508,92 @@ pub struct UserInfo {
     pub username: String,
     pub quota_tier: String,
     pub is_active: bool,
+    pub unlimited: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_login_at: Option<String>,
+}
+
+/// 每页默认条数
+const DEFAULT_PER_PAGE: usize = 100;
+/// 每页最多条数
+const MAX_PER_PAGE: usize = 1000;
+
+/// 用户列表的查询条件（`GET /admin/users?page=&per_page=&sort=&filter=&tier=&search=`）
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct UserQuery {
+    /// 页码，从 1 开始
+    pub page: Option<usize>,
+    /// 每页条数，默认 100，最多 1000
+    pub per_page: Option<usize>,
+    /// 排序字段：username / tier / last_login，前缀 `-` 表示降序
+    pub sort: Option<String>,
+    /// 只列出启用（active）或停用（inactive）的用户
+    pub filter: Option<UserFilter>,
+    /// 只列出该配额档次的用户
+    pub tier: Option<String>,
+    /// 用户名前缀
+    pub search: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserFilter {
+    Active,
+    Inactive,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SortField {
+    Username,
+    Tier,
+    LastLogin,
+}
+
+/// 排序方式：`username` / `tier` / `last_login`，前缀 `-` 表示降序
+#[derive(Debug, Clone, Copy)]
+struct UserSort {
+    field: SortField,
+    descending: bool,
+}
+
+impl UserSort {
+    fn parse(sort: &str) -> Result<Self, AppError> {
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort, false),
+        };
+        let field = match field {
+            "username" => SortField::Username,
+            "tier" => SortField::Tier,
+            "last_login" => SortField::LastLogin,
+            _ => return Err(AppError::BadRequest(format!("不支持的排序字段: {}（可选 username / tier / last_login）", field))),
+        };
+        Ok(Self { field, descending })
+    }
+
+    /// 排序；同值时按用户名排列，保证分页结果稳定
+    fn apply(self, users: &mut [UserInfo]) {
+        users.sort_by(|a, b| {
+            let ordering = match self.field {
+                SortField::Username => a.username.cmp(&b.username),
+                SortField::Tier => a.quota_tier.cmp(&b.quota_tier),
+                // 从未登录的用户排在最前（降序时在最后）
+                SortField::LastLogin => a.last_login_at.cmp(&b.last_login_at),
+            };
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| a.username.cmp(&b.username))
+        });
+    }
+}
+
+/// 一页用户列表
+#[derive(Debug, serde::Serialize)]
+pub struct UserPage {
+    pub users: Vec<UserInfo>,
+    /// 符合条件的用户总数
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
 }
@@ -0,0 +1,213 @@
+use crate::{config::BackupConfig, error::AppError, object_storage::ObjectStorage, quota::QuotaManager};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// 备份文件名前缀与后缀：data-YYYYMMDD-HHMMSS.tar.gz
+const ARCHIVE_PREFIX: &str = "data-";
+const ARCHIVE_SUFFIX: &str = ".tar.gz";
+
+/// 一次备份的结果
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    pub path: String,
+    pub size_bytes: u64,
+    pub created_at: String,
+    /// 已转存到对象存储时为对象 key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_key: Option<String>,
+    /// 按保留数量删除的旧备份
+    pub pruned: Vec<String>,
+}
+
+/// data/ 目录备份：先落盘配额缓存，再打包为 tar.gz 并按数量保留
+pub struct BackupService {
+    cfg: BackupConfig,
+    data_dir: PathBuf,
+    quota_manager: Arc<QuotaManager>,
+    storage: Option<Arc<ObjectStorage>>,
+    /// 定时任务与管理接口互斥，避免同时打包
+    running: Mutex<()>,
+}
+
+impl BackupService {
+    pub fn new(
+        cfg: BackupConfig,
+        data_dir: impl Into<PathBuf>,
+        quota_manager: Arc<QuotaManager>,
+        storage: Option<Arc<ObjectStorage>>,
+    ) -> Self {
+        Self {
+            cfg,
+            data_dir: data_dir.into(),
+            quota_manager,
+            storage,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// 启动定时备份（未启用或只读模式下不启动）；首次备份在一个周期之后执行
+    pub fn spawn_schedule(self: &Arc<Self>) {
+        if !self.cfg.enabled || crate::read_only::is_enabled() {
+            return;
+        }
+        let service = self.clone();
+        crate::supervisor::spawn("backup_schedule", move || {
+            let service = service.clone();
+            async move {
+                let period = Duration::from_secs(service.cfg.interval_hours.max(1) * 3600);
+                let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = service.run().await {
+                        tracing::error!(error = %e, "定时备份失败");
+                    }
+                }
+            }
+        });
+    }
+
+    /// 执行一次备份
+    pub async fn run(&self) -> Result<BackupInfo, AppError> {
+        crate::read_only::ensure_writable()?;
+        let _guard = self.running.try_lock().map_err(|_| AppError::Conflict("已有备份正在进行".to_string()))?;
+
+        // 配额计数按间隔写盘，打包前先全部落盘
+        self.quota_manager.save_all().await?;
+
+        let data_dir = self.data_dir.clone();
+        let backup_dir = PathBuf::from(&self.cfg.dir);
+        let retention = self.cfg.retention;
+        let result = tokio::task::spawn_blocking(move || -> std::io::Result<(PathBuf, u64, Vec<PathBuf>)> {
+            let archive = create_archive(&data_dir, &backup_dir)?;
+            let size = std::fs::metadata(&archive)?.len();
+            let pruned = prune(&backup_dir, retention)?;
+            Ok((archive, size, pruned))
+        })
+        .await
+        .map_err(|e| AppError::InternalError(format!("备份任务异常退出: {}", e)))?;
+
+        let (archive, size_bytes, pruned) = result.map_err(|e| {
+            crate::metrics::METRICS.backups.with_label_values(&["failure"]).inc();
+            AppError::InternalError(format!("备份数据目录失败: {}", e))
+        })?;
+        crate::metrics::METRICS.backups.with_label_values(&["success"]).inc();
+        tracing::info!(path = %archive.display(), size_bytes, pruned = pruned.len(), "数据目录备份完成");
+
+        // 转存失败不影响本地备份结果，只记录日志与指标
+        let uploaded_key = match &self.storage {
+            Some(storage) => self.upload(storage, &archive).await,
+            None => None,
+        };
+
+        Ok(BackupInfo {
+            path: archive.display().to_string(),
+            size_bytes,
+            created_at: crate::utils::now_beijing_rfc3339(),
+            uploaded_key,
+            pruned: pruned.iter().map(|p| p.display().to_string()).collect(),
+        })
+    }
+
+    async fn upload(&self, storage: &ObjectStorage, archive: &Path) -> Option<String> {
+        let name = archive.file_name()?.to_str()?;
+        match storage.put_file(&format!("backups/{}", name), archive).await {
+            Ok(key) => {
+                tracing::info!(key = %key, "备份已转存到对象存储");
+                if storage.delete_local_after_upload() {
+                    if let Err(e) = tokio::fs::remove_file(archive).await {
+                        tracing::warn!(path = %archive.display(), error = %e, "删除已转存的本地备份失败");
+                    }
+                }
+                Some(key)
+            }
+            Err(e) => {
+                crate::metrics::METRICS.backups.with_label_values(&["upload_failure"]).inc();
+                tracing::error!(error = %e, "备份转存到对象存储失败");
+                None
+            }
+        }
+    }
+}
+
+/// 将 data_dir 打包为 backup_dir/data-YYYYMMDD-HHMMSS.tar.gz（先写 .partial 再重命名）
+fn create_archive(data_dir: &Path, backup_dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(backup_dir)?;
+    let name = format!(
+        "{}{}{}",
+        ARCHIVE_PREFIX,
+        crate::utils::now_beijing().format("%Y%m%d-%H%M%S"),
+        ARCHIVE_SUFFIX
+    );
+    let archive = backup_dir.join(&name);
+    let partial = backup_dir.join(format!("{}.partial", name));
+
+    let file = std::fs::File::create(&partial)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.append_dir_all("data", data_dir)?;
+    builder.into_inner()?.finish()?.sync_all()?;
+
+    std::fs::rename(&partial, &archive)?;
+    Ok(archive)
+}
+
+/// 只保留最新的 retention 个备份，返回被删除的文件
+fn prune(backup_dir: &Path, retention: usize) -> std::io::Result<Vec<PathBuf>> {
+    let mut archives: Vec<PathBuf> = std::fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(ARCHIVE_PREFIX) && n.ends_with(ARCHIVE_SUFFIX))
+                .unwrap_or(false)
+        })
+        .collect();
+    // 文件名含时间戳，按名称倒序即最新在前
+    archives.sort_by(|a, b| b.cmp(a));
+
+    let mut pruned = Vec::new();
+    for path in archives.into_iter().skip(retention.max(1)) {
+        std::fs::remove_file(&path)?;
+        pruned.push(path);
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_and_prune() {
+        let root = std::env::temp_dir().join("test_backup_archive");
+        let _ = std::fs::remove_dir_all(&root);
+        let data_dir = root.join("data");
+        let backup_dir = root.join("backups");
+        std::fs::create_dir_all(data_dir.join("users")).unwrap();
+        std::fs::write(data_dir.join("users/alice.toml"), "username = \"alice\"").unwrap();
+
+        std::fs::create_dir_all(&backup_dir).unwrap();
+        for old in ["data-20250101-000000.tar.gz", "data-20250102-000000.tar.gz"] {
+            std::fs::write(backup_dir.join(old), "").unwrap();
+        }
+        std::fs::write(backup_dir.join("unrelated.txt"), "").unwrap();
+
+        let archive = create_archive(&data_dir, &backup_dir).unwrap();
+        let mut entries: Vec<String> = tar::Archive::new(flate2::read::GzDecoder::new(std::fs::File::open(&archive).unwrap()))
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        entries.sort();
+        assert!(entries.contains(&"data/users/alice.toml".to_string()));
+
+        let pruned = prune(&backup_dir, 2).unwrap();
+        assert_eq!(pruned, vec![backup_dir.join("data-20250101-000000.tar.gz")]);
+        assert!(archive.exists() && backup_dir.join("unrelated.txt").exists());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
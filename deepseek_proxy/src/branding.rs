@@ -0,0 +1,81 @@
+use crate::config::BrandingConfig;
+use once_cell::sync::OnceCell;
+use serde_json::{Map, Value};
+
+/// 部署品牌信息（错误响应在 IntoResponse 中构建，拿不到 AppState，因此与 METRICS 一样做成全局）
+static BRANDING: OnceCell<BrandingConfig> = OnceCell::new();
+
+/// 设置品牌信息；进程内只生效一次，未调用时错误响应不带品牌字段
+pub fn init(cfg: &BrandingConfig) {
+    if BRANDING.set(cfg.clone()).is_err() {
+        tracing::debug!("品牌信息已设置，忽略重复初始化");
+    }
+}
+
+fn current() -> Option<&'static BrandingConfig> {
+    BRANDING.get()
+}
+
+/// 在错误对象中附加服务名与客服联系方式（未配置的字段省略）
+pub fn error_object(code: &str, message: impl Into<Value>) -> Map<String, Value> {
+    let mut error = Map::new();
+    error.insert("code".to_string(), Value::String(code.to_string()));
+    error.insert("message".to_string(), message.into());
+    if let Some(branding) = current() {
+        insert_contact(&mut error, branding);
+    }
+    error
+}
+
+/// 配额耗尽（402）响应：另外附加升级链接
+pub fn quota_exceeded_body(used: u32, limit: u32, reset_at: String) -> Value {
+    quota_exceeded_body_with(current(), used, limit, reset_at)
+}
+
+fn quota_exceeded_body_with(branding: Option<&BrandingConfig>, used: u32, limit: u32, reset_at: String) -> Value {
+    let mut body = Map::new();
+    body.insert("error".to_string(), Value::from("quota_exceeded"));
+    body.insert("message".to_string(), Value::from("月度配额已耗尽，请升级套餐或等待下月重置"));
+    body.insert(
+        "details".to_string(),
+        serde_json::json!({ "used": used, "limit": limit, "reset_at": reset_at }),
+    );
+    if let Some(branding) = branding {
+        if let Some(url) = &branding.upgrade_url {
+            body.insert("upgrade_url".to_string(), Value::from(url.as_str()));
+        }
+        insert_contact(&mut body, branding);
+    }
+    Value::Object(body)
+}
+
+fn insert_contact(target: &mut Map<String, Value>, branding: &BrandingConfig) {
+    if let Some(name) = &branding.service_name {
+        target.insert("service".to_string(), Value::from(name.as_str()));
+    }
+    if let Some(contact) = &branding.support_contact {
+        target.insert("support".to_string(), Value::from(contact.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_body_includes_configured_branding_only() {
+        let plain = quota_exceeded_body_with(None, 10, 10, "2026-11-01".to_string());
+        assert!(plain.get("upgrade_url").is_none());
+        assert_eq!(plain["details"]["limit"], 10);
+
+        let branding = BrandingConfig {
+            service_name: Some("Acme AI".to_string()),
+            upgrade_url: Some("https://acme.example/upgrade".to_string()),
+            support_contact: None,
+        };
+        let body = quota_exceeded_body_with(Some(&branding), 10, 10, "2026-11-01".to_string());
+        assert_eq!(body["upgrade_url"], "https://acme.example/upgrade");
+        assert_eq!(body["service"], "Acme AI");
+        assert!(body.get("support").is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{config::ChaosConfig, error::AppError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 故障注入类型（chaos_injections_total 的 kind 标签）
+pub const CHAOS_KINDS: [&str; 3] = ["delay", "drop_chunk", "error"];
+
+/// 上游故障注入器（仅用于测试环境）：按配置的概率延迟上游请求、丢弃流式数据块、返回 5xx，
+/// 用来覆盖超时、重试与错误流的处理路径
+#[derive(Debug)]
+pub struct FaultInjector {
+    cfg: ChaosConfig,
+    /// splitmix64 状态；不需要密码学强度，只要各次判定互相独立
+    state: AtomicU64,
+}
+
+impl FaultInjector {
+    /// `[chaos] enabled = true` 时校验配置并创建注入器
+    pub fn from_config(cfg: &ChaosConfig) -> Result<Option<Self>, AppError> {
+        if !cfg.enabled {
+            return Ok(None);
+        }
+        validate(cfg)?;
+        tracing::warn!(
+            delay_probability = cfg.delay_probability,
+            drop_chunk_probability = cfg.drop_chunk_probability,
+            error_probability = cfg.error_probability,
+            "已开启上游故障注入（[chaos]），请勿在生产环境使用"
+        );
+        let seed = cfg.seed.unwrap_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64);
+        Ok(Some(Self { cfg: cfg.clone(), state: AtomicU64::new(seed) }))
+    }
+
+    /// 以概率 p 返回 true
+    fn roll(&self, p: f64) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+        let mut z = self.state.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// 发送上游请求前调用：按概率延迟，再按概率返回模拟的上游错误状态码
+    pub async fn before_request(&self) -> Option<u16> {
+        if self.roll(self.cfg.delay_probability) {
+            crate::metrics::METRICS.chaos_injections.with_label_values(&["delay"]).inc();
+            tracing::debug!(delay_ms = self.cfg.delay_ms, "故障注入：延迟上游请求");
+            tokio::time::sleep(Duration::from_millis(self.cfg.delay_ms)).await;
+        }
+        if self.roll(self.cfg.error_probability) {
+            crate::metrics::METRICS.chaos_injections.with_label_values(&["error"]).inc();
+            tracing::debug!(status = self.cfg.error_status, "故障注入：模拟上游错误");
+            return Some(self.cfg.error_status);
+        }
+        None
+    }
+
+    /// 流式响应的每个数据块调用一次，返回 true 时丢弃该数据块
+    pub fn drop_chunk(&self) -> bool {
+        let dropped = self.roll(self.cfg.drop_chunk_probability);
+        if dropped {
+            crate::metrics::METRICS.chaos_injections.with_label_values(&["drop_chunk"]).inc();
+        }
+        dropped
+    }
+}
+
+fn validate(cfg: &ChaosConfig) -> Result<(), AppError> {
+    for (name, p) in [
+        ("delay_probability", cfg.delay_probability),
+        ("drop_chunk_probability", cfg.drop_chunk_probability),
+        ("error_probability", cfg.error_probability),
+    ] {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(AppError::configuration_error(format!("[chaos] {} 必须在 0 到 1 之间，当前为 {}", name, p)));
+        }
+    }
+    if !(500..=599).contains(&cfg.error_status) && cfg.error_status != 429 {
+        return Err(AppError::configuration_error(format!("[chaos] error_status 应为 5xx 或 429，当前为 {}", cfg.error_status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn injector(p: f64) -> FaultInjector {
+        FaultInjector::from_config(&ChaosConfig {
+            enabled: true,
+            drop_chunk_probability: p,
+            seed: Some(42),
+            ..ChaosConfig::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_probabilities() {
+        assert!(FaultInjector::from_config(&ChaosConfig::default()).unwrap().is_none());
+        assert!((0..1000).all(|_| !injector(0.0).drop_chunk()));
+        let always = injector(1.0);
+        assert!((0..1000).all(|_| always.drop_chunk()));
+        let half = injector(0.5);
+        let dropped = (0..10_000).filter(|_| half.drop_chunk()).count();
+        assert!((4_500..5_500).contains(&dropped), "dropped = {}", dropped);
+    }
+
+    #[tokio::test]
+    async fn test_forced_error_status() {
+        let chaos = FaultInjector::from_config(&ChaosConfig {
+            enabled: true,
+            error_probability: 1.0,
+            ..ChaosConfig::default()
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(chaos.before_request().await, Some(503));
+        assert!(FaultInjector::from_config(&ChaosConfig { enabled: true, error_probability: 1.5, ..ChaosConfig::default() }).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{app::AppState, config::ChatOpsConfig, error::AppError, metrics::METRICS};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::HeaderMap,
+    Json,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+/// 请求时间戳与服务器时间的最大偏差（秒），超出视为重放
+const MAX_TIMESTAMP_SKEW: i64 = 300;
+
+const SLACK_TIMESTAMP_HEADER: &str = "x-slack-request-timestamp";
+const SLACK_SIGNATURE_HEADER: &str = "x-slack-signature";
+const DISCORD_TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+const DISCORD_SIGNATURE_HEADER: &str = "x-signature-ed25519";
+
+const HELP_TEXT: &str = "可用命令：\n• user <用户名>：账户状态\n• quota <用户名>：配额用量\n• stats：今日统计";
+
+/// 斜杠命令（全部为只读查询）
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    User(String),
+    Quota(String),
+    Stats,
+    Help,
+}
+
+impl Command {
+    /// 解析命令参数文本，如 "quota alice"；无法识别时返回帮助
+    pub fn parse(text: &str) -> Self {
+        let mut words = text.split_whitespace();
+        match (words.next().map(str::to_lowercase).as_deref(), words.next()) {
+            (Some("user"), Some(name)) => Command::User(name.to_string()),
+            (Some("quota"), Some(name)) => Command::Quota(name.to_string()),
+            (Some("stats"), _) => Command::Stats,
+            _ => Command::Help,
+        }
+    }
+}
+
+/// Slack 斜杠命令的表单字段
+#[derive(Debug, Deserialize)]
+struct SlackCommand {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    user_id: String,
+}
+
+/// Slack 斜杠命令入口：校验 Signing Secret 签名后执行命令，回复仅调用者可见
+pub async fn slack(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Result<Json<Value>, AppError> {
+    let cfg = &state.config.chatops;
+    let secret = cfg.slack_signing_secret.as_deref().unwrap_or_default();
+    verify_slack(secret, header(&headers, SLACK_TIMESTAMP_HEADER), header(&headers, SLACK_SIGNATURE_HEADER), &body, now())
+        .map_err(|reason| rejected("slack", reason))?;
+
+    let command: SlackCommand = serde_urlencoded::from_bytes(&body)
+        .map_err(|e| AppError::BadRequest(format!("无法解析 Slack 命令: {}", e)))?;
+    let text = run(&state, cfg, "slack", &command.user_id, &command.text).await;
+    Ok(Json(json!({ "response_type": "ephemeral", "text": text })))
+}
+
+/// Discord 交互入口：校验 Ed25519 签名，响应 PING 与斜杠命令（命令参数取各字符串选项，以空格拼接）
+pub async fn discord(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Result<Json<Value>, AppError> {
+    let cfg = &state.config.chatops;
+    let public_key = cfg.discord_public_key.as_deref().unwrap_or_default();
+    verify_discord(
+        public_key,
+        header(&headers, DISCORD_TIMESTAMP_HEADER),
+        header(&headers, DISCORD_SIGNATURE_HEADER),
+        &body,
+        now(),
+    )
+    .map_err(|reason| rejected("discord", reason))?;
+
+    let interaction: Value = serde_json::from_slice(&body)?;
+    // 1 = PING（Discord 保存 Interactions Endpoint URL 时的校验请求）
+    if interaction["type"] == 1 {
+        return Ok(Json(json!({ "type": 1 })));
+    }
+    let user_id = interaction
+        .pointer("/member/user/id")
+        .or_else(|| interaction.pointer("/user/id"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let text = interaction
+        .pointer("/data/options")
+        .and_then(Value::as_array)
+        .map(|options| {
+            options
+                .iter()
+                .filter_map(|o| o["value"].as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+    let content = run(&state, cfg, "discord", user_id, &text).await;
+    // 4 = 直接回复消息，flags 64 = 仅调用者可见
+    Ok(Json(json!({ "type": 4, "data": { "content": content, "flags": 64 } })))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn rejected(platform: &str, reason: &str) -> AppError {
+    tracing::warn!(platform, reason, "拒绝聊天运维命令：签名校验失败");
+    AppError::Unauthorized("签名校验失败".to_string())
+}
+
+/// 检查调用者权限并执行命令
+async fn run(state: &AppState, cfg: &ChatOpsConfig, platform: &str, user_id: &str, text: &str) -> String {
+    if !cfg.allowed_user_ids.is_empty() && !cfg.allowed_user_ids.iter().any(|id| id == user_id) {
+        tracing::warn!(platform, user_id, "聊天运维命令：调用者不在允许列表中");
+        return "你没有权限执行此命令".to_string();
+    }
+    let command = Command::parse(text);
+    tracing::info!(platform, user_id, command = ?command, "执行聊天运维命令");
+    execute(state, command).await
+}
+
+async fn execute(state: &AppState, command: Command) -> String {
+    match command {
+        Command::User(name) => match state.user_manager.get_user(&name).await {
+            Some(user) => format!(
+                "用户 {}：{}，档次 {}{}",
+                user.username,
+                if user.is_active { "已启用" } else { "已停用" },
+                user.quota_tier,
+                if user.unlimited { "（服务账户，不限配额）" } else { "" }
+            ),
+            None => format!("用户 {} 不存在", name),
+        },
+        Command::Quota(name) => {
+            if state.user_manager.get_user(&name).await.is_none() {
+                return format!("用户 {} 不存在", name);
+            }
+            match state.quota_manager.get_quota(&name).await {
+                Ok(quota) => format!(
+                    "用户 {} 配额（{}）：已用 {}/{}，预付费额度 {}，重置时间 {}",
+                    name, quota.tier, quota.used_count, quota.monthly_limit, quota.credits, quota.reset_at
+                ),
+                Err(e) => format!("查询用户 {} 配额失败: {}", name, e),
+            }
+        }
+        Command::Stats => {
+            let users = state.user_manager.list_users().await;
+            let active = users.iter().filter(|u| u.is_active).count();
+            let tokens = METRICS.today_tokens();
+            format!(
+                "今日 token：输入 {}，输出 {}（缓存命中 {}）\n启动以来聊天请求：成功 {}，失败 {}\n用户：{} 个（启用 {} 个）",
+                tokens.input,
+                tokens.output,
+                tokens.cache_hit,
+                METRICS.chat_requests.with_label_values(&["success"]).get() as u64,
+                METRICS.chat_requests.with_label_values(&["fail"]).get() as u64,
+                users.len(),
+                active
+            )
+        }
+        Command::Help => HELP_TEXT.to_string(),
+    }
+}
+
+fn check_timestamp(timestamp: Option<&str>, now: i64) -> Result<(), &'static str> {
+    let timestamp: i64 = timestamp.ok_or("缺少时间戳")?.parse().map_err(|_| "时间戳格式错误")?;
+    if (now - timestamp).abs() > MAX_TIMESTAMP_SKEW {
+        return Err("时间戳超出允许范围");
+    }
+    Ok(())
+}
+
+/// Slack 签名："v0=" + hex(HMAC-SHA256(signing_secret, "v0:{timestamp}:{body}"))
+fn verify_slack(secret: &str, timestamp: Option<&str>, signature: Option<&str>, body: &[u8], now: i64) -> Result<(), &'static str> {
+    check_timestamp(timestamp, now)?;
+    let signature = signature.and_then(|s| s.strip_prefix("v0=")).ok_or("缺少签名")?;
+    let expected = hex::decode(signature).map_err(|_| "签名格式错误")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC 接受任意长度的密钥");
+    mac.update(format!("v0:{}:", timestamp.unwrap_or_default()).as_bytes());
+    mac.update(body);
+    mac.verify_slice(&expected).map_err(|_| "签名不匹配")
+}
+
+/// Discord 签名：Ed25519(timestamp + body)，公钥与签名均为十六进制
+fn verify_discord(public_key: &str, timestamp: Option<&str>, signature: Option<&str>, body: &[u8], now: i64) -> Result<(), &'static str> {
+    check_timestamp(timestamp, now)?;
+    let key = hex::decode(public_key).map_err(|_| "公钥格式错误")?;
+    let signature = hex::decode(signature.ok_or("缺少签名")?).map_err(|_| "签名格式错误")?;
+    let mut message = timestamp.unwrap_or_default().as_bytes().to_vec();
+    message.extend_from_slice(body);
+    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+        .verify(&message, &signature)
+        .map_err(|_| "签名不匹配")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse("quota alice"), Command::Quota("alice".to_string()));
+        assert_eq!(Command::parse("  USER bob "), Command::User("bob".to_string()));
+        assert_eq!(Command::parse("stats"), Command::Stats);
+        assert_eq!(Command::parse("quota"), Command::Help);
+        assert_eq!(Command::parse(""), Command::Help);
+    }
+
+    #[test]
+    fn test_verify_slack() {
+        let body = b"command=%2Fproxy&text=stats&user_id=U1";
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"v0:1000:");
+        mac.update(body);
+        let signature = format!("v0={}", hex::encode(mac.finalize().into_bytes()));
+
+        assert!(verify_slack("secret", Some("1000"), Some(&signature), body, 1100).is_ok());
+        assert!(verify_slack("other", Some("1000"), Some(&signature), body, 1100).is_err());
+        assert!(verify_slack("secret", Some("1000"), Some(&signature), b"text=quota", 1100).is_err());
+        // 超出时间窗口视为重放
+        assert!(verify_slack("secret", Some("1000"), Some(&signature), body, 2000).is_err());
+    }
+
+    #[test]
+    fn test_verify_discord() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = hex::encode(pair.public_key().as_ref());
+        let body = br#"{"type":1}"#;
+        let signature = hex::encode(pair.sign(&[b"1000".as_slice(), body].concat()).as_ref());
+
+        assert!(verify_discord(&public_key, Some("1000"), Some(&signature), body, 1000).is_ok());
+        assert!(verify_discord(&public_key, Some("1001"), Some(&signature), body, 1000).is_err());
+        assert!(verify_discord(&public_key, Some("1000"), None, body, 1000).is_err());
+    }
+}
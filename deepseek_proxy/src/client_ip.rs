@@ -0,0 +1,259 @@
+use crate::config::TrustedProxiesConfig;
+use crate::error::AppError;
+use crate::listener::ListenerInfo;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, Extensions, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED: &str = "forwarded";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// 配置中代表 Unix socket 对端的条目（本机 nginx 等通过 socket 转发时使用）
+const UNIX_ENTRY: &str = "unix";
+
+/// 受信任的反向代理：对端地址在列表内时，从转发头中取真实客户端 IP
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+    /// 信任 Unix socket 对端（配置条目 `"unix"`）
+    unix: bool,
+}
+
+impl TrustedProxies {
+    /// 解析 CIDR 列表；单个 IP（不带前缀长度）视为 /32 或 /128，`"unix"` 表示信任 Unix socket 对端
+    pub fn from_config(cfg: &TrustedProxiesConfig) -> anyhow::Result<Self> {
+        let unix = cfg.cidrs.iter().any(|cidr| cidr.trim() == UNIX_ENTRY);
+        let nets = cfg
+            .cidrs
+            .iter()
+            .filter(|cidr| cidr.trim() != UNIX_ENTRY)
+            .map(|cidr| parse_net(cidr).ok_or_else(|| anyhow::anyhow!("server.trusted_proxies 中的 CIDR 无效: {}", cidr)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { nets, unix })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nets.is_empty() && !self.unix
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// 对端为受信任代理时返回转发头中的客户端 IP，否则返回 None（保持对端地址）
+    ///
+    /// X-Forwarded-For 从右向左跳过受信任代理，第一个不受信任的地址即客户端（防止客户端伪造最左侧的值）；
+    /// 没有可用的 X-Forwarded-For 时依次使用 Forwarded（RFC 7239，按同样规则取 `for=`）与 X-Real-IP
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> Option<IpAddr> {
+        if !self.is_trusted(peer) {
+            return None;
+        }
+        self.forwarded_client(headers)
+    }
+
+    /// Unix socket 对端没有地址：配置了 `"unix"` 时从转发头取客户端 IP，否则返回 None
+    pub fn unix_client_ip(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        if !self.unix {
+            return None;
+        }
+        self.forwarded_client(headers)
+    }
+
+    fn forwarded_client(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        forwarded_for(headers)
+            .or_else(|| forwarded(headers))
+            .map(|chain| {
+                chain
+                    .iter()
+                    .rev()
+                    .find(|ip| !self.is_trusted(**ip))
+                    .or_else(|| chain.first())
+                    .copied()
+            })
+            .unwrap_or_else(|| headers.get(X_REAL_IP)?.to_str().ok()?.trim().parse().ok())
+            .map(|ip| ip.to_canonical())
+    }
+}
+
+/// 解析 CIDR；单个 IP（不带前缀长度）视为 /32 或 /128
+pub fn parse_net(cidr: &str) -> Option<IpNet> {
+    let cidr = cidr.trim();
+    cidr.parse::<IpNet>().ok().or_else(|| cidr.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// ip 是否落在任一 CIDR 内（无法解析的条目不匹配任何地址）
+pub fn matches_any(cidrs: &[String], ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    cidrs.iter().filter_map(|cidr| parse_net(cidr)).any(|net| net.contains(&ip))
+}
+
+/// 解析全部 X-Forwarded-For 头部；存在无法解析的地址时整体忽略
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<IpAddr>> {
+    let mut chain = Vec::new();
+    for value in headers.get_all(X_FORWARDED_FOR) {
+        for part in value.to_str().ok()?.split(',') {
+            chain.push(part.trim().parse::<IpAddr>().ok()?);
+        }
+    }
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// 解析全部 Forwarded 头部中的 `for=` 地址（可带引号、端口，IPv6 放在方括号内）；
+/// 存在无法解析的地址（如 `unknown` 或混淆标识）时整体忽略
+fn forwarded(headers: &HeaderMap) -> Option<Vec<IpAddr>> {
+    let mut chain = Vec::new();
+    for value in headers.get_all(FORWARDED) {
+        for element in value.to_str().ok()?.split(',') {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then(|| value.trim().trim_matches('"'))
+            });
+            if let Some(node) = node {
+                chain.push(parse_node(node)?);
+            }
+        }
+    }
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// 解析 Forwarded 的节点标识：`192.0.2.1`、`192.0.2.1:8080`、`[2001:db8::1]`、`[2001:db8::1]:8080`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// 请求的客户端 IP（经 [`resolve_client_ip`] 解析后）；监听器未注入 `ConnectInfo` 时返回 None
+pub fn peer_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip())
+}
+
+/// 提取器：请求的客户端 IP，位于受信任代理之后时为转发头中的真实地址
+///
+/// 基于客户端 IP 的功能（登录防爆破、行为日志、IP 限制）统一通过它或 [`peer_ip`] 取地址，不要直接读转发头
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        peer_ip(&parts.extensions)
+            .map(ClientIp)
+            .ok_or_else(|| AppError::InternalError("无法获取客户端地址".to_string()))
+    }
+}
+
+/// 中间件：对端为受信任代理时，用真实客户端 IP 替换 `ConnectInfo`，
+/// 使登录防爆破、行为日志与管理接口 localhost 检查看到的都是客户端地址
+pub async fn resolve_client_ip(State(proxies): State<Arc<TrustedProxies>>, mut request: Request, next: Next) -> Response {
+    if !proxies.is_empty() {
+        if let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>().copied() {
+            let unix = request.extensions().get::<ListenerInfo>().is_some_and(|listener| listener.unix);
+            let ip = if unix {
+                proxies.unix_client_ip(request.headers())
+            } else {
+                proxies.client_ip(peer.ip(), request.headers())
+            };
+            if let Some(ip) = ip {
+                request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip, peer.port())));
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(cidrs: &[&str]) -> TrustedProxies {
+        TrustedProxies::from_config(&TrustedProxiesConfig {
+            cidrs: cidrs.iter().map(|c| c.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_client_ip_from_trusted_proxy() {
+        let p = proxies(&["10.0.0.0/8", "127.0.0.1"]);
+        let lb: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // 客户端伪造的最左侧地址被忽略，取最右侧的非代理地址
+        let h = headers(&[(X_FORWARDED_FOR, "1.1.1.1, 203.0.113.5, 10.0.0.7")]);
+        assert_eq!(p.client_ip(lb, &h), Some("203.0.113.5".parse().unwrap()));
+
+        assert_eq!(p.client_ip(lb, &headers(&[(X_REAL_IP, "198.51.100.2")])), Some("198.51.100.2".parse().unwrap()));
+        assert_eq!(p.client_ip(lb, &headers(&[])), None);
+        // 不受信任的对端：不读取转发头
+        assert_eq!(p.client_ip("203.0.113.9".parse().unwrap(), &h), None);
+        // IPv4 映射地址按 IPv4 匹配
+        assert_eq!(p.client_ip("::ffff:127.0.0.1".parse().unwrap(), &h), Some("203.0.113.5".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_unix_socket_peer() {
+        let h = headers(&[(X_FORWARDED_FOR, "203.0.113.5")]);
+        // 未配置 "unix"：socket 对端不读取转发头，回环网段的信任也不适用于 socket
+        let p = proxies(&["127.0.0.1"]);
+        assert_eq!(p.unix_client_ip(&h), None);
+        let p = proxies(&["unix"]);
+        assert!(!p.is_empty());
+        assert_eq!(p.unix_client_ip(&h), Some("203.0.113.5".parse().unwrap()));
+        assert_eq!(p.client_ip("127.0.0.1".parse().unwrap(), &h), None);
+    }
+
+    #[test]
+    fn test_matches_any() {
+        let cidrs = vec!["203.0.113.0/24".to_string(), "2001:db8::1".to_string(), "bogus".to_string()];
+        assert!(matches_any(&cidrs, "203.0.113.77".parse().unwrap()));
+        assert!(matches_any(&cidrs, "::ffff:203.0.113.1".parse().unwrap()));
+        assert!(matches_any(&cidrs, "2001:db8::1".parse().unwrap()));
+        assert!(!matches_any(&cidrs, "198.51.100.1".parse().unwrap()));
+        assert!(!matches_any(&[], "198.51.100.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_from_forwarded() {
+        let p = proxies(&["10.0.0.0/8"]);
+        let lb: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let h = headers(&[(FORWARDED, "for=1.1.1.1, for=\"[2001:db8::17]:4711\";proto=https, For=10.0.0.7:80;by=10.0.0.1")]);
+        assert_eq!(p.client_ip(lb, &h), Some("2001:db8::17".parse().unwrap()));
+        let h = headers(&[(FORWARDED, "for=198.51.100.3;proto=http"), (X_REAL_IP, "192.0.2.9")]);
+        assert_eq!(p.client_ip(lb, &h), Some("198.51.100.3".parse().unwrap()));
+        // X-Forwarded-For 优先；混淆标识使 Forwarded 整体失效，退回 X-Real-IP
+        let h = headers(&[(FORWARDED, "for=198.51.100.3"), (X_FORWARDED_FOR, "203.0.113.5")]);
+        assert_eq!(p.client_ip(lb, &h), Some("203.0.113.5".parse().unwrap()));
+        let h = headers(&[(FORWARDED, "for=unknown, for=198.51.100.3"), (X_REAL_IP, "192.0.2.9")]);
+        assert_eq!(p.client_ip(lb, &h), Some("192.0.2.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_config_and_headers() {
+        assert!(TrustedProxies::from_config(&TrustedProxiesConfig { cidrs: vec!["10.0.0.0/33".to_string()] }).is_err());
+        let p = proxies(&["10.0.0.0/8"]);
+        let h = headers(&[(X_FORWARDED_FOR, "not-an-ip"), (X_REAL_IP, "198.51.100.2")]);
+        assert_eq!(p.client_ip("10.0.0.1".parse().unwrap(), &h), Some("198.51.100.2".parse().unwrap()));
+    }
+}
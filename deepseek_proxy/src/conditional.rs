@@ -0,0 +1,130 @@
+//! 条件 GET：为仪表盘频繁轮询的只读接口（/admin/users、/admin/quotas、/metrics）加上 ETag 与 Last-Modified，
+//! 内容未变化时返回 304，不再经上行带宽重复传输完整内容
+//!
+//! ETag 取响应体的 SHA-256；Last-Modified 为同一地址（含查询参数）的响应体最近一次变化的时间
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+/// 最多记录的地址数（查询参数不同视为不同地址），超过时清空重新计时
+const MAX_TRACKED: usize = 1024;
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// 地址 -> (最近一次的 ETag, 内容变化时间)
+static LAST_CHANGED: Lazy<DashMap<String, (String, DateTime<Utc>)>> = Lazy::new(DashMap::new);
+
+/// 中间件：成功的 GET 响应带上 ETag / Last-Modified，条件满足时返回 304
+///
+/// If-None-Match 优先于 If-Modified-Since（RFC 9110）
+pub async fn conditional_get(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+    let key = request.uri().to_string();
+    let conditions = request.headers().clone();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(uri = %key, error = %e, "读取响应体失败");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let etag = format!("\"{}\"", &hex::encode(Sha256::digest(&bytes))[..32]);
+    let changed_at = last_changed(key, &etag);
+
+    let validators = [
+        (header::ETAG, HeaderValue::from_str(&etag).ok()),
+        (header::LAST_MODIFIED, HeaderValue::from_str(&changed_at.format(HTTP_DATE_FORMAT).to_string()).ok()),
+        // 允许缓存，但每次使用前都要重新验证
+        (header::CACHE_CONTROL, Some(HeaderValue::from_static("no-cache"))),
+    ];
+    let mut headers = HeaderMap::new();
+    for (name, value) in validators {
+        if let Some(value) = value {
+            headers.insert(name, value);
+        }
+    }
+
+    if not_modified(&conditions, &etag, changed_at) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    parts.headers.extend(headers);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// 记录本次的 ETag，返回内容最近一次变化的时间（精确到秒，与 HTTP 日期一致）
+fn last_changed(key: String, etag: &str) -> DateTime<Utc> {
+    if LAST_CHANGED.len() >= MAX_TRACKED && !LAST_CHANGED.contains_key(&key) {
+        LAST_CHANGED.clear();
+    }
+    let now = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+    let mut entry = LAST_CHANGED.entry(key).or_insert_with(|| (etag.to_string(), now));
+    if entry.0 != etag {
+        *entry = (etag.to_string(), now);
+    }
+    entry.1
+}
+
+fn not_modified(conditions: &HeaderMap, etag: &str, changed_at: DateTime<Utc>) -> bool {
+    if let Some(value) = conditions.get(header::IF_NONE_MATCH) {
+        let Ok(value) = value.to_str() else { return false };
+        // 弱比较：忽略 W/ 前缀
+        return value.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    conditions
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| changed_at <= since)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_str(value).unwrap())])
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let changed_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let etag = "\"abc\"";
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "\"x\", W/\"abc\""), etag, changed_at));
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "*"), etag, changed_at));
+        assert!(!not_modified(&headers(header::IF_NONE_MATCH, "\"x\""), etag, changed_at));
+        // If-None-Match 存在时忽略 If-Modified-Since
+        let mut both = headers(header::IF_NONE_MATCH, "\"x\"");
+        both.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_static("Fri, 02 Jan 2026 03:04:05 GMT"));
+        assert!(!not_modified(&both, etag, changed_at));
+
+        assert!(not_modified(&headers(header::IF_MODIFIED_SINCE, "Fri, 02 Jan 2026 03:04:05 GMT"), etag, changed_at));
+        assert!(!not_modified(&headers(header::IF_MODIFIED_SINCE, "Fri, 02 Jan 2026 03:04:04 GMT"), etag, changed_at));
+        assert!(!not_modified(&headers(header::IF_MODIFIED_SINCE, "garbage"), etag, changed_at));
+        assert!(!not_modified(&HeaderMap::new(), etag, changed_at));
+        assert_eq!(changed_at.format(HTTP_DATE_FORMAT).to_string(), "Fri, 02 Jan 2026 03:04:05 GMT");
+    }
+
+    #[test]
+    fn test_last_changed_only_moves_when_content_changes() {
+        let key = "/test/conditional".to_string();
+        let first = last_changed(key.clone(), "\"a\"");
+        assert_eq!(last_changed(key.clone(), "\"a\""), first);
+        assert!(last_changed(key, "\"b\"") >= first);
+    }
+}
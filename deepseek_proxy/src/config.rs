@@ -1,7 +1,11 @@
-use serde::Deserialize;
+use crate::auth::access_schedule::AccessSchedule;
+use crate::quota::ResetPolicy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Config {
     pub server: ServerConfig,
     pub auth: AuthConfig,
@@ -11,30 +15,269 @@ pub struct Config {
     pub quota: QuotaConfig,
     #[serde(default)]
     pub security: SecurityConfig,
+    #[serde(default)]
+    pub probe: ProbeConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+    #[serde(default)]
+    pub observability: ObservabilityConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub load_shedding: LoadSheddingConfig,
+    #[serde(default)]
+    pub activity_log: ActivityLogConfig,
+    #[serde(default)]
+    pub data_integrity: DataIntegrityConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub streaming: StreamingConfig,
+    #[serde(default)]
+    pub spam: SpamConfig,
+    #[serde(default)]
+    pub prompt_cache: PromptCacheConfig,
+    #[serde(default)]
+    pub disk_health: DiskHealthConfig,
+    #[serde(default)]
+    pub slo: SloConfig,
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    /// 实验性功能开关（名称见 flags::Flag），修改后无需重启即可生效
+    #[serde(default)]
+    pub flags: std::collections::HashMap<String, bool>,
+    #[serde(default)]
+    pub branding: BrandingConfig,
+    #[serde(default)]
+    pub reconciliation: ReconciliationConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub request_metadata: RequestMetadataConfig,
+    #[serde(default)]
+    pub chatops: ChatOpsConfig,
+    /// 各档次允许使用服务的时段（档次名 -> 时段），用户记录中的 access_schedule 优先
+    #[serde(default)]
+    pub access_schedules: AccessSchedulesConfig,
+    #[serde(default)]
+    pub user_expiry: UserExpiryConfig,
+    #[serde(default)]
+    pub vision: VisionConfig,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    /// 上游模型能力表（模型名 -> 能力），请求校验与 GET /models/:name/capabilities 共用
+    #[serde(default)]
+    pub models: ModelRegistryConfig,
+    /// S3 兼容对象存储（可选），用于转存备份与滚动后的行为日志
+    #[serde(default)]
+    pub object_storage: Option<ObjectStorageConfig>,
+    /// 各配置项的来源（仅用于生效配置报告）
+    #[serde(skip)]
+    pub sources: ConfigSources,
+}
+
+/// 记录每个配置项由谁设置：配置文件、环境变量、命令行，其余为默认值
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    /// 配置文件中出现的配置项路径（如 `server.port`）
+    pub file: HashSet<String>,
+    /// 被环境变量或命令行覆盖的配置项 -> 来源
+    pub overrides: HashMap<String, &'static str>,
+}
+
+impl ConfigSources {
+    pub fn source_of(&self, key: &str) -> &'static str {
+        use crate::effective_config::{SOURCE_DEFAULT, SOURCE_FILE};
+        if let Some(source) = self.overrides.get(key) {
+            return source;
+        }
+        if self.file.contains(key) { SOURCE_FILE } else { SOURCE_DEFAULT }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// 监听地址覆盖：`unix:/run/proxy.sock` 表示监听 Unix domain socket（不开放 TCP 端口）
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// 内部监听地址（如 `127.0.0.1:9877` 或 `unix:/run/proxy-admin.sock`）
+    ///
+    /// 配置后 /admin、/probe 与 /metrics 只在该地址提供（明文 HTTP），公开地址只提供登录与代理接口
+    #[serde(default)]
+    pub internal_listen: Option<String>,
+    /// host 为 IPv6 地址（如 "::"）时只接受 IPv6 连接；默认 false 即同时接受 IPv4 与 IPv6
+    #[serde(default)]
+    pub ipv6_only: bool,
+    /// 公开 TCP 监听要求每个连接以 PROXY protocol（v1/v2）头部开始，客户端 IP 取自头部
+    ///
+    /// 只在四层负载均衡之后启用：开启后直连的客户端会被断开
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// 受信任的反向代理（nginx、负载均衡），来自这些地址的请求按转发头识别客户端 IP
+    #[serde(default)]
+    pub trusted_proxies: TrustedProxiesConfig,
+    /// 启用 HTTPS（仅 TCP 监听生效）；配置 client_ca 时开启 mTLS
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// 只读模式（灾难恢复）：照常服务但不写 data/ 目录，也可用命令行参数 `--read-only` 开启
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// 受信任的反向代理地址
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct TrustedProxiesConfig {
+    /// CIDR 列表，如 ["10.0.0.0/8", "127.0.0.1"]，`"unix"` 表示 Unix socket 对端；为空时不读取 X-Forwarded-For / X-Real-IP
+    #[serde(default)]
+    pub cidrs: Vec<String>,
+}
+
+/// TLS / mTLS 配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct TlsConfig {
+    /// 服务端证书链（PEM）
+    pub cert: String,
+    /// 服务端私钥（PEM）
+    pub key: String,
+    /// 客户端证书 CA（PEM）；配置后开启 mTLS，管理接口只接受 admin 角色证书
+    #[serde(default)]
+    pub client_ca: Option<String>,
+    /// 客户端证书要求范围：all（所有连接必须出示证书）/ admin（仅管理接口需要）
+    #[serde(default)]
+    pub client_auth: ClientAuthScope,
+    /// 证书 CN -> 用户名/角色映射
+    #[serde(default)]
+    pub client_identities: std::collections::HashMap<String, ClientIdentityConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthScope {
+    #[default]
+    All,
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientRole {
+    #[default]
+    User,
+    Admin,
+}
+
+/// 单个客户端证书身份
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ClientIdentityConfig {
+    /// 映射到的用户名；配置后该证书可免 Bearer token 访问受保护接口
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub role: ClientRole,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct AuthConfig {
     #[serde(default)]
     pub users: Vec<User>,  // 可选，默认为空数组（用户从 data/users/ 加载）
     pub jwt_secret: String,
     pub token_ttl_seconds: u64,
+    /// 登录接口处理超时（秒），与流式聊天的超时相互独立
+    #[serde(default = "default_login_timeout_seconds")]
+    pub login_timeout_seconds: u64,
+    /// 登录请求体上限（字节）
+    #[serde(default = "default_login_max_body_bytes")]
+    pub login_max_body_bytes: usize,
+    /// 各档次同时持有有效 token 的来源 IP 数上限，超出时挤出最早的会话（未配置或为 0 表示不限制）
+    #[serde(default)]
+    pub max_sessions: TierCountLimitsConfig,
+    /// 新密码哈希（Argon2id）的内存开销（KiB）；已有哈希按各自记录的参数校验
+    #[serde(default = "default_password_hash_memory_kib")]
+    pub password_hash_memory_kib: u32,
+    /// 新密码哈希（Argon2id）的迭代轮数
+    #[serde(default = "default_password_hash_time_cost")]
+    pub password_hash_time_cost: u32,
+    /// refresh token 有效期（秒），登录时随访问 token 一起签发，0 表示不签发
+    #[serde(default = "default_refresh_token_ttl_seconds")]
+    pub refresh_token_ttl_seconds: u64,
+    /// token 签名算法：HS256 使用 jwt_secret；RS256 / EdDSA 使用 jwt_private_key_path 的私钥，
+    /// 公钥通过 GET /.well-known/jwks.json 发布
+    #[serde(default)]
+    pub jwt_algorithm: JwtAlgorithm,
+    /// RS256 / EdDSA 的私钥文件（PEM）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt_private_key_path: Option<String>,
+}
+
+/// token 签名算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum JwtAlgorithm {
+    #[default]
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+impl JwtAlgorithm {
+    pub fn is_asymmetric(self) -> bool {
+        self != JwtAlgorithm::HS256
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, serde::Serialize)]
+fn default_refresh_token_ttl_seconds() -> u64 { 7 * 24 * 3600 }
+
+fn default_password_hash_memory_kib() -> u32 { 19 * 1024 }
+fn default_password_hash_time_cost() -> u32 { 2 }
+fn default_login_timeout_seconds() -> u64 { 5 }
+fn default_login_max_body_bytes() -> usize { 4096 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct User {
     pub username: String,
+    /// 明文密码：仅用于 [[auth.users]] 初始导入与旧版用户文件，加载时迁移为 password_hash 并清空
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub password: String,
+    /// 密码哈希（见 auth::password）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
     #[serde(default = "default_quota_tier")]
     pub quota_tier: String,  // "basic", "pro", "premium"
     #[serde(default = "default_is_active")]
     pub is_active: bool,
+    /// 服务账户/监控探针：跳过配额检查（仍记录用量）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unlimited: bool,
+    /// 允许的来源 IP / CIDR（如办公网出口），为空表示不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+    /// 从新 IP 登录时通知账户所有者：POST 通用 JSON 告警到该地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+    /// 从新 IP 登录时通知账户所有者的邮箱（经 [notifications.email] 邮件网关发送）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_email: Option<String>,
+    /// TOTP 两步验证密钥（base32），设置后登录需要附带验证码；经管理接口生成，不要手工填写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    /// 允许使用服务的时段，优先于 [access_schedules] 中该档次的配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_schedule: Option<AccessSchedule>,
+    /// 固定使用的聊天模型：覆盖请求中的 model 字段（沙箱档次仍改用 sandbox.model）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_model: Option<String>,
+    /// 允许调用方自选模型，此时 pinned_model 只在请求未指定 model 时作为默认值
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub model_override: bool,
+    /// 最近一次登录时间（每天最多更新一次），用于不活跃账户自动过期；
+    /// 没有登录记录的用户在第一次过期检查时以检查时间作为起点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_login_at: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -49,16 +292,73 @@ fn default_is_active() -> bool {
     true
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct DeepSeekConfig {
     pub api_key: String,
     pub base_url: String,
     pub timeout_seconds: u64,
     #[serde(default)]
     pub http_client: HttpClientConfig,
+    /// 模型列表等静态元数据的缓存时间（秒），0 表示不缓存
+    #[serde(default = "default_metadata_cache_ttl_seconds")]
+    pub metadata_cache_ttl_seconds: u64,
+    /// 转发上游前对请求体的修改，按顺序执行
+    #[serde(default)]
+    pub request_transforms: Vec<crate::deepseek::transform::RequestTransform>,
+    /// 出站限流：发往上游的全部请求合计不超过服务商规定的 RPS（与入站的 [rate_limit] 独立）
+    #[serde(default)]
+    pub outbound_rate_limit: OutboundRateLimitConfig,
+    /// 其他上游（如预发布环境）：受信任的调用方可用请求头 `X-Upstream: <名称>` 选用，用于经生产代理链路验证服务商迁移
+    #[serde(default)]
+    pub upstreams: HashMap<String, AlternateUpstreamConfig>,
+}
+
+/// 可按请求选用的其他上游
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct AlternateUpstreamConfig {
+    pub base_url: String,
+    /// 未配置时使用 deepseek.api_key
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// 未配置时使用 deepseek.timeout_seconds
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+}
+
+fn default_metadata_cache_ttl_seconds() -> u64 { 300 }
+
+/// 出站令牌桶（按上游密钥分别计数）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct OutboundRateLimitConfig {
+    /// 每秒允许发往上游的请求数，0 表示不限制
+    #[serde(default)]
+    pub requests_per_second: u32,
+    /// 突发容量
+    #[serde(default = "default_outbound_burst")]
+    pub burst: u32,
+    /// 令牌不足时最多排队等待的毫秒数，超过则拒绝（503）
+    #[serde(default = "default_outbound_max_wait_ms")]
+    pub max_wait_ms: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Default for OutboundRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 0,
+            burst: default_outbound_burst(),
+            max_wait_ms: default_outbound_max_wait_ms(),
+        }
+    }
+}
+
+fn default_outbound_burst() -> u32 { 1 }
+fn default_outbound_max_wait_ms() -> u64 { 2000 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct HttpClientConfig {
     #[serde(default = "default_pool_max_idle_per_host")]
     pub pool_max_idle_per_host: usize,
@@ -70,6 +370,9 @@ pub struct HttpClientConfig {
     pub tcp_nodelay: bool,
     #[serde(default = "default_http2_adaptive_window")]
     pub http2_adaptive_window: bool,
+    /// 启动时及连接池空闲过期前预热上游连接（DNS + TLS）
+    #[serde(default = "default_warmup")]
+    pub warmup: bool,
 }
 
 impl Default for HttpClientConfig {
@@ -80,6 +383,7 @@ impl Default for HttpClientConfig {
             connect_timeout_seconds: 10,
             tcp_nodelay: true,
             http2_adaptive_window: true,
+            warmup: true,
         }
     }
 }
@@ -89,20 +393,105 @@ fn default_pool_idle_timeout_seconds() -> u64 { 90 }
 fn default_connect_timeout_seconds() -> u64 { 10 }
 fn default_tcp_nodelay() -> bool { true }
 fn default_http2_adaptive_window() -> bool { true }
+fn default_warmup() -> bool { true }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct RateLimitConfig {
     pub requests_per_second: usize,
+    /// 突发容量 = requests_per_second × burst_multiplier
+    #[serde(default = "default_burst_multiplier")]
+    pub burst_multiplier: f64,
+    /// 启动时桶中的令牌数，未配置时为满桶
+    #[serde(default)]
+    pub initial_tokens: Option<usize>,
+    /// 启动后的冷启动保护时长（秒），期间按 cold_start_factor 降低速率与突发容量，0 表示不启用
+    #[serde(default)]
+    pub cold_start_seconds: u64,
+    /// 冷启动期间的限额比例（0~1）
+    #[serde(default = "default_cold_start_factor")]
+    pub cold_start_factor: f64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn default_burst_multiplier() -> f64 { 2.0 }
+fn default_cold_start_factor() -> f64 { 0.5 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct SecurityConfig {
     #[serde(default = "default_login_fail_window_seconds")]
     pub login_fail_window_seconds: u64,
     #[serde(default = "default_login_fail_threshold")]
     pub login_fail_threshold: usize,
+    /// 逐级递增的登录阻断时长（秒），超过级数后沿用最后一级
+    #[serde(default = "default_login_lockout_steps_seconds")]
+    pub login_lockout_steps_seconds: Vec<u64>,
     #[serde(default)]
     pub webhook_url: Option<String>,
+    /// webhook 负载格式：generic / slack / dingtalk / feishu
+    #[serde(default)]
+    pub webhook_format: WebhookFormat,
+    /// 自定义 JSON 负载模板（优先于 webhook_format），支持 {{username}} 等占位符
+    #[serde(default)]
+    pub webhook_template: Option<String>,
+    /// 从文件读取负载模板（未配置 webhook_template 时生效）
+    #[serde(default)]
+    pub webhook_template_file: Option<String>,
+    /// 全局 IP 允许 / 拒绝名单
+    #[serde(default)]
+    pub ip_rules: IpRulesConfig,
+    /// 新密码的强度要求
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+}
+
+/// 密码策略：创建用户与修改密码时校验，已有密码不受影响
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct PasswordPolicyConfig {
+    /// 最短长度（按字符计）
+    #[serde(default = "default_password_min_length")]
+    pub min_length: usize,
+    #[serde(default)]
+    pub require_lowercase: bool,
+    #[serde(default)]
+    pub require_uppercase: bool,
+    #[serde(default)]
+    pub require_digit: bool,
+    /// 要求包含字母与数字以外的字符
+    #[serde(default)]
+    pub require_symbol: bool,
+    /// 拒绝内置的常见弱密码
+    #[serde(default = "default_deny_common")]
+    pub deny_common: bool,
+    /// 额外拒绝的密码（不区分大小写）
+    #[serde(default)]
+    pub denylist: Vec<String>,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: default_password_min_length(),
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            deny_common: true,
+            denylist: Vec::new(),
+        }
+    }
+}
+
+fn default_password_min_length() -> usize { 8 }
+fn default_deny_common() -> bool { true }
+
+/// 全局 IP 规则：拒绝名单优先；允许名单非空时只放行名单内的地址（本机地址始终放行）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct IpRulesConfig {
+    /// 允许的 IP / CIDR，空表示不限制
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 拒绝的 IP / CIDR
+    #[serde(default)]
+    pub deny: Vec<String>,
 }
 
 impl Default for SecurityConfig {
@@ -110,81 +499,1364 @@ impl Default for SecurityConfig {
         Self {
             login_fail_window_seconds: 60,
             login_fail_threshold: 5,
+            login_lockout_steps_seconds: default_login_lockout_steps_seconds(),
             webhook_url: None,
+            webhook_format: WebhookFormat::default(),
+            webhook_template: None,
+            webhook_template_file: None,
+            ip_rules: IpRulesConfig::default(),
+            password_policy: PasswordPolicyConfig::default(),
+        }
+    }
+}
+
+/// 告警通知配置（钉钉 / 飞书机器人）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct NotificationsConfig {
+    /// 同一告警（如同一用户配额耗尽）的最短重复间隔（秒）
+    #[serde(default = "default_notification_cooldown_seconds")]
+    pub cooldown_seconds: u64,
+    /// 上游连续失败多少次后告警（0 表示不告警）
+    #[serde(default = "default_upstream_failure_threshold")]
+    pub upstream_failure_threshold: u32,
+    #[serde(default)]
+    pub dingtalk: Option<ChatBotConfig>,
+    #[serde(default)]
+    pub feishu: Option<ChatBotConfig>,
+    /// HTTP 邮件网关，用于发送用户 notify_email 通知（未配置时忽略 notify_email）
+    #[serde(default)]
+    pub email: Option<EmailRelayConfig>,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            cooldown_seconds: default_notification_cooldown_seconds(),
+            upstream_failure_threshold: default_upstream_failure_threshold(),
+            dingtalk: None,
+            feishu: None,
+            email: None,
+        }
+    }
+}
+
+/// HTTP 邮件网关：POST JSON `{"from", "to", "subject", "text"}`
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct EmailRelayConfig {
+    pub url: String,
+    #[serde(default)]
+    pub from: Option<String>,
+    /// 作为 `Authorization: Bearer <token>` 发送
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+}
+
+/// 群机器人配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ChatBotConfig {
+    pub webhook_url: String,
+    /// 加签密钥（机器人安全设置选择“加签”时填写）
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// 自定义关键词（机器人安全设置选择“关键词”时填写），会加在消息标题前
+    #[serde(default)]
+    pub keyword: Option<String>,
+}
+
+fn default_notification_cooldown_seconds() -> u64 { 600 }
+fn default_upstream_failure_threshold() -> u32 { 5 }
+
+/// 告警 webhook 的内置负载格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// 原始事件 JSON
+    #[default]
+    Generic,
+    Slack,
+    Dingtalk,
+    /// 飞书消息卡片
+    Feishu,
+}
+
+/// 管理接口访问配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct AdminConfig {
+    /// 配置后允许非 localhost 的请求通过 HMAC-SHA256 签名访问管理接口
+    #[serde(default)]
+    pub signing_secret: Option<String>,
+    /// 签名时间戳允许的最大偏差（秒），超出视为过期/重放
+    #[serde(default = "default_signature_max_skew_seconds")]
+    pub signature_max_skew_seconds: u64,
+    /// 代管 token（POST /admin/impersonate/:username）的有效期（秒）
+    #[serde(default = "default_impersonation_ttl_seconds")]
+    pub impersonation_ttl_seconds: u64,
+    /// 导出文件签名下载链接（GET /downloads/:token）的有效期（秒）
+    #[serde(default = "default_download_ttl_seconds")]
+    pub download_ttl_seconds: u64,
+    /// 下载链接的地址前缀（如 https://proxy.example.com），未配置时返回相对路径
+    #[serde(default)]
+    pub download_base_url: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            signing_secret: None,
+            signature_max_skew_seconds: default_signature_max_skew_seconds(),
+            impersonation_ttl_seconds: default_impersonation_ttl_seconds(),
+            download_ttl_seconds: default_download_ttl_seconds(),
+            download_base_url: None,
         }
     }
 }
 
+fn default_signature_max_skew_seconds() -> u64 { 300 }
+fn default_impersonation_ttl_seconds() -> u64 { 300 }
+fn default_download_ttl_seconds() -> u64 { 3600 }
+
 fn default_login_fail_window_seconds() -> u64 { 60 }
 fn default_login_fail_threshold() -> usize { 5 }
+fn default_login_lockout_steps_seconds() -> Vec<u64> { vec![60, 300, 1800, 86400] }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct QuotaConfig {
-    #[serde(default = "default_save_interval")]
-    pub save_interval: u32,  // 每N次请求写一次磁盘
-    #[serde(default = "default_monthly_reset_day")]
-    pub monthly_reset_day: u32,  // 每月几号重置
+/// 采样参数越界时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplingMode {
+    /// 夹到允许范围内
+    #[default]
+    Clamp,
+    /// 直接拒绝（400）
+    Reject,
+}
+
+/// 采样参数允许范围 [min, max]，未配置表示不限制
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct SamplingRanges {
     #[serde(default)]
-    pub tiers: QuotaTiersConfig,  // 配额档次限制
+    pub temperature: Option<[f32; 2]>,
+    #[serde(default)]
+    pub top_p: Option<[f32; 2]>,
+    #[serde(default)]
+    pub frequency_penalty: Option<[f32; 2]>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct QuotaTiersConfig {
-    #[serde(default = "default_basic_quota")]
-    pub basic: u32,
-    #[serde(default = "default_pro_quota")]
-    pub pro: u32,
-    #[serde(default = "default_premium_quota")]
-    pub premium: u32,
+/// 采样参数限制配置（默认范围 + 按档次覆盖）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SamplingConfig {
+    #[serde(default)]
+    pub mode: SamplingMode,
+    #[serde(default = "default_sampling_ranges")]
+    pub default: SamplingRanges,
+    #[serde(default)]
+    pub tiers: std::collections::HashMap<String, SamplingRanges>,
 }
 
-impl Default for QuotaConfig {
+impl Default for SamplingConfig {
     fn default() -> Self {
         Self {
-            save_interval: 100,
-            monthly_reset_day: 1,
-            tiers: QuotaTiersConfig::default(),
+            mode: SamplingMode::default(),
+            default: default_sampling_ranges(),
+            tiers: std::collections::HashMap::new(),
         }
     }
 }
 
-impl Default for QuotaTiersConfig {
+impl SamplingConfig {
+    /// 档次专属范围优先，未配置的参数回退到默认范围
+    pub fn ranges_for(&self, tier: Option<&str>) -> SamplingRanges {
+        let tier_ranges = tier.and_then(|t| self.tiers.get(&t.to_lowercase()));
+        SamplingRanges {
+            temperature: tier_ranges.and_then(|r| r.temperature).or(self.default.temperature),
+            top_p: tier_ranges.and_then(|r| r.top_p).or(self.default.top_p),
+            frequency_penalty: tier_ranges.and_then(|r| r.frequency_penalty).or(self.default.frequency_penalty),
+        }
+    }
+}
+
+/// 默认范围与上游 API 文档一致
+fn default_sampling_ranges() -> SamplingRanges {
+    SamplingRanges {
+        temperature: Some([0.0, 2.0]),
+        top_p: Some([0.0, 1.0]),
+        frequency_penalty: Some([-2.0, 2.0]),
+    }
+}
+
+/// 多轮对话历史压缩配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 估算输入 token 超过该值时触发压缩
+    #[serde(default = "default_compression_token_budget")]
+    pub token_budget: u32,
+    /// 保留不压缩的最近消息数
+    #[serde(default = "default_compression_keep_recent")]
+    pub keep_recent_messages: usize,
+    /// 生成摘要使用的模型
+    #[serde(default = "default_probe_model")]
+    pub model: String,
+    #[serde(default = "default_compression_max_summary_tokens")]
+    pub max_summary_tokens: u32,
+    /// 每次压缩额外计入用户配额的次数
+    #[serde(default = "default_compression_quota_weight")]
+    pub quota_weight: u32,
+}
+
+impl Default for CompressionConfig {
     fn default() -> Self {
         Self {
-            basic: 500,
-            pro: 1000,
-            premium: 1500,
+            enabled: false,
+            token_budget: default_compression_token_budget(),
+            keep_recent_messages: default_compression_keep_recent(),
+            model: default_probe_model(),
+            max_summary_tokens: default_compression_max_summary_tokens(),
+            quota_weight: default_compression_quota_weight(),
         }
     }
 }
 
-fn default_save_interval() -> u32 { 100 }
-fn default_monthly_reset_day() -> u32 { 1 }
-fn default_basic_quota() -> u32 { 500 }
-fn default_pro_quota() -> u32 { 1000 }
-fn default_premium_quota() -> u32 { 1500 }
+fn default_compression_token_budget() -> u32 { 4000 }
+fn default_compression_keep_recent() -> usize { 6 }
+fn default_compression_max_summary_tokens() -> u32 { 512 }
+fn default_compression_quota_weight() -> u32 { 1 }
 
-impl Config {
-    pub fn load() -> anyhow::Result<Self> {
-        // 加载 .env 文件 (如果存在)
-        let _ = dotenvy::dotenv();
+/// 合成监控探测配置（GET /probe/chat）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ProbeConfig {
+    /// 探针用户（建议 unlimited = true）；未配置时只探测上游
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default = "default_probe_model")]
+    pub model: String,
+    #[serde(default = "default_probe_prompt")]
+    pub prompt: String,
+}
 
-        // 加载 config.toml
-        let mut config: Config = config::Config::builder()
-            .add_source(config::File::with_name("config"))
-            .build()?
-            .try_deserialize()?;
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            username: None,
+            model: default_probe_model(),
+            prompt: default_probe_prompt(),
+        }
+    }
+}
 
-        // 从环境变量读取 OpenAI API Key (优先级高于配置文件)
-        if let Ok(api_key) = env::var("OPENAI_API_KEY") {
-            config.deepseek.api_key = api_key;
+fn default_probe_model() -> String { "deepseek-chat".to_string() }
+fn default_probe_prompt() -> String { "ping".to_string() }
+
+/// S3 兼容对象存储配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ObjectStorageConfig {
+    /// 服务地址，如 `https://s3.amazonaws.com`、`http://127.0.0.1:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_object_storage_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// 对象 key 前缀
+    #[serde(default)]
+    pub prefix: String,
+    /// true 时使用 `endpoint/bucket/key`（MinIO 等），否则使用 `bucket.endpoint/key`
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    /// 上传成功后删除本地文件，节省磁盘
+    #[serde(default)]
+    pub delete_local_after_upload: bool,
+    #[serde(default = "default_object_storage_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn default_object_storage_region() -> String { "us-east-1".to_string() }
+fn default_path_style() -> bool { true }
+fn default_object_storage_timeout() -> u64 { 120 }
+
+/// data/ 目录定时备份
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct BackupConfig {
+    /// 是否启用定时备份（POST /admin/backup 手动备份不受影响）
+    #[serde(default)]
+    pub enabled: bool,
+    /// 备份存放目录
+    #[serde(default = "default_backup_dir")]
+    pub dir: String,
+    #[serde(default = "default_backup_interval_hours")]
+    pub interval_hours: u64,
+    /// 保留最近的备份数量
+    #[serde(default = "default_backup_retention")]
+    pub retention: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: default_backup_dir(),
+            interval_hours: default_backup_interval_hours(),
+            retention: default_backup_retention(),
         }
+    }
+}
 
-        // 验证必需配置
-        if config.deepseek.api_key.is_empty() {
-            anyhow::bail!("OPENAI_API_KEY 未设置! 请在环境变量或 .env 文件中配置");
+fn default_backup_dir() -> String { "backups".to_string() }
+fn default_backup_interval_hours() -> u64 { 24 }
+fn default_backup_retention() -> usize { 7 }
+
+/// 不活跃账户自动过期：超过 inactive_days 天没有登录的用户被停用，数据文件移入 data/archive/
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct UserExpiryConfig {
+    /// 不活跃天数阈值，0 表示不启用
+    #[serde(default)]
+    pub inactive_days: u32,
+    #[serde(default = "default_expiry_check_interval_hours")]
+    pub check_interval_hours: u64,
+    /// 不参与过期的用户（unlimited 服务账户总是不参与）
+    #[serde(default)]
+    pub exempt_users: Vec<String>,
+}
+
+impl Default for UserExpiryConfig {
+    fn default() -> Self {
+        Self {
+            inactive_days: 0,
+            check_interval_hours: default_expiry_check_interval_hours(),
+            exempt_users: Vec::new(),
         }
+    }
+}
 
-        Ok(config)
+fn default_expiry_check_interval_hours() -> u64 { 1 }
+
+/// 可用性报告（GET /admin/slo）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SloConfig {
+    /// 目标成功率，用于计算剩余错误预算
+    #[serde(default = "default_slo_target")]
+    pub target: f64,
+    /// 采样间隔（秒）；关闭服务时最多丢失一个间隔的数据
+    #[serde(default = "default_slo_sample_interval")]
+    pub sample_interval_seconds: u64,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            target: default_slo_target(),
+            sample_interval_seconds: default_slo_sample_interval(),
+        }
+    }
+}
+
+fn default_slo_target() -> f64 { 0.99 }
+fn default_slo_sample_interval() -> u64 { 60 }
+
+/// 部署品牌信息，写入错误响应；未配置的字段不输出
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct BrandingConfig {
+    /// 服务名称
+    #[serde(default)]
+    pub service_name: Option<String>,
+    /// 配额耗尽（402）时引导用户升级套餐的链接
+    #[serde(default)]
+    pub upgrade_url: Option<String>,
+    /// 客服联系方式（邮箱或链接）
+    #[serde(default)]
+    pub support_contact: Option<String>,
+}
+
+/// 开发者沙箱档次（演示账户）：配额按天重置且不使用预付费额度，强制使用指定模型，响应总是带水印
+///
+/// 沙箱档次需同时在 [quota.tiers] 中定义，其值即每日请求次数
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub tiers: Vec<String>,
+    /// 沙箱请求一律改用的模型
+    #[serde(default = "default_sandbox_model")]
+    pub model: String,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            tiers: Vec::new(),
+            model: default_sandbox_model(),
+        }
+    }
+}
+
+impl SandboxConfig {
+    pub fn is_sandbox(&self, tier: &str) -> bool {
+        self.tiers.iter().any(|t| t.eq_ignore_ascii_case(tier))
+    }
+}
+
+fn default_sandbox_model() -> String { "deepseek-chat".to_string() }
+
+/// 请求体中 metadata 对象的限制（调用方用它关联自己的任务 ID）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct RequestMetadataConfig {
+    /// metadata 序列化后的最大字节数，超过时返回 400
+    #[serde(default = "default_metadata_max_bytes")]
+    pub max_bytes: usize,
+}
+
+impl Default for RequestMetadataConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: default_metadata_max_bytes(),
+        }
+    }
+}
+
+fn default_metadata_max_bytes() -> usize { 1024 }
+
+/// Slack / Discord 斜杠命令：值班人员在聊天中做只读查询（用户状态、配额、今日统计）
+///
+/// 配置了对应平台的密钥才会注册 /chatops/slack、/chatops/discord 路由
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct ChatOpsConfig {
+    /// Slack App 的 Signing Secret
+    #[serde(default)]
+    pub slack_signing_secret: Option<String>,
+    /// Discord 应用的 Public Key（十六进制）
+    #[serde(default)]
+    pub discord_public_key: Option<String>,
+    /// 允许执行命令的聊天平台用户 ID，为空时不限制
+    #[serde(default)]
+    pub allowed_user_ids: Vec<String>,
+}
+
+/// 上游用量对账：每晚比对代理估算的前一天 token 费用与上游账户余额的减少量
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ReconciliationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 每天执行时间（本地时间 HH:MM），应在零点后不久，使余额变化与前一天的用量对应
+    #[serde(default = "default_reconcile_run_at")]
+    pub run_at: String,
+    /// 余额接口地址；默认由 deepseek.base_url 推导（去掉 /v1 后加 /user/balance）
+    #[serde(default)]
+    pub balance_url: Option<String>,
+    /// 对账使用的币种
+    #[serde(default = "default_reconcile_currency")]
+    pub currency: String,
+    /// 每百万 token 单价（默认为 deepseek-chat 标准价，上游调价时需同步修改）
+    #[serde(default = "default_price_cache_hit")]
+    pub input_cache_hit_price: f64,
+    #[serde(default = "default_price_cache_miss")]
+    pub input_cache_miss_price: f64,
+    #[serde(default = "default_price_output")]
+    pub output_price: f64,
+    /// 偏差比例超过该值时记录警告日志
+    #[serde(default = "default_drift_threshold")]
+    pub drift_threshold: f64,
+}
+
+impl Default for ReconciliationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            run_at: default_reconcile_run_at(),
+            balance_url: None,
+            currency: default_reconcile_currency(),
+            input_cache_hit_price: default_price_cache_hit(),
+            input_cache_miss_price: default_price_cache_miss(),
+            output_price: default_price_output(),
+            drift_threshold: default_drift_threshold(),
+        }
+    }
+}
+
+fn default_reconcile_run_at() -> String { "00:10".to_string() }
+fn default_reconcile_currency() -> String { "CNY".to_string() }
+fn default_price_cache_hit() -> f64 { 0.5 }
+fn default_price_cache_miss() -> f64 { 2.0 }
+fn default_price_output() -> f64 { 8.0 }
+fn default_drift_threshold() -> f64 { 0.1 }
+
+/// 上游故障注入（仅用于测试环境，覆盖超时、错误流等处理路径）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ChaosConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 延迟上游请求的概率（0~1）
+    #[serde(default)]
+    pub delay_probability: f64,
+    /// 注入的延迟（毫秒）
+    #[serde(default = "default_chaos_delay_ms")]
+    pub delay_ms: u64,
+    /// 丢弃每个流式数据块的概率（0~1）
+    #[serde(default)]
+    pub drop_chunk_probability: f64,
+    /// 不请求上游、直接返回错误的概率（0~1）
+    #[serde(default)]
+    pub error_probability: f64,
+    /// 模拟的上游错误状态码（5xx 或 429）
+    #[serde(default = "default_chaos_error_status")]
+    pub error_status: u16,
+    /// 随机数种子，固定后注入序列可复现
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_probability: 0.0,
+            delay_ms: default_chaos_delay_ms(),
+            drop_chunk_probability: 0.0,
+            error_probability: 0.0,
+            error_status: default_chaos_error_status(),
+            seed: None,
+        }
+    }
+}
+
+fn default_chaos_delay_ms() -> u64 { 2000 }
+fn default_chaos_error_status() -> u16 { 503 }
+
+/// 磁盘剩余空间检查（data/ 与 logs/ 所在文件系统）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct DiskHealthConfig {
+    /// 剩余空间低于该值（MB）时告警，0 表示只导出指标不告警
+    #[serde(default = "default_disk_min_free_mb")]
+    pub min_free_mb: u64,
+    #[serde(default = "default_disk_check_interval")]
+    pub check_interval_seconds: u64,
+}
+
+impl Default for DiskHealthConfig {
+    fn default() -> Self {
+        Self {
+            min_free_mb: default_disk_min_free_mb(),
+            check_interval_seconds: default_disk_check_interval(),
+        }
+    }
+}
+
+fn default_disk_min_free_mb() -> u64 { 500 }
+fn default_disk_check_interval() -> u64 { 60 }
+
+/// 重复提问检测：同一用户在窗口内提交相同内容超过 max_duplicates 次时返回 429
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SpamConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_spam_max_duplicates")]
+    pub max_duplicates: usize,
+    #[serde(default = "default_spam_window_seconds")]
+    pub window_seconds: u64,
+}
+
+impl Default for SpamConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_duplicates: default_spam_max_duplicates(),
+            window_seconds: default_spam_window_seconds(),
+        }
+    }
+}
+
+fn default_spam_max_duplicates() -> usize { 5 }
+fn default_spam_window_seconds() -> u64 { 60 }
+
+/// 重复 system 提示检测（上游前缀缓存的对齐建议）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct PromptCacheConfig {
+    #[serde(default = "default_prompt_cache_enabled")]
+    pub enabled: bool,
+    /// system 提示估算 tokens 达到该值才参与检测
+    #[serde(default = "default_prompt_cache_min_system_tokens")]
+    pub min_system_tokens: u32,
+    /// 与上一次请求相隔超过该秒数时不再视为连续重复
+    #[serde(default = "default_prompt_cache_window_seconds")]
+    pub window_seconds: u64,
+}
+
+impl Default for PromptCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_prompt_cache_enabled(),
+            min_system_tokens: default_prompt_cache_min_system_tokens(),
+            window_seconds: default_prompt_cache_window_seconds(),
+        }
+    }
+}
+
+fn default_prompt_cache_enabled() -> bool { true }
+fn default_prompt_cache_min_system_tokens() -> u32 { 256 }
+fn default_prompt_cache_window_seconds() -> u64 { 3600 }
+
+/// 流式响应变换管道
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct StreamingConfig {
+    /// 变换名称，按从上游到客户端的顺序依次应用（必须包含 counting）
+    #[serde(default = "default_stream_transforms")]
+    pub transforms: Vec<String>,
+    /// watermark 变换的配置（需同时把 "watermark" 加入 transforms）
+    #[serde(default)]
+    pub watermark: WatermarkConfig,
+    /// coalesce 变换的配置（需同时把 "coalesce" 加入 transforms）
+    #[serde(default)]
+    pub coalesce: CoalesceConfig,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            transforms: default_stream_transforms(),
+            watermark: WatermarkConfig::default(),
+            coalesce: CoalesceConfig::default(),
+        }
+    }
+}
+
+fn default_stream_transforms() -> Vec<String> { vec!["counting".to_string()] }
+
+/// 小数据块合并：把上游的细碎 SSE 块攒成批次再下发，减少写调用与 TLS 记录开销
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct CoalesceConfig {
+    /// 缓冲的最长时间（毫秒），即合并带来的最大额外延迟
+    #[serde(default = "default_coalesce_window_ms")]
+    pub window_ms: u64,
+    /// 缓冲达到该字节数时立即下发；不小于该值的数据块直接透传
+    #[serde(default = "default_coalesce_max_bytes")]
+    pub max_bytes: usize,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: default_coalesce_window_ms(),
+            max_bytes: default_coalesce_max_bytes(),
+        }
+    }
+}
+
+fn default_coalesce_window_ms() -> u64 { 30 }
+fn default_coalesce_max_bytes() -> usize { 4096 }
+
+/// 响应水印：在完成的响应末尾（[DONE] 之前）追加来源标记
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct WatermarkConfig {
+    /// 需要加水印的档次
+    #[serde(default = "default_watermark_tiers")]
+    pub tiers: Vec<String>,
+    #[serde(default)]
+    pub mode: WatermarkMode,
+    /// 标记文本
+    #[serde(default = "default_watermark_text")]
+    pub text: String,
+    /// 签名密钥；配置后水印带 HMAC 签名，可用于核验来源与用户
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            tiers: default_watermark_tiers(),
+            mode: WatermarkMode::default(),
+            text: default_watermark_text(),
+            secret: None,
+        }
+    }
+}
+
+fn default_watermark_tiers() -> Vec<String> { vec!["basic".to_string()] }
+fn default_watermark_text() -> String { "AI-generated content".to_string() }
+
+/// 水印形式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkMode {
+    /// 独立的 `event: watermark` SSE 事件（OpenAI SDK 会忽略，不影响正文）
+    #[default]
+    Event,
+    /// 作为最后一个 delta 追加到正文
+    Content,
+}
+
+/// 启动时数据目录完整性检查
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct DataIntegrityConfig {
+    /// true 时把问题文件移动到 data/quarantine/，否则只报告
+    #[serde(default)]
+    pub quarantine: bool,
+}
+
+/// 用户行为日志配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ActivityLogConfig {
+    /// 缓冲通道容量；写满时新记录直接丢弃并计数，不阻塞请求
+    #[serde(default = "default_activity_channel_capacity")]
+    pub channel_capacity: usize,
+    /// 有丢弃时汇总告警的间隔（秒）
+    #[serde(default = "default_activity_drop_warn_interval")]
+    pub drop_warn_interval_seconds: u64,
+}
+
+impl Default for ActivityLogConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: default_activity_channel_capacity(),
+            drop_warn_interval_seconds: default_activity_drop_warn_interval(),
+        }
+    }
+}
+
+fn default_activity_channel_capacity() -> usize { 10_000 }
+fn default_activity_drop_warn_interval() -> u64 { 60 }
+
+/// 可观测性配置（Prometheus 之外的指标/错误上报）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct ObservabilityConfig {
+    #[serde(default)]
+    pub statsd: StatsdConfig,
+    /// Sentry DSN：`https://<key>@<host>/<project_id>`，500 错误与 panic 会上报到该项目
+    #[serde(default)]
+    pub sentry_dsn: Option<String>,
+    /// 通用错误 webhook：500 错误与 panic 以 JSON POST 到该地址
+    #[serde(default)]
+    pub error_webhook_url: Option<String>,
+    /// 上报事件附带的环境名（如 production / staging）
+    #[serde(default)]
+    pub environment: Option<String>,
+    #[serde(default)]
+    pub tail_sampling: TailSamplingConfig,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+}
+
+/// 请求日志（TraceLayer）过滤
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct AccessLogConfig {
+    /// 不记录请求日志的路径（精确匹配，以 `*` 结尾时按前缀匹配），默认排除探针与指标抓取
+    #[serde(default = "default_access_log_exclude_paths")]
+    pub exclude_paths: Vec<String>,
+    /// 在请求 span 中记录请求头（debug 级别）
+    #[serde(default)]
+    pub log_headers: bool,
+    /// 记录请求头时跳过的头（不区分大小写）
+    #[serde(default = "default_access_log_exclude_headers")]
+    pub exclude_headers: Vec<String>,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            exclude_paths: default_access_log_exclude_paths(),
+            log_headers: false,
+            exclude_headers: default_access_log_exclude_headers(),
+        }
+    }
+}
+
+fn default_access_log_exclude_paths() -> Vec<String> {
+    vec!["/readyz".to_string(), "/metrics".to_string()]
+}
+fn default_access_log_exclude_headers() -> Vec<String> {
+    ["authorization", "proxy-authorization", "cookie", "x-admin-signature"].map(String::from).to_vec()
+}
+
+/// debug 日志尾部采样：只为出错或慢请求保留 debug 日志（仅影响日志文件）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct TailSamplingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 请求耗时达到该值（毫秒）时保留其 debug 日志；流式请求按整个流的时长计算
+    #[serde(default = "default_tail_latency_threshold_ms")]
+    pub latency_threshold_ms: u64,
+    /// 每个请求最多缓冲的 debug 事件数，超出部分丢弃并计数
+    #[serde(default = "default_tail_max_events")]
+    pub max_events_per_request: usize,
+}
+
+impl Default for TailSamplingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latency_threshold_ms: default_tail_latency_threshold_ms(),
+            max_events_per_request: default_tail_max_events(),
+        }
+    }
+}
+
+fn default_tail_latency_threshold_ms() -> u64 { 20_000 }
+fn default_tail_max_events() -> usize { 500 }
+
+/// StatsD / DogStatsD 指标推送配置
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct StatsdConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// agent 的 UDP 地址
+    #[serde(default = "default_statsd_addr")]
+    pub addr: String,
+    /// 指标名前缀
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_statsd_interval")]
+    pub interval_seconds: u64,
+    /// true 时 label 以 DogStatsD 标签（`|#k:v`）发送，否则拼接到指标名
+    #[serde(default)]
+    pub dogstatsd: bool,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: default_statsd_addr(),
+            prefix: default_statsd_prefix(),
+            interval_seconds: default_statsd_interval(),
+            dogstatsd: false,
+        }
+    }
+}
+
+fn default_statsd_addr() -> String { "127.0.0.1:8125".to_string() }
+fn default_statsd_prefix() -> String { "deepseek_proxy".to_string() }
+fn default_statsd_interval() -> u64 { 10 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct QuotaConfig {
+    #[serde(default = "default_save_interval")]
+    pub save_interval: u32,  // 每N次请求写一次磁盘
+    #[serde(default = "default_monthly_reset_day")]
+    pub monthly_reset_day: u32,  // 每月几号重置
+    #[serde(default)]
+    pub tiers: QuotaTiersConfig,  // 配额档次限制
+    #[serde(default)]
+    pub max_response_bytes: TierByteLimitsConfig,  // 各档次单次响应字节上限
+    #[serde(default)]
+    pub max_n: TierCountLimitsConfig,  // 各档次单次请求的候选回复数 n 上限
+    #[serde(default)]
+    pub reset_policies: ResetPoliciesConfig,  // 各档次配额重置策略
+    /// 用户的档次已从 [quota.tiers] 中删除或改名时改用的档次；未配置时这些用户的请求返回 403
+    #[serde(default)]
+    pub fallback_tier: Option<String>,
+    /// 定时发放奖励额度（[[quota.topups]]），按 cron 表达式执行
+    #[serde(default)]
+    pub topups: Vec<TopUpSchedule>,
+    /// 进程在流式响应完成前退出（崩溃、被强制终止）时，对已扣费请求的处理，下次启动时执行
+    #[serde(default)]
+    pub crash_recovery: ChargeRecovery,
+}
+
+/// 未完成响应的扣费处理策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChargeRecovery {
+    /// 退还扣减的周期配额与预付费额度
+    #[default]
+    Refund,
+    /// 保留扣费，只记录日志
+    Confirm,
+}
+
+/// 定时发放额度：到点给指定用户或档次内的所有启用用户发放预付费额度（如周末加油包）
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct TopUpSchedule {
+    /// 计划名称，唯一，用于记录执行情况
+    pub name: String,
+    /// 五段式 cron 表达式（分 时 日 月 周），按北京时间计算
+    pub schedule: String,
+    /// 每次发放的次数
+    pub amount: u32,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub tiers: Vec<String>,
+}
+
+/// 各档次配额重置策略：monthly / rolling_30d / weekly / daily / never（档次名 -> 策略）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct ResetPoliciesConfig(pub HashMap<String, ResetPolicy>);
+
+impl ResetPoliciesConfig {
+    /// 按档次名称查询重置策略（未配置的档次按月重置）
+    pub fn for_tier(&self, tier: &str) -> ResetPolicy {
+        self.0.get(&tier.to_lowercase()).copied().unwrap_or_default()
+    }
+}
+
+/// 配额档次定义（档次名 -> 每周期请求次数），档次完全由配置决定
+///
+/// 配置 `[quota.tiers]` 后只有其中列出的档次有效；未配置时为 basic / pro / premium
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct QuotaTiersConfig(pub HashMap<String, u32>);
+
+impl QuotaTiersConfig {
+    /// 档次的配额上限；档次未定义时返回 None
+    pub fn limit(&self, tier: &str) -> Option<u32> {
+        self.0.get(&tier.to_lowercase()).copied()
+    }
+
+    pub fn contains(&self, tier: &str) -> bool {
+        self.limit(tier).is_some()
+    }
+
+    /// 已定义的档次名（排序后）
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+/// 各档次的访问时段（档次名 -> 时段；未配置的档次不限制）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct AccessSchedulesConfig(pub HashMap<String, AccessSchedule>);
+
+impl AccessSchedulesConfig {
+    /// 按档次名称查询访问时段
+    pub fn for_tier(&self, tier: &str) -> Option<&AccessSchedule> {
+        self.0.get(&tier.to_lowercase())
+    }
+}
+
+/// 各档次单次响应的字节上限（档次名 -> 字节数；未配置或为 0 表示不限制）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct TierByteLimitsConfig(pub HashMap<String, u64>);
+
+impl TierByteLimitsConfig {
+    /// 按档次名称查询上限
+    pub fn for_tier(&self, tier: &str) -> Option<u64> {
+        self.0.get(&tier.to_lowercase()).copied().filter(|v| *v > 0)
+    }
+}
+
+/// 各档次的数量上限，如候选回复数 n、并发会话数（档次名 -> 数量；未配置或为 0 表示不限制）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct TierCountLimitsConfig(pub HashMap<String, u32>);
+
+impl TierCountLimitsConfig {
+    /// 按档次名称查询上限
+    pub fn for_tier(&self, tier: &str) -> Option<u32> {
+        self.0.get(&tier.to_lowercase()).copied().filter(|v| *v > 0)
+    }
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            save_interval: 100,
+            monthly_reset_day: 1,
+            tiers: QuotaTiersConfig::default(),
+            max_response_bytes: TierByteLimitsConfig::default(),
+            max_n: TierCountLimitsConfig::default(),
+            reset_policies: ResetPoliciesConfig::default(),
+            fallback_tier: None,
+            topups: Vec::new(),
+            crash_recovery: ChargeRecovery::default(),
+        }
+    }
+}
+
+impl Default for QuotaTiersConfig {
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("basic".to_string(), default_basic_quota()),
+            ("pro".to_string(), default_pro_quota()),
+            ("premium".to_string(), default_premium_quota()),
+        ]))
+    }
+}
+
+impl QuotaConfig {
+    /// 档次表不能为空，fallback_tier 必须是已定义的档次；按档次配置的表引用未定义档次时只警告
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tiers.0.is_empty() {
+            return Err("[quota.tiers] 至少需要定义一个档次".to_string());
+        }
+        if let Some(tier) = &self.fallback_tier {
+            if !self.tiers.contains(tier) {
+                return Err(format!("fallback_tier = \"{}\" 未在 [quota.tiers] 中定义", tier));
+            }
+        }
+        let mut names = std::collections::HashSet::new();
+        for topup in &self.topups {
+            if !names.insert(topup.name.as_str()) {
+                return Err(format!("[[quota.topups]] 名称 {} 重复", topup.name));
+            }
+            crate::quota::CronSchedule::parse(&topup.schedule)
+                .map_err(|e| format!("[[quota.topups]] {}: {}", topup.name, e))?;
+            if topup.amount == 0 || (topup.users.is_empty() && topup.tiers.is_empty()) {
+                return Err(format!("[[quota.topups]] {} 需要 amount > 0 且至少指定 users 或 tiers", topup.name));
+            }
+            if let Some(tier) = topup.tiers.iter().find(|t| !self.tiers.contains(t)) {
+                return Err(format!("[[quota.topups]] {} 的档次 {} 未在 [quota.tiers] 中定义", topup.name, tier));
+            }
+        }
+        let referenced = [
+            ("max_response_bytes", self.max_response_bytes.0.keys().collect::<Vec<_>>()),
+            ("max_n", self.max_n.0.keys().collect()),
+            ("reset_policies", self.reset_policies.0.keys().collect()),
+        ];
+        for (table, names) in referenced {
+            for name in names.into_iter().filter(|n| !self.tiers.contains(n)) {
+                tracing::warn!(tier = %name, "[quota.{}] 引用了未在 [quota.tiers] 中定义的档次", table);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_save_interval() -> u32 { 100 }
+fn default_monthly_reset_day() -> u32 { 1 }
+fn default_basic_quota() -> u32 { 500 }
+fn default_pro_quota() -> u32 { 1000 }
+fn default_premium_quota() -> u32 { 1500 }
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        // 加载 .env 文件 (如果存在)
+        let _ = dotenvy::dotenv();
+
+        // 加载 config.toml
+        let settings = config::Config::builder()
+            .add_source(config::File::with_name("config"))
+            .build()?;
+        let mut config = Self::from_settings(settings)?;
+
+        // 从环境变量读取 OpenAI API Key (优先级高于配置文件)
+        if let Ok(api_key) = env::var("OPENAI_API_KEY") {
+            config.deepseek.api_key = api_key;
+            config.sources.overrides.insert("deepseek.api_key".to_string(), crate::effective_config::SOURCE_ENV);
+        }
+
+        // 验证必需配置
+        if config.deepseek.api_key.is_empty() {
+            anyhow::bail!("OPENAI_API_KEY 未设置! 请在环境变量或 .env 文件中配置");
+        }
+        config.quota.validate().map_err(|e| anyhow::anyhow!("[quota] 配置无效: {}", e))?;
+        if config.auth.jwt_algorithm.is_asymmetric() && config.auth.jwt_private_key_path.is_none() {
+            anyhow::bail!("auth.jwt_algorithm = {:?} 需要配置 auth.jwt_private_key_path", config.auth.jwt_algorithm);
+        }
+        for tier in config.access_schedules.0.keys().filter(|t| !config.quota.tiers.contains(t)) {
+            tracing::warn!(tier = %tier, "[access_schedules] 引用了未在 [quota.tiers] 中定义的档次");
+        }
+        let vision_tiers = config.vision.max_images.0.keys().chain(config.vision.max_image_bytes.0.keys());
+        for tier in vision_tiers.filter(|t| !config.quota.tiers.contains(t)) {
+            tracing::warn!(tier = %tier, "[vision] 引用了未在 [quota.tiers] 中定义的档次");
+        }
+        config.models.validate().map_err(|e| anyhow::anyhow!("[models] 配置无效: {}", e))?;
+        if let Some(tier) = config.sandbox.tiers.iter().find(|t| !config.quota.tiers.contains(t)) {
+            anyhow::bail!("[sandbox] 档次 {} 未在 [quota.tiers] 中定义（其值为每日请求次数）", tier);
+        }
+        crate::proxy::stream_transform::validate(&config.streaming.transforms)
+            .map_err(|e| anyhow::anyhow!("[streaming] 配置无效: {}", e))?;
+        crate::deepseek::transform::validate(&config.deepseek.request_transforms)
+            .map_err(|e| anyhow::anyhow!("[[deepseek.request_transforms]] 配置无效: {}", e))?;
+
+        Ok(config)
+    }
+
+    /// 反序列化配置，并记下配置文件中出现的配置项
+    pub fn from_settings(settings: config::Config) -> anyhow::Result<Self> {
+        let file: serde_json::Value = settings.clone().try_deserialize()?;
+        let mut config: Config = serde_path_to_error::deserialize(settings).map_err(|e| {
+            match expected_at(&Self::json_schema(), e.path()) {
+                Some(expected) => anyhow::anyhow!("配置项 {} 无效: {}（{}）", e.path(), e.inner(), expected),
+                None => anyhow::anyhow!("配置项 {} 无效: {}", e.path(), e.inner()),
+            }
+        })?;
+        config.sources.file = crate::effective_config::leaf_paths(&file);
+        Ok(config)
+    }
+
+    /// 配置文件的 JSON Schema（`--print-config-schema`），用于发布前校验部署清单
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(Config)).expect("JSON Schema 可以序列化")
+    }
+}
+
+/// 按 JSON Schema 描述配置项期望的取值：枚举列出可选值，其余给出类型
+fn expected_at(root: &serde_json::Value, path: &serde_path_to_error::Path) -> Option<String> {
+    use serde_path_to_error::Segment;
+    // 展开 $ref、单项 allOf 与 Option 生成的 anyOf [T, null]
+    fn resolve<'a>(root: &'a serde_json::Value, mut node: &'a serde_json::Value) -> &'a serde_json::Value {
+        loop {
+            if let Some(name) = node["$ref"].as_str().and_then(|r| r.strip_prefix("#/definitions/")) {
+                node = &root["definitions"][name];
+            } else if let Some([only]) = node["allOf"].as_array().map(Vec::as_slice) {
+                node = only;
+            } else if let Some([a, b]) = node["anyOf"].as_array().map(Vec::as_slice) {
+                match (a["type"] == "null", b["type"] == "null") {
+                    (false, true) => node = a,
+                    (true, false) => node = b,
+                    _ => return node,
+                }
+            } else {
+                return node;
+            }
+        }
+    }
+    let mut node = resolve(root, root);
+    for segment in path.iter() {
+        node = resolve(root, match segment {
+            Segment::Map { key } => node["properties"].get(key).or_else(|| node.get("additionalProperties"))?,
+            Segment::Seq { .. } => node.get("items")?,
+            _ => return None,
+        });
+    }
+    let variants: Vec<&str> = node["enum"]
+        .as_array()
+        .into_iter()
+        .chain(node["oneOf"].as_array().into_iter().flatten().filter_map(|v| v["enum"].as_array()))
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .collect();
+    if !variants.is_empty() {
+        return Some(format!("可选值: {}", variants.join(", ")));
+    }
+    let kind = match &node["type"] {
+        serde_json::Value::String(kind) => kind.clone(),
+        serde_json::Value::Array(kinds) => kinds.iter().filter_map(|k| k.as_str()).collect::<Vec<_>>().join(" / "),
+        _ => return None,
+    };
+    Some(match node["format"].as_str() {
+        Some(format) => format!("应为 {}（{}）", kind, format),
+        None => format!("应为 {}", kind),
+    })
+}
+
+/// 图片输入（`image_url` 内容片段）的限制，在转发上游前检查
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct VisionConfig {
+    /// 各档次单次请求的图片数上限
+    #[serde(default)]
+    pub max_images: TierCountLimitsConfig,
+    /// 各档次单张 base64 图片解码后的字节上限（http(s) 链接不检查）
+    #[serde(default)]
+    pub max_image_bytes: TierByteLimitsConfig,
+    /// 聊天请求体的字节上限（内联图片时需要调大）
+    #[serde(default = "default_max_chat_body_bytes")]
+    pub max_request_body_bytes: usize,
+}
+
+impl Default for VisionConfig {
+    fn default() -> Self {
+        Self {
+            max_images: TierCountLimitsConfig::default(),
+            max_image_bytes: TierByteLimitsConfig::default(),
+            max_request_body_bytes: default_max_chat_body_bytes(),
+        }
+    }
+}
+
+/// 与 axum 默认的请求体上限一致
+fn default_max_chat_body_bytes() -> usize { 2 * 1024 * 1024 }
+
+/// 语音接口（/audio/transcriptions 语音识别、/audio/speech 语音合成），需上游支持
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct AudioConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 每次语音识别计入用户配额的次数
+    #[serde(default = "default_audio_quota_weight")]
+    pub transcription_quota_weight: u32,
+    /// 每次语音合成计入用户配额的次数
+    #[serde(default = "default_audio_quota_weight")]
+    pub speech_quota_weight: u32,
+    /// 语音识别上传（multipart）的字节上限
+    #[serde(default = "default_audio_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            transcription_quota_weight: default_audio_quota_weight(),
+            speech_quota_weight: default_audio_quota_weight(),
+            max_upload_bytes: default_audio_max_upload_bytes(),
+        }
+    }
+}
+
+fn default_audio_quota_weight() -> u32 { 1 }
+/// 与 OpenAI 语音识别接口的文件上限一致
+fn default_audio_max_upload_bytes() -> usize { 25 * 1024 * 1024 }
+
+/// 上游模型能力表（模型名 -> 能力；未登记的模型不做能力校验）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct ModelRegistryConfig(pub HashMap<String, ModelCapabilities>);
+
+impl ModelRegistryConfig {
+    /// 按模型名查询能力（不区分大小写）
+    pub fn get(&self, model: &str) -> Option<&ModelCapabilities> {
+        self.0.get(model).or_else(|| self.0.iter().find(|(name, _)| name.eq_ignore_ascii_case(model)).map(|(_, caps)| caps))
+    }
+
+    /// 上下文窗口与输出上限必须大于 0，输出上限不能超过上下文窗口
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, caps) in &self.0 {
+            if caps.context_window == Some(0) || caps.max_output_tokens == Some(0) {
+                return Err(format!("{} 的 context_window / max_output_tokens 必须大于 0", name));
+            }
+            if let (Some(window), Some(output)) = (caps.context_window, caps.max_output_tokens) {
+                if output > window {
+                    return Err(format!("{} 的 max_output_tokens ({}) 超过 context_window ({})", name, output, window));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 单个模型的能力
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ModelCapabilities {
+    /// 上下文窗口（输入估算 tokens + max_tokens 不能超过），不配置表示不检查
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    /// 单次输出 tokens 上限（max_tokens 不能超过），不配置表示不检查
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    /// 是否支持工具调用（`tools` 参数）
+    #[serde(default = "default_supports_tools")]
+    pub supports_tools: bool,
+    /// 是否支持图片输入（`image_url` 内容片段）
+    #[serde(default)]
+    pub supports_vision: bool,
+}
+
+fn default_supports_tools() -> bool { true }
+
+/// 基于系统压力的降级：超过任一阈值时拒绝指定档次的聊天请求（503），高档次用户不受影响
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct LoadSheddingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 进程常驻内存上限（MB）
+    #[serde(default)]
+    pub max_rss_mb: Option<u64>,
+    /// 进程 CPU 使用率上限（单核百分比，多核可超过 100）
+    #[serde(default)]
+    pub max_cpu_percent: Option<f64>,
+    /// tokio 全局任务队列深度上限
+    #[serde(default)]
+    pub max_queue_depth: Option<usize>,
+    #[serde(default = "default_load_check_interval")]
+    pub check_interval_seconds: u64,
+    /// 过载时被拒绝的档次
+    #[serde(default = "default_shed_tiers")]
+    pub shed_tiers: Vec<String>,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_rss_mb: None,
+            max_cpu_percent: None,
+            max_queue_depth: None,
+            check_interval_seconds: default_load_check_interval(),
+            shed_tiers: default_shed_tiers(),
+        }
+    }
+}
+
+fn default_load_check_interval() -> u64 { 2 }
+fn default_shed_tiers() -> Vec<String> { vec!["basic".to_string()] }
+
+/// 单元测试用的最小配置：必填项之外追加 `extra_toml`（可以是任意其他表）
+#[cfg(test)]
+pub(crate) fn test_config(extra_toml: &str) -> Config {
+    let toml = format!(
+        r#"
+[server]
+host = "127.0.0.1"
+port = 8877
+[auth]
+jwt_secret = "s"
+token_ttl_seconds = 60
+[deepseek]
+api_key = "k"
+base_url = "https://api.deepseek.com"
+timeout_seconds = 30
+[rate_limit]
+requests_per_second = 10
+{}"#,
+        extra_toml
+    );
+    let settings = config::Config::builder()
+        .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    Config::from_settings(settings).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota_config(toml: &str) -> QuotaConfig {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_custom_tiers_from_config() {
+        let cfg = quota_config(
+            "fallback_tier = \"starter\"\n[tiers]\nstarter = 50\nteam = 5000\n[max_n]\nteam = 4\nlegacy = 2\n",
+        );
+        assert_eq!(cfg.tiers.names(), vec!["starter", "team"]);
+        assert_eq!(cfg.tiers.limit("Team"), Some(5000));
+        assert_eq!(cfg.tiers.limit("basic"), None);
+        assert_eq!(cfg.max_n.for_tier("team"), Some(4));
+        // 引用未定义档次只警告
+        assert!(cfg.validate().is_ok());
+
+        let bad = quota_config("fallback_tier = \"gold\"\n[tiers]\nstarter = 50\n");
+        assert!(bad.validate().is_err());
+        assert_eq!(QuotaConfig::default().tiers.limit("premium"), Some(1500));
+    }
+
+    #[test]
+    fn test_invalid_value_reports_field_path() {
+        let settings = |toml: &str| {
+            config::Config::builder()
+                .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+                .build()
+                .unwrap()
+        };
+        let base = include_str!("../config.toml");
+        let err = Config::from_settings(settings(&base.replacen("port = 8877", "port = \"http\"", 1))).unwrap_err();
+        assert!(err.to_string().contains("server.port") && err.to_string().contains("应为 integer"), "{}", err);
+
+        let err = Config::from_settings(settings(&base.replacen("[auth]", "[auth]\njwt_algorithm = \"XS256\"", 1))).unwrap_err();
+        assert!(err.to_string().contains("auth.jwt_algorithm") && err.to_string().contains("可选值: HS256, RS256, EdDSA"), "{}", err);
+
+        let schema = Config::json_schema();
+        assert!(schema["properties"]["server"].is_object());
+        assert!(schema["definitions"]["ResetPolicy"].to_string().contains("rolling_30d"));
+    }
+
+    #[test]
+    fn test_topups_validation() {
+        let base = "[tiers]\nbasic = 50\n[[topups]]\nname = \"weekend\"\namount = 20\n";
+        assert!(quota_config(&format!("{}schedule = \"0 0 * * 6\"\ntiers = [\"basic\"]\n", base)).validate().is_ok());
+        assert!(quota_config(&format!("{}schedule = \"0 0 * *\"\ntiers = [\"basic\"]\n", base)).validate().is_err());
+        assert!(quota_config(&format!("{}schedule = \"0 0 * * 6\"\ntiers = [\"gold\"]\n", base)).validate().is_err());
+        assert!(quota_config(&format!("{}schedule = \"0 0 * * 6\"\n", base)).validate().is_err());
     }
 }
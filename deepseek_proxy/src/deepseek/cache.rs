@@ -0,0 +1,64 @@
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// 上游静态元数据（模型列表等）的内存 TTL 缓存
+///
+/// key 为上游路径（如 `/models`），value 为原始响应体
+#[derive(Debug)]
+pub struct MetadataCache {
+    entries: DashMap<String, (Bytes, Instant)>,
+    ttl: Duration,
+}
+
+impl MetadataCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+        }
+    }
+
+    /// 读取未过期的缓存；TTL 为 0 表示禁用缓存
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let entry = self.entries.get(key)?;
+        let (body, fetched_at) = entry.value();
+        if fetched_at.elapsed() < self.ttl {
+            Some(body.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, key: &str, body: Bytes) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries.insert(key.to_string(), (body, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_hit_and_expiry() {
+        let cache = MetadataCache::new(Duration::from_millis(50));
+        assert!(cache.get("/models").is_none());
+        cache.insert("/models", Bytes::from_static(b"{}"));
+        assert_eq!(cache.get("/models"), Some(Bytes::from_static(b"{}")));
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("/models").is_none());
+    }
+
+    #[test]
+    fn test_zero_ttl_disables_cache() {
+        let cache = MetadataCache::new(Duration::ZERO);
+        cache.insert("/models", Bytes::from_static(b"{}"));
+        assert!(cache.get("/models").is_none());
+    }
+}
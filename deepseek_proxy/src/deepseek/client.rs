@@ -1,15 +1,100 @@
 use crate::{error::AppError, config::HttpClientConfig};
 use bytes::Bytes;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::notify::{AlertEvent, Notifier};
+
+/// 预热失败后的首次重试间隔，之后逐次翻倍
+const WARMUP_RETRY_INITIAL: Duration = Duration::from_secs(1);
+/// 预热重试间隔上限
+const WARMUP_RETRY_MAX: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct DeepSeekClient {
     client: Client,
     api_key: String,
     base_url: String,
+    /// 最近一次预热结果
+    last_warmup: Arc<Mutex<Option<WarmupReport>>>,
+    /// 最近一次上游活动时间（用于判断连接池是否即将空闲过期）
+    last_activity: Arc<Mutex<Instant>>,
+    /// 静态元数据缓存（模型列表等）
+    metadata_cache: Arc<super::MetadataCache>,
+    /// 上游连续失败统计（用于告警）
+    health: Arc<UpstreamHealth>,
+    /// 故障注入（仅测试环境配置 [chaos] 时存在）
+    chaos: Option<Arc<crate::chaos::FaultInjector>>,
+    /// 转发前对请求体的修改（[[deepseek.request_transforms]]）
+    request_transforms: Arc<Vec<super::transform::RequestTransform>>,
+    /// 出站限流（[deepseek.outbound_rate_limit]），未配置时为空
+    outbound: Option<Arc<super::outbound::OutboundLimiter>>,
+    /// 上游密钥指纹（出站限流的桶与指标标签）
+    key_id: String,
+}
+
+/// 上游连续失败计数；达到阈值时告警一次，恢复后再发恢复通知
+#[derive(Debug, Default)]
+struct UpstreamHealth {
+    consecutive_failures: AtomicU32,
+    unhealthy: AtomicBool,
+    /// 0 表示不告警
+    threshold: u32,
+    notifier: Option<Arc<Notifier>>,
+}
+
+impl UpstreamHealth {
+    fn record_failure(&self, error: &str) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.threshold == 0 || failures < self.threshold {
+            return;
+        }
+        if !self.unhealthy.swap(true, Ordering::Relaxed) {
+            tracing::error!("上游连续失败 {} 次，最近错误: {}", failures, error);
+            if let Some(notifier) = &self.notifier {
+                notifier.notify(
+                    AlertEvent::new("upstream_unhealthy", "")
+                        .with_detail(format!("连续失败 {} 次，最近错误: {}", failures, error)),
+                );
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if self.unhealthy.swap(false, Ordering::Relaxed) {
+            tracing::info!("上游服务已恢复");
+            if let Some(notifier) = &self.notifier {
+                notifier.notify(AlertEvent::new("upstream_recovered", ""));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BalanceResponse {
+    #[serde(default)]
+    balance_infos: Vec<BalanceInfo>,
+}
+
+/// 单一币种的账户余额（上游以字符串返回金额）
+#[derive(Debug, Clone, Deserialize)]
+pub struct BalanceInfo {
+    pub currency: String,
+    pub total_balance: String,
+}
+
+/// 上游连接预热结果
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupReport {
+    pub ok: bool,
+    pub latency_ms: u64,
+    pub at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 impl DeepSeekClient {
@@ -39,31 +124,294 @@ impl DeepSeekClient {
 
         Ok(Self {
             client,
+            key_id: super::outbound::key_fingerprint(&api_key),
             api_key,
             base_url,
+            last_warmup: Arc::new(Mutex::new(None)),
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            metadata_cache: Arc::new(super::MetadataCache::new(Duration::ZERO)),
+            health: Arc::new(UpstreamHealth::default()),
+            chaos: None,
+            request_transforms: Arc::new(Vec::new()),
+            outbound: None,
         })
     }
 
+    /// 开启出站限流
+    pub fn with_outbound_limit(mut self, limiter: Option<super::outbound::OutboundLimiter>) -> Self {
+        self.outbound = limiter.map(Arc::new);
+        self
+    }
+
+    /// 按出站限流排队；等待超过上限时返回 503，不发往上游
+    async fn throttle(&self) -> Result<(), AppError> {
+        let Some(limiter) = &self.outbound else {
+            return Ok(());
+        };
+        limiter.acquire(&self.key_id).await.map_err(|retry_after| {
+            tracing::warn!(key = %self.key_id, "出站限流：上游请求排队超时，建议 {:.2} 秒后重试", retry_after);
+            AppError::Overloaded
+        })
+    }
+
+    /// 开启上游故障注入
+    pub fn with_fault_injector(mut self, chaos: Option<crate::chaos::FaultInjector>) -> Self {
+        self.chaos = chaos.map(Arc::new);
+        self
+    }
+
+    /// 上游连续失败 threshold 次时通过 notifier 告警（0 表示不告警）
+    pub fn with_failure_alerts(mut self, notifier: Arc<Notifier>, threshold: u32) -> Self {
+        self.health = Arc::new(UpstreamHealth {
+            threshold,
+            notifier: Some(notifier),
+            ..UpstreamHealth::default()
+        });
+        self
+    }
+
+    /// 设置转发前的请求体修改
+    pub fn with_request_transforms(mut self, transforms: Vec<super::transform::RequestTransform>) -> Self {
+        self.request_transforms = Arc::new(transforms);
+        self
+    }
+
+    /// 设置静态元数据缓存时间
+    pub fn with_metadata_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata_cache = Arc::new(super::MetadataCache::new(ttl));
+        self
+    }
+
+    /// 读取已缓存的静态元数据（命中时无需访问上游）
+    pub fn cached_metadata(&self, path: &str) -> Option<Bytes> {
+        self.metadata_cache.get(path)
+    }
+
+    /// 从上游获取静态元数据（如 `/models`），成功响应写入缓存
+    pub async fn fetch_metadata(&self, path: &str) -> Result<Bytes, AppError> {
+        let url = format!("{}{}", self.base_url, path);
+        self.throttle().await?;
+        let response = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await
+            .map_err(|e| {
+                crate::metrics::METRICS.upstream_errors.with_label_values(&["network"]).inc();
+                AppError::GlmError(format!("请求 DeepSeek API 失败: {}", e))
+            })?;
+        self.touch();
+
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| AppError::GlmError(format!("读取 DeepSeek API 响应失败: {}", e)))?;
+        if !status.is_success() {
+            crate::metrics::METRICS.upstream_errors.with_label_values(&["api"]).inc();
+            return Err(AppError::GlmError(format!(
+                "DeepSeek API 返回错误 {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        self.metadata_cache.insert(path, body.clone());
+        Ok(body)
+    }
+
+    /// 查询上游账户余额（DeepSeek `GET /user/balance`）；上游不提供该接口（404）时返回 Ok(None)
+    pub async fn fetch_balance(&self, url: &str) -> Result<Option<Vec<BalanceInfo>>, AppError> {
+        self.throttle().await?;
+        let response = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await
+            .map_err(|e| AppError::GlmError(format!("查询上游余额失败: {}", e)))?;
+        self.touch();
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(AppError::GlmError(format!("上游余额接口返回错误 {}: {}", status, body)));
+        }
+        let body: BalanceResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::GlmError(format!("解析上游余额失败: {}", e)))?;
+        Ok(Some(body.balance_infos))
+    }
+
+    /// 由 base_url 推导余额接口地址（余额接口不在 /v1 下）
+    pub fn default_balance_url(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        format!("{}/user/balance", base.strip_suffix("/v1").unwrap_or(base))
+    }
+
+    /// 预热上游连接：完成 DNS 解析与 TCP/TLS 握手并放入连接池
+    ///
+    /// 只要收到任意 HTTP 响应即视为成功（状态码不影响连接复用）
+    pub async fn warmup(&self) -> WarmupReport {
+        let url = format!("{}/models", self.base_url);
+        let started = Instant::now();
+        let result = match self.throttle().await {
+            Ok(()) => self
+                .client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        // 失败时不更新活动时间：连接没有进入连接池，后台任务按退避重试
+        if result.is_ok() {
+            self.touch();
+        }
+
+        let report = WarmupReport {
+            ok: result.is_ok(),
+            latency_ms: started.elapsed().as_millis() as u64,
+            at: crate::utils::now_beijing_rfc3339(),
+            error: result.err(),
+        };
+        match &report.error {
+            None => tracing::info!("上游连接预热完成，耗时 {} ms", report.latency_ms),
+            Some(e) => tracing::warn!("上游连接预热失败: {}", e),
+        }
+        *self.last_warmup.lock().unwrap() = Some(report.clone());
+        report
+    }
+
+    /// 最近一次预热结果
+    pub fn last_warmup(&self) -> Option<WarmupReport> {
+        self.last_warmup.lock().unwrap().clone()
+    }
+
+    /// 预热直到成功，失败后按退避重试（/readyz 在此期间返回 503）
+    async fn warmup_until_ok(&self) {
+        let mut backoff = WARMUP_RETRY_INITIAL;
+        while !self.warmup().await.ok {
+            tracing::info!("{} 秒后重试上游连接预热", backoff.as_secs());
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(WARMUP_RETRY_MAX);
+        }
+    }
+
+    /// 后台预热任务：启动时预热（失败则退避重试直到成功），之后在连接池空闲过期前重新预热
+    pub fn spawn_warmup_task(self: Arc<Self>, pool_idle_timeout: Duration) {
+        crate::supervisor::spawn("upstream_warmup", move || {
+            let client = self.clone();
+            async move {
+                client.warmup_until_ok().await;
+                let check_every = (pool_idle_timeout / 4).max(Duration::from_secs(1));
+                let rewarm_after = pool_idle_timeout * 3 / 4;
+                let mut tick = tokio::time::interval(check_every);
+                tick.tick().await;
+                loop {
+                    tick.tick().await;
+                    let idle = client.last_activity.lock().unwrap().elapsed();
+                    if idle >= rewarm_after {
+                        tracing::debug!("上游连接已空闲 {} 秒，重新预热", idle.as_secs());
+                        client.warmup_until_ok().await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// 记录上游活动时间
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
     /// 流式请求 DeepSeek API
     pub async fn chat_stream(
         &self,
         request: ChatRequest,
     ) -> Result<impl Stream<Item = Result<Bytes, reqwest::Error>>, AppError> {
-        let url = format!("{}/chat/completions", self.base_url);
         let timer = crate::metrics::UpstreamTimer::start();
+        let response = self.send_chat(&request).await?;
+        timer.observe();
+        let chaos = self.chaos.clone();
+        Ok(response.bytes_stream().filter(move |chunk| {
+            let dropped = chunk.is_ok() && chaos.as_ref().is_some_and(|c| c.drop_chunk());
+            futures::future::ready(!dropped)
+        }))
+    }
+
+    /// 非流式请求 DeepSeek API，返回完整 JSON 响应（用于内部辅助调用）
+    pub async fn chat_once(&self, request: ChatRequest) -> Result<serde_json::Value, AppError> {
+        let timer = crate::metrics::UpstreamTimer::start();
+        let response = self.send_chat(&request).await?;
+        let value = response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|e| AppError::GlmError(format!("解析 DeepSeek API 响应失败: {}", e)))?;
+        timer.observe();
+        Ok(value)
+    }
+
+    /// 发送 chat/completions 请求并检查响应状态
+    async fn send_chat(&self, request: &ChatRequest) -> Result<reqwest::Response, AppError> {
+        let url = format!("{}/chat/completions", self.base_url);
+        self.touch();
+
+        if let Some(chaos) = &self.chaos {
+            if let Some(status) = chaos.before_request().await {
+                // 与真实上游错误走同一套计数与健康统计
+                crate::metrics::METRICS.upstream_errors.with_label_values(&["api"]).inc();
+                self.health.record_failure(&format!("HTTP {}（故障注入）", status));
+                return Err(AppError::GlmError(format!("DeepSeek API 返回错误 {}: 故障注入", status)));
+            }
+        }
+
+        let mut body = serde_json::to_value(request)
+            .map_err(|e| AppError::InternalError(format!("序列化上游请求失败: {}", e)))?;
+        super::transform::apply(&self.request_transforms, &mut body);
+        self.throttle().await?;
 
         let response = self
             .client
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
-            .json(&request)
+            .json(&body)
             .send()
-            .await
-            .map_err(|e| {
-                crate::metrics::METRICS.upstream_errors.with_label_values(&["network"]).inc();
-                AppError::GlmError(format!("请求 DeepSeek API 失败: {}", e))
-            })?;
+            .await;
+        self.check_response(response).await
+    }
+
+    /// 转发语音请求（`/audio/transcriptions` 的 multipart 表单或 `/audio/speech` 的 JSON），请求体原样发送
+    pub async fn send_audio(&self, path: &str, content_type: &str, body: Bytes) -> Result<reqwest::Response, AppError> {
+        let url = format!("{}{}", self.base_url, path);
+        self.throttle().await?;
+        self.touch();
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await;
+        self.check_response(response).await
+    }
+
+    /// 统计网络错误与上游错误状态，成功时返回响应
+    async fn check_response(&self, response: reqwest::Result<reqwest::Response>) -> Result<reqwest::Response, AppError> {
+        let response = response.map_err(|e| {
+            crate::metrics::METRICS.upstream_errors.with_label_values(&["network"]).inc();
+            self.health.record_failure(&e.to_string());
+            AppError::GlmError(format!("请求 DeepSeek API 失败: {}", e))
+        })?;
 
         // 检查响应状态
         if !response.status().is_success() {
@@ -73,14 +421,18 @@ impl DeepSeekClient {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             crate::metrics::METRICS.upstream_errors.with_label_values(&["api"]).inc();
+            // 4xx（请求本身的问题）不计入上游健康状况
+            if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                self.health.record_failure(&format!("HTTP {}", status));
+            }
             return Err(AppError::GlmError(format!(
                 "DeepSeek API 返回错误 {}: {}",
                 status, error_text
             )));
         }
 
-        timer.observe();
-        Ok(response.bytes_stream())
+        self.health.record_success();
+        Ok(response)
     }
 }
 
@@ -88,6 +440,8 @@ impl DeepSeekClient {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatRequest {
+    /// 账户固定了模型时可以省略（见 `User::pinned_model`）
+    #[serde(default)]
     pub model: String,
     pub messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -96,14 +450,112 @@ pub struct ChatRequest {
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    /// 生成的候选回复数，每个候选都按一次请求计费
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
     pub stream: bool,
+    /// 调用方自带的关联信息（如任务 ID），只写入行为日志并在 usage 块中回显，不转发上游
+    #[serde(default, skip_serializing)]
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
     // 支持其他参数透传
     #[serde(flatten)]
     pub extra: serde_json::Value,
 }
 
+impl ChatRequest {
+    /// 上游将生成的候选回复数（未指定时为 1）
+    pub fn choice_count(&self) -> u32 {
+        self.n.unwrap_or(1)
+    }
+
+    /// 是否请求 JSON 模式输出（response_format.type = "json_object"）
+    pub fn json_mode(&self) -> bool {
+        self.extra.pointer("/response_format/type").and_then(|t| t.as_str()) == Some("json_object")
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    /// 纯文本或多模态内容；携带 tool_calls 的 assistant 消息可以没有 content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<MessageContent>,
+    // 其他字段透传（name、tool_calls、tool_call_id 等）
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// 消息内容：纯文本或结构化内容片段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// 结构化内容片段（text / image_url 等），未知字段原样透传
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ContentPart {
+    /// text 片段的文本内容
+    pub fn text(&self) -> Option<&str> {
+        if self.kind != "text" {
+            return None;
+        }
+        self.fields.get("text").and_then(|v| v.as_str())
+    }
+
+    /// 是否为图片片段
+    pub fn is_image(&self) -> bool {
+        self.kind == "image_url"
+    }
+
+    /// 图片片段的地址（http(s) 链接或 base64 data URL）
+    pub fn image_url(&self) -> Option<&str> {
+        self.fields.get("image_url").and_then(|v| v.get("url")).and_then(|v| v.as_str())
+    }
+
+    /// 图片片段的 detail 级别（low / high / auto），缺省为 auto
+    pub fn image_detail(&self) -> &str {
+        self.fields
+            .get("image_url")
+            .and_then(|v| v.get("detail"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("auto")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_warmup_is_retried_until_upstream_is_reachable() {
+        // 先占一个端口再释放，得到一个暂时没有服务监听的地址
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let client = Arc::new(
+            DeepSeekClient::new("k".to_string(), format!("http://{}", addr), 5, &HttpClientConfig::default()).unwrap(),
+        );
+        let retrying = tokio::spawn({
+            let client = client.clone();
+            async move { client.warmup_until_ok().await }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(client.last_warmup().is_some_and(|w| !w.ok));
+
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move { axum::serve(listener, axum::Router::new()).await });
+        tokio::time::timeout(Duration::from_secs(5), retrying).await.unwrap().unwrap();
+        assert!(client.last_warmup().is_some_and(|w| w.ok));
+    }
 }
@@ -1,3 +1,8 @@
+pub mod cache;
 pub mod client;
+pub mod outbound;
+pub mod transform;
+pub mod upstreams;
 
+pub use cache::*;
 pub use client::*;
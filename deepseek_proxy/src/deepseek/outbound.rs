@@ -0,0 +1,95 @@
+//! 出站限流：所有发往上游的请求（聊天、语音、历史压缩、模型列表、余额查询、预热）按上游密钥共享令牌桶，
+//! 合计不超过服务商规定的 RPS，与入站的全局限流（`[rate_limit]`）相互独立
+//!
+//! 令牌不足时按预约排队等待，等待超过 `max_wait_ms` 才拒绝，先到的请求先获得令牌
+
+use crate::config::OutboundRateLimitConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct OutboundLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    max_wait: Duration,
+    /// 上游密钥指纹 -> 令牌桶（令牌数可为负，表示已被排队请求预约）
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl OutboundLimiter {
+    /// 未配置（requests_per_second 为 0）时返回 None
+    pub fn from_config(cfg: &OutboundRateLimitConfig) -> Option<Self> {
+        (cfg.requests_per_second > 0).then(|| Self {
+            requests_per_second: cfg.requests_per_second as f64,
+            burst: cfg.burst.max(1) as f64,
+            max_wait: Duration::from_millis(cfg.max_wait_ms),
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 预约一个令牌，返回需要等待的时间；等待超过上限时返回 None（不占用令牌）
+    fn reserve(&self, key: &str, now: Instant) -> Option<Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: self.burst, last_refill: now });
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+        bucket.last_refill = now;
+
+        let wait = Duration::from_secs_f64(((1.0 - bucket.tokens) / self.requests_per_second).max(0.0));
+        if wait > self.max_wait {
+            return None;
+        }
+        bucket.tokens -= 1.0;
+        Some(wait)
+    }
+
+    /// 等待轮到本次请求；排队时间超过上限时返回 Err（建议等待的秒数）
+    pub async fn acquire(&self, key: &str) -> Result<(), f64> {
+        let Some(wait) = self.reserve(key, Instant::now()) else {
+            crate::metrics::METRICS.outbound_throttled.with_label_values(&[key]).inc();
+            return Err(self.max_wait.as_secs_f64().max(1.0 / self.requests_per_second));
+        };
+        crate::metrics::METRICS.outbound_wait.with_label_values(&[key]).observe(wait.as_secs_f64());
+        if !wait.is_zero() {
+            tracing::debug!(key, wait_ms = wait.as_millis() as u64, "出站限流：排队等待上游令牌");
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// 上游密钥的指纹（SHA-256 前 4 字节），用作令牌桶与指标标签，避免密钥出现在日志和指标中
+pub fn key_fingerprint(api_key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, api_key.as_bytes());
+    hex::encode(&digest.as_ref()[..4])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_queues_then_rejects() {
+        let cfg: OutboundRateLimitConfig = toml::from_str("requests_per_second = 10\nburst = 2\nmax_wait_ms = 150\n").unwrap();
+        let limiter = OutboundLimiter::from_config(&cfg).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve("a", now), Some(Duration::ZERO));
+        assert_eq!(limiter.reserve("a", now), Some(Duration::ZERO));
+        // 桶空后依次排队：第 3、4 个分别等 100ms、200ms（超过上限被拒，且不占用令牌）
+        assert_eq!(limiter.reserve("a", now), Some(Duration::from_millis(100)));
+        assert_eq!(limiter.reserve("a", now), None);
+        assert!(limiter.reserve("a", now + Duration::from_millis(100)).is_some());
+        // 不同上游密钥互不影响
+        assert_eq!(limiter.reserve("b", now), Some(Duration::ZERO));
+
+        assert!(OutboundLimiter::from_config(&OutboundRateLimitConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// 转发上游前对请求体做的一步修改（`[[deepseek.request_transforms]]`），按配置顺序执行
+///
+/// 用于适配不同服务商的参数差异（如去掉上游不支持的字段、参数改名、注入安全设置），无需改代码
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct RequestTransform {
+    pub op: TransformOp,
+    /// 字段路径，嵌套字段用点分隔，如 `response_format.type`
+    pub field: String,
+    /// rename 的新路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// set / set_default 写入的值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    /// 只对这些模型生效（精确匹配，以 `*` 结尾时按前缀匹配），为空表示所有模型
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformOp {
+    /// 删除字段
+    Remove,
+    /// 字段改名（原字段不存在时跳过）
+    Rename,
+    /// 写入字段，覆盖客户端的值
+    Set,
+    /// 客户端未提供时写入
+    SetDefault,
+}
+
+impl RequestTransform {
+    fn applies_to(&self, model: &str) -> bool {
+        self.models.is_empty()
+            || self.models.iter().any(|m| match m.strip_suffix('*') {
+                Some(prefix) => model.starts_with(prefix),
+                None => m == model,
+            })
+    }
+}
+
+/// 检查配置：rename 需要 `to`，set / set_default 需要 `value`，路径不能为空
+pub fn validate(transforms: &[RequestTransform]) -> Result<(), String> {
+    for (i, t) in transforms.iter().enumerate() {
+        let path_ok = |p: &str| !p.is_empty() && p.split('.').all(|s| !s.is_empty());
+        if !path_ok(&t.field) {
+            return Err(format!("第 {} 项的 field \"{}\" 无效", i + 1, t.field));
+        }
+        match t.op {
+            TransformOp::Rename if !t.to.as_deref().is_some_and(path_ok) => {
+                return Err(format!("第 {} 项 rename 缺少有效的 to", i + 1));
+            }
+            TransformOp::Set | TransformOp::SetDefault if t.value.is_none() => {
+                return Err(format!("第 {} 项 set / set_default 缺少 value", i + 1));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 对序列化后的请求体依次执行修改
+pub fn apply(transforms: &[RequestTransform], body: &mut Value) {
+    let model = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    for t in transforms.iter().filter(|t| t.applies_to(&model)) {
+        match t.op {
+            TransformOp::Remove => {
+                take(body, &t.field);
+            }
+            TransformOp::Rename => {
+                if let (Some(value), Some(to)) = (take(body, &t.field), t.to.as_deref()) {
+                    insert(body, to, value, true);
+                }
+            }
+            TransformOp::Set | TransformOp::SetDefault => {
+                if let Some(value) = &t.value {
+                    insert(body, &t.field, value.clone(), t.op == TransformOp::Set);
+                }
+            }
+        }
+    }
+}
+
+/// 取出字段（中间层不存在时返回 None）
+fn take(body: &mut Value, path: &str) -> Option<Value> {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (parent.split('.').try_fold(body, |v, k| v.get_mut(k))?, key),
+        None => (body, path),
+    };
+    parent.as_object_mut()?.remove(key)
+}
+
+/// 写入字段，按需创建中间对象；路径上遇到非对象的值时跳过
+fn insert(body: &mut Value, path: &str, value: Value, overwrite: bool) {
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let Some(last) = keys.pop() else { return };
+    let mut current = body;
+    for key in keys {
+        let Some(map) = current.as_object_mut() else { return };
+        current = map.entry(key).or_insert_with(|| Value::Object(Map::new()));
+    }
+    if let Some(map) = current.as_object_mut() {
+        if overwrite || !map.contains_key(last) {
+            map.insert(last.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_transforms_in_order() {
+        #[derive(Deserialize)]
+        struct Config {
+            t: Vec<RequestTransform>,
+        }
+        let transforms = toml::from_str::<Config>(
+            r#"
+[[t]]
+op = "remove"
+field = "logprobs"
+[[t]]
+op = "rename"
+field = "max_tokens"
+to = "max_new_tokens"
+models = ["glm-*"]
+[[t]]
+op = "set"
+field = "safety.level"
+value = "strict"
+[[t]]
+op = "set_default"
+field = "temperature"
+value = 0.7
+"#,
+        )
+        .unwrap()
+        .t;
+        assert_eq!(validate(&transforms), Ok(()));
+
+        let mut body = json!({"model": "glm-4-flash", "logprobs": true, "max_tokens": 100, "temperature": 0.2});
+        apply(&transforms, &mut body);
+        assert_eq!(
+            body,
+            json!({"model": "glm-4-flash", "max_new_tokens": 100, "temperature": 0.2, "safety": {"level": "strict"}})
+        );
+
+        // 模型不匹配时跳过 rename；未提供 temperature 时写入默认值
+        let mut body = json!({"model": "deepseek-chat", "max_tokens": 100});
+        apply(&transforms, &mut body);
+        assert_eq!(body, json!({"model": "deepseek-chat", "max_tokens": 100, "temperature": 0.7, "safety": {"level": "strict"}}));
+    }
+
+    #[test]
+    fn test_validate_rejects_incomplete_transforms() {
+        let t = |op, to: Option<&str>, value: Option<Value>| RequestTransform {
+            op,
+            field: "a".to_string(),
+            to: to.map(String::from),
+            value,
+            models: Vec::new(),
+        };
+        assert!(validate(&[t(TransformOp::Rename, None, None)]).is_err());
+        assert!(validate(&[t(TransformOp::Set, None, None)]).is_err());
+        assert!(validate(&[t(TransformOp::Rename, Some("b..c"), None)]).is_err());
+        assert!(validate(&[t(TransformOp::Remove, None, None)]).is_ok());
+    }
+}
@@ -0,0 +1,80 @@
+//! 按请求选用的其他上游（`[deepseek.upstreams.<名称>]`）
+//!
+//! 只有受信任的调用方（管理员代管签发的 token、不受配额限制的服务账户）可以通过请求头 `X-Upstream` 选用，
+//! 普通账户携带该请求头返回 403；每次选用都记录日志，便于在生产代理链路上验证服务商迁移
+
+use super::DeepSeekClient;
+use crate::config::DeepSeekConfig;
+use crate::error::{AppError, AuthError};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 选择上游的请求头
+pub const X_UPSTREAM: &str = "x-upstream";
+
+#[derive(Default)]
+pub struct UpstreamRegistry {
+    clients: HashMap<String, Arc<DeepSeekClient>>,
+}
+
+impl UpstreamRegistry {
+    /// 为每个配置的上游创建独立的 HTTP 客户端（连接池参数、请求体修改与出站限流沿用 [deepseek] 的配置）
+    pub fn from_config(cfg: &DeepSeekConfig) -> anyhow::Result<Self> {
+        let mut clients = HashMap::new();
+        for (name, upstream) in &cfg.upstreams {
+            let client = DeepSeekClient::new(
+                upstream.api_key.clone().unwrap_or_else(|| cfg.api_key.clone()),
+                upstream.base_url.clone(),
+                upstream.timeout_seconds.unwrap_or(cfg.timeout_seconds),
+                &cfg.http_client,
+            )
+            .map_err(|e| anyhow::anyhow!("上游 {} 的客户端初始化失败: {}", name, e))?
+            .with_request_transforms(cfg.request_transforms.clone())
+            .with_outbound_limit(super::outbound::OutboundLimiter::from_config(&cfg.outbound_rate_limit));
+            tracing::info!(upstream = %name, base_url = %upstream.base_url, "已配置可按请求选用的上游");
+            clients.insert(name.clone(), Arc::new(client));
+        }
+        Ok(Self { clients })
+    }
+
+    /// 已配置的上游名称（排序后）
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.clients.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// 按请求头选择上游：未携带时返回 None（使用默认上游）；调用方不受信任时返回 403，名称未配置时返回 400
+    pub fn select(&self, requested: Option<&str>, trusted: bool) -> Result<Option<(&str, Arc<DeepSeekClient>)>, AppError> {
+        let Some(name) = requested.map(str::trim).filter(|name| !name.is_empty()) else {
+            return Ok(None);
+        };
+        if !trusted {
+            return Err(AuthError::UpstreamOverrideForbidden.into());
+        }
+        match self.clients.get_key_value(name) {
+            Some((name, client)) => Ok(Some((name.as_str(), client.clone()))),
+            None => Err(AppError::BadRequest(format!(
+                "未配置的上游: {}，可选: {}",
+                name,
+                self.names().join(", ")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_upstream() {
+        let cfg = crate::config::test_config("[deepseek.upstreams.staging]\nbase_url = \"https://staging.example.com\"\n");
+        let registry = UpstreamRegistry::from_config(&cfg.deepseek).unwrap();
+        assert!(registry.select(None, false).unwrap().is_none());
+        assert!(registry.select(Some(" "), false).unwrap().is_none());
+        assert_eq!(registry.select(Some("staging"), true).unwrap().map(|(name, _)| name), Some("staging"));
+        assert!(matches!(registry.select(Some("staging"), false), Err(AppError::Auth(AuthError::UpstreamOverrideForbidden))));
+        assert!(matches!(registry.select(Some("prod-b"), true), Err(AppError::BadRequest(_))));
+    }
+}
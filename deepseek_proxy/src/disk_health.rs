@@ -0,0 +1,67 @@
+use crate::{config::DiskHealthConfig, notify::{AlertEvent, Notifier}};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 定期检查 data/、logs/ 所在文件系统的剩余空间，导出指标并在低于阈值时告警
+///
+/// 磁盘写满时配额与行为日志会写入失败（见 persist_failures_total），需要在此之前发现
+pub fn spawn_monitor(cfg: DiskHealthConfig, dirs: Vec<(&'static str, PathBuf)>, notifier: Arc<Notifier>) {
+    crate::supervisor::spawn("disk_health_monitor", move || {
+        let (cfg, dirs, notifier) = (cfg.clone(), dirs.clone(), notifier.clone());
+        async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(cfg.check_interval_seconds.max(1)));
+            let min_free_bytes = cfg.min_free_mb.saturating_mul(1024 * 1024);
+            loop {
+                ticker.tick().await;
+                for (label, dir) in &dirs {
+                    let Some(free) = free_bytes(dir) else { continue };
+                    crate::metrics::METRICS.disk_free_bytes.with_label_values(&[label]).set(free as i64);
+                    if min_free_bytes > 0 && free < min_free_bytes {
+                        tracing::warn!(dir = %dir.display(), free_mb = free / 1024 / 1024, min_free_mb = cfg.min_free_mb, "磁盘剩余空间不足");
+                        notifier.notify_throttled(
+                            &format!("disk_space_low:{}", label),
+                            AlertEvent::new("disk_space_low", "").with_detail(format!(
+                                "{} 所在磁盘剩余 {} MB，低于阈值 {} MB",
+                                dir.display(),
+                                free / 1024 / 1024,
+                                cfg.min_free_mb
+                            )),
+                        );
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// 目录所在文件系统对非特权用户可用的字节数（目录不存在或非 Unix 平台返回 None）
+#[cfg(unix)]
+fn free_bytes(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path 为以 NUL 结尾的有效 C 字符串，stat 为可写的 statvfs 结构
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_bytes(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_bytes() {
+        assert!(free_bytes(&std::env::temp_dir()).is_some_and(|b| b > 0));
+        assert!(free_bytes(Path::new("/definitely/not/here")).is_none());
+    }
+}
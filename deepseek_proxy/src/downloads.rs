@@ -0,0 +1,192 @@
+//! 导出文件的签名下载链接（GET /downloads/:token）
+//!
+//! 导出接口把生成的文件写入 downloads/（不在 data/ 下，不进入备份），只返回带有效期的签名链接，
+//! 文件由下载接口以流的方式发送，大文件不再占用管理接口的请求。
+//! 签名密钥在启动时随机生成：重启后旧链接全部失效，遗留文件在启动时清理；过期文件每分钟清理一次
+
+use crate::{error::AppError, AppState};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use sha2::Sha256;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 导出接口返回的下载链接
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadLink {
+    /// 配置 `admin.download_base_url` 时为完整地址，否则为相对路径
+    pub url: String,
+    pub filename: String,
+    pub bytes: u64,
+    pub expires_at: String,
+}
+
+pub struct DownloadStore {
+    dir: PathBuf,
+    key: [u8; 32],
+    ttl_seconds: u64,
+    base_url: Option<String>,
+}
+
+impl DownloadStore {
+    /// 创建下载目录并清理上次运行遗留的文件（旧链接的签名已无法验证）
+    pub async fn new(dir: PathBuf, ttl_seconds: u64, base_url: Option<String>) -> Self {
+        let mut key = [0u8; 32];
+        SystemRandom::new().fill(&mut key).expect("系统随机数生成失败");
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(path = %dir.display(), error = %e, "清理遗留的导出文件失败");
+            }
+        }
+        let base_url = base_url.map(|url| url.trim_end_matches('/').to_string());
+        Self { dir, key, ttl_seconds: ttl_seconds.max(1), base_url }
+    }
+
+    /// 保存导出文件，返回签名下载链接
+    pub async fn publish(&self, filename: &str, content: Vec<u8>) -> Result<DownloadLink, AppError> {
+        let mut id = [0u8; 16];
+        SystemRandom::new().fill(&mut id).expect("系统随机数生成失败");
+        let id = hex::encode(id);
+        let dir = self.dir.join(&id);
+        let bytes = content.len() as u64;
+        let write = async {
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(dir.join(filename), content).await
+        };
+        write.await.map_err(|e| AppError::InternalError(format!("写入导出文件失败: {}", e)))?;
+
+        let expires = chrono::Utc::now().timestamp() + self.ttl_seconds as i64;
+        let token = format!("{}.{}.{}", id, expires, hex::encode(self.mac(&id, expires).finalize().into_bytes()));
+        let path = format!("/downloads/{}", token);
+        let beijing = chrono::FixedOffset::east_opt(8 * 3600).expect("Invalid timezone offset");
+        let expires_at = chrono::DateTime::from_timestamp(expires, 0).unwrap_or_default().with_timezone(&beijing);
+        Ok(DownloadLink {
+            url: match &self.base_url {
+                Some(base) => format!("{}{}", base, path),
+                None => path,
+            },
+            filename: filename.to_string(),
+            bytes,
+            expires_at: expires_at.to_rfc3339(),
+        })
+    }
+
+    fn mac(&self, id: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC 接受任意长度的密钥");
+        mac.update(format!("{}.{}", id, expires).as_bytes());
+        mac
+    }
+
+    /// 校验 token（签名正确且未过期），返回文件所在目录
+    fn verify(&self, token: &str, now: i64) -> Option<PathBuf> {
+        let mut parts = token.splitn(3, '.');
+        let (id, expires, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let expires: i64 = expires.parse().ok()?;
+        if expires < now || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.mac(id, expires).verify_slice(&hex::decode(signature).ok()?).ok()?;
+        Some(self.dir.join(id))
+    }
+
+    /// 打开 token 对应的文件，返回文件名与文件
+    async fn open(&self, token: &str) -> Option<(String, tokio::fs::File)> {
+        let dir = self.verify(token, chrono::Utc::now().timestamp())?;
+        let entry = tokio::fs::read_dir(&dir).await.ok()?.next_entry().await.ok()??;
+        let file = tokio::fs::File::open(entry.path()).await.ok()?;
+        Some((entry.file_name().to_string_lossy().to_string(), file))
+    }
+
+    /// 删除超过有效期的导出文件
+    async fn cleanup(&self) {
+        let Ok(mut read_dir) = tokio::fs::read_dir(&self.dir).await else { return };
+        let ttl = Duration::from_secs(self.ttl_seconds);
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let expired = entry
+                .metadata()
+                .await
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > ttl));
+            if expired {
+                if let Err(e) = tokio::fs::remove_dir_all(entry.path()).await {
+                    tracing::warn!(path = %entry.path().display(), error = %e, "删除过期的导出文件失败");
+                }
+            }
+        }
+    }
+
+    /// 后台任务：定期删除过期的导出文件
+    pub fn spawn_cleaner(self: Arc<Self>) {
+        crate::supervisor::spawn("download_cleaner", move || {
+            let store = self.clone();
+            async move {
+                let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    store.cleanup().await;
+                }
+            }
+        });
+    }
+}
+
+/// 下载导出文件：链接本身即凭证，无效或过期统一返回 404
+pub async fn download(State(state): State<AppState>, Path(token): Path<String>) -> Result<Response, AppError> {
+    let (filename, file) = state
+        .downloads
+        .open(&token)
+        .await
+        .ok_or_else(|| AppError::NotFound("下载链接无效或已过期".to_string()))?;
+    let content_type = if filename.ends_with(".json") { "application/json" } else { "application/octet-stream" };
+    let disposition = format!("attachment; filename=\"{}\"", filename);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signed_link_verifies_and_expires() {
+        let dir = std::env::temp_dir().join("test_downloads");
+        let store = DownloadStore::new(dir.clone(), 60, Some("https://proxy.example.com/".to_string())).await;
+        let link = store.publish("alice-export.json", b"{}".to_vec()).await.unwrap();
+        assert_eq!(link.bytes, 2);
+        let token = link.url.strip_prefix("https://proxy.example.com/downloads/").unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        assert!(store.verify(token, now).is_some());
+        assert!(store.verify(token, now + 61).is_none());
+        // 篡改有效期或签名
+        let (id, rest) = token.split_once('.').unwrap();
+        let (expires, signature) = rest.split_once('.').unwrap();
+        assert!(store.verify(&format!("{}.{}.{}", id, expires.parse::<i64>().unwrap() + 3600, signature), now).is_none());
+        assert!(store.verify(&format!("{}.{}.{}", id, expires, "00".repeat(32)), now).is_none());
+        assert!(store.verify("../etc.1.00", now).is_none());
+
+        let (filename, _) = store.open(token).await.unwrap();
+        assert_eq!(filename, "alice-export.json");
+        // 重启后密钥改变，旧链接失效
+        let restarted = DownloadStore::new(dir.clone(), 60, None).await;
+        assert!(restarted.open(token).await.is_none());
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
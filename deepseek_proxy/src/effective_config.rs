@@ -0,0 +1,165 @@
+use crate::config::Config;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+/// 按字段名脱敏（任意层级，如 [[auth.users]] 中的 password）；webhook 地址通常内嵌访问令牌，一并脱敏
+const SECRET_FIELDS: &[&str] = &[
+    "jwt_secret",
+    "password",
+    "password_hash",
+    "api_key",
+    "bearer_token",
+    "secret",
+    "totp_secret",
+    "signing_secret",
+    "slack_signing_secret",
+    "access_key",
+    "secret_key",
+    "sentry_dsn",
+    "webhook_url",
+    "error_webhook_url",
+    "notify_webhook",
+];
+const MASK: &str = "******";
+
+/// 配置项来源
+pub const SOURCE_DEFAULT: &str = "default";
+pub const SOURCE_FILE: &str = "file";
+pub const SOURCE_ENV: &str = "env";
+pub const SOURCE_CLI: &str = "cli";
+
+/// 一个配置项的生效值与来源
+#[derive(Debug, Serialize)]
+pub struct EffectiveSetting {
+    pub value: Value,
+    /// default / file / env / cli
+    pub source: &'static str,
+}
+
+/// 合并后的生效配置（GET /admin/config/effective），键为点分路径，如 `server.port`、`quota.tiers.basic`
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub settings: BTreeMap<String, EffectiveSetting>,
+}
+
+/// 生成脱敏后的生效配置
+pub fn report(config: &Config) -> EffectiveConfig {
+    let mut value = serde_json::to_value(config).unwrap_or(Value::Null);
+    mask_secrets(&mut value);
+    let mut settings = BTreeMap::new();
+    flatten(String::new(), value, &mut |key, value| {
+        let source = config.sources.source_of(&key);
+        settings.insert(key, EffectiveSetting { value, source });
+    });
+    EffectiveConfig { settings }
+}
+
+/// 启动时输出生效配置：配置文件、环境变量与命令行设置的项逐条输出，使用默认值的项只在 debug 级别输出
+pub fn log_startup(config: &Config) {
+    let report = report(config);
+    let defaults = report.settings.values().filter(|s| s.source == SOURCE_DEFAULT).count();
+    for (key, setting) in &report.settings {
+        if setting.source == SOURCE_DEFAULT {
+            tracing::debug!(key = %key, value = %setting.value, source = setting.source, "生效配置");
+        } else {
+            tracing::info!(key = %key, value = %setting.value, source = setting.source, "生效配置");
+        }
+    }
+    tracing::info!(
+        "生效配置共 {} 项，其中 {} 项为默认值（完整列表见 GET /admin/config/effective）",
+        report.settings.len(),
+        defaults
+    );
+}
+
+/// 配置文件中出现的全部配置项路径（与 report 的键一致）
+pub fn leaf_paths(value: &Value) -> HashSet<String> {
+    let mut paths = HashSet::new();
+    flatten(String::new(), value.clone(), &mut |key, _| {
+        paths.insert(key);
+    });
+    paths
+}
+
+/// 表逐层展开为点分路径；数组（如 [[auth.users]]）与空表作为一个整体
+fn flatten(prefix: String, value: Value, f: &mut impl FnMut(String, Value)) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                let key = if prefix.is_empty() { k } else { format!("{}.{}", prefix, k) };
+                flatten(key, v, f);
+            }
+        }
+        other => f(prefix, other),
+    }
+}
+
+/// 已设置的敏感字段替换为掩码；未设置（null 或空字符串）的保留原样，便于确认是否配置
+fn mask_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                let is_set = !(v.is_null() || v.as_str() == Some(""));
+                if SECRET_FIELDS.contains(&key.as_str()) && is_set {
+                    *v = Value::from(MASK);
+                } else {
+                    mask_secrets(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_secrets),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_and_masking() {
+        let toml = r#"
+[server]
+host = "127.0.0.1"
+port = 8877
+
+[auth]
+jwt_secret = "very-secret"
+token_ttl_seconds = 60
+
+[[auth.users]]
+username = "alice"
+password = "pass123"
+
+[deepseek]
+api_key = ""
+base_url = "https://api.deepseek.com"
+timeout_seconds = 30
+
+[rate_limit]
+requests_per_second = 10
+"#;
+        let settings = config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap();
+        let mut config = Config::from_settings(settings).unwrap();
+        config.deepseek.api_key = "sk-from-env".to_string();
+        config.sources.overrides.insert("deepseek.api_key".to_string(), SOURCE_ENV);
+
+        let report = report(&config);
+        let setting = |key: &str| report.settings.get(key).unwrap_or_else(|| panic!("缺少 {}", key));
+        assert_eq!(setting("server.port").value, 8877);
+        assert_eq!(setting("server.port").source, SOURCE_FILE);
+        assert_eq!(setting("quota.save_interval").source, SOURCE_DEFAULT);
+        assert_eq!(setting("deepseek.api_key").source, SOURCE_ENV);
+        assert_eq!(setting("deepseek.api_key").value, MASK);
+        assert_eq!(setting("auth.jwt_secret").value, MASK);
+        assert_eq!(setting("auth.users").value[0]["password"], MASK);
+        assert_eq!(setting("auth.users").value[0]["username"], "alice");
+
+        let text = serde_json::to_string(&report).unwrap();
+        assert!(!text.contains("very-secret") && !text.contains("pass123") && !text.contains("sk-from-env"));
+    }
+}
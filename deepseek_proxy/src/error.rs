@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderMap, HeaderName, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -26,9 +26,30 @@ pub enum AuthError {
     
     #[error("账户已被停用")]
     AccountDisabled,
+
+    #[error("来源 IP 不在账户允许范围内")]
+    AccountIpRestricted,
+
+    #[error("来源 IP 被全局 IP 规则拦截")]
+    IpBlocked,
+
+    #[error("不在账户允许的访问时段内: {0}")]
+    OutsideAccessHours(String),
     
     #[error("密码错误")]
     InvalidCredentials,
+
+    #[error("需要两步验证码")]
+    TotpRequired,
+
+    #[error("两步验证码错误")]
+    InvalidTotp,
+
+    #[error("无权选择上游")]
+    UpstreamOverrideForbidden,
+
+    #[error("登录失败次数过多，{retry_after} 秒后可重试")]
+    LoginLocked { retry_after: u64 },
 }
 
 /// 配额相关错误
@@ -111,9 +132,18 @@ pub enum AppError {
     #[error("请求参数错误: {0}")]
     BadRequest(String),
 
+    #[error("密码不符合策略: {0:?}")]
+    WeakPassword(Vec<crate::auth::password_policy::PolicyViolation>),
+
     #[error("资源不存在: {0}")]
     NotFound(String),
 
+    #[error("状态冲突: {0}")]
+    Conflict(String),
+
+    #[error("不支持的请求体类型: {0}")]
+    UnsupportedMediaType(String),
+
     #[error("配额已耗尽，需要付费")]
     PaymentRequired {
         used: u32,
@@ -124,8 +154,17 @@ pub enum AppError {
     #[error("排队超时")]
     QueueTimeout,
 
+    #[error("请求处理超时")]
+    RequestTimeout,
+
     #[error("队列已满")]
-    TooManyRequests,
+    TooManyRequests(RateLimit),
+
+    #[error("服务器负载过高")]
+    Overloaded,
+
+    #[error("只读模式下不允许修改数据")]
+    ReadOnly,
 
     #[error("GLM API 超时")]
     GatewayTimeout,
@@ -137,8 +176,13 @@ pub enum AppError {
     InternalError(String),
 }
 
+/// 500 响应携带的错误详情（放入响应 extensions，供错误上报中间件读取）
+#[derive(Debug, Clone)]
+pub struct ServerErrorDetail(pub String);
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let detail = self.to_string();
         let (status, code, message) = match self {
             // 分层错误处理
             AppError::Auth(auth_err) => match auth_err {
@@ -147,26 +191,29 @@ impl IntoResponse for AppError {
                 AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "invalid_token", "Token 无效".to_string()),
                 AuthError::UserNotFound => (StatusCode::UNAUTHORIZED, "user_not_found", "用户不存在".to_string()),
                 AuthError::AccountDisabled => (StatusCode::FORBIDDEN, "account_disabled", "账户已被停用".to_string()),
+                AuthError::AccountIpRestricted => (StatusCode::FORBIDDEN, "account_ip_restricted", "当前来源 IP 不在该账户允许的范围内".to_string()),
+                AuthError::IpBlocked => (StatusCode::FORBIDDEN, "ip_blocked", "当前来源 IP 不允许访问本服务".to_string()),
+                AuthError::OutsideAccessHours(allowed) => (StatusCode::FORBIDDEN, "outside_access_hours", format!("当前不在账户允许的访问时段内，允许时段：{}", allowed)),
                 AuthError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "invalid_credentials", "用户名或密码错误".to_string()),
+                AuthError::TotpRequired => (StatusCode::UNAUTHORIZED, "totp_required", "该账户已开启两步验证，请提供 totp_code".to_string()),
+                AuthError::InvalidTotp => (StatusCode::UNAUTHORIZED, "invalid_totp", "两步验证码错误或已使用".to_string()),
+                AuthError::UpstreamOverrideForbidden => (StatusCode::FORBIDDEN, "upstream_override_forbidden", "只有管理员代管的 token 与服务账户可以通过 X-Upstream 选择上游".to_string()),
+                AuthError::LoginLocked { retry_after } => {
+                    let mut error = crate::branding::error_object("login_locked", format!("登录失败次数过多，请 {} 秒后重试", retry_after));
+                    error.insert("retry_after_seconds".to_string(), json!(retry_after));
+                    let headers = [(axum::http::header::RETRY_AFTER, retry_after.to_string())];
+                    return (StatusCode::TOO_MANY_REQUESTS, headers, Json(json!({ "error": error }))).into_response();
+                }
             },
             
             AppError::Quota(quota_err) => match quota_err {
                 QuotaError::Exceeded { used, limit, reset_at } => {
-                    let body = Json(json!({
-                        "error": "quota_exceeded",
-                        "message": "月度配额已耗尽，请升级套餐或等待下月重置",
-                        "details": {
-                            "used": used,
-                            "limit": limit,
-                            "reset_at": reset_at
-                        },
-                        "upgrade_url": "https://your-site.com/upgrade"
-                    }));
+                    let body = Json(crate::branding::quota_exceeded_body(used, limit, reset_at));
                     return (StatusCode::PAYMENT_REQUIRED, body).into_response();
                 },
                 QuotaError::FileReadError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "quota_file_read_error", msg),
                 QuotaError::FileWriteError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "quota_file_write_error", msg),
-                QuotaError::InvalidTier(msg) => (StatusCode::BAD_REQUEST, "invalid_quota_tier", msg),
+                QuotaError::InvalidTier(msg) => (StatusCode::FORBIDDEN, "invalid_quota_tier", msg),
             },
             
             AppError::Upstream(upstream_err) => match upstream_err {
@@ -203,18 +250,17 @@ impl IntoResponse for AppError {
             // 向后兼容的快捷变体
             AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, "unauthorized", msg),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, "bad_request", msg),
+            AppError::WeakPassword(violations) => {
+                let message = violations.iter().map(|v| v.message.as_str()).collect::<Vec<_>>().join("；");
+                let mut error = crate::branding::error_object("weak_password", message);
+                error.insert("violations".to_string(), json!(violations));
+                return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+            }
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, "not_found", msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, "conflict", msg),
+            AppError::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", msg),
             AppError::PaymentRequired { used, limit, reset_at } => {
-                let body = Json(json!({
-                    "error": "quota_exceeded",
-                    "message": "月度配额已耗尽，请升级套餐或等待下月重置",
-                    "details": {
-                        "used": used,
-                        "limit": limit,
-                        "reset_at": reset_at
-                    },
-                    "upgrade_url": "https://your-site.com/upgrade"
-                }));
+                let body = Json(crate::branding::quota_exceeded_body(used, limit, reset_at));
                 return (StatusCode::PAYMENT_REQUIRED, body).into_response();
             }
             AppError::QueueTimeout => (
@@ -222,10 +268,26 @@ impl IntoResponse for AppError {
                 "queue_timeout",
                 "请求排队超时，请等待 2-3 秒后重试".to_string(),
             ),
-            AppError::TooManyRequests => (
-                StatusCode::TOO_MANY_REQUESTS,
-                "too_many_requests",
-                "服务繁忙，请等待 3-5 秒后重试".to_string(),
+            AppError::RequestTimeout => (
+                StatusCode::REQUEST_TIMEOUT,
+                "request_timeout",
+                "请求处理超时，请稍后重试".to_string(),
+            ),
+            AppError::TooManyRequests(rate_limit) => {
+                let body = Json(json!({
+                    "error": crate::branding::error_object("too_many_requests", "服务繁忙，请等待 3-5 秒后重试".to_string())
+                }));
+                return (StatusCode::TOO_MANY_REQUESTS, rate_limit.rejection_headers(), body).into_response();
+            }
+            AppError::Overloaded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server_overloaded",
+                "服务器负载过高，请稍后重试".to_string(),
+            ),
+            AppError::ReadOnly => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "read_only",
+                "服务处于只读模式（灾难恢复中），暂不支持修改数据的操作".to_string(),
             ),
             AppError::GatewayTimeout => (
                 StatusCode::GATEWAY_TIMEOUT,
@@ -237,13 +299,51 @@ impl IntoResponse for AppError {
         };
 
         let body = Json(json!({
-            "error": {
-                "code": code,
-                "message": message
-            }
+            "error": crate::branding::error_object(code, message)
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            response.extensions_mut().insert(ServerErrorDetail(detail));
+        }
+        response
+    }
+}
+
+/// 限流反馈头（与 OpenAI 客户端自适应退避解析的约定一致）
+const X_RATELIMIT_LIMIT: &str = "x-ratelimit-limit";
+const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+const X_RATELIMIT_RESET: &str = "x-ratelimit-reset";
+
+/// 限流器的当前状态，写入 X-RateLimit-* 头：放行时来自全局令牌桶，429 时来自拒绝请求的限流器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// 限流器的额度：令牌桶容量、并发请求数或窗口内允许的重复次数
+    pub limit: u32,
+    /// 本次请求之后的剩余额度，拒绝时为 0
+    pub remaining: u32,
+    /// 放行时为令牌桶补满的秒数，拒绝时为恢复可用的秒数；无法预知（如等待在途请求结束）时为 None
+    pub reset_after: Option<u64>,
+}
+
+impl RateLimit {
+    /// 写入限流反馈头
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(HeaderName::from_static(X_RATELIMIT_LIMIT), self.limit.into());
+        headers.insert(HeaderName::from_static(X_RATELIMIT_REMAINING), self.remaining.into());
+        if let Some(reset_after) = self.reset_after {
+            headers.insert(HeaderName::from_static(X_RATELIMIT_RESET), reset_after.into());
+        }
+    }
+
+    /// 429 响应头：限流反馈头，Reset 已知时同时写入 Retry-After
+    fn rejection_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        self.insert_headers(&mut headers);
+        if let Some(reset_after) = self.reset_after {
+            headers.insert(header::RETRY_AFTER, reset_after.into());
+        }
+        headers
     }
 }
 
@@ -367,7 +467,7 @@ impl AppError {
     /// 创建带上下文的内部错误
     /// 
     /// 使用示例：
-    /// ```
+    /// ```ignore
     /// AppError::internal_with_context("配额保存失败", &err)
     /// ```
     pub fn internal_with_context(context: &str, err: &dyn std::fmt::Display) -> Self {
@@ -382,7 +482,7 @@ impl AppError {
     /// 创建带错误码的内部错误（便于运维查询日志）
     /// 
     /// 使用示例：
-    /// ```
+    /// ```ignore
     /// AppError::internal_with_code("CFG001", "配置文件加载失败")
     /// ```
     pub fn internal_with_code(code: &str, message: &str) -> Self {
@@ -397,7 +497,7 @@ impl AppError {
     /// 从 anyhow::Error 创建带上下文的错误
     /// 
     /// 使用示例：
-    /// ```
+    /// ```ignore
     /// AppError::from_anyhow_with_context("用户文件加载失败", err)
     /// ```
     pub fn from_anyhow_with_context(context: &str, err: anyhow::Error) -> Self {
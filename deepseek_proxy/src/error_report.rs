@@ -0,0 +1,224 @@
+use crate::{config::ObservabilityConfig, error::ServerErrorDetail, AppState};
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+};
+use once_cell::sync::OnceCell;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 全局错误上报器（panic hook 中无法拿到 AppState，因此与 METRICS 一样做成全局）
+static REPORTER: OnceCell<ErrorReporter> = OnceCell::new();
+
+/// Sentry 上报目标（由 DSN 解析）
+#[derive(Debug, Clone, PartialEq)]
+struct SentryTarget {
+    store_url: String,
+    public_key: String,
+}
+
+impl SentryTarget {
+    /// 解析 `scheme://<key>@<host>[/path]/<project_id>`
+    fn from_dsn(dsn: &str) -> Result<Self, String> {
+        let (scheme, rest) = dsn.split_once("://").ok_or("DSN 缺少协议")?;
+        let (public_key, host_path) = rest.split_once('@').ok_or("DSN 缺少公钥")?;
+        let (host_path, project_id) = host_path.rsplit_once('/').ok_or("DSN 缺少项目 ID")?;
+        let public_key = public_key.split(':').next().unwrap_or_default();
+        if public_key.is_empty() || project_id.is_empty() {
+            return Err("DSN 格式错误".to_string());
+        }
+        Ok(Self {
+            store_url: format!("{}://{}/api/{}/store/", scheme, host_path, project_id),
+            public_key: public_key.to_string(),
+        })
+    }
+}
+
+/// 待上报的错误事件
+#[derive(Debug, Clone)]
+pub struct ErrorEvent {
+    /// "error"（500 响应）或 "fatal"（panic）
+    pub level: &'static str,
+    pub message: String,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    /// 已脱敏的用户名
+    pub user: Option<String>,
+}
+
+struct ErrorReporter {
+    client: reqwest::Client,
+    sentry: Option<SentryTarget>,
+    webhook_url: Option<String>,
+    environment: Option<String>,
+}
+
+/// 初始化错误上报并安装 panic hook；未配置任何目标时返回 Ok(false)
+pub fn init(cfg: &ObservabilityConfig) -> anyhow::Result<bool> {
+    let sentry = cfg
+        .sentry_dsn
+        .as_deref()
+        .map(SentryTarget::from_dsn)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("sentry_dsn 配置错误: {}", e))?;
+    if sentry.is_none() && cfg.error_webhook_url.is_none() {
+        return Ok(false);
+    }
+
+    let reporter = ErrorReporter {
+        client: reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(5))
+            .build()?,
+        sentry,
+        webhook_url: cfg.error_webhook_url.clone(),
+        environment: cfg.environment.clone(),
+    };
+    if REPORTER.set(reporter).is_err() {
+        return Ok(true);
+    }
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // 请求内的 panic 由 CatchPanicLayer 转为 500，经 report_server_errors 带请求上下文上报
+        if crate::panic_guard::current_request_id().is_some() {
+            previous(info);
+            return;
+        }
+        let location = info
+            .location()
+            .map(|l| format!(" at {}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        capture(ErrorEvent {
+            level: "fatal",
+            message: format!("panic: {}{}", payload, location),
+            method: None,
+            path: None,
+            user: None,
+        });
+        previous(info);
+    }));
+    Ok(true)
+}
+
+/// 异步上报事件（未初始化或不在 tokio 运行时内时忽略）
+pub fn capture(event: ErrorEvent) {
+    let Some(reporter) = REPORTER.get() else { return };
+    let Ok(handle) = tokio::runtime::Handle::try_current() else { return };
+    handle.spawn(async move {
+        if let Some(target) = &reporter.sentry {
+            let auth = format!(
+                "Sentry sentry_version=7, sentry_key={}, sentry_client=deepseek_proxy/{}",
+                target.public_key,
+                env!("CARGO_PKG_VERSION")
+            );
+            let body = sentry_payload(&event, reporter.environment.as_deref());
+            if let Err(e) = reporter.client.post(&target.store_url).header("X-Sentry-Auth", auth).json(&body).send().await {
+                tracing::warn!(error = %e, "Sentry 上报失败");
+            }
+        }
+        if let Some(url) = &reporter.webhook_url {
+            let body = json!({
+                "event": "server_error",
+                "level": event.level,
+                "message": event.message,
+                "method": event.method,
+                "path": event.path,
+                "user": event.user,
+                "environment": reporter.environment,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+            });
+            if let Err(e) = reporter.client.post(url).json(&body).send().await {
+                tracing::warn!(error = %e, "错误 webhook 上报失败");
+            }
+        }
+    });
+}
+
+/// 中间件：捕获 500 响应并附带请求上下文上报
+pub async fn report_server_errors(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if REPORTER.get().is_none() {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    // 中间件位于认证之外，自行解析 token 以获取用户名
+    let user = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .and_then(|t| state.jwt_service.validate_token(t).ok())
+        .map(|claims| redact_username(&claims.sub));
+
+    let response = next.run(request).await;
+    if let Some(ServerErrorDetail(message)) = response.extensions().get::<ServerErrorDetail>() {
+        capture(ErrorEvent {
+            level: "error",
+            message: message.clone(),
+            method: Some(method),
+            path: Some(path),
+            user,
+        });
+    }
+    response
+}
+
+/// 用户名脱敏：保留首字符，其余替换为 *
+fn redact_username(username: &str) -> String {
+    let mut chars = username.chars();
+    match chars.next() {
+        Some(first) => format!("{}{}", first, "*".repeat(chars.count().max(3))),
+        None => String::new(),
+    }
+}
+
+fn sentry_payload(event: &ErrorEvent, environment: Option<&str>) -> serde_json::Value {
+    json!({
+        "event_id": event_id(),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "level": event.level,
+        "platform": "other",
+        "logger": "deepseek_proxy",
+        "message": { "formatted": event.message },
+        "environment": environment,
+        "request": { "method": event.method, "url": event.path },
+        "user": event.user.as_ref().map(|u| json!({ "username": u })),
+    })
+}
+
+/// 生成 32 位十六进制事件 ID（时间戳 + 进程内序号，足以区分事件）
+fn event_id() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    format!("{:016x}{:016x}", nanos, SEQ.fetch_add(1, Ordering::Relaxed) ^ std::process::id() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sentry_dsn() {
+        let target = SentryTarget::from_dsn("https://abc123@o1.ingest.sentry.io/42").unwrap();
+        assert_eq!(target.store_url, "https://o1.ingest.sentry.io/api/42/store/");
+        assert_eq!(target.public_key, "abc123");
+        assert!(SentryTarget::from_dsn("not-a-dsn").is_err());
+    }
+
+    #[test]
+    fn test_redact_username_and_event_id() {
+        assert_eq!(redact_username("alice"), "a****");
+        assert_eq!(redact_username("bo"), "b***");
+        assert_eq!(event_id().len(), 32);
+        assert_ne!(event_id(), event_id());
+    }
+}
@@ -0,0 +1,199 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// 检查配置文件是否被修改的间隔
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 实验性功能开关（config.toml 的 [flags] 表，默认全部关闭）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// 相同请求复用上游响应
+    ResponseCache,
+    /// 上游首包过慢时再发一次请求，取先返回的结果
+    HedgedRequests,
+    /// 限制流式输出速度
+    OutputThrottle,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [Flag::ResponseCache, Flag::HedgedRequests, Flag::OutputThrottle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::ResponseCache => "response_cache",
+            Flag::HedgedRequests => "hedged_requests",
+            Flag::OutputThrottle => "output_throttle",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Flag::ResponseCache => "相同请求复用上游响应",
+            Flag::HedgedRequests => "上游首包过慢时再发一次请求，取先返回的结果",
+            Flag::OutputThrottle => "限制流式输出速度",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+}
+
+/// 单个开关的当前状态
+#[derive(Debug, Serialize)]
+pub struct FlagStatus {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub description: &'static str,
+}
+
+/// GET /admin/flags 的返回内容
+#[derive(Debug, Serialize)]
+pub struct FlagsReport {
+    pub flags: Vec<FlagStatus>,
+    /// 配置中无法识别的开关名（拼写错误或已移除的开关）
+    pub unknown: Vec<String>,
+    /// 最近一次从配置加载的时间
+    pub loaded_at: String,
+}
+
+struct FlagState {
+    enabled: HashSet<Flag>,
+    unknown: Vec<String>,
+    loaded_at: String,
+}
+
+/// 功能开关：按部署启用实验性行为，修改 config.toml 的 [flags] 后无需重启即可生效
+pub struct FeatureFlags {
+    state: RwLock<FlagState>,
+}
+
+impl FeatureFlags {
+    pub fn new(values: &HashMap<String, bool>) -> Self {
+        let flags = Self {
+            state: RwLock::new(FlagState { enabled: HashSet::new(), unknown: Vec::new(), loaded_at: String::new() }),
+        };
+        flags.apply(values);
+        flags
+    }
+
+    /// 开关是否开启
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).enabled.contains(&flag)
+    }
+
+    pub fn report(&self) -> FlagsReport {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        FlagsReport {
+            flags: Flag::ALL
+                .into_iter()
+                .map(|flag| FlagStatus {
+                    name: flag.name(),
+                    enabled: state.enabled.contains(&flag),
+                    description: flag.description(),
+                })
+                .collect(),
+            unknown: state.unknown.clone(),
+            loaded_at: state.loaded_at.clone(),
+        }
+    }
+
+    /// 以新的配置替换全部开关，记录发生变化的开关
+    fn apply(&self, values: &HashMap<String, bool>) {
+        let mut enabled = HashSet::new();
+        let mut unknown = Vec::new();
+        for (name, on) in values {
+            match Flag::from_name(name) {
+                Some(flag) if *on => {
+                    enabled.insert(flag);
+                }
+                Some(_) => {}
+                None => {
+                    tracing::warn!(flag = %name, "未知的功能开关，已忽略");
+                    unknown.push(name.clone());
+                }
+            }
+        }
+        unknown.sort();
+
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        for flag in Flag::ALL {
+            let on = enabled.contains(&flag);
+            if on != state.enabled.contains(&flag) {
+                tracing::info!(flag = flag.name(), enabled = on, "功能开关已更新");
+            }
+        }
+        *state = FlagState { enabled, unknown, loaded_at: crate::utils::now_beijing_rfc3339() };
+    }
+
+    /// 定期检查配置文件的修改时间，变化时重新加载 [flags]；配置文件解析失败时保留原有开关
+    pub fn spawn_watcher(self: Arc<Self>, path: PathBuf) {
+        crate::supervisor::spawn("flags_watcher", move || {
+            let (flags, path) = (self.clone(), path.clone());
+            async move {
+                let mut last_modified = modified(&path).await;
+                let mut ticker = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    let current = modified(&path).await;
+                    if current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    match load_flags(&path) {
+                        Ok(values) => flags.apply(&values),
+                        Err(e) => tracing::warn!(path = %path.display(), error = %e, "重新加载功能开关失败，保留原有设置"),
+                    }
+                }
+            }
+        });
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok()
+}
+
+/// 从配置文件读取 [flags] 表；没有该表时所有开关关闭
+fn load_flags(path: &Path) -> anyhow::Result<HashMap<String, bool>> {
+    let config = config::Config::builder().add_source(config::File::from(path)).build()?;
+    match config.get::<HashMap<String, bool>>("flags") {
+        Ok(values) => Ok(values),
+        Err(config::ConfigError::NotFound(_)) => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_reload_from_file() {
+        let flags = FeatureFlags::new(&HashMap::from([
+            ("response_cache".to_string(), true),
+            ("hedged_requests".to_string(), false),
+            ("typo_flag".to_string(), true),
+        ]));
+        assert!(flags.is_enabled(Flag::ResponseCache));
+        assert!(!flags.is_enabled(Flag::HedgedRequests));
+        assert_eq!(flags.report().unknown, vec!["typo_flag".to_string()]);
+
+        let dir = std::env::temp_dir().join("test_feature_flags");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[server]\nport = 1\n\n[flags]\noutput_throttle = true\n").unwrap();
+        flags.apply(&load_flags(&path).unwrap());
+        assert!(!flags.is_enabled(Flag::ResponseCache));
+        assert!(flags.is_enabled(Flag::OutputThrottle));
+        assert!(flags.report().unknown.is_empty());
+
+        // 删除 [flags] 表即全部关闭
+        std::fs::write(&path, "[server]\nport = 1\n").unwrap();
+        assert!(load_flags(&path).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// 就绪探针：上游连接预热成功后返回 200，否则返回 503
+///
+/// 未启用预热时始终视为就绪；入站限流处于冷启动保护期时附带剩余秒数。
+/// `background_tasks` 列出受监管的后台任务（panic 后等待重启的任务不影响就绪状态，只在这里与指标中体现）
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let (status, mut body) = if !state.config.deepseek.http_client.warmup {
+        (StatusCode::OK, json!({ "ready": true }))
+    } else {
+        let warmup = state.deepseek_client.last_warmup();
+        let ready = warmup.as_ref().map(|w| w.ok).unwrap_or(false);
+        let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        (status, json!({
+            "ready": ready,
+            "upstream_warmup": warmup,
+        }))
+    };
+
+    if let Some(remaining) = state.global_rate_limiter.cold_start_remaining() {
+        body["rate_limit_cold_start_seconds"] = json!(remaining.as_secs_f64().ceil() as u64);
+    }
+    body["background_tasks"] = json!(crate::supervisor::report());
+    (status, Json(body)).into_response()
+}
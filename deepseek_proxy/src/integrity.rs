@@ -0,0 +1,214 @@
+use crate::{config::User, quota::QuotaState};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// 问题类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// 无法解析的用户/配额文件
+    Unparseable,
+    /// 文件名与记录中的 username 不一致
+    UsernameMismatch,
+    /// 配额文件没有对应用户
+    OrphanedQuota,
+    /// 原子写入残留的 .tmp 文件
+    TempLeftover,
+}
+
+/// 单个问题文件
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityIssue {
+    pub path: String,
+    pub kind: IssueKind,
+    pub detail: String,
+    /// 已移动到隔离目录时为隔离后的路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantined_to: Option<String>,
+}
+
+/// 数据目录检查报告
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub checked_at: String,
+    pub user_files: usize,
+    pub quota_files: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+/// 扫描 data/users 与 data/quotas
+///
+/// known_users 为已加载的用户（含从配置导入的用户），用于判断配额文件是否孤立；
+/// 匿名化擦除留下的假名配额文件不算孤立
+pub async fn scan(data_dir: &Path, known_users: &HashSet<String>) -> IntegrityReport {
+    let mut report = IntegrityReport {
+        checked_at: crate::utils::now_beijing_rfc3339(),
+        user_files: 0,
+        quota_files: 0,
+        issues: Vec::new(),
+    };
+
+    for (path, ext) in list_files(&data_dir.join("users")).await {
+        match ext.as_str() {
+            "toml" => {
+                report.user_files += 1;
+                let parsed = tokio::fs::read_to_string(&path)
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| toml::from_str::<User>(&content).map_err(|e| e.to_string()));
+                check_record(&mut report, &path, parsed.map(|u| u.username));
+            }
+            "tmp" => push(&mut report, &path, IssueKind::TempLeftover, "写入中断留下的临时文件".to_string()),
+            _ => {}
+        }
+    }
+
+    for (path, ext) in list_files(&data_dir.join("quotas")).await {
+        match ext.as_str() {
+            "json" => {
+                report.quota_files += 1;
+                let parsed = tokio::fs::read_to_string(&path)
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| serde_json::from_str::<QuotaState>(&content).map_err(|e| e.to_string()));
+                let stem = file_stem(&path);
+                if check_record(&mut report, &path, parsed.map(|q| q.username))
+                    && !known_users.contains(&stem)
+                    && !stem.starts_with(crate::admin::PSEUDONYM_PREFIX)
+                {
+                    push(&mut report, &path, IssueKind::OrphanedQuota, format!("用户 {} 不存在", stem));
+                }
+            }
+            "tmp" => push(&mut report, &path, IssueKind::TempLeftover, "写入中断留下的临时文件".to_string()),
+            _ => {}
+        }
+    }
+
+    report
+}
+
+/// 将问题文件移动到 data/quarantine/{时间}/ 下（保留 users/quotas 子目录）
+pub async fn quarantine(data_dir: &Path, report: &mut IntegrityReport) {
+    if report.issues.is_empty() {
+        return;
+    }
+    let target_root = data_dir
+        .join("quarantine")
+        .join(crate::utils::now_beijing().format("%Y%m%d-%H%M%S").to_string());
+
+    for issue in &mut report.issues {
+        let source = PathBuf::from(&issue.path);
+        let Ok(relative) = source.strip_prefix(data_dir) else { continue };
+        let target = target_root.join(relative);
+        if let Some(parent) = target.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                tracing::error!(error = %e, "创建隔离目录失败");
+                return;
+            }
+        }
+        match tokio::fs::rename(&source, &target).await {
+            Ok(()) => issue.quarantined_to = Some(target.display().to_string()),
+            Err(e) => tracing::warn!(path = %issue.path, error = %e, "隔离问题文件失败"),
+        }
+    }
+}
+
+/// 输出结构化启动摘要
+pub fn log_summary(report: &IntegrityReport) {
+    tracing::info!(
+        user_files = report.user_files,
+        quota_files = report.quota_files,
+        issues = report.issues.len(),
+        "数据目录完整性检查完成"
+    );
+    for issue in &report.issues {
+        tracing::warn!(
+            path = %issue.path,
+            kind = ?issue.kind,
+            quarantined_to = ?issue.quarantined_to,
+            "数据文件异常: {}",
+            issue.detail
+        );
+    }
+}
+
+/// 检查记录是否可解析、username 是否与文件名一致，返回记录是否正常
+fn check_record(report: &mut IntegrityReport, path: &Path, parsed: Result<String, String>) -> bool {
+    match parsed {
+        Err(e) => {
+            push(report, path, IssueKind::Unparseable, e);
+            false
+        }
+        Ok(username) if username != file_stem(path) => {
+            push(report, path, IssueKind::UsernameMismatch, format!("文件中的 username 为 {}", username));
+            false
+        }
+        Ok(_) => true,
+    }
+}
+
+fn push(report: &mut IntegrityReport, path: &Path, kind: IssueKind, detail: String) {
+    report.issues.push(IntegrityIssue {
+        path: path.display().to_string(),
+        kind,
+        detail,
+        quarantined_to: None,
+    });
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string()
+}
+
+/// 列出目录下的文件及扩展名（目录不存在时为空），按路径排序
+async fn list_files(dir: &Path) -> Vec<(PathBuf, String)> {
+    let mut files = Vec::new();
+    let Ok(mut read_dir) = tokio::fs::read_dir(dir).await else { return files };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_string();
+        files.push((path, ext));
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scan_and_quarantine() {
+        let dir = std::env::temp_dir().join("test_integrity_scan");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("users")).await.unwrap();
+        tokio::fs::create_dir_all(dir.join("quotas")).await.unwrap();
+
+        let quota = |name: &str| format!(
+            r#"{{"username":"{}","tier":"basic","monthly_limit":10,"used_count":0,"last_saved_count":0,"reset_at":"2026-01-01T00:00:00+08:00"}}"#,
+            name
+        );
+        tokio::fs::write(dir.join("users/alice.toml"), "username = \"alice\"\npassword = \"x\"\n").await.unwrap();
+        tokio::fs::write(dir.join("users/bob.toml"), "not = [valid").await.unwrap();
+        tokio::fs::write(dir.join("quotas/alice.json"), quota("alice")).await.unwrap();
+        tokio::fs::write(dir.join("quotas/ghost.json"), quota("ghost")).await.unwrap();
+        tokio::fs::write(dir.join("quotas/erased-0123456789ab.json"), quota("erased-0123456789ab")).await.unwrap();
+        tokio::fs::write(dir.join("quotas/alice.tmp"), "{").await.unwrap();
+
+        let known: HashSet<String> = ["alice".to_string()].into();
+        let mut report = scan(&dir, &known).await;
+        let kinds: Vec<IssueKind> = report.issues.iter().map(|i| i.kind).collect();
+        assert_eq!(kinds, vec![IssueKind::Unparseable, IssueKind::TempLeftover, IssueKind::OrphanedQuota]);
+
+        quarantine(&dir, &mut report).await;
+        assert!(report.issues.iter().all(|i| i.quarantined_to.is_some()));
+        assert!(!dir.join("quotas/ghost.json").exists());
+        assert!(scan(&dir, &known).await.issues.is_empty());
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
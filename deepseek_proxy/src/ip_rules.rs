@@ -0,0 +1,152 @@
+//! 全局 IP 允许 / 拒绝名单（`[security.ip_rules]`），在路由之前按来源 IP 放行或拦截
+//!
+//! 拒绝名单优先；允许名单非空时只放行名单内的地址。本机地址始终放行，避免规则写错后连管理接口也无法访问。
+//! 通过管理接口修改的规则保存在 data/ip_rules.json，重启后优先于配置文件
+
+use crate::config::IpRulesConfig;
+use crate::error::{AppError, AuthError};
+use crate::listener::ListenerInfo;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// 被拦截的原因（ip_rule_rejections_total 的 reason 标签）
+pub const REJECT_REASONS: [&str; 2] = ["denylist", "not_allowlisted"];
+
+struct CompiledRules {
+    source: IpRulesConfig,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl CompiledRules {
+    fn compile(source: IpRulesConfig) -> Result<Self, String> {
+        let parse = |cidrs: &[String]| {
+            cidrs
+                .iter()
+                .map(|cidr| crate::client_ip::parse_net(cidr).ok_or_else(|| format!("无效的 IP 或 CIDR: {}", cidr)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let allow = parse(&source.allow)?;
+        let deny = parse(&source.deny)?;
+        Ok(Self { source, allow, deny })
+    }
+}
+
+pub struct IpRules {
+    path: PathBuf,
+    rules: RwLock<CompiledRules>,
+}
+
+impl IpRules {
+    /// 加载规则：data/ip_rules.json 存在且有效时使用其中的规则，否则使用配置文件；配置中的 CIDR 无效时返回错误
+    pub async fn load(cfg: &IpRulesConfig, path: PathBuf) -> anyhow::Result<Self> {
+        let mut rules = CompiledRules::compile(cfg.clone()).map_err(|e| anyhow::anyhow!("security.ip_rules 配置无效: {}", e))?;
+        if let Ok(content) = tokio::fs::read_to_string(&path).await {
+            match serde_json::from_str::<IpRulesConfig>(&content).map_err(|e| e.to_string()).and_then(CompiledRules::compile) {
+                Ok(saved) => {
+                    tracing::info!(path = %path.display(), "使用管理接口保存的 IP 规则（覆盖配置文件中的 security.ip_rules）");
+                    rules = saved;
+                }
+                Err(e) => tracing::warn!(path = %path.display(), error = %e, "IP 规则文件无效，使用配置文件中的规则"),
+            }
+        }
+        if !rules.allow.is_empty() || !rules.deny.is_empty() {
+            tracing::info!("全局 IP 规则: 允许 {:?}，拒绝 {:?}", rules.source.allow, rules.source.deny);
+        }
+        Ok(Self { path, rules: RwLock::new(rules) })
+    }
+
+    /// 当前生效的规则
+    pub fn rules(&self) -> IpRulesConfig {
+        self.rules.read().unwrap().source.clone()
+    }
+
+    /// 替换规则并保存到 data/ip_rules.json，立即对新请求生效
+    pub async fn update(&self, source: IpRulesConfig) -> Result<(), AppError> {
+        let compiled = CompiledRules::compile(source.clone()).map_err(AppError::BadRequest)?;
+        crate::utils::write_json_atomic(&self.path, &source).await.map_err(|e| {
+            crate::metrics::METRICS.record_persist_failure("ip_rules");
+            AppError::InternalError(format!("写入 IP 规则失败: {}", e))
+        })?;
+        *self.rules.write().unwrap() = compiled;
+        tracing::info!(allow = ?source.allow, deny = ?source.deny, "全局 IP 规则已更新");
+        Ok(())
+    }
+
+    /// 检查来源 IP，被拦截时返回原因
+    pub fn check(&self, ip: IpAddr) -> Result<(), &'static str> {
+        let ip = ip.to_canonical();
+        if ip.is_loopback() {
+            return Ok(());
+        }
+        let rules = self.rules.read().unwrap();
+        if rules.deny.iter().any(|net| net.contains(&ip)) {
+            return Err("denylist");
+        }
+        if !rules.allow.is_empty() && !rules.allow.iter().any(|net| net.contains(&ip)) {
+            return Err("not_allowlisted");
+        }
+        Ok(())
+    }
+}
+
+/// 中间件：按全局 IP 规则拦截请求（在受信任代理解析出客户端 IP 之后执行；取不到来源 IP 时不拦截）
+///
+/// 内部监听的 Unix socket 只有本机有权限的进程能连接，不受 IP 规则限制
+pub async fn enforce(State(rules): State<Arc<IpRules>>, request: Request, next: Next) -> Response {
+    if request.extensions().get::<ListenerInfo>().is_some_and(|listener| listener.unix && listener.internal) {
+        return next.run(request).await;
+    }
+    if let Some(ip) = crate::client_ip::peer_ip(request.extensions()) {
+        if let Err(reason) = rules.check(ip) {
+            crate::metrics::METRICS.record_ip_rule_rejection(reason);
+            tracing::warn!(ip = %ip, reason, path = %request.uri().path(), "来源 IP 被全局 IP 规则拦截");
+            return AppError::Auth(AuthError::IpBlocked).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allow: &[&str], deny: &[&str]) -> IpRulesConfig {
+        IpRulesConfig {
+            allow: allow.iter().map(|c| c.to_string()).collect(),
+            deny: deny.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rules_check_update_and_reload() {
+        let path = std::env::temp_dir().join("test_ip_rules.json");
+        let _ = tokio::fs::remove_file(&path).await;
+        assert!(IpRules::load(&config(&["10.0.0.0/33"], &[]), path.clone()).await.is_err());
+
+        let rules = IpRules::load(&config(&["10.0.0.0/8"], &["10.1.0.0/16"]), path.clone()).await.unwrap();
+        assert_eq!(rules.check("10.2.3.4".parse().unwrap()), Ok(()));
+        assert_eq!(rules.check("10.1.2.3".parse().unwrap()), Err("denylist"));
+        assert_eq!(rules.check("203.0.113.1".parse().unwrap()), Err("not_allowlisted"));
+        // 本机地址（含 IPv4 映射形式）始终放行
+        assert_eq!(rules.check("127.0.0.1".parse().unwrap()), Ok(()));
+        assert_eq!(rules.check("::ffff:127.0.0.1".parse().unwrap()), Ok(()));
+
+        assert!(matches!(rules.update(config(&[], &["bogus"])).await, Err(AppError::BadRequest(_))));
+        rules.update(config(&[], &["203.0.113.0/24"])).await.unwrap();
+        assert_eq!(rules.check("10.1.2.3".parse().unwrap()), Ok(()));
+        assert_eq!(rules.check("203.0.113.1".parse().unwrap()), Err("denylist"));
+
+        // 保存的规则在重启后优先于配置文件
+        let reloaded = IpRules::load(&IpRulesConfig::default(), path.clone()).await.unwrap();
+        assert_eq!(reloaded.rules(), config(&[], &["203.0.113.0/24"]));
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -0,0 +1,50 @@
+//! DeepSeek 代理服务
+//!
+//! 各模块以库的形式提供，`main.rs` 负责加载配置、组装并启动服务；基准测试等也通过库访问内部组件
+
+pub mod access_log;
+pub mod activity_schema;
+pub mod admin;
+pub mod app;
+pub mod auth;
+pub mod backup;
+pub mod branding;
+pub mod chaos;
+pub mod chatops;
+pub mod client_ip;
+pub mod conditional;
+pub mod config;
+pub mod deepseek;
+pub mod disk_health;
+pub mod downloads;
+pub mod effective_config;
+pub mod error;
+pub mod error_report;
+pub mod flags;
+pub mod health;
+pub mod integrity;
+pub mod ip_rules;
+pub mod listener;
+pub mod load_shed;
+pub mod logger;
+pub mod metrics;
+pub mod notify;
+pub mod object_storage;
+pub mod panic_guard;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod qr;
+pub mod quota;
+pub mod read_only;
+pub mod reconcile;
+pub mod routes;
+pub mod shutdown_report;
+pub mod slo;
+pub mod statsd;
+pub mod supervisor;
+pub mod tail_sampling;
+pub mod tls;
+pub mod user_activity;
+pub mod utils;
+
+pub use app::{build_app, AppBuilder, AppState, Routers};
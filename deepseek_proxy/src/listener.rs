@@ -0,0 +1,375 @@
+use crate::config::{ServerConfig, TlsConfig};
+use axum::{extract::ConnectInfo, Extension, Router};
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// TLS 握手 / PROXY protocol 头部读取超时，防止慢速客户端占用连接
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Unix socket 地址前缀，例如 `unix:/run/proxy.sock`
+const UNIX_PREFIX: &str = "unix:";
+
+/// 服务监听地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// TCP 地址（host:port）
+    Tcp(String),
+    /// Unix domain socket 文件路径
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// 从配置解析监听地址
+    ///
+    /// 优先使用 `server.listen`，未配置时回退到 `server.host:server.port`
+    pub fn from_config(server: &ServerConfig) -> anyhow::Result<Self> {
+        match server.listen.as_deref().map(str::trim) {
+            Some(listen) if !listen.is_empty() => Self::parse("server.listen", listen),
+            // IPv6 地址（如 "::"）需要加方括号
+            _ if server.host.contains(':') && !server.host.starts_with('[') => {
+                Ok(ListenAddr::Tcp(format!("[{}]:{}", server.host, server.port)))
+            }
+            _ => Ok(ListenAddr::Tcp(format!("{}:{}", server.host, server.port))),
+        }
+    }
+
+    /// 解析内部监听地址（`server.internal_listen`），未配置时返回 None
+    pub fn internal_from_config(server: &ServerConfig) -> anyhow::Result<Option<Self>> {
+        match server.internal_listen.as_deref().map(str::trim) {
+            Some(listen) if !listen.is_empty() => Self::parse("server.internal_listen", listen).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse(field: &str, listen: &str) -> anyhow::Result<Self> {
+        if let Some(path) = listen.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                anyhow::bail!("{} 缺少 Unix socket 路径: {}", field, listen);
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        Ok(ListenAddr::Tcp(listen.to_string()))
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "http://{}", addr),
+            ListenAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Unix socket 连接的占位对端地址（未指定地址，不视为 localhost）
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// 请求所经的监听，由监听器注入每个请求的 extensions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListenerInfo {
+    /// `server.internal_listen` 的监听
+    pub internal: bool,
+    /// Unix socket 连接：没有对端 IP，`ConnectInfo` 为 [`UNIX_PEER`]
+    pub unix: bool,
+    /// 该监听开启了 mTLS（`server.tls.client_ca`），管理接口要求 admin 客户端证书
+    pub mtls: bool,
+}
+
+/// 监听选项（TLS、PROXY protocol、IPv6 选项只对 TCP 生效）
+#[derive(Clone, Copy, Default)]
+pub struct TcpOptions<'a> {
+    /// 启用 HTTPS（配置 client_ca 时为 mTLS）
+    pub tls: Option<&'a TlsConfig>,
+    /// 连接以 PROXY protocol 头部开始（部署在四层负载均衡之后）
+    pub proxy_protocol: bool,
+    /// 监听 IPv6 地址时只接受 IPv6 连接；默认 false 即双栈
+    pub ipv6_only: bool,
+    /// 内部监听（`server.internal_listen`）
+    pub internal: bool,
+}
+
+/// 在指定地址提供服务
+pub async fn serve<F>(addr: ListenAddr, app: Router, options: TcpOptions<'_>, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match addr {
+        ListenAddr::Tcp(addr) => serve_tcp(&addr, app, options, shutdown).await,
+        ListenAddr::Unix(path) => serve_unix(&path, app, options.internal, shutdown).await,
+    }
+}
+
+/// 在 Unix domain socket 上提供服务
+///
+/// Unix socket 没有对端 IP：`ConnectInfo` 为 [`UNIX_PEER`]（不视为 localhost），并标记 [`ListenerInfo::unix`]。
+/// `[server.trusted_proxies]` 包含 `"unix"` 时按转发头识别客户端 IP（nginx 等反向代理在前）；
+/// 公开 socket 上的管理接口需要签名，内部 socket（`internal_listen`）由文件权限控制访问
+#[cfg(unix)]
+pub async fn serve_unix<F>(path: &std::path::Path, app: Router, internal: bool, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::server::graceful::GracefulShutdown;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::FileTypeExt;
+
+    // 清理上次异常退出遗留的 socket 文件；同名的其他文件不动
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => anyhow::bail!("{} 已存在且不是 socket 文件", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+
+    let app = app
+        .layer(Extension(ConnectInfo(UNIX_PEER)))
+        .layer(Extension(ListenerInfo { internal, unix: true, mtls: false }));
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Unix socket 接受连接失败");
+                        continue;
+                    }
+                };
+                let service = TowerToHyperService::new(app.clone());
+                let conn = builder
+                    .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                    .into_owned();
+                let conn = graceful.watch(conn);
+                tokio::spawn(async move {
+                    if let Err(e) = conn.await {
+                        tracing::debug!(error = %e, "Unix socket 连接处理结束");
+                    }
+                });
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    // 停止接受新连接，等待在途请求完成
+    drop(listener);
+    graceful.shutdown().await;
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
+
+/// 绑定 TCP 地址；IPv6 地址显式设置 IPV6_V6ONLY，不依赖系统默认值
+async fn bind_tcp(addr: &str, ipv6_only: bool) -> anyhow::Result<tokio::net::TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let addr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("无法解析监听地址: {}", addr))?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
+/// 在 TCP 上提供 HTTP / HTTPS 服务
+///
+/// 每个连接注入客户端地址 `ConnectInfo`（启用 PROXY protocol 时取头部中的源地址，
+/// 双栈监听下的 IPv4 映射地址还原为 IPv4）；客户端出示证书时额外注入 `ClientCertIdentity`
+async fn serve_tcp<F>(addr: &str, app: Router, options: TcpOptions<'_>, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    use hyper_util::rt::TokioExecutor;
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::server::graceful::GracefulShutdown;
+
+    let acceptor = options.tls.map(crate::tls::build_acceptor).transpose()?;
+    let identities = Arc::new(options.tls.map(|cfg| cfg.client_identities.clone()).unwrap_or_default());
+    let proxy_protocol = options.proxy_protocol;
+    let info = ListenerInfo {
+        internal: options.internal,
+        unix: false,
+        mtls: options.tls.is_some_and(|cfg| cfg.client_ca.is_some()),
+    };
+    let listener = bind_tcp(addr, options.ipv6_only).await?;
+
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut stream, mut peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "TCP 接受连接失败");
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let identities = identities.clone();
+                let app = app.clone();
+                let builder = builder.clone();
+                let watcher = graceful.watcher();
+                tokio::spawn(async move {
+                    if proxy_protocol {
+                        match tokio::time::timeout(HANDSHAKE_TIMEOUT, crate::proxy_protocol::read_header(&mut stream)).await {
+                            Ok(Ok(Some(client))) => peer = client,
+                            Ok(Ok(None)) => {}
+                            Ok(Err(e)) => {
+                                tracing::debug!(peer = %peer, error = %e, "PROXY protocol 头部无效");
+                                return;
+                            }
+                            Err(_) => {
+                                tracing::debug!(peer = %peer, "PROXY protocol 头部读取超时");
+                                return;
+                            }
+                        }
+                    }
+                    let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+
+                    let Some(acceptor) = acceptor else {
+                        serve_connection(stream, app, peer, info, None, builder, watcher).await;
+                        return;
+                    };
+                    let tls_stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(s)) => s,
+                        Ok(Err(e)) => {
+                            tracing::debug!(peer = %peer, error = %e, "TLS 握手失败");
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::debug!(peer = %peer, "TLS 握手超时");
+                            return;
+                        }
+                    };
+                    let identity = tls_stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certs| certs.first())
+                        .and_then(|cert| crate::tls::identity_from_cert(cert, &identities));
+                    if let Some(identity) = &identity {
+                        tracing::debug!(peer = %peer, cn = %identity.cn, "客户端证书认证通过");
+                    }
+                    serve_connection(tls_stream, app, peer, info, identity, builder, watcher).await;
+                });
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// 处理单个连接，向每个请求注入客户端地址、所经监听与证书身份
+async fn serve_connection<I>(
+    io: I,
+    app: Router,
+    peer: SocketAddr,
+    info: ListenerInfo,
+    identity: Option<crate::tls::ClientCertIdentity>,
+    builder: hyper_util::server::conn::auto::Builder<hyper_util::rt::TokioExecutor>,
+    watcher: hyper_util::server::graceful::Watcher,
+) where
+    I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    use hyper_util::rt::TokioIo;
+    use hyper_util::service::TowerToHyperService;
+    use tower::ServiceExt;
+
+    let service = app.map_request(move |mut request: axum::http::Request<_>| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request.extensions_mut().insert(info);
+        if let Some(identity) = &identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        request
+    });
+    let conn = builder
+        .serve_connection_with_upgrades(TokioIo::new(io), TowerToHyperService::new(service))
+        .into_owned();
+    if let Err(e) = watcher.watch(conn).await {
+        tracing::debug!(peer = %peer, error = %e, "连接处理结束");
+    }
+}
+
+/// 非 Unix 平台不支持 Unix domain socket
+#[cfg(not(unix))]
+pub async fn serve_unix<F>(path: &std::path::Path, _app: Router, _internal: bool, _shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    anyhow::bail!("当前平台不支持 Unix domain socket: {}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(listen: Option<&str>) -> ServerConfig {
+        ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 8877,
+            listen: listen.map(|s| s.to_string()),
+            internal_listen: None,
+            ipv6_only: false,
+            proxy_protocol: false,
+            trusted_proxies: Default::default(),
+            tls: None,
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_listen_addr_defaults_to_host_port() {
+        let addr = ListenAddr::from_config(&server(None)).unwrap();
+        assert_eq!(addr, ListenAddr::Tcp("0.0.0.0:8877".to_string()));
+    }
+
+    #[test]
+    fn test_listen_addr_unix() {
+        let addr = ListenAddr::from_config(&server(Some("unix:/run/proxy.sock"))).unwrap();
+        assert_eq!(addr, ListenAddr::Unix(PathBuf::from("/run/proxy.sock")));
+        assert_eq!(addr.to_string(), "unix:/run/proxy.sock");
+    }
+
+    #[test]
+    fn test_listen_addr_unix_requires_path() {
+        assert!(ListenAddr::from_config(&server(Some("unix:"))).is_err());
+    }
+
+    #[test]
+    fn test_internal_listen_addr() {
+        let mut cfg = server(None);
+        assert_eq!(ListenAddr::internal_from_config(&cfg).unwrap(), None);
+        cfg.internal_listen = Some("127.0.0.1:9877".to_string());
+        assert_eq!(
+            ListenAddr::internal_from_config(&cfg).unwrap(),
+            Some(ListenAddr::Tcp("127.0.0.1:9877".to_string()))
+        );
+        cfg.internal_listen = Some("unix:".to_string());
+        assert!(ListenAddr::internal_from_config(&cfg).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::config::LoadSheddingConfig;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 恢复阈值系数：过载后需降到阈值的 90% 以下才解除，避免在阈值附近反复切换
+const RECOVERY_RATIO: f64 = 0.9;
+/// /proc/self/stat 中 CPU 时间的单位（Linux 上 USER_HZ 固定为 100）
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// 一次采样得到的系统压力
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pressure {
+    pub rss_mb: Option<u64>,
+    pub cpu_percent: Option<f64>,
+    pub queue_depth: usize,
+}
+
+/// 负载降级器：后台定期采样进程内存、CPU 与 tokio 队列深度，过载时拒绝低档次请求
+pub struct LoadShedder {
+    cfg: LoadSheddingConfig,
+    overloaded: AtomicBool,
+}
+
+impl LoadShedder {
+    pub fn new(cfg: LoadSheddingConfig) -> Self {
+        Self {
+            cfg,
+            overloaded: AtomicBool::new(false),
+        }
+    }
+
+    /// 当前是否应拒绝该档次的请求（用户不存在时按最低档次处理）
+    pub fn should_shed(&self, tier: Option<&str>) -> bool {
+        if !self.cfg.enabled || !self.overloaded.load(Ordering::Relaxed) {
+            return false;
+        }
+        match tier {
+            Some(tier) => self.cfg.shed_tiers.iter().any(|t| t == tier),
+            None => true,
+        }
+    }
+
+    /// 启动后台采样任务（未启用时不启动）
+    pub fn spawn_monitor(self: &Arc<Self>) {
+        if !self.cfg.enabled {
+            return;
+        }
+        let shedder = self.clone();
+        crate::supervisor::spawn("load_shed_monitor", move || {
+            let shedder = shedder.clone();
+            async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(shedder.cfg.check_interval_seconds.max(1)));
+                let mut cpu = CpuSampler::default();
+                loop {
+                    ticker.tick().await;
+                    let pressure = Pressure {
+                        rss_mb: read_rss_mb(),
+                        cpu_percent: cpu.sample(),
+                        queue_depth: tokio::runtime::Handle::current().metrics().global_queue_depth(),
+                    };
+                    shedder.update(&pressure);
+                }
+            }
+        });
+    }
+
+    /// 根据采样结果更新过载状态，状态变化时记录日志
+    fn update(&self, pressure: &Pressure) {
+        let was_overloaded = self.overloaded.load(Ordering::Relaxed);
+        let ratio = if was_overloaded { RECOVERY_RATIO } else { 1.0 };
+        let reason = exceeded(&self.cfg, pressure, ratio);
+        let now_overloaded = reason.is_some();
+        if now_overloaded == was_overloaded {
+            return;
+        }
+
+        self.overloaded.store(now_overloaded, Ordering::Relaxed);
+        crate::metrics::METRICS.load_shedding_active.set(now_overloaded as i64);
+        match reason {
+            Some(reason) => tracing::warn!(
+                reason,
+                rss_mb = ?pressure.rss_mb,
+                cpu_percent = ?pressure.cpu_percent,
+                queue_depth = pressure.queue_depth,
+                tiers = ?self.cfg.shed_tiers,
+                "系统压力过高，开始拒绝低档次请求"
+            ),
+            None => tracing::info!(
+                rss_mb = ?pressure.rss_mb,
+                cpu_percent = ?pressure.cpu_percent,
+                queue_depth = pressure.queue_depth,
+                "系统压力恢复，停止降级"
+            ),
+        }
+    }
+}
+
+/// 返回第一个超过阈值（乘以 ratio）的指标名
+fn exceeded(cfg: &LoadSheddingConfig, pressure: &Pressure, ratio: f64) -> Option<&'static str> {
+    if let (Some(max), Some(rss)) = (cfg.max_rss_mb, pressure.rss_mb) {
+        if rss as f64 >= max as f64 * ratio {
+            return Some("memory");
+        }
+    }
+    if let (Some(max), Some(cpu)) = (cfg.max_cpu_percent, pressure.cpu_percent) {
+        if cpu >= max * ratio {
+            return Some("cpu");
+        }
+    }
+    if let Some(max) = cfg.max_queue_depth {
+        if pressure.queue_depth as f64 >= max as f64 * ratio {
+            return Some("queue_depth");
+        }
+    }
+    None
+}
+
+/// 两次采样之间的进程 CPU 使用率
+#[derive(Default)]
+struct CpuSampler {
+    last: Option<(u64, Instant)>,
+}
+
+impl CpuSampler {
+    fn sample(&mut self) -> Option<f64> {
+        let ticks = std::fs::read_to_string("/proc/self/stat").ok().and_then(|s| parse_cpu_ticks(&s))?;
+        let now = Instant::now();
+        let percent = self.last.map(|(last_ticks, last_at)| {
+            let elapsed = now.duration_since(last_at).as_secs_f64().max(f64::EPSILON);
+            (ticks.saturating_sub(last_ticks) as f64 / CLOCK_TICKS_PER_SECOND) / elapsed * 100.0
+        });
+        self.last = Some((ticks, now));
+        percent
+    }
+}
+
+/// 读取进程常驻内存（非 Linux 返回 None）
+fn read_rss_mb() -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status").ok().and_then(|s| parse_vm_rss_kb(&s)).map(|kb| kb / 1024)
+}
+
+/// 解析 /proc/self/status 中的 VmRSS（kB）
+fn parse_vm_rss_kb(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|v| v.parse().ok())
+}
+
+/// 解析 /proc/self/stat 中的 utime + stime（进程名可能含空格与括号，从最后一个 ')' 之后开始计数）
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // ')' 之后第一个字段是第 3 列（state），utime/stime 为第 14/15 列
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LoadSheddingConfig {
+        LoadSheddingConfig {
+            enabled: true,
+            max_rss_mb: Some(800),
+            ..LoadSheddingConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_sheds_only_configured_tiers_with_hysteresis() {
+        let shedder = LoadShedder::new(config());
+        shedder.update(&Pressure { rss_mb: Some(820), ..Pressure::default() });
+        assert!(shedder.should_shed(Some("basic")));
+        assert!(!shedder.should_shed(Some("premium")));
+
+        // 降到阈值以下但未低于 90%，仍保持降级
+        shedder.update(&Pressure { rss_mb: Some(750), ..Pressure::default() });
+        assert!(shedder.should_shed(Some("basic")));
+        shedder.update(&Pressure { rss_mb: Some(700), ..Pressure::default() });
+        assert!(!shedder.should_shed(Some("basic")));
+    }
+
+    #[test]
+    fn test_parse_proc_files() {
+        assert_eq!(parse_vm_rss_kb("Name:\tproxy\nVmRSS:\t  123456 kB\n"), Some(123456));
+        let stat = "42 (deepseek (proxy)) S 1 42 42 0 -1 4194560 100 0 0 0 250 50 0 0 20 0 8";
+        assert_eq!(parse_cpu_ticks(stat), Some(300));
+    }
+}
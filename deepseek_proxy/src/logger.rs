@@ -1,8 +1,24 @@
 use std::path::Path;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use std::sync::Arc;
+use tracing_appender::rolling::{RollingFileAppender, RollingWriter};
+use tracing_subscriber::{
+    filter::filter_fn, fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
 use anyhow::Result;
 
+/// 文件 appender 的共享句柄：普通文件层与尾部采样层写入同一日志文件
+#[derive(Clone)]
+struct SharedAppender(Arc<RollingFileAppender>);
+
+impl<'a> MakeWriter<'a> for SharedAppender {
+    type Writer = RollingWriter<'a>;
+    fn make_writer(&'a self) -> Self::Writer {
+        self.0.make_writer()
+    }
+}
+
 /// 日志配置
+#[derive(Clone)]
 pub struct LoggerConfig {
     /// 日志目录
     pub log_dir: String,
@@ -43,7 +59,7 @@ pub fn init_logger(config: LoggerConfig) -> Result<()> {
     );
 
     // 创建文件 appender，使用每日滚动策略
-    let file_appender = tracing_appender::rolling::daily(&config.log_dir, &config.file_prefix);
+    let file_appender = SharedAppender(Arc::new(tracing_appender::rolling::daily(&config.log_dir, &config.file_prefix)));
     
     // 创建非阻塞写入器（避免日志 IO 阻塞主线程）
     // 注意：不能使用 non_blocking，因为 guard 会被立即丢弃
@@ -54,12 +70,19 @@ pub fn init_logger(config: LoggerConfig) -> Result<()> {
         .unwrap_or_else(|_| "deepseek_proxy=debug,tower_http=debug,axum=debug".into());
 
     // 文件输出层（普通文本格式，便于查看）
+    // 启用尾部采样后，debug 事件不直接写入文件，由采样层在请求结束时决定是否写出
     let file_layer = tracing_subscriber::fmt::layer()
-        .with_writer(file_appender)
+        .with_writer(file_appender.clone())
         .with_timer(timer.clone())
         .with_ansi(false) // 文件中不使用颜色代码
         .with_target(true)
-        .with_thread_ids(true);
+        .with_thread_ids(true)
+        .with_filter(filter_fn(crate::tail_sampling::file_filter));
+
+    let tail_layer = crate::tail_sampling::TailSamplingLayer::new(
+        crate::tail_sampling::SETTINGS.clone(),
+        file_appender,
+    );
 
     // 控制台输出层（人类可读格式）
     let console_layer = tracing_subscriber::fmt::layer()
@@ -71,11 +94,12 @@ pub fn init_logger(config: LoggerConfig) -> Result<()> {
     tracing_subscriber::registry()
         .with(env_filter)
         .with(file_layer)
+        .with(tail_layer)
         .with(console_layer)
         .init();
 
     // 启动后台任务来管理日志文件大小
-    tokio::spawn(log_rotation_task(config));
+    crate::supervisor::spawn("log_rotation", move || log_rotation_task(config.clone()));
 
     Ok(())
 }
@@ -126,7 +150,7 @@ async fn manage_log_files(config: &LoggerConfig) -> Result<()> {
     }
 
     // 按修改时间排序（最新的在前）
-    target_files.sort_by(|a, b| b.2.cmp(&a.2));
+    target_files.sort_by_key(|f| std::cmp::Reverse(f.2));
 
     let mut total_size = 0u64;
     let mut files_to_delete = Vec::new();
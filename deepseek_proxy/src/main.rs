@@ -1,49 +1,34 @@
-mod admin;
-mod auth;
-mod config;
-mod error;
-mod deepseek;
-mod logger;
-mod proxy;
-mod quota;
-mod user_activity;
-mod utils;
-mod metrics;
-
-use auth::{login, auth_middleware, JwtService};
-use axum::{
-    middleware,
-    routing::post,
-    Router,
+use deepseek_proxy::{
+    config::Config,
+    effective_config,
+    listener::{self, ListenAddr},
+    logger, metrics, panic_guard,
+    quota::QuotaManager,
+    read_only,
+    shutdown_report::ShutdownReporter,
+    tail_sampling, AppBuilder, AppState, Routers,
 };
-use config::Config;
-use deepseek::DeepSeekClient;
-use proxy::{proxy_chat, LoginLimiter, GlobalRateLimiter};
-use quota::QuotaManager;
-use user_activity::UserActivityLogger;
-use auth::bruteforce::BruteForceGuard;
-use std::path::PathBuf;
+use std::future::Future;
 use std::sync::Arc;
-use tower_http::trace::TraceLayer;
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
 
-// 统一的应用状态
-#[derive(Clone)]
-pub struct AppState {
-    pub config: Arc<Config>,
-    pub jwt_service: Arc<JwtService>,
-    pub deepseek_client: Arc<DeepSeekClient>,
-    pub login_limiter: Arc<LoginLimiter>, // 现在统一管理Token生命周期和并发控制
-    pub quota_manager: Arc<QuotaManager>,
-    pub user_manager: Arc<auth::UserManager>, // 用户管理器（内存+持久化）
-    pub global_rate_limiter: Arc<GlobalRateLimiter>, // 全局速率限制器
-    pub activity_logger: Arc<UserActivityLogger>, // 用户行为日志记录器
-    pub brute_force_guard: Arc<BruteForceGuard>, // 登录失败检测
+fn main() -> anyhow::Result<()> {
+    // 由 Windows 服务控制管理器启动（sc.exe create ... binPath= "...\deepseek_proxy.exe --service"）
+    #[cfg(windows)]
+    if std::env::args().skip(1).any(|arg| arg == "--service") {
+        return service::run();
+    }
+    // 输出配置文件的 JSON Schema 后退出，不读取配置、不启动服务
+    if std::env::args().skip(1).any(|arg| arg == "--print-config-schema") {
+        println!("{}", serde_json::to_string_pretty(&Config::json_schema())?);
+        return Ok(());
+    }
+    tokio::runtime::Runtime::new()?.block_on(serve(wait_for_signal()))
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+/// 启动服务，直到 stop 完成后保存数据并优雅关闭
+async fn serve(stop: impl Future<Output = ()> + Send + 'static) -> anyhow::Result<()> {
     // 初始化日志系统（自动滚动，最大 10MB/文件，保留 5 个文件）
     logger::init_logger(logger::LoggerConfig {
         log_dir: "logs".to_string(),
@@ -52,203 +37,242 @@ async fn main() -> anyhow::Result<()> {
         max_files: 5,
     })?;
     
+    panic_guard::install_panic_hook();
+
     tracing::info!("========================================");
     tracing::info!("DeepSeek Proxy 服务启动");
     tracing::info!("========================================");
 
     // 加载配置
-    let config = Config::load()?;
+    let mut config = Config::load()?;
     tracing::info!("配置加载成功");
-    tracing::info!("服务器地址: {}:{}", config.server.host, config.server.port);
-    tracing::info!("DeepSeek API: {}", config.deepseek.base_url);
-    tracing::info!("限流: 每个 token 同时只允许1个请求");
-    
-    // 安全限制：登录缓存和 JWT TTL 最多 60 秒，防止 token 长时间有效
-    let effective_ttl = config.auth.token_ttl_seconds.min(60);
-    if config.auth.token_ttl_seconds > 60 {
-        tracing::warn!(
-            "配置的 token_ttl_seconds ({}) 超过安全限制，已强制限制为 60 秒",
-            config.auth.token_ttl_seconds
-        );
-    }
-    tracing::info!("登录缓存: 每个用户 {} 秒内复用同一 token", effective_ttl);
-    tracing::info!("JWT有效期: {} 秒", effective_ttl);
-    
-    tracing::info!("HTTP客户端: 连接池={}个, 保活={}秒, 连接超时={}秒", 
-        config.deepseek.http_client.pool_max_idle_per_host,
-        config.deepseek.http_client.pool_idle_timeout_seconds,
-        config.deepseek.http_client.connect_timeout_seconds
-    );
-
-    // 初始化组件
-    // 加载今日指标快照（如果存在）
-    if let Err(e) = metrics::METRICS.load_today() {
-        tracing::warn!("加载今日指标快照失败: {}", e);
-    } else {
-        tracing::info!("今日指标快照加载完成");
-    }
-    // 清理超过 90 天的历史指标文件
-    if let Err(e) = metrics::METRICS.cleanup_old_days(90) {
-        tracing::warn!("清理指标历史文件失败: {}", e);
-    }
-    let jwt_service = Arc::new(JwtService::new(
-        config.auth.jwt_secret.clone(),
-        effective_ttl,  // 使用安全限制后的 TTL
-    ).map_err(|e| anyhow::anyhow!("JWT服务初始化失败: {}", e))?);
-
-    let deepseek_client = Arc::new(DeepSeekClient::new(
-        config.deepseek.api_key.clone(),
-        config.deepseek.base_url.clone(),
-        config.deepseek.timeout_seconds,
-        &config.deepseek.http_client,
-    ).map_err(|e| anyhow::anyhow!("DeepSeek客户端初始化失败: {}", e))?);
-
-    let login_limiter = Arc::new(LoginLimiter::new(effective_ttl));  // 使用安全限制后的 TTL
-
-    // 初始化用户管理器（基于文件存储）- 必须在配额管理器之前
-    let users_dir = PathBuf::from("data/users");
-    let user_manager = Arc::new(
-        auth::UserManager::new(users_dir, config.auth.users.clone())
-            .await
-            .map_err(|e| anyhow::anyhow!("用户管理器初始化失败: {}", e))?
-    );
-    tracing::info!("用户管理器初始化完成，用户数据存储在 data/users/");
-
-    // 初始化配额管理器（需要 user_manager 来查询动态用户）
-    let data_dir = PathBuf::from("data/quotas");
-    tokio::fs::create_dir_all(&data_dir).await?;
-    let config_arc = Arc::new(config.clone());
-    let quota_manager = Arc::new(QuotaManager::new(
-        config_arc,
-        user_manager.clone(),
-        data_dir,
-        config.quota.save_interval,
-    ));
-
-    tracing::info!("配额: 每 {} 次请求写一次磁盘", config.quota.save_interval);
-
-    // 初始化全局速率限制器
-    let global_rate_limiter = Arc::new(GlobalRateLimiter::new(config.rate_limit.requests_per_second));
-    tracing::info!("全局速率限制: {}", global_rate_limiter.info());
-
-    // 初始化用户行为日志记录器
-    let activity_logger = Arc::new(UserActivityLogger::new("logs/users"));
-    tracing::info!("用户行为日志: logs/users/");
-    let brute_force_guard = Arc::new(BruteForceGuard::new(config.security.clone()));
-
-    let config = Arc::new(config);
-
-    // 创建统一的应用状态
-    let app_state = AppState {
-        config: config.clone(),
-        jwt_service,
-        deepseek_client,
-        login_limiter, // 统一管理Token生命周期和并发控制
-        quota_manager: quota_manager.clone(),
-        user_manager,
-        global_rate_limiter,
-        activity_logger,
-        brute_force_guard,
-    };
+    if std::env::args().skip(1).any(|arg| arg == "--read-only") {
+        config.server.read_only = true;
+        config.sources.overrides.insert("server.read_only".to_string(), effective_config::SOURCE_CLI);
+    }
+    effective_config::log_startup(&config);
+    tail_sampling::configure(&config.observability.tail_sampling);
+    let listen_addr = ListenAddr::from_config(&config.server)?;
+    tracing::info!("服务器地址: {}", listen_addr);
+    let internal_addr = ListenAddr::internal_from_config(&config.server)?;
+    if let Some(addr) = &internal_addr {
+        tracing::info!("内部地址: {}（/admin、/probe、/metrics 只在此提供）", addr);
+    }
+    if !config.server.trusted_proxies.cidrs.is_empty() {
+        tracing::info!("受信任代理: {:?}，按 X-Forwarded-For / X-Real-IP 识别客户端 IP", config.server.trusted_proxies.cidrs);
+    }
+    let app_state = AppState::new(config).await?;
+    let config = app_state.config.clone();
+    let quota_manager = app_state.quota_manager.clone();
+    let activity_logger = app_state.activity_logger.clone();
+    let charge_journal = app_state.charge_journal.clone();
+    let brute_force_guard = app_state.brute_force_guard.clone();
+    // 检查上次运行是否正常关闭，并标记本次运行开始
+    let reporter = Arc::new(ShutdownReporter::start("data").await);
+    let Routers { public: app, internal } = AppBuilder::new(app_state)
+        .separate_internal(internal_addr.is_some())
+        .build()?;
+    let internal = internal_addr.zip(internal);
 
-    // 构建路由
-    // 公开路由（无需认证）
-    let public_routes = Router::new()
-        .route("/auth/login", post(login))
-        .route("/metrics", axum::routing::get(|| async {
-            use axum::{response::IntoResponse, http::StatusCode};
-            match metrics::METRICS.render() {
-                Ok(body) => (
-                    StatusCode::OK,
-                    [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
-                    body
-                ).into_response(),
-                Err(e) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("metrics render error: {}", e)
-                ).into_response(),
-            }
-        }));
+    // 启动服务器
+    tracing::info!("🚀 DeepSeek 代理服务启动成功: {}", listen_addr);
+    tracing::info!("📝 登录接口: POST {}/auth/login", listen_addr);
+    tracing::info!("🔄 代理接口: POST {}/chat/completions", listen_addr);
+    match &internal {
+        Some((addr, _)) => tracing::info!("🔧 管理接口: POST {}/admin/users/{{username}}/active (内部地址)", addr),
+        None => tracing::info!("🔧 管理接口: POST {}/admin/users/{{username}}/active (仅localhost)", listen_addr),
+    }
+    if config.server.proxy_protocol {
+        tracing::info!("PROXY protocol 已启用：客户端 IP 取自负载均衡器转发的头部");
+    }
+    if let Some(tls_config) = &config.server.tls {
+        if matches!(listen_addr, ListenAddr::Tcp(_)) {
+            tracing::info!(
+                "HTTPS 已启用{}",
+                if tls_config.client_ca.is_some() { "（mTLS 客户端证书认证）" } else { "" }
+            );
+        }
+    }
 
-    // 受保护路由（需要 Token）
-    let protected_routes = Router::new()
-        .route("/chat/completions", post(proxy_chat))
-        .layer(middleware::from_fn_with_state(
-            app_state.clone(),
-            auth_middleware,
-        ));
-
-    // 管理路由（只允许 localhost 访问）
-    let admin_routes = Router::new()
-        .route("/admin/users/:username/active", post(admin::set_user_active))
-        .route("/admin/users/:username", axum::routing::get(admin::get_user))
-        .route("/admin/users",
-            axum::routing::get(admin::list_users)
-                .post(admin::create_user)
-        )
-        .layer(middleware::from_fn(admin::localhost_only))
-        .with_state(app_state.clone());
-
-    // 合并路由
-    let app = public_routes
-        .merge(protected_routes)
-        .merge(admin_routes)
-        .with_state(app_state)
-        .layer(TraceLayer::new_for_http());
+    // 优雅关闭处理：收到信号（或 Windows 服务停止请求）后保存数据，并通知所有监听停止接受新连接
+    let shutdown = tokio_util::sync::CancellationToken::new();
+    let stop_task = {
+        let shutdown = shutdown.clone();
+        let reporter = reporter.clone();
+        tokio::spawn(async move {
+            stop.await;
+            let drain = reporter.begin_drain();
+            let quota_flush = save_on_shutdown(quota_manager).await;
+            shutdown.cancel();
+            (drain, quota_flush)
+        })
+    };
+    let public_options = listener::TcpOptions {
+        tls: config.server.tls.as_ref(),
+        proxy_protocol: config.server.proxy_protocol,
+        ipv6_only: config.server.ipv6_only,
+        ..Default::default()
+    };
+    let public_server = listener::serve(listen_addr, app, public_options, shutdown.clone().cancelled_owned());
+    match internal {
+        Some((addr, internal_app)) => {
+            let internal_options = listener::TcpOptions { internal: true, ipv6_only: config.server.ipv6_only, ..Default::default() };
+            let internal_server = listener::serve(addr, internal_app, internal_options, shutdown.cancelled_owned());
+            tokio::try_join!(public_server, internal_server)?;
+        }
+        None => public_server.await?,
+    }
 
-    // 启动服务器
-    let addr = format!("{}:{}", config.server.host, config.server.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-
-    tracing::info!("🚀 DeepSeek 代理服务启动成功: http://{}", addr);
-    tracing::info!("📝 登录接口: POST http://{}/auth/login", addr);
-    tracing::info!("🔄 代理接口: POST http://{}/chat/completions", addr);
-    tracing::info!("🔧 管理接口: POST http://{}/admin/users/{{username}}/active (仅localhost)", addr);
-
-    // 优雅关闭处理
-    let quota_manager_shutdown = quota_manager.clone();
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<std::net::SocketAddr>()
-    )
-        .with_graceful_shutdown(shutdown_signal(quota_manager_shutdown))
-        .await?;
+    // 在途请求已全部结束：写出剩余行为日志并输出关闭报告
+    let (drain, quota_flush) = stop_task.await?;
+    // 响应均已结束，其扣费记录已注销；注销后的写入可能还没来得及执行
+    charge_journal.persist().await;
+    brute_force_guard.persist().await;
+    reporter.finish(drain, quota_flush, &activity_logger).await;
 
     Ok(())
 }
 
-/// 优雅关闭信号处理
-async fn shutdown_signal(quota_manager: Arc<QuotaManager>) {
-    // 同时监听 Ctrl+C 与 SIGTERM (unix)
+/// 等待关闭信号：Ctrl+C；Unix 上另有 SIGTERM，Windows 控制台另有 Ctrl+Break、关闭窗口与系统关机
+async fn wait_for_signal() {
     #[cfg(unix)]
-    let mut term_stream = signal(SignalKind::terminate()).expect("无法监听 SIGTERM");
+    {
+        let mut term_stream = signal(SignalKind::terminate()).expect("无法监听 SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => { println!("\n🔻 收到 Ctrl+C，开始优雅关闭..."); }
+            _ = term_stream.recv() => { println!("\n🔻 收到 SIGTERM，开始优雅关闭..."); }
+        };
+    }
 
-    #[cfg(unix)]
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => { println!("\n🔻 收到 Ctrl+C，开始优雅关闭..."); }
-        _ = term_stream.recv() => { println!("\n🔻 收到 SIGTERM，开始优雅关闭..."); }
-    };
+    // 关闭控制台窗口或系统关机时，Windows 只给几秒时间，足够保存配额与指标快照
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_break, ctrl_close, ctrl_shutdown};
+        let mut break_stream = ctrl_break().expect("无法监听 Ctrl+Break");
+        let mut close_stream = ctrl_close().expect("无法监听控制台关闭事件");
+        let mut shutdown_stream = ctrl_shutdown().expect("无法监听系统关机事件");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => { println!("\n🔻 收到 Ctrl+C，开始优雅关闭..."); }
+            _ = break_stream.recv() => { println!("\n🔻 收到 Ctrl+Break，开始优雅关闭..."); }
+            _ = close_stream.recv() => { println!("\n🔻 控制台窗口关闭，开始优雅关闭..."); }
+            _ = shutdown_stream.recv() => { println!("\n🔻 系统关机，开始优雅关闭..."); }
+        };
+    }
 
-    #[cfg(not(unix))]
+    #[cfg(not(any(unix, windows)))]
     {
         if let Err(e) = tokio::signal::ctrl_c().await {
             eprintln!("无法监听 Ctrl+C 信号: {}", e);
-            return;
+            std::future::pending::<()>().await;
         }
         println!("\n🔻 收到 Ctrl+C，开始优雅关闭...");
     }
-    
+}
+
+/// 关闭前保存配额数据与今日指标快照，返回保存的配额条数
+async fn save_on_shutdown(quota_manager: Arc<QuotaManager>) -> Result<usize, String> {
+    if read_only::is_enabled() {
+        println!("\n📦 只读模式：不保存配额数据与指标快照");
+        return Ok(0);
+    }
+
     println!("\n📦 正在保存配额数据...");
     
-    if let Err(e) = quota_manager.save_all().await {
-        eprintln!("❌ 保存失败: {}", e);
-    } else {
-        println!("✅ 数据已保存");
+    let result = quota_manager.save_all().await.map_err(|e| e.to_string());
+    match &result {
+        Ok(_) => println!("✅ 数据已保存"),
+        Err(e) => eprintln!("❌ 保存失败: {}", e),
     }
 
     println!("📝 正在保存今日指标快照...");
-    match crate::metrics::METRICS.save_today() {
+    match metrics::METRICS.save_today() {
         Ok(()) => println!("✅ 指标快照已保存"),
         Err(e) => eprintln!("❌ 指标保存失败: {}", e),
     }
+    result
+}
+
+/// Windows 服务模式（`deepseek_proxy --service`）
+///
+/// 服务控制管理器发出停止或系统关机通知时，与控制台的 Ctrl+C 走同一条路径：
+/// 先保存配额与指标快照，再停止接受新连接并等待进行中的请求结束
+#[cfg(windows)]
+mod service {
+    use std::ffi::OsString;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+    use windows_service::{
+        define_windows_service,
+        service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    /// 服务名（sc.exe create 时使用同一名称）
+    const SERVICE_NAME: &str = "deepseek_proxy";
+    /// 报告"停止中"时告知服务控制管理器的预计耗时
+    const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// 把当前线程交给服务控制管理器，直到服务停止
+    pub fn run() -> anyhow::Result<()> {
+        // 服务的工作目录默认是 System32；config.toml、data/ 与 logs/ 均相对于可执行文件所在目录
+        if let Some(dir) = std::env::current_exe()?.parent() {
+            std::env::set_current_dir(dir)?;
+        }
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            tracing::error!(error = %e, "Windows 服务异常退出");
+        }
+    }
+
+    fn run_service() -> anyhow::Result<()> {
+        let stop = CancellationToken::new();
+        let stop_requested = stop.clone();
+        let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                stop_requested.cancel();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+        set_state(status, ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+
+        let result = tokio::runtime::Runtime::new()?.block_on(super::serve(async move {
+            stop.cancelled().await;
+            tracing::info!("收到 Windows 服务停止请求，开始优雅关闭");
+            if let Err(e) = set_state(status, ServiceState::StopPending, ServiceExitCode::NO_ERROR) {
+                tracing::warn!(error = %e, "报告服务停止中状态失败");
+            }
+        }));
+
+        let exit_code = match &result {
+            Ok(()) => ServiceExitCode::NO_ERROR,
+            Err(_) => ServiceExitCode::ServiceSpecific(1),
+        };
+        set_state(status, ServiceState::Stopped, exit_code)?;
+        result
+    }
+
+    fn set_state(status: ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> windows_service::Result<()> {
+        let controls_accepted = match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        };
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: if state == ServiceState::StopPending { STOP_WAIT_HINT } else { Duration::default() },
+            process_id: None,
+        })
+    }
 }
@@ -1,5 +1,5 @@
 use once_cell::sync::Lazy;
-use prometheus::{Registry, Counter, CounterVec, Histogram, HistogramOpts, TextEncoder, Encoder, IntGauge};
+use prometheus::{Registry, Counter, CounterVec, Gauge, Histogram, HistogramOpts, HistogramVec, TextEncoder, Encoder, IntGauge, IntGaugeVec};
 use std::time::Instant;
 use std::sync::Mutex;
 use chrono::{Local};
@@ -7,6 +7,28 @@ use serde::{Serialize, Deserialize};
 use std::path::PathBuf;
 use std::fs;
 use anyhow::Result;
+use std::collections::HashMap;
+
+/// 限流拒绝原因（rate_limit_rejections_total 的 reason 标签）
+///
+/// queue_full / queue_timeout / per_ip 预留给排队与按 IP 限流，启动时即以 0 值导出
+pub const RATE_LIMIT_REASONS: [&str; 5] = ["global_bucket", "per_user_permit", "queue_full", "queue_timeout", "per_ip"];
+
+/// 上游首包延迟直方图的区间上界（秒），/admin/slo 按同样的区间估算 p95
+pub const UPSTREAM_LATENCY_BUCKETS: [f64; 7] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0];
+
+/// 请求从到达到获得并发许可的等待时间直方图的区间上界（秒）
+pub const PERMIT_WAIT_BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// 出站限流排队时间直方图的区间上界（秒）
+pub const OUTBOUND_WAIT_BUCKETS: [f64; 7] = [0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// 用量对账结果（usage_reconciliations_total 的 result 标签）
+pub const RECONCILE_RESULTS: [&str; 4] = ["ok", "drift", "skipped", "error"];
+
+/// 持久化写入失败的数据类型（persist_failures_total 的 kind 标签）
+pub const PERSIST_KINDS: [&str; 8] =
+    ["quota", "user", "activity_log", "refresh_token", "token_revocation", "charge_journal", "ip_rules", "login_attempts"];
 
 #[derive(Serialize, Deserialize)]
 struct DailySnapshot {
@@ -15,6 +37,9 @@ struct DailySnapshot {
     login_fail: u64,
     login_bruteforce_blocked: u64,
     rate_limit_rejections: u64,
+    /// 按原因拆分的限流拒绝数（旧快照没有该字段，只有总数）
+    #[serde(default)]
+    rate_limit_rejections_by_reason: HashMap<String, u64>,
     chat_success: u64,
     chat_fail: u64,
     today_input_tokens: i64,
@@ -24,15 +49,54 @@ struct DailySnapshot {
     updated_at: String,
 }
 
+/// 一天的 token 用量（取自每日快照）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DayTokens {
+    pub input: u64,
+    pub output: u64,
+    pub cache_hit: u64,
+    pub cache_miss: u64,
+}
+
 pub struct Metrics {
     pub registry: Registry,
     pub login_attempts: CounterVec,
     pub login_bruteforce_blocked: Counter,
-    pub rate_limit_rejections: Counter,
+    pub sessions_evicted: Counter,
+    pub rate_limit_rejections: CounterVec,
     pub quota_status: CounterVec,
     pub upstream_latency: Histogram,
     pub upstream_errors: CounterVec,
     pub chat_requests: CounterVec,
+    pub audio_requests: CounterVec,
+    pub request_body_bytes: Histogram,
+    pub response_body_bytes: Histogram,
+    pub response_truncated: Counter,
+    pub chat_aborted: Counter,
+    pub activity_log_dropped: Counter,
+    pub spam_suspected: Counter,
+    pub disk_free_bytes: IntGaugeVec,
+    pub persist_failures: CounterVec,
+    pub probe_runs: CounterVec,
+    pub context_compressions: CounterVec,
+    pub load_shed_rejections: CounterVec,
+    pub chaos_injections: CounterVec,
+    pub backups: CounterVec,
+    pub load_shedding_active: IntGauge,
+    pub permit_wait: HistogramVec,
+    pub permits_held: IntGaugeVec,
+    pub outbound_wait: HistogramVec,
+    pub outbound_throttled: CounterVec,
+    pub usage_reconciliations: CounterVec,
+    pub usage_drift_ratio: Gauge,
+    pub quota_topup_grants: CounterVec,
+    pub json_output_invalid: Counter,
+    pub users_expired: Counter,
+    pub ip_rule_rejections: CounterVec,
+    pub background_task_restarts: CounterVec,
+    pub background_task_up: IntGaugeVec,
+    pub duplicate_system_prompts: CounterVec,
+    pub duplicate_system_prompt_tokens: CounterVec,
     // 今日 token 消耗 (粗略估算) - input/output
     pub today_input_tokens: IntGauge,
     pub today_output_tokens: IntGauge,
@@ -57,8 +121,14 @@ impl Metrics {
         let login_bruteforce_blocked = Counter::new("login_bruteforce_blocked_total", "Blocked brute force logins").unwrap();
         registry.register(Box::new(login_bruteforce_blocked.clone())).unwrap();
 
-        let rate_limit_rejections = Counter::new("rate_limit_rejections_total", "Requests rejected by rate limiter").unwrap();
+        let rate_limit_rejections = CounterVec::new(
+            prometheus::Opts::new("rate_limit_rejections_total", "Requests rejected by rate limiter grouped by reason"),
+            &["reason"],
+        ).unwrap();
         registry.register(Box::new(rate_limit_rejections.clone())).unwrap();
+        for reason in RATE_LIMIT_REASONS {
+            rate_limit_rejections.with_label_values(&[reason]);
+        }
 
         let quota_status = CounterVec::new(
             prometheus::Opts::new("quota_checks_total", "Quota check results"),
@@ -69,7 +139,7 @@ impl Metrics {
         let upstream_latency = Histogram::with_opts(HistogramOpts::new(
             "upstream_latency_seconds",
             "Latency of upstream (DeepSeek) requests",
-        ).buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0])).unwrap();
+        ).buckets(UPSTREAM_LATENCY_BUCKETS.to_vec())).unwrap();
         registry.register(Box::new(upstream_latency.clone())).unwrap();
 
         let upstream_errors = CounterVec::new(
@@ -84,6 +154,168 @@ impl Metrics {
         ).unwrap();
         registry.register(Box::new(chat_requests.clone())).unwrap();
 
+        let audio_requests = CounterVec::new(
+            prometheus::Opts::new("audio_requests_total", "Speech requests grouped by endpoint and status"),
+            &["endpoint", "status"],
+        ).unwrap();
+        registry.register(Box::new(audio_requests.clone())).unwrap();
+
+        // 请求/响应体大小分布
+        let size_buckets = vec![1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0];
+        let request_body_bytes = Histogram::with_opts(HistogramOpts::new(
+            "request_body_bytes",
+            "Size of chat request bodies in bytes",
+        ).buckets(size_buckets.clone())).unwrap();
+        registry.register(Box::new(request_body_bytes.clone())).unwrap();
+
+        let response_body_bytes = Histogram::with_opts(HistogramOpts::new(
+            "response_body_bytes",
+            "Size of streamed chat responses in bytes",
+        ).buckets(size_buckets)).unwrap();
+        registry.register(Box::new(response_body_bytes.clone())).unwrap();
+
+        let response_truncated = Counter::new("response_truncated_total", "Streams terminated by per-tier response byte ceiling").unwrap();
+        registry.register(Box::new(response_truncated.clone())).unwrap();
+
+        let chat_aborted = Counter::new("chat_aborted_total", "Chat streams cancelled by the client before completion").unwrap();
+        registry.register(Box::new(chat_aborted.clone())).unwrap();
+
+        let activity_log_dropped = Counter::new("activity_log_dropped_total", "Activity log records dropped because the buffer was full").unwrap();
+        registry.register(Box::new(activity_log_dropped.clone())).unwrap();
+        let sessions_evicted = Counter::new("sessions_evicted_total", "Login sessions evicted because the account exceeded its concurrent session cap").unwrap();
+        registry.register(Box::new(sessions_evicted.clone())).unwrap();
+        let spam_suspected = Counter::new("spam_suspected_total", "Chat requests rejected as duplicate-prompt spam").unwrap();
+        registry.register(Box::new(spam_suspected.clone())).unwrap();
+        let disk_free_bytes = IntGaugeVec::new(
+            prometheus::Opts::new("disk_free_bytes", "Free bytes on the file system holding each data directory"),
+            &["dir"],
+        ).unwrap();
+        registry.register(Box::new(disk_free_bytes.clone())).unwrap();
+        let persist_failures = CounterVec::new(
+            prometheus::Opts::new("persist_failures_total", "Failed writes of quota, user and activity log files"),
+            &["kind"],
+        ).unwrap();
+        registry.register(Box::new(persist_failures.clone())).unwrap();
+        for kind in PERSIST_KINDS {
+            persist_failures.with_label_values(&[kind]);
+        }
+
+        let probe_runs = CounterVec::new(
+            prometheus::Opts::new("probe_runs_total", "Synthetic probe runs grouped by result"),
+            &["result"],
+        ).unwrap();
+        registry.register(Box::new(probe_runs.clone())).unwrap();
+
+        let context_compressions = CounterVec::new(
+            prometheus::Opts::new("context_compressions_total", "History compression attempts grouped by result"),
+            &["result"],
+        ).unwrap();
+        registry.register(Box::new(context_compressions.clone())).unwrap();
+
+        let load_shed_rejections = CounterVec::new(
+            prometheus::Opts::new("load_shed_rejections_total", "Requests rejected by load shedding grouped by tier"),
+            &["tier"],
+        ).unwrap();
+        registry.register(Box::new(load_shed_rejections.clone())).unwrap();
+
+        let chaos_injections = CounterVec::new(
+            prometheus::Opts::new("chaos_injections_total", "Faults injected into upstream calls by [chaos] grouped by kind"),
+            &["kind"],
+        ).unwrap();
+        registry.register(Box::new(chaos_injections.clone())).unwrap();
+        for kind in crate::chaos::CHAOS_KINDS {
+            chaos_injections.with_label_values(&[kind]);
+        }
+
+        let backups = CounterVec::new(
+            prometheus::Opts::new("backups_total", "Data directory backups grouped by result"),
+            &["result"],
+        ).unwrap();
+        registry.register(Box::new(backups.clone())).unwrap();
+
+        let load_shedding_active = IntGauge::new("load_shedding_active", "1 while system pressure is above load shedding thresholds").unwrap();
+        registry.register(Box::new(load_shedding_active.clone())).unwrap();
+
+        // 按档次的公平性：付费档次的准入等待应更短
+        let permit_wait = HistogramVec::new(
+            HistogramOpts::new(
+                "permit_wait_seconds",
+                "Time from request arrival to acquiring the per-user permit, grouped by tier",
+            ).buckets(PERMIT_WAIT_BUCKETS.to_vec()),
+            &["tier"],
+        ).unwrap();
+        registry.register(Box::new(permit_wait.clone())).unwrap();
+
+        let permits_held = IntGaugeVec::new(
+            prometheus::Opts::new("permits_held", "Per-user permits currently held by in-flight requests, grouped by tier"),
+            &["tier"],
+        ).unwrap();
+        registry.register(Box::new(permits_held.clone())).unwrap();
+
+        // 出站限流（按上游密钥指纹）
+        let outbound_wait = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_outbound_wait_seconds",
+                "Time spent queueing for an outbound upstream rate limit token, grouped by upstream key fingerprint",
+            ).buckets(OUTBOUND_WAIT_BUCKETS.to_vec()),
+            &["key"],
+        ).unwrap();
+        registry.register(Box::new(outbound_wait.clone())).unwrap();
+
+        let outbound_throttled = CounterVec::new(
+            prometheus::Opts::new("upstream_outbound_throttled_total", "Upstream calls rejected because the outbound rate limit queue wait exceeded max_wait_ms"),
+            &["key"],
+        ).unwrap();
+        registry.register(Box::new(outbound_throttled.clone())).unwrap();
+
+        let usage_reconciliations = CounterVec::new(
+            prometheus::Opts::new("usage_reconciliations_total", "Nightly usage reconciliations against the upstream balance grouped by result"),
+            &["result"],
+        ).unwrap();
+        registry.register(Box::new(usage_reconciliations.clone())).unwrap();
+        for result in RECONCILE_RESULTS {
+            usage_reconciliations.with_label_values(&[result]);
+        }
+        let usage_drift_ratio = Gauge::new("usage_drift_ratio", "(upstream spend - estimated cost) / upstream spend of the last reconciled day").unwrap();
+        registry.register(Box::new(usage_drift_ratio.clone())).unwrap();
+        let quota_topup_grants = CounterVec::new(
+            prometheus::Opts::new("quota_topup_grants_total", "Scheduled quota top-ups granted to users grouped by schedule name"),
+            &["schedule"],
+        ).unwrap();
+        registry.register(Box::new(quota_topup_grants.clone())).unwrap();
+        let json_output_invalid = Counter::new("json_output_invalid_total", "JSON mode responses whose assembled output failed to parse as JSON").unwrap();
+        registry.register(Box::new(json_output_invalid.clone())).unwrap();
+        let users_expired = Counter::new("users_expired_total", "Users deactivated and archived after the configured number of days without login").unwrap();
+        registry.register(Box::new(users_expired.clone())).unwrap();
+        let ip_rule_rejections = CounterVec::new(
+            prometheus::Opts::new("ip_rule_rejections_total", "Requests rejected by the global IP allow/deny rules grouped by reason"),
+            &["reason"],
+        ).unwrap();
+        registry.register(Box::new(ip_rule_rejections.clone())).unwrap();
+        for reason in crate::ip_rules::REJECT_REASONS {
+            ip_rule_rejections.with_label_values(&[reason]);
+        }
+        let background_task_restarts = CounterVec::new(
+            prometheus::Opts::new("background_task_restarts_total", "Background task restarts after a panic grouped by task"),
+            &["task"],
+        ).unwrap();
+        registry.register(Box::new(background_task_restarts.clone())).unwrap();
+        let background_task_up = IntGaugeVec::new(
+            prometheus::Opts::new("background_task_up", "1 while a supervised background task is running, 0 while it waits to be restarted"),
+            &["task"],
+        ).unwrap();
+        registry.register(Box::new(background_task_up.clone())).unwrap();
+        let duplicate_system_prompts = CounterVec::new(
+            prometheus::Opts::new("duplicate_system_prompts_total", "Chat requests repeating the previous request's large system prompt grouped by whether it leads the messages"),
+            &["aligned"],
+        ).unwrap();
+        registry.register(Box::new(duplicate_system_prompts.clone())).unwrap();
+        let duplicate_system_prompt_tokens = CounterVec::new(
+            prometheus::Opts::new("duplicate_system_prompt_tokens_total", "Estimated tokens of repeated system prompts; aligned=false cannot hit the upstream prefix cache"),
+            &["aligned"],
+        ).unwrap();
+        registry.register(Box::new(duplicate_system_prompt_tokens.clone())).unwrap();
+
         // 今日 input/output token 统计 (Gauge 可重置)
         let today_input_tokens = IntGauge::new("today_input_tokens", "Estimated input tokens consumed today").unwrap();
         registry.register(Box::new(today_input_tokens.clone())).unwrap();
@@ -101,11 +333,41 @@ impl Metrics {
             registry,
             login_attempts,
             login_bruteforce_blocked,
+            sessions_evicted,
             rate_limit_rejections,
             quota_status,
             upstream_latency,
             upstream_errors,
             chat_requests,
+            audio_requests,
+            request_body_bytes,
+            response_body_bytes,
+            response_truncated,
+            chat_aborted,
+            activity_log_dropped,
+            spam_suspected,
+            disk_free_bytes,
+            persist_failures,
+            probe_runs,
+            context_compressions,
+            load_shed_rejections,
+            chaos_injections,
+            backups,
+            load_shedding_active,
+            permit_wait,
+            permits_held,
+            outbound_wait,
+            outbound_throttled,
+            usage_reconciliations,
+            usage_drift_ratio,
+            quota_topup_grants,
+            json_output_invalid,
+            users_expired,
+            ip_rule_rejections,
+            background_task_restarts,
+            background_task_up,
+            duplicate_system_prompts,
+            duplicate_system_prompt_tokens,
             today_input_tokens,
             today_output_tokens,
             today_prompt_cache_hit_tokens,
@@ -123,10 +385,14 @@ impl Metrics {
         String::from_utf8(buffer).map_err(|e| e.to_string())
     }
 
-    fn rollover_if_needed(&self) {
+    /// 跨天时先保存前一天的快照（供用量对账读取），再重置今日 gauge
+    pub fn rollover_if_needed(&self) {
         let today = Local::now().format("%Y-%m-%d").to_string();
         let mut guard = self.current_day.lock().unwrap();
         if *guard != today {
+            if let Err(e) = self.write_snapshot(&guard) {
+                tracing::warn!(day = %guard, error = %e, "保存前一天的指标快照失败");
+            }
             // 新的一天，重置 gauge
             self.today_input_tokens.set(0);
             self.today_output_tokens.set(0);
@@ -136,6 +402,20 @@ impl Metrics {
         }
     }
 
+    /// 记录一次限流拒绝
+    pub fn record_rate_limit_rejection(&self, reason: &str) {
+        self.rate_limit_rejections.with_label_values(&[reason]).inc();
+    }
+
+    /// 记录一次被全局 IP 规则拦截的请求
+    pub fn record_ip_rule_rejection(&self, reason: &str) {
+        self.ip_rule_rejections.with_label_values(&[reason]).inc();
+    }
+
+    pub fn record_persist_failure(&self, kind: &str) {
+        self.persist_failures.with_label_values(&[kind]).inc();
+    }
+
     pub fn record_input_tokens(&self, tokens: u32) {
         self.rollover_if_needed();
         if tokens > 0 {
@@ -163,7 +443,10 @@ impl Metrics {
     // ===== 持久化实现（简化版：仅今日，启动加载 / 关闭保存） =====
 
     fn today_file_path(&self) -> PathBuf {
-        let day = Local::now().format("%Y-%m-%d").to_string();
+        self.day_file_path(&Local::now().format("%Y-%m-%d").to_string())
+    }
+
+    fn day_file_path(&self, day: &str) -> PathBuf {
         self.persist_dir.join(format!("{}.json", day))
     }
 
@@ -178,13 +461,20 @@ impl Metrics {
     fn counter_simple(&self, c: &Counter) -> u64 { c.get() as u64 }
     fn gauge_value(&self, g: &IntGauge) -> i64 { g.get() }
 
-    fn build_snapshot(&self) -> DailySnapshot {
+    fn build_snapshot(&self, day: &str) -> DailySnapshot {
         DailySnapshot {
-            date: Local::now().format("%Y-%m-%d").to_string(),
+            date: day.to_string(),
             login_success: self.counter_value(&self.login_attempts, &["success"]),
             login_fail: self.counter_value(&self.login_attempts, &["fail"]),
             login_bruteforce_blocked: self.counter_simple(&self.login_bruteforce_blocked),
-            rate_limit_rejections: self.counter_simple(&self.rate_limit_rejections),
+            rate_limit_rejections: RATE_LIMIT_REASONS
+                .iter()
+                .map(|r| self.counter_value(&self.rate_limit_rejections, &[r]))
+                .sum(),
+            rate_limit_rejections_by_reason: RATE_LIMIT_REASONS
+                .iter()
+                .map(|r| (r.to_string(), self.counter_value(&self.rate_limit_rejections, &[r])))
+                .collect(),
             chat_success: self.counter_value(&self.chat_requests, &["success"]),
             chat_fail: self.counter_value(&self.chat_requests, &["fail"]),
             today_input_tokens: self.gauge_value(&self.today_input_tokens),
@@ -196,16 +486,47 @@ impl Metrics {
     }
 
     pub fn save_today(&self) -> Result<()> {
+        self.write_snapshot(&Local::now().format("%Y-%m-%d").to_string())
+    }
+
+    fn write_snapshot(&self, day: &str) -> Result<()> {
+        if crate::read_only::is_enabled() {
+            return Ok(());
+        }
         self.ensure_dir()?;
-        let path = self.today_file_path();
+        let path = self.day_file_path(day);
         let tmp = path.with_extension("json.tmp");
-        let snapshot = self.build_snapshot();
+        let snapshot = self.build_snapshot(day);
         let json = serde_json::to_string_pretty(&snapshot)?;
         fs::write(&tmp, json)?;
         fs::rename(tmp, path)?;
         Ok(())
     }
 
+    /// 今天截至目前的 token 用量
+    pub fn today_tokens(&self) -> DayTokens {
+        self.rollover_if_needed();
+        DayTokens {
+            input: self.today_input_tokens.get().max(0) as u64,
+            output: self.today_output_tokens.get().max(0) as u64,
+            cache_hit: self.today_prompt_cache_hit_tokens.get().max(0) as u64,
+            cache_miss: self.today_prompt_cache_miss_tokens.get().max(0) as u64,
+        }
+    }
+
+    /// 读取某一天快照中的 token 用量（没有快照时返回 None）
+    pub fn day_tokens(&self, day: &str) -> Result<Option<DayTokens>> {
+        let path = self.day_file_path(day);
+        if !path.exists() { return Ok(None); }
+        let snapshot: DailySnapshot = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        Ok(Some(DayTokens {
+            input: snapshot.today_input_tokens.max(0) as u64,
+            output: snapshot.today_output_tokens.max(0) as u64,
+            cache_hit: snapshot.today_prompt_cache_hit_tokens.max(0) as u64,
+            cache_miss: snapshot.today_prompt_cache_miss_tokens.max(0) as u64,
+        }))
+    }
+
     pub fn load_today(&self) -> Result<()> {
         self.ensure_dir()?;
         let path = self.today_file_path();
@@ -227,14 +548,23 @@ impl Metrics {
         let cur_login_success = success_metric.get();
         let cur_login_fail = fail_metric.get();
         let cur_bruteforce = self.login_bruteforce_blocked.get();
-        let cur_rate_rej = self.rate_limit_rejections.get();
         let cur_chat_success = chat_success_metric.get();
         let cur_chat_fail = chat_fail_metric.get();
 
         if snapshot.login_success as f64 > cur_login_success { success_metric.inc_by(snapshot.login_success as f64 - cur_login_success); }
         if snapshot.login_fail as f64 > cur_login_fail { fail_metric.inc_by(snapshot.login_fail as f64 - cur_login_fail); }
         if snapshot.login_bruteforce_blocked as f64 > cur_bruteforce { self.login_bruteforce_blocked.inc_by(snapshot.login_bruteforce_blocked as f64 - cur_bruteforce); }
-        if snapshot.rate_limit_rejections as f64 > cur_rate_rej { self.rate_limit_rejections.inc_by(snapshot.rate_limit_rejections as f64 - cur_rate_rej); }
+        // 旧快照只有总数，归入 global_bucket
+        let by_reason = if snapshot.rate_limit_rejections_by_reason.is_empty() {
+            HashMap::from([("global_bucket".to_string(), snapshot.rate_limit_rejections)])
+        } else {
+            snapshot.rate_limit_rejections_by_reason
+        };
+        for (reason, count) in by_reason {
+            if !RATE_LIMIT_REASONS.contains(&reason.as_str()) { continue; }
+            let metric = self.rate_limit_rejections.with_label_values(&[&reason]);
+            if count as f64 > metric.get() { metric.inc_by(count as f64 - metric.get()); }
+        }
         if snapshot.chat_success as f64 > cur_chat_success { chat_success_metric.inc_by(snapshot.chat_success as f64 - cur_chat_success); }
         if snapshot.chat_fail as f64 > cur_chat_fail { chat_fail_metric.inc_by(snapshot.chat_fail as f64 - cur_chat_fail); }
 
@@ -247,20 +577,21 @@ impl Metrics {
     }
 
     pub fn cleanup_old_days(&self, keep_days: u32) -> Result<()> {
+        if crate::read_only::is_enabled() {
+            return Ok(());
+        }
         self.ensure_dir()?;
         let entries = fs::read_dir(&self.persist_dir)?;
         let today = Local::now();
-        for entry in entries {
-            if let Ok(e) = entry {
-                let path = e.path();
-                if path.extension().and_then(|s| s.to_str()) != Some("json") { continue; }
-                if let Some(fname) = path.file_stem().and_then(|s| s.to_str()) {
-                    // 解析日期
-                    if let Ok(file_date) = chrono::NaiveDate::parse_from_str(fname, "%Y-%m-%d") {
-                        let duration = today.date_naive() - file_date;
-                        if duration.num_days() > keep_days as i64 {
-                            let _ = fs::remove_file(&path); // 忽略错误
-                        }
+        for e in entries.flatten() {
+            let path = e.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") { continue; }
+            if let Some(fname) = path.file_stem().and_then(|s| s.to_str()) {
+                // 解析日期
+                if let Ok(file_date) = chrono::NaiveDate::parse_from_str(fname, "%Y-%m-%d") {
+                    let duration = today.date_naive() - file_date;
+                    if duration.num_days() > keep_days as i64 {
+                        let _ = fs::remove_file(&path); // 忽略错误
                     }
                 }
             }
@@ -269,7 +600,7 @@ impl Metrics {
     }
 }
 
-pub static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics::new());
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
 
 pub struct UpstreamTimer {
     start: Instant,
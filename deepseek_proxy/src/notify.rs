@@ -0,0 +1,421 @@
+use crate::config::{ChatBotConfig, EmailRelayConfig, NotificationsConfig, SecurityConfig, User, WebhookFormat};
+use base64::Engine;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+
+/// 告警事件
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    /// 事件类型，如 `login_bruteforce_blocked`
+    pub event: &'static str,
+    pub username: String,
+    pub ip: Option<String>,
+    pub fail_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub timestamp: String,
+}
+
+impl AlertEvent {
+    pub fn new(event: &'static str, username: &str) -> Self {
+        Self {
+            event,
+            username: username.to_string(),
+            ip: None,
+            fail_count: None,
+            detail: None,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    pub fn with_ip(mut self, ip: &str) -> Self {
+        self.ip = Some(ip.to_string());
+        self
+    }
+
+    pub fn with_fail_count(mut self, fail_count: Option<usize>) -> Self {
+        self.fail_count = fail_count;
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// 告警标题
+    pub fn title(&self) -> &'static str {
+        match self.event {
+            "login_bruteforce_blocked" => "登录暴力破解已阻断",
+            "quota_exhausted" => "用户配额已耗尽",
+            "upstream_unhealthy" => "上游服务连续失败",
+            "upstream_recovered" => "上游服务已恢复",
+            "new_ip_login" => "账户在新的 IP 登录",
+            _ => "DeepSeek Proxy 告警",
+        }
+    }
+
+    /// 是否为登录安全类事件（只有这类事件会发往 `security.webhook_url`）
+    fn is_security_event(&self) -> bool {
+        self.event.starts_with("login_")
+    }
+
+    /// Markdown 格式的告警正文（每个字段一行）
+    pub fn markdown(&self) -> String {
+        self.markdown_with_title(self.title())
+    }
+
+    fn markdown_with_title(&self, title: &str) -> String {
+        let mut lines = vec![
+            format!("**{}**", title),
+            format!("- 事件: {}", self.event),
+        ];
+        if !self.username.is_empty() {
+            lines.push(format!("- 用户: {}", self.username));
+        }
+        if let Some(ip) = &self.ip {
+            lines.push(format!("- IP: {}", ip));
+        }
+        if let Some(fails) = self.fail_count {
+            lines.push(format!("- 失败次数: {}", fails));
+        }
+        if let Some(detail) = &self.detail {
+            lines.push(format!("- 详情: {}", detail));
+        }
+        lines.push(format!("- 时间: {}", self.timestamp));
+        lines.join("\n")
+    }
+
+    /// 单行纯文本摘要
+    pub fn summary(&self) -> String {
+        self.markdown().replace("**", "").replace("\n- ", " | ")
+    }
+}
+
+/// 群机器人加签方式
+#[derive(Debug, Clone)]
+enum BotSigner {
+    /// 钉钉：签名放在 URL 的 timestamp / sign 参数
+    Dingtalk(String),
+    /// 飞书：签名放在请求体的 timestamp / sign 字段
+    Feishu(String),
+}
+
+/// Webhook 通知目标
+#[derive(Debug, Clone)]
+struct WebhookTarget {
+    url: String,
+    format: WebhookFormat,
+    /// 自定义 JSON 模板，优先于 format
+    template: Option<String>,
+    signer: Option<BotSigner>,
+    /// 机器人关键词，加在标题前
+    keyword: Option<String>,
+    /// 只接收登录安全类事件（兼容原有 security.webhook_url 的行为）
+    security_only: bool,
+}
+
+impl WebhookTarget {
+    fn chat_bot(bot: &ChatBotConfig, format: WebhookFormat) -> Self {
+        let signer = bot.secret.clone().map(|secret| match format {
+            WebhookFormat::Feishu => BotSigner::Feishu(secret),
+            _ => BotSigner::Dingtalk(secret),
+        });
+        Self {
+            url: bot.webhook_url.clone(),
+            format,
+            template: None,
+            signer,
+            keyword: bot.keyword.clone(),
+            security_only: false,
+        }
+    }
+
+    /// 渲染负载并按机器人要求加签，返回 (url, body)
+    fn build_request(&self, event: &AlertEvent, now_ms: i64) -> (String, Value) {
+        let mut payload = match &self.template {
+            Some(template) => render_template(template, event).unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "Webhook 模板渲染失败，改用通用格式");
+                render_payload(WebhookFormat::Generic, event, None)
+            }),
+            None => render_payload(self.format, event, self.keyword.as_deref()),
+        };
+
+        let mut url = self.url.clone();
+        match &self.signer {
+            Some(BotSigner::Dingtalk(secret)) => {
+                let sign = hmac_base64(secret.as_bytes(), format!("{}\n{}", now_ms, secret).as_bytes());
+                if let Ok(mut parsed) = reqwest::Url::parse(&self.url) {
+                    parsed
+                        .query_pairs_mut()
+                        .append_pair("timestamp", &now_ms.to_string())
+                        .append_pair("sign", &sign);
+                    url = parsed.to_string();
+                }
+            }
+            Some(BotSigner::Feishu(secret)) => {
+                let timestamp = now_ms / 1000;
+                let sign = hmac_base64(format!("{}\n{}", timestamp, secret).as_bytes(), b"");
+                if let Some(obj) = payload.as_object_mut() {
+                    obj.insert("timestamp".to_string(), json!(timestamp.to_string()));
+                    obj.insert("sign".to_string(), json!(sign));
+                }
+            }
+            None => {}
+        }
+        (url, payload)
+    }
+}
+
+fn hmac_base64(key: &[u8], message: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC 接受任意长度的密钥");
+    mac.update(message);
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// 通知子系统：把告警事件按配置的格式投递到各个 webhook / 群机器人
+#[derive(Debug)]
+pub struct Notifier {
+    client: reqwest::Client,
+    targets: Vec<WebhookTarget>,
+    cooldown: Duration,
+    /// 节流 key -> 上次发送时间
+    last_sent: DashMap<String, Instant>,
+    email: Option<EmailRelayConfig>,
+}
+
+impl Notifier {
+    /// 从配置构建；模板文件读取失败时启动失败
+    pub fn from_config(security: &SecurityConfig, notifications: &NotificationsConfig) -> anyhow::Result<Self> {
+        let mut targets = Vec::new();
+        if let Some(url) = &security.webhook_url {
+            let template = match (&security.webhook_template, &security.webhook_template_file) {
+                (Some(template), _) => Some(template.clone()),
+                (None, Some(path)) => Some(
+                    std::fs::read_to_string(path)
+                        .map_err(|e| anyhow::anyhow!("读取 webhook 模板 {} 失败: {}", path, e))?,
+                ),
+                (None, None) => None,
+            };
+            if let Some(template) = &template {
+                // 启动时用示例事件校验模板能渲染为合法 JSON
+                render_template(template, &AlertEvent::new("template_check", "user"))
+                    .map_err(|e| anyhow::anyhow!("webhook 模板不是合法 JSON: {}", e))?;
+            }
+            targets.push(WebhookTarget {
+                url: url.clone(),
+                format: security.webhook_format,
+                template,
+                signer: None,
+                keyword: None,
+                security_only: true,
+            });
+        }
+        if let Some(bot) = &notifications.dingtalk {
+            targets.push(WebhookTarget::chat_bot(bot, WebhookFormat::Dingtalk));
+        }
+        if let Some(bot) = &notifications.feishu {
+            targets.push(WebhookTarget::chat_bot(bot, WebhookFormat::Feishu));
+        }
+        Ok(Self {
+            client: reqwest::Client::new(),
+            targets,
+            cooldown: Duration::from_secs(notifications.cooldown_seconds),
+            last_sent: DashMap::new(),
+            email: notifications.email.clone(),
+        })
+    }
+
+    /// 用户是否配置了可用的个人通知渠道
+    pub fn has_user_channel(&self, user: &User) -> bool {
+        user.notify_webhook.is_some() || (user.notify_email.is_some() && self.email.is_some())
+    }
+
+    /// 通知账户所有者（用户记录上的 notify_webhook / notify_email），不发往运维告警渠道
+    pub fn notify_user(&self, user: &User, event: AlertEvent) {
+        let mut requests = Vec::new();
+        if let Some(url) = &user.notify_webhook {
+            requests.push(self.client.post(url).json(&render_payload(WebhookFormat::Generic, &event, None)));
+        }
+        if let (Some(to), Some(relay)) = (&user.notify_email, &self.email) {
+            let mut request = self.client.post(&relay.url).json(&json!({
+                "from": relay.from,
+                "to": to,
+                "subject": event.title(),
+                "text": event.markdown().replace("**", ""),
+            }));
+            if let Some(token) = &relay.bearer_token {
+                request = request.bearer_auth(token);
+            }
+            requests.push(request);
+        }
+        for request in requests {
+            let username = user.username.clone();
+            tokio::spawn(async move {
+                match request.send().await {
+                    Ok(resp) if !resp.status().is_success() => {
+                        tracing::warn!(user = %username, status = %resp.status(), "用户通知被拒绝");
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(user = %username, error = %e, "用户通知发送失败"),
+                }
+            });
+        }
+    }
+
+    /// 异步投递告警（不阻塞调用方，失败只记录日志）
+    pub fn notify(&self, event: AlertEvent) {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        for target in &self.targets {
+            if target.security_only && !event.is_security_event() {
+                continue;
+            }
+            let (url, payload) = target.build_request(&event, now_ms);
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                match client.post(&url).json(&payload).send().await {
+                    Ok(resp) if !resp.status().is_success() => {
+                        tracing::warn!(status = %resp.status(), "Webhook 通知被拒绝");
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error=%e, "Webhook 通知发送失败"),
+                }
+            });
+        }
+    }
+
+    /// 带节流的告警：同一 key 在 cooldown 内只发送一次
+    pub fn notify_throttled(&self, key: &str, event: AlertEvent) {
+        let now = Instant::now();
+        let mut due = false;
+        self.last_sent
+            .entry(key.to_string())
+            .and_modify(|last| {
+                if now.duration_since(*last) >= self.cooldown {
+                    *last = now;
+                    due = true;
+                }
+            })
+            .or_insert_with(|| {
+                due = true;
+                now
+            });
+        if due {
+            self.notify(event);
+        }
+    }
+}
+
+/// 按内置格式渲染告警负载；keyword 用于满足机器人的关键词安全设置
+pub fn render_payload(format: WebhookFormat, event: &AlertEvent, keyword: Option<&str>) -> Value {
+    let title = match keyword {
+        Some(keyword) => format!("【{}】{}", keyword, event.title()),
+        None => event.title().to_string(),
+    };
+    let markdown = event.markdown_with_title(&title);
+    match format {
+        WebhookFormat::Generic => serde_json::to_value(event).unwrap_or(Value::Null),
+        WebhookFormat::Slack => json!({ "text": markdown.replace("**", "*") }),
+        WebhookFormat::Dingtalk => json!({
+            "msgtype": "markdown",
+            "markdown": { "title": title, "text": markdown }
+        }),
+        WebhookFormat::Feishu => json!({
+            "msg_type": "interactive",
+            "card": {
+                "header": {
+                    "title": { "tag": "plain_text", "content": title },
+                    "template": if event.event == "upstream_recovered" { "green" } else { "red" }
+                },
+                "elements": [
+                    { "tag": "div", "text": { "tag": "lark_md", "content": markdown } }
+                ]
+            }
+        }),
+    }
+}
+
+/// 渲染自定义模板：`{{event}}` `{{username}}` `{{ip}}` `{{fail_count}}` `{{detail}}`
+/// `{{timestamp}}` `{{title}}` `{{summary}}` 会被替换为 JSON 转义后的值（不含引号）
+pub fn render_template(template: &str, event: &AlertEvent) -> Result<Value, serde_json::Error> {
+    let fields = [
+        ("event", event.event.to_string()),
+        ("username", event.username.clone()),
+        ("ip", event.ip.clone().unwrap_or_default()),
+        ("fail_count", event.fail_count.map(|n| n.to_string()).unwrap_or_default()),
+        ("detail", event.detail.clone().unwrap_or_default()),
+        ("timestamp", event.timestamp.clone()),
+        ("title", event.title().to_string()),
+        ("summary", event.summary()),
+    ];
+    let mut rendered = template.to_string();
+    for (name, value) in fields {
+        let escaped = serde_json::to_string(&value)?;
+        rendered = rendered.replace(&format!("{{{{{}}}}}", name), &escaped[1..escaped.len() - 1]);
+    }
+    serde_json::from_str(&rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> AlertEvent {
+        AlertEvent::new("login_bruteforce_blocked", "alice")
+            .with_ip("10.0.0.1")
+            .with_fail_count(Some(5))
+    }
+
+    #[test]
+    fn test_generic_payload_keeps_legacy_fields() {
+        let payload = render_payload(WebhookFormat::Generic, &event(), None);
+        assert_eq!(payload["event"], "login_bruteforce_blocked");
+        assert_eq!(payload["username"], "alice");
+        assert_eq!(payload["ip"], "10.0.0.1");
+        assert_eq!(payload["fail_count"], 5);
+        assert!(payload.get("detail").is_none());
+    }
+
+    #[test]
+    fn test_chat_formats() {
+        let feishu = render_payload(WebhookFormat::Feishu, &event(), None);
+        assert_eq!(feishu["msg_type"], "interactive");
+        assert!(feishu["card"]["elements"][0]["text"]["content"].as_str().unwrap().contains("alice"));
+
+        let dingtalk = render_payload(WebhookFormat::Dingtalk, &event(), Some("告警"));
+        assert_eq!(dingtalk["markdown"]["title"], "【告警】登录暴力破解已阻断");
+    }
+
+    #[test]
+    fn test_render_template_escapes_values() {
+        let mut e = event();
+        e.username = "a\"b".to_string();
+        let payload = render_template(r#"{"content": {"text": "{{username}} @ {{ip}} x{{fail_count}}"}}"#, &e).unwrap();
+        assert_eq!(payload["content"]["text"], "a\"b @ 10.0.0.1 x5");
+    }
+
+    #[test]
+    fn test_bot_signing() {
+        let bot = ChatBotConfig {
+            webhook_url: "https://oapi.dingtalk.com/robot/send?access_token=t".to_string(),
+            secret: Some("SEC123".to_string()),
+            keyword: None,
+        };
+        let (url, _) = WebhookTarget::chat_bot(&bot, WebhookFormat::Dingtalk).build_request(&event(), 1_700_000_000_000);
+        let expected = hmac_base64(b"SEC123", b"1700000000000\nSEC123");
+        let parsed = reqwest::Url::parse(&url).unwrap();
+        let query: std::collections::HashMap<_, _> = parsed.query_pairs().into_owned().collect();
+        assert_eq!(query["access_token"], "t");
+        assert_eq!(query["timestamp"], "1700000000000");
+        assert_eq!(query["sign"], expected);
+
+        let (url, body) = WebhookTarget::chat_bot(&bot, WebhookFormat::Feishu).build_request(&event(), 1_700_000_000_000);
+        assert_eq!(url, bot.webhook_url);
+        assert_eq!(body["timestamp"], "1700000000");
+        assert_eq!(body["sign"], hmac_base64(b"1700000000\nSEC123", b""));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::error::ServerErrorDetail;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::any::Any;
+
+/// 请求 ID 头（由 SetRequestIdLayer 生成，客户端传入时沿用）
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    /// 当前请求的 ID；panic hook 与 panic 响应在同一任务内执行，可直接读取
+    static REQUEST_ID: String;
+}
+
+/// 当前任务所属请求的 ID（不在请求上下文中时返回 None）
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 中间件：将请求 ID 绑定到处理该请求的任务上
+pub async fn request_id_scope(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    REQUEST_ID.scope(request_id, next.run(request)).await
+}
+
+/// 安装 panic hook：以结构化日志记录 panic 信息、位置与请求 ID，然后交给原有 hook
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        tracing::error!(
+            request_id = current_request_id().as_deref().unwrap_or("-"),
+            location = %location,
+            message = %panic_message(info.payload()),
+            "处理过程中发生 panic"
+        );
+        previous(info);
+    }));
+}
+
+/// CatchPanicLayer 的响应：返回标准错误 JSON（不向客户端暴露 panic 细节）
+pub fn panic_response(err: Box<dyn Any + Send + 'static>) -> Response<Body> {
+    let request_id = current_request_id();
+    let mut error = crate::branding::error_object("internal_panic", "服务内部错误，请稍后重试");
+    error.insert("request_id".to_string(), json!(request_id));
+    let body = Json(json!({ "error": error }));
+    let mut response = (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [(header::CACHE_CONTROL, "no-store")],
+        body,
+    ).into_response();
+    // 交给错误上报中间件，附带请求上下文上报
+    response
+        .extensions_mut()
+        .insert(ServerErrorDetail(format!("panic: {}", panic_message(err.as_ref()))));
+    response
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    async fn boom() -> &'static str {
+        panic!("boom")
+    }
+
+    #[tokio::test]
+    async fn test_panic_returns_structured_500_with_request_id() {
+        let app: Router = Router::new()
+            .route("/boom", get(boom))
+            .layer(CatchPanicLayer::custom(panic_response))
+            .layer(axum::middleware::from_fn(request_id_scope));
+
+        let request = Request::builder()
+            .uri("/boom")
+            .header(REQUEST_ID_HEADER, "req-1")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let detail = response.extensions().get::<ServerErrorDetail>().unwrap();
+        assert_eq!(detail.0, "panic: boom");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "internal_panic");
+        assert_eq!(body["error"]["request_id"], "req-1");
+    }
+}
@@ -0,0 +1,304 @@
+//! 语音接口代理（`[audio]`）：`/audio/transcriptions` 上传 multipart 音频返回识别结果，
+//! `/audio/speech` 提交 JSON 返回音频流
+//!
+//! 与聊天接口共用鉴权、全局速率限制、会话并发许可与配额，按 `[audio]` 配置的权重扣费；
+//! 请求体校验后原样转发，上游响应（含 Content-Type）以流的方式透传
+
+use crate::{auth::Claims, error::{AppError, RateLimit}, quota::QuotaStatus, AppState};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use bytes::Bytes;
+use futures::StreamExt;
+use serde::Deserialize;
+
+const TRANSCRIPTIONS_PATH: &str = "/audio/transcriptions";
+const SPEECH_PATH: &str = "/audio/speech";
+
+/// 语音接口类型，用作指标与行为日志的 endpoint 标签
+#[derive(Debug, Clone, Copy)]
+enum Endpoint {
+    Transcription,
+    Speech,
+}
+
+impl Endpoint {
+    fn label(self) -> &'static str {
+        match self {
+            Endpoint::Transcription => "transcription",
+            Endpoint::Speech => "speech",
+        }
+    }
+
+    fn path(self) -> &'static str {
+        match self {
+            Endpoint::Transcription => TRANSCRIPTIONS_PATH,
+            Endpoint::Speech => SPEECH_PATH,
+        }
+    }
+
+    fn quota_weight(self, config: &crate::config::AudioConfig) -> u32 {
+        match self {
+            Endpoint::Transcription => config.transcription_quota_weight,
+            Endpoint::Speech => config.speech_quota_weight,
+        }
+    }
+}
+
+/// 语音合成请求中需要校验的字段，其余字段随原始请求体透传
+#[derive(Debug, Deserialize)]
+struct SpeechRequest {
+    model: String,
+    input: String,
+}
+
+/// 代理语音识别请求（multipart/form-data，必须包含 `file` 与 `model` 字段）
+pub async fn proxy_transcription(
+    State(state): State<AppState>,
+    Extension(token): Extension<String>,
+    Extension(claims): Extension<Claims>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let boundary = multipart_boundary(&content_type)
+        .ok_or_else(|| AppError::BadRequest("语音识别请求必须是 multipart/form-data".to_string()))?;
+    let parts = parse_multipart(&body, &boundary)?;
+
+    if !parts.iter().any(|p| p.name == "file" && p.filename.is_some() && !p.data.is_empty()) {
+        return Err(AppError::BadRequest("缺少音频文件字段 file".to_string()));
+    }
+    let model = parts
+        .iter()
+        .find(|p| p.name == "model")
+        .and_then(|p| std::str::from_utf8(p.data).ok())
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .ok_or_else(|| AppError::BadRequest("缺少 model 字段".to_string()))?
+        .to_string();
+
+    forward(&state, &token, &claims, Endpoint::Transcription, &model, &content_type, body).await
+}
+
+/// 代理语音合成请求（JSON，必须包含 `model` 与非空的 `input`），音频以流的方式返回
+pub async fn proxy_speech(
+    State(state): State<AppState>,
+    Extension(token): Extension<String>,
+    Extension(claims): Extension<Claims>,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let request: SpeechRequest = serde_json::from_slice(&body)?;
+    if request.input.trim().is_empty() {
+        return Err(AppError::BadRequest("input 不能为空".to_string()));
+    }
+
+    forward(&state, &token, &claims, Endpoint::Speech, &request.model, "application/json", body).await
+}
+
+/// 限流、配额检查、转发上游、扣费并透传响应
+async fn forward(
+    state: &AppState,
+    token: &str,
+    claims: &Claims,
+    endpoint: Endpoint,
+    model: &str,
+    content_type: &str,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let arrived = std::time::Instant::now();
+    crate::metrics::METRICS.request_body_bytes.observe(body.len() as f64);
+
+    let rate_limit = match state.global_rate_limiter.acquire().await {
+        Ok(rate_limit) => rate_limit,
+        Err(wait_time) => {
+            tracing::warn!("全局速率限制：拒绝语音请求，建议等待 {:.2} 秒", wait_time);
+            crate::metrics::METRICS.record_rate_limit_rejection("global_bucket");
+            return Err(AppError::TooManyRequests(state.global_rate_limiter.rate_limit(wait_time)));
+        }
+    };
+
+    let user = state.user_manager.get_user(&claims.sub).await;
+    let user_tier = user.as_ref().map(|u| u.quota_tier.clone());
+    let unlimited = user.map(|u| u.unlimited).unwrap_or(false);
+
+    if state.load_shedder.should_shed(user_tier.as_deref()) {
+        let tier = user_tier.as_deref().unwrap_or("unknown");
+        tracing::warn!(user = %claims.sub, tier, "负载降级：拒绝语音请求");
+        crate::metrics::METRICS.load_shed_rejections.with_label_values(&[tier]).inc();
+        return Err(AppError::Overloaded);
+    }
+
+    // 配额检查（不扣费），剩余次数需覆盖本次权重
+    let weight = endpoint.quota_weight(&state.config.audio);
+    let (limit, remaining, reset_at) = match state.quota_manager.check_quota(&claims.sub).await? {
+        QuotaStatus::Exceeded { used, limit, reset_at } => {
+            tracing::warn!("用户 {} 配额已耗尽: {}/{}", claims.sub, used, limit);
+            state.activity_logger.log_quota_exceeded(&claims.sub, used, limit).await;
+            crate::metrics::METRICS.quota_status.with_label_values(&["exceeded"]).inc();
+            return Ok(payment_required(rate_limit, used, limit, 0, reset_at));
+        }
+        QuotaStatus::Ok { used, limit, remaining, credits, reset_at } => {
+            crate::metrics::METRICS.quota_status.with_label_values(&["ok"]).inc();
+            if weight > remaining && !unlimited {
+                tracing::warn!(user = %claims.sub, weight, remaining, "剩余配额不足以完成语音请求");
+                return Ok(payment_required(rate_limit, used, limit + credits, remaining, reset_at));
+            }
+            (limit + credits, remaining, reset_at)
+        }
+    };
+
+    // 会话许可随响应流存活，音频传输结束后才释放
+    let permit = state.login_limiter
+        .acquire_permit(&claims.sub, token)
+        .await?
+        .track_tier(user_tier.as_deref().unwrap_or("unknown"), arrived);
+
+    let timer = crate::metrics::UpstreamTimer::start();
+    let response = match state.deepseek_client.send_audio(endpoint.path(), content_type, body.clone()).await {
+        Ok(response) => response,
+        Err(e) => {
+            crate::metrics::METRICS.audio_requests.with_label_values(&[endpoint.label(), "failure"]).inc();
+            return Err(e);
+        }
+    };
+    timer.observe();
+
+    // 上游请求成功，现在扣费
+    let charge = state.quota_manager.increment_quota_by(&claims.sub, weight).await?;
+    let charge_guard = state.charge_journal.open(&claims.sub, charge).await;
+    state.activity_logger.log_audio_request(&claims.sub, endpoint.label(), model, body.len()).await;
+    crate::metrics::METRICS.audio_requests.with_label_values(&[endpoint.label(), "success"]).inc();
+    tracing::info!(user = %claims.sub, endpoint = endpoint.label(), model, bytes = body.len(), "语音请求已转发");
+
+    let mut headers = HeaderMap::new();
+    let upstream_type = response.headers().get(reqwest::header::CONTENT_TYPE).cloned();
+    headers.insert(
+        header::CONTENT_TYPE,
+        upstream_type.unwrap_or_else(|| HeaderValue::from_static("application/octet-stream")),
+    );
+    rate_limit.insert_headers(&mut headers);
+    if !unlimited {
+        super::handler::insert_quota_headers(&mut headers, limit, remaining.saturating_sub(weight), reset_at);
+    }
+    let stream = response.bytes_stream().map(move |chunk| {
+        let _permit = &permit;
+        let _charge = &charge_guard;
+        chunk
+    });
+
+    Ok((StatusCode::OK, headers, Body::from_stream(stream)).into_response())
+}
+
+fn payment_required(
+    rate_limit: RateLimit,
+    used: u32,
+    limit: u32,
+    remaining: u32,
+    reset_at: chrono::DateTime<chrono::FixedOffset>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    rate_limit.insert_headers(&mut headers);
+    super::handler::insert_quota_headers(&mut headers, limit, remaining, reset_at);
+    let error = AppError::PaymentRequired { used, limit, reset_at: reset_at.to_rfc3339() };
+    (headers, error).into_response()
+}
+
+/// multipart 表单中的一个字段
+struct FormPart<'a> {
+    name: String,
+    filename: Option<String>,
+    data: &'a [u8],
+}
+
+/// 从 `multipart/form-data; boundary=...` 中取出分隔符
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"').to_string())
+        .filter(|b| !b.is_empty())
+}
+
+/// 解析 multipart 表单的各字段（只校验结构，不复制内容）
+fn parse_multipart<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<FormPart<'a>>, AppError> {
+    let invalid = || AppError::BadRequest("multipart 表单格式无效".to_string());
+    let first = format!("--{}", boundary);
+    let delimiter = format!("\r\n--{}", boundary);
+
+    let start = find(body, first.as_bytes()).ok_or_else(invalid)?;
+    let mut rest = &body[start + first.len()..];
+    let mut parts = Vec::new();
+    // 每个字段以 CRLF 开头，最后一个分隔符后紧跟 "--"
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n").ok_or_else(invalid)?;
+        let end = find(rest, delimiter.as_bytes()).ok_or_else(invalid)?;
+        let part = &rest[..end];
+        rest = &rest[end + delimiter.len()..];
+
+        let header_end = find(part, b"\r\n\r\n").ok_or_else(invalid)?;
+        let headers = std::str::from_utf8(&part[..header_end]).map_err(|_| invalid())?;
+        let disposition = headers
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim().eq_ignore_ascii_case("content-disposition").then_some(value)
+            })
+            .ok_or_else(invalid)?;
+        parts.push(FormPart {
+            name: disposition_param(disposition, "name").ok_or_else(invalid)?,
+            filename: disposition_param(disposition, "filename"),
+            data: &part[header_end + 4..],
+        });
+    }
+    Ok(parts)
+}
+
+/// Content-Disposition 中的参数值（如 `name="file"`）
+fn disposition_param(header: &str, key: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|param| {
+        let (k, v) = param.trim().split_once('=')?;
+        k.eq_ignore_ascii_case(key).then(|| v.trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart_form() {
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        let boundary = multipart_boundary(content_type).unwrap();
+        assert_eq!(boundary, "XyZ");
+        assert!(multipart_boundary("application/json").is_none());
+
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\nContent-Type: audio/wav\r\n\r\nRIFF\r\n--\r\n\
+--XyZ--\r\n";
+        let parts = parse_multipart(body, &boundary).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!((parts[0].name.as_str(), parts[0].data), ("model", &b"whisper-1"[..]));
+        assert_eq!(parts[1].filename.as_deref(), Some("a.wav"));
+        // 内容中出现的 "--" 不会被当作分隔符
+        assert_eq!(parts[1].data, b"RIFF\r\n--");
+
+        assert!(parse_multipart(b"--XyZ\r\nno headers", &boundary).is_err());
+        assert!(parse_multipart(b"garbage", &boundary).is_err());
+    }
+}
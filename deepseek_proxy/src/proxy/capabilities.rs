@@ -0,0 +1,97 @@
+use crate::config::ModelCapabilities;
+use crate::deepseek::ChatRequest;
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+
+/// 按模型能力检查请求的功能：工具调用、图片输入与 max_tokens（未登记的模型不检查）
+pub fn enforce_features(caps: Option<&ModelCapabilities>, request: &ChatRequest, images: u32) -> Result<(), AppError> {
+    let Some(caps) = caps else {
+        return Ok(());
+    };
+    if !caps.supports_tools && request.extra.get("tools").is_some_and(|t| !t.is_null()) {
+        return Err(AppError::BadRequest(format!("模型 {} 不支持工具调用", request.model)));
+    }
+    if !caps.supports_vision && images > 0 {
+        return Err(AppError::BadRequest(format!("模型 {} 不支持图片输入", request.model)));
+    }
+    if let (Some(max), Some(requested)) = (caps.max_output_tokens, request.max_tokens) {
+        if requested > max {
+            return Err(AppError::BadRequest(format!("max_tokens {} 超过模型 {} 的输出上限 {}", requested, request.model, max)));
+        }
+    }
+    Ok(())
+}
+
+/// 估算的输入 tokens 加上 max_tokens 不能超过模型的上下文窗口
+pub fn enforce_context_window(caps: Option<&ModelCapabilities>, request: &ChatRequest, input_tokens: u32) -> Result<(), AppError> {
+    let Some(window) = caps.and_then(|c| c.context_window) else {
+        return Ok(());
+    };
+    let needed = input_tokens.saturating_add(request.max_tokens.unwrap_or(0));
+    if needed > window {
+        return Err(AppError::BadRequest(format!(
+            "请求约需 {} tokens（输入约 {}），超过模型 {} 的上下文窗口 {}",
+            needed, input_tokens, request.model, window
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapabilitiesResponse {
+    pub model: String,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+/// 查询模型能力（GET /models/:name/capabilities），未登记的模型返回 404
+pub async fn model_capabilities(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<CapabilitiesResponse>, AppError> {
+    let capabilities = state
+        .config
+        .models
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(format!("模型 {} 未登记能力信息", name)))?;
+    Ok(Json(CapabilitiesResponse { model: name, capabilities }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(extra: serde_json::Value) -> ChatRequest {
+        let mut body = serde_json::json!({
+            "model": "deepseek-chat",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        });
+        body.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_enforce_model_capabilities() {
+        let caps: ModelCapabilities = toml::from_str("context_window = 100\nmax_output_tokens = 50\nsupports_tools = false\n").unwrap();
+        assert!(!caps.supports_vision);
+
+        assert!(enforce_features(Some(&caps), &request(serde_json::json!({"max_tokens": 50})), 0).is_ok());
+        assert!(enforce_features(Some(&caps), &request(serde_json::json!({"max_tokens": 51})), 0).is_err());
+        assert!(enforce_features(Some(&caps), &request(serde_json::json!({"tools": []})), 0).is_err());
+        assert!(enforce_features(Some(&caps), &request(serde_json::json!({})), 1).is_err());
+        // 未登记的模型不检查
+        assert!(enforce_features(None, &request(serde_json::json!({"tools": [], "max_tokens": 1000})), 3).is_ok());
+
+        let req = request(serde_json::json!({"max_tokens": 40}));
+        assert!(enforce_context_window(Some(&caps), &req, 60).is_ok());
+        assert!(enforce_context_window(Some(&caps), &req, 61).is_err());
+        assert!(enforce_context_window(None, &req, 10_000).is_ok());
+    }
+}
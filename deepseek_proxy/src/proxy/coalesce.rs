@@ -0,0 +1,111 @@
+use crate::config::CoalesceConfig;
+use crate::proxy::stream_transform::{StreamTransform, TransformOutput};
+use bytes::{Bytes, BytesMut};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 小数据块合并变换：上游常以几个字节一个 SSE 事件的粒度推送，逐块下发会产生大量写调用与 TLS 记录
+///
+/// 首个数据块进入缓冲后最多等待 window，期间到达的数据块合并为一块下发；缓冲达到 max_bytes 时提前下发
+pub struct CoalesceTransform {
+    window: Duration,
+    max_bytes: usize,
+    buf: BytesMut,
+    deadline: Option<Instant>,
+}
+
+impl CoalesceTransform {
+    pub fn new(cfg: &CoalesceConfig) -> Self {
+        Self {
+            window: Duration::from_millis(cfg.window_ms),
+            max_bytes: cfg.max_bytes,
+            buf: BytesMut::new(),
+            deadline: None,
+        }
+    }
+
+    fn take(&mut self) -> Vec<Bytes> {
+        self.deadline = None;
+        if self.buf.is_empty() {
+            return Vec::new();
+        }
+        vec![self.buf.split().freeze()]
+    }
+}
+
+impl StreamTransform for CoalesceTransform {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        // 本身足够大的数据块不必复制
+        if self.buf.is_empty() && chunk.len() >= self.max_bytes {
+            return TransformOutput::Continue(vec![chunk]);
+        }
+        self.buf.extend_from_slice(&chunk);
+        if self.buf.len() >= self.max_bytes {
+            return TransformOutput::Continue(self.take());
+        }
+        let window = self.window;
+        self.deadline.get_or_insert_with(|| Instant::now() + window);
+        TransformOutput::Continue(Vec::new())
+    }
+
+    fn flush_deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    fn on_flush(&mut self) -> Vec<Bytes> {
+        self.take()
+    }
+
+    fn on_end(&mut self) -> Vec<Bytes> {
+        self.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::stream_transform::TransformedStream;
+    use futures::StreamExt;
+
+    fn coalesce(window_ms: u64, max_bytes: usize) -> CoalesceTransform {
+        CoalesceTransform::new(&CoalesceConfig { window_ms, max_bytes })
+    }
+
+    #[test]
+    fn test_flushes_at_max_bytes() {
+        let mut t = coalesce(30, 8);
+        assert!(matches!(t.on_chunk(Bytes::from_static(b"abc")), TransformOutput::Continue(v) if v.is_empty()));
+        assert!(t.flush_deadline().is_some());
+        match t.on_chunk(Bytes::from_static(b"defgh")) {
+            TransformOutput::Continue(v) => assert_eq!(v, vec![Bytes::from_static(b"abcdefgh")]),
+            TransformOutput::Terminate(_) => panic!("不应终止"),
+        }
+        assert!(t.flush_deadline().is_none());
+        // 大块直接透传
+        assert!(matches!(t.on_chunk(Bytes::from_static(b"0123456789")), TransformOutput::Continue(v) if v.len() == 1));
+        assert!(t.on_end().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flushes_after_window_while_upstream_idle() {
+        // 上游连续推送 a、b 后停顿 300ms 再推送 c
+        let upstream = futures::stream::unfold(0, |i| async move {
+            match i {
+                0 => Some((Ok::<_, reqwest::Error>(Bytes::from_static(b"a")), 1)),
+                1 => Some((Ok(Bytes::from_static(b"b")), 2)),
+                2 => {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    Some((Ok(Bytes::from_static(b"c")), 3))
+                }
+                _ => None,
+            }
+        });
+        let start = Instant::now();
+        let mut stream = TransformedStream::new(Box::pin(upstream), vec![Box::new(coalesce(30, 4096))]);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from_static(b"ab"));
+        assert!(start.elapsed() < Duration::from_millis(300));
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from_static(b"c"));
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    config::CompressionConfig,
+    deepseek::{ChatRequest, DeepSeekClient, Message, MessageContent},
+    error::AppError,
+};
+
+/// 摘要请求的系统提示词
+const SUMMARY_INSTRUCTION: &str =
+    "请将以下对话压缩为简洁的摘要，保留关键事实、用户约束、已做出的决定和未完成的任务，不要添加新内容。";
+/// 摘要注入时的前缀
+const SUMMARY_PREFIX: &str = "以下是之前对话的摘要：\n";
+
+/// 历史压缩结果
+#[derive(Debug)]
+pub struct CompressionOutcome {
+    /// 被摘要替换的消息数
+    pub compressed_messages: usize,
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+}
+
+/// 历史超过 token 预算时，用一次廉价模型调用将较早的对话压缩为摘要并替换
+///
+/// 开头的 system 消息与最近 keep_recent_messages 条消息保持不变；
+/// 未超预算或没有可压缩的消息时返回 Ok(None)
+pub async fn compress_history(
+    client: &DeepSeekClient,
+    cfg: &CompressionConfig,
+    request: &mut ChatRequest,
+) -> Result<Option<CompressionOutcome>, AppError> {
+    let tokens_before = super::estimate_input_tokens(request);
+    if tokens_before <= cfg.token_budget {
+        return Ok(None);
+    }
+
+    let Some((start, end)) = compressible_range(&request.messages, cfg.keep_recent_messages) else {
+        return Ok(None);
+    };
+
+    let transcript = render_transcript(&request.messages[start..end]);
+    let summary_request = ChatRequest {
+        model: cfg.model.clone(),
+        messages: vec![
+            text_message("system", SUMMARY_INSTRUCTION),
+            text_message("user", &transcript),
+        ],
+        temperature: Some(0.0),
+        top_p: None,
+        max_tokens: Some(cfg.max_summary_tokens),
+        n: None,
+        logprobs: None,
+        top_logprobs: None,
+        stream: false,
+        metadata: None,
+        extra: serde_json::json!({}),
+    };
+
+    let response = client.chat_once(summary_request).await?;
+    let summary = response
+        .pointer("/choices/0/message/content")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| AppError::GlmError("摘要响应缺少内容".to_string()))?;
+
+    let summary_message = text_message("system", &format!("{}{}", SUMMARY_PREFIX, summary.trim()));
+    request.messages.splice(start..end, std::iter::once(summary_message));
+
+    Ok(Some(CompressionOutcome {
+        compressed_messages: end - start,
+        tokens_before,
+        tokens_after: super::estimate_input_tokens(request),
+    }))
+}
+
+/// 计算可压缩的消息区间 [start, end)
+///
+/// 跳过开头的 system 消息，保留最近 keep_recent 条；
+/// 若保留区以 tool 消息开头，则将其对应的 tool_calls 一并保留，避免上游拒绝孤立的 tool 消息
+fn compressible_range(messages: &[Message], keep_recent: usize) -> Option<(usize, usize)> {
+    let start = messages.iter().take_while(|m| m.role == "system").count();
+    let mut end = messages.len().saturating_sub(keep_recent);
+    while end > start && messages.get(end).map(|m| m.role == "tool").unwrap_or(false) {
+        end -= 1;
+    }
+    // 至少压缩 2 条消息才有意义
+    if end >= start + 2 { Some((start, end)) } else { None }
+}
+
+/// 将消息渲染为 "role: content" 文本（非文本内容片段忽略）
+fn render_transcript(messages: &[Message]) -> String {
+    let mut out = String::new();
+    for m in messages {
+        let text = match &m.content {
+            Some(MessageContent::Text(text)) => text.clone(),
+            Some(MessageContent::Parts(parts)) => parts
+                .iter()
+                .filter_map(|p| p.text())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => String::new(),
+        };
+        if !text.is_empty() {
+            out.push_str(&format!("{}: {}\n", m.role, text));
+        }
+    }
+    out
+}
+
+fn text_message(role: &str, text: &str) -> Message {
+    Message {
+        role: role.to_string(),
+        content: Some(MessageContent::Text(text.to_string())),
+        extra: serde_json::Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msgs(roles: &[&str]) -> Vec<Message> {
+        roles.iter().map(|r| text_message(r, "x")).collect()
+    }
+
+    #[test]
+    fn test_compressible_range_keeps_system_and_recent() {
+        let messages = msgs(&["system", "user", "assistant", "user", "assistant", "user"]);
+        assert_eq!(compressible_range(&messages, 2), Some((1, 4)));
+    }
+
+    #[test]
+    fn test_compressible_range_does_not_orphan_tool_messages() {
+        let messages = msgs(&["user", "assistant", "user", "assistant", "tool", "user"]);
+        // 保留区若从 tool 开始，需把发起 tool_calls 的 assistant 一并保留
+        assert_eq!(compressible_range(&messages, 2), Some((0, 3)));
+    }
+
+    #[test]
+    fn test_compressible_range_too_short() {
+        let messages = msgs(&["system", "user", "assistant"]);
+        assert_eq!(compressible_range(&messages, 2), None);
+    }
+}
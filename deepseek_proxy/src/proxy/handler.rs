@@ -1,7 +1,9 @@
 use crate::{
     auth::Claims,
-    error::AppError,
-    deepseek::ChatRequest,
+    error::{AppError, RateLimit},
+    deepseek::{upstreams::X_UPSTREAM, ChatRequest, MessageContent},
+    proxy::spam::SpamVerdict,
+    proxy::stream_transform::{build_pipeline, StreamTransform, TransformContext, TransformedStream},
     quota::QuotaStatus,
     AppState,
 };
@@ -15,170 +17,325 @@ use axum::{
     extract::State,
     http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Extension, Json,
+    Extension,
 };
-use futures::Stream;
 use bytes::Bytes;
-use std::pin::Pin;
-use std::task::{Context, Poll};
 
-/// 简单估算输入 tokens: 按空白分词 + 中文字符单字
-fn estimate_input_tokens(messages: &[crate::deepseek::Message]) -> u32 {
-    let mut count = 0u32;
-    for m in messages {
-        let text = m.content.as_str();
-        // 中文单字
-        count += text.chars().filter(|c| ('\u{4e00}'..='\u{9fff}').contains(c)).count() as u32;
-        // 英文/数字等按空白分词
-        for part in text.split_whitespace() {
-            if !part.is_empty() { count += 1; }
-        }
-    }
-    count
-}
+/// 配额反馈头（与 X-RateLimit-* 分开：周期配额耗尽返回的是 402 而不是 429）
+const X_QUOTA_LIMIT: &str = "x-quota-limit";
+const X_QUOTA_REMAINING: &str = "x-quota-remaining";
+const X_QUOTA_RESET: &str = "x-quota-reset";
 
-/// 统计输出 token 的流包装器：累计字节数，在 Drop 时估算 token 数 (粗略: 字节/4)
-struct CountingStream<S> {
-    inner: S,
-    bytes_acc: usize,
-    recorded: bool,
-    username: String,
-    real_output_recorded: bool,
+/// 低精度图片的固定 token 成本
+const IMAGE_TOKENS_LOW: u32 = 85;
+/// 高精度 / auto 图片的固定 token 成本
+const IMAGE_TOKENS_HIGH: u32 = 765;
+
+/// 简单估算文本 tokens: 按空白分词 + 中文字符单字
+pub(crate) fn estimate_text_tokens(text: &str) -> u32 {
+    // 中文单字
+    let cjk = text.chars().filter(|c| ('\u{4e00}'..='\u{9fff}').contains(c)).count() as u32;
+    // 英文/数字等按空白分词
+    cjk + text.split_whitespace().count() as u32
 }
 
-impl<S> CountingStream<S> {
-    fn new(inner: S, username: String) -> Self { Self { inner, bytes_acc: 0, recorded: false, username, real_output_recorded: false } }
+/// 估算 JSON 结构（工具定义、tool_calls）的 tokens：粗略按 字节/4
+fn estimate_json_tokens(value: &serde_json::Value) -> u32 {
+    (value.to_string().len() / 4) as u32
 }
 
-impl<S> Stream for CountingStream<S>
-where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
-{
-    type Item = Result<Bytes, reqwest::Error>;
-
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.inner).poll_next(cx) {
-            Poll::Ready(Some(Ok(chunk))) => {
-                self.bytes_acc += chunk.len();
-                // 尝试解析 usage
-                if !self.real_output_recorded {
-                    if let Ok(text) = std::str::from_utf8(&chunk) {
-                        for line in text.lines() {
-                            let line = line.trim();
-                            if line.starts_with("data:") {
-                                let json_part = line.trim_start_matches("data:").trim();
-                                if json_part == "[DONE]" { continue; }
-                                if let Ok(v) = serde_json::from_str::<serde_json::Value>(json_part) {
-                                    if let Some(usage) = v.get("usage") {
-                                        let completion = usage.get("completion_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
-                                        let prompt = usage.get("prompt_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
-                                        let cache_hit = usage.get("prompt_cache_hit_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
-                                        let cache_miss = usage.get("prompt_cache_miss_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
-                                        let reasoning = usage.get("completion_tokens_details").and_then(|d| d.get("reasoning_tokens")).and_then(|x| x.as_u64()).unwrap_or(0) as u32;
-                                        // 记录输出与输入
-                                        crate::metrics::METRICS.record_output_tokens(completion);
-                                        crate::metrics::METRICS.record_input_tokens(prompt); // 修正输入 gauge
-                                        crate::metrics::METRICS.record_prompt_cache_hit_tokens(cache_hit);
-                                        crate::metrics::METRICS.record_prompt_cache_miss_tokens(cache_miss);
-                                        tracing::debug!(user=%self.username, prompt_tokens=prompt, completion_tokens=completion, cache_hit=cache_hit, cache_miss=cache_miss, reasoning_tokens=reasoning, "使用真实 usage 字段记录 token 与缓存命中");
-                                        self.real_output_recorded = true;
-                                    }
-                                }
-                            }
-                        }
+/// 估算输入 tokens：文本、图片（按 detail 固定成本）、工具定义与 tool_calls
+pub fn estimate_input_tokens(request: &ChatRequest) -> u32 {
+    let mut count = 0u32;
+    for m in &request.messages {
+        match &m.content {
+            Some(MessageContent::Text(text)) => count += estimate_text_tokens(text),
+            Some(MessageContent::Parts(parts)) => {
+                for part in parts {
+                    if let Some(text) = part.text() {
+                        count += estimate_text_tokens(text);
+                    } else if part.is_image() {
+                        count += match part.image_detail() {
+                            "low" => IMAGE_TOKENS_LOW,
+                            _ => IMAGE_TOKENS_HIGH,
+                        };
                     }
                 }
-                Poll::Ready(Some(Ok(chunk)))
             }
-            other => other,
+            None => {}
         }
+        if let Some(tool_calls) = m.extra.get("tool_calls") {
+            count += estimate_json_tokens(tool_calls);
+        }
+    }
+    // 工具 schema 也会计入上游 prompt
+    if let Some(tools) = request.extra.get("tools") {
+        count += estimate_json_tokens(tools);
     }
+    count
 }
 
-impl<S> Drop for CountingStream<S> {
-    fn drop(&mut self) {
-        // 如果已经通过 usage 记录过真实 completion，则不再估算
-        if !self.recorded && !self.real_output_recorded {
-            let bytes = self.bytes_acc as u32;
-            // 粗略估算：假设平均 4 字节一个 token
-            let tokens = bytes / 4;
-            crate::metrics::METRICS.record_output_tokens(tokens);
-            tracing::debug!(user = %self.username, bytes = bytes, tokens = tokens, "输出 token 估算");
-            self.recorded = true;
+/// 上游模型列表路径
+const MODELS_PATH: &str = "/models";
+
+/// 代理模型列表请求（带内存缓存）
+///
+/// 缓存命中时直接返回，不消耗全局速率限制；未命中时才访问上游
+pub async fn proxy_models(State(state): State<AppState>) -> Result<Response, AppError> {
+    let (body, cache_status) = match state.deepseek_client.cached_metadata(MODELS_PATH) {
+        Some(body) => (body, "HIT"),
+        None => {
+            if let Err(wait_time) = state.global_rate_limiter.acquire().await {
+                tracing::warn!("全局速率限制：拒绝模型列表请求，建议等待 {:.2} 秒", wait_time);
+                crate::metrics::METRICS.record_rate_limit_rejection("global_bucket");
+                return Err(AppError::TooManyRequests(state.global_rate_limiter.rate_limit(wait_time)));
+            }
+            (state.deepseek_client.fetch_metadata(MODELS_PATH).await?, "MISS")
         }
-    }
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json"), (header::HeaderName::from_static("x-cache"), cache_status)],
+        body,
+    ).into_response())
 }
 
 /// 代理聊天请求到 DeepSeek API
 pub async fn proxy_chat(
     State(state): State<AppState>,
-    Extension(_token): Extension<String>,
+    Extension(token): Extension<String>,
     Extension(claims): Extension<Claims>,
-    Json(mut request): Json<ChatRequest>,
+    request_headers: HeaderMap,
+    body: Bytes,
 ) -> Result<Response, AppError> {
-    // 0. 全局速率限制检查（最优先，防止 DoS）
-    if let Err(wait_time) = state.global_rate_limiter.acquire().await {
-        tracing::warn!("全局速率限制：拒绝请求，建议等待 {:.2} 秒", wait_time);
-        crate::metrics::METRICS.rate_limit_rejections.inc();
-        return Err(AppError::TooManyRequests);
+    let arrived = std::time::Instant::now();
+    crate::metrics::METRICS.request_body_bytes.observe(body.len() as f64);
+    require_json_content_type(&request_headers)?;
+    let mut request: ChatRequest = serde_json::from_slice(&body)?;
+
+    // 0. 全局速率限制检查（最优先，防止 DoS）；放行时令牌桶的状态写入响应的限流反馈头
+    let rate_limit = match state.global_rate_limiter.acquire().await {
+        Ok(rate_limit) => rate_limit,
+        Err(wait_time) => {
+            tracing::warn!("全局速率限制：拒绝请求，建议等待 {:.2} 秒", wait_time);
+            crate::metrics::METRICS.record_rate_limit_rejection("global_bucket");
+            return Err(AppError::TooManyRequests(state.global_rate_limiter.rate_limit(wait_time)));
+        }
+    };
+
+    let user = state.user_manager.get_user(&claims.sub).await;
+    let user_tier = user.as_ref().map(|u| u.quota_tier.clone());
+    // 服务账户不受配额限制，不返回配额反馈头（限流反馈头照常返回）
+    let unlimited = user.as_ref().is_some_and(|u| u.unlimited);
+
+    // 管理员代管的 token 与服务账户可用 X-Upstream 选择其他上游（如预发布环境）
+    let requested_upstream = request_headers.get(X_UPSTREAM).and_then(|v| v.to_str().ok());
+    let upstream = state
+        .upstreams
+        .select(requested_upstream, unlimited || claims.impersonated_by.is_some())
+        .inspect_err(|e| tracing::warn!(user = %claims.sub, upstream = ?requested_upstream, error = %e, "拒绝按 X-Upstream 选择上游"))?;
+    if let Some((name, _)) = &upstream {
+        tracing::info!(user = %claims.sub, upstream = %name, impersonated_by = ?claims.impersonated_by, "按 X-Upstream 选用上游");
+    }
+
+    // 账户固定了模型时改用该模型；允许自选模型的账户只在请求未指定 model 时使用
+    match user.as_ref().and_then(|u| u.pinned_model.as_ref().map(|model| (model, u.model_override))) {
+        Some((pinned, model_override)) if request.model != *pinned && (!model_override || request.model.is_empty()) => {
+            tracing::debug!(user = %claims.sub, requested = %request.model, model = %pinned, "账户固定模型，改用指定模型");
+            request.model = pinned.clone();
+        }
+        None if request.model.is_empty() => return Err(AppError::BadRequest("缺少 model 字段".to_string())),
+        _ => {}
+    }
+
+    // 系统压力过高时优先拒绝低档次请求，避免小内存主机在流式响应中途 OOM
+    if state.load_shedder.should_shed(user_tier.as_deref()) {
+        let tier = user_tier.as_deref().unwrap_or("unknown");
+        tracing::warn!(user = %claims.sub, tier, "负载降级：拒绝请求");
+        crate::metrics::METRICS.load_shed_rejections.with_label_values(&[tier]).inc();
+        return Err(AppError::Overloaded);
+    }
+
+    // 图片数量与大小按档次检查（在扣费前）
+    let images = crate::proxy::vision::enforce_image_limits(&state.config.vision, user_tier.as_deref(), &request)?;
+    if images > 0 {
+        tracing::debug!(user = %claims.sub, images, "请求包含图片");
     }
 
+    // 重复提问检测：在扣费前拦截机器人循环，避免消耗配额
+    if let SpamVerdict::Suspected { prompt_hash, count, first } = state.spam_guard.check(&claims.sub, &request) {
+        crate::metrics::METRICS.spam_suspected.inc();
+        if first {
+            tracing::warn!(user = %claims.sub, prompt_hash = %prompt_hash, count, "疑似重复提问刷量，开始限流");
+            state.activity_logger.log_spam_suspected(&claims.sub, &prompt_hash, count).await;
+            state.notifier.notify_throttled(
+                &format!("spam_suspected:{}", claims.sub),
+                crate::notify::AlertEvent::new("spam_suspected", &claims.sub)
+                    .with_detail(format!("{} 秒内相同内容提交 {} 次", state.config.spam.window_seconds, count)),
+            );
+        }
+        // 相同内容移出窗口前持续拒绝
+        let spam = &state.config.spam;
+        return Err(AppError::TooManyRequests(RateLimit { limit: spam.max_duplicates as u32, remaining: 0, reset_after: Some(spam.window_seconds) }));
+    }
+
+    // 调用方的 metadata 只做记录与回显，大小受限
+    let metadata = crate::proxy::metadata::validate(&state.config.request_metadata, &request)?;
+
     // 1. 检查配额（不扣费）
     let quota_status = state.quota_manager
         .check_quota(&claims.sub)
         .await?;
 
-    match quota_status {
+    let (quota_used, quota_limit, quota_remaining, quota_reset_at) = match quota_status {
         QuotaStatus::Exceeded { used, limit, reset_at } => {
             tracing::warn!("用户 {} 配额已耗尽: {}/{}", claims.sub, used, limit);
             // 记录配额耗尽
             state.activity_logger.log_quota_exceeded(&claims.sub, used, limit).await;
+            state.notifier.notify_throttled(
+                &format!("quota_exhausted:{}", claims.sub),
+                crate::notify::AlertEvent::new("quota_exhausted", &claims.sub)
+                    .with_detail(format!("已用 {}/{}，重置时间 {}", used, limit, reset_at.to_rfc3339())),
+            );
             crate::metrics::METRICS.quota_status.with_label_values(&["exceeded"]).inc();
-            return Err(AppError::PaymentRequired {
+            let mut headers = HeaderMap::new();
+            rate_limit.insert_headers(&mut headers);
+            insert_quota_headers(&mut headers, limit, 0, reset_at);
+            let error = AppError::PaymentRequired {
                 used,
                 limit,
                 reset_at: reset_at.to_rfc3339(),
-            });
+            };
+            return Ok((headers, error).into_response());
         }
-        QuotaStatus::Ok { used, remaining, .. } => {
+        QuotaStatus::Ok { used, limit, remaining, credits, reset_at } => {
             tracing::debug!("用户 {} 配额检查通过: {}次已用, {}次剩余", claims.sub, used, remaining);
             // 记录配额检查
             state.activity_logger.log_quota_check(&claims.sub, used, remaining).await;
             crate::metrics::METRICS.quota_status.with_label_values(&["ok"]).inc();
+            (used, limit + credits, remaining, reset_at)
         }
+    };
+    // n > 1 时每个候选回复各计一次配额
+    let max_n = user_tier.as_deref().and_then(|tier| state.config.quota.max_n.for_tier(tier));
+    let choices = crate::proxy::sampling::enforce_choice_limit(max_n, &request)?;
+    if choices > quota_remaining && !unlimited {
+        tracing::warn!(user = %claims.sub, choices, remaining = quota_remaining, "剩余配额不足以生成全部候选回复");
+        let mut headers = HeaderMap::new();
+        rate_limit.insert_headers(&mut headers);
+        insert_quota_headers(&mut headers, quota_limit, quota_remaining, quota_reset_at);
+        let error = AppError::PaymentRequired {
+            used: quota_used,
+            limit: quota_limit,
+            reset_at: quota_reset_at.to_rfc3339(),
+        };
+        return Ok((headers, error).into_response());
     }
+    // 本次请求实际扣减的次数（含历史压缩的辅助调用），上游请求成功后一次性扣费
+    let mut charged = choices;
 
-    // 2. 通过用户名获取Token许可（统一的生命周期和并发控制）
-    let permit = state.login_limiter.acquire_permit_by_username(&claims.sub).await?;
+    // 采样参数按档次校验（clamp 或 reject）
+    let adjusted = crate::proxy::sampling::enforce_sampling_limits(&state.config.sampling, user_tier.as_deref(), &mut request)?;
+    if !adjusted.is_empty() {
+        tracing::info!(user = %claims.sub, params = ?adjusted, "采样参数超出允许范围，已夹取");
+    }
+
+    // 2. 通过 token 所属会话获取许可（统一的生命周期和并发控制；被挤出的会话需重新登录）
+    let permit = state.login_limiter
+        .acquire_permit(&claims.sub, &token)
+        .await?
+        .track_tier(user_tier.as_deref().unwrap_or("unknown"), arrived);
 
     // 3. 强制设置为流式
     request.stream = true;
 
+    // 沙箱档次一律使用配置的廉价模型
+    let sandbox = user_tier.as_deref().is_some_and(|tier| state.config.sandbox.is_sandbox(tier));
+    if sandbox && request.model != state.config.sandbox.model {
+        tracing::debug!(user = %claims.sub, requested = %request.model, model = %state.config.sandbox.model, "沙箱账户改用指定模型");
+        request.model = state.config.sandbox.model.clone();
+    }
+
+    // 按最终使用的模型检查能力（工具、图片、输出上限）
+    let capabilities = state.config.models.get(&request.model).cloned();
+    if let Err(e) = crate::proxy::capabilities::enforce_features(capabilities.as_ref(), &request, images) {
+        tracing::warn!(user = %claims.sub, model = %request.model, error = %e, "请求超出模型能力");
+        return Err(e);
+    }
+
+    // 历史超过预算时压缩较早的对话（失败则按原请求继续）；剩余配额须同时够支付压缩与回复，否则不压缩
+    let compression_weight = state.config.compression.quota_weight;
+    let compression_affordable = unlimited || choices.saturating_add(compression_weight) <= quota_remaining;
+    if state.config.compression.enabled && !compression_affordable {
+        tracing::debug!(user = %claims.sub, remaining = quota_remaining, "剩余配额不足以支付历史压缩，按原请求转发");
+    }
+    if state.config.compression.enabled && compression_affordable {
+        match crate::proxy::compression::compress_history(&state.deepseek_client, &state.config.compression, &mut request).await {
+            Ok(Some(outcome)) => {
+                crate::metrics::METRICS.context_compressions.with_label_values(&["success"]).inc();
+                tracing::info!(
+                    user = %claims.sub,
+                    messages = outcome.compressed_messages,
+                    tokens_before = outcome.tokens_before,
+                    tokens_after = outcome.tokens_after,
+                    "对话历史已压缩"
+                );
+                charged += compression_weight;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                crate::metrics::METRICS.context_compressions.with_label_values(&["failure"]).inc();
+                tracing::warn!(user = %claims.sub, error = %e, "对话历史压缩失败，使用原始请求");
+            }
+        }
+    }
+
     // 记录聊天请求（获取模型和消息数量）
     let model = request.model.clone();
     let message_count = request.messages.len();
+    let json_mode = request.json_mode();
     
+    // 同一用户重复发送相同的大段 system 提示时给出缓存对齐建议
+    let cache_hint = state.prompt_cache.inspect(&claims.sub, &request);
+    if let Some(hint) = cache_hint.as_ref().filter(|hint| hint.misaligned) {
+        tracing::debug!(user = %claims.sub, tokens = hint.tokens, repeats = hint.repeats, "重复的 system 提示不在消息开头，无法命中上游缓存");
+    }
+
     // 4. 估算输入 token
-    let input_tokens = estimate_input_tokens(&request.messages);
+    let input_tokens = estimate_input_tokens(&request);
     crate::metrics::METRICS.record_input_tokens(input_tokens);
     tracing::debug!(user = %claims.sub, tokens = input_tokens, "输入 token 估算");
+    crate::proxy::capabilities::enforce_context_window(capabilities.as_ref(), &request, input_tokens)?;
 
-    // 5. 转发到 DeepSeek API
-    let byte_stream = state.deepseek_client.chat_stream(request).await?;
+    // 5. 转发到 DeepSeek API（或 X-Upstream 选用的上游）
+    let client = upstream.as_ref().map_or(&state.deepseek_client, |(_, client)| client);
+    let byte_stream = client.chat_stream(request).await?;
 
-    // 6. 上游请求成功，现在扣费
-    state.quota_manager.increment_quota(&claims.sub).await?;
+    // 6. 上游请求成功，现在扣费；回复与压缩作为一笔记入扣费日志。客户端中途断开不退还，
+    //    只有进程在响应结束前退出时，下次启动按 [quota] crash_recovery 整笔处理遗留的记录
+    let charge = state.quota_manager.increment_quota_by(&claims.sub, charged).await?;
+    let charge_guard = state.charge_journal.open(&claims.sub, charge).await;
 
     // 记录聊天请求成功
-    state.activity_logger.log_chat_request(&claims.sub, &model, message_count, None).await;
-    tracing::info!("用户 {} 发起聊天请求: 模型={}, 消息数={}", claims.sub, model, message_count);
+    state.activity_logger.log_chat_request(&claims.sub, &model, message_count, None, metadata.clone()).await;
+    tracing::info!("用户 {} 发起聊天请求: 模型={}, 消息数={}, 上游={}", claims.sub, model, message_count, upstream.as_ref().map_or("default", |(name, _)| *name));
     crate::metrics::METRICS.chat_requests.with_label_values(&["success"]).inc();
 
-    // 7. 用 PermitGuardedStream 包装流，确保 permit 在整个流的生命周期内被持有
-    let guarded_stream = crate::proxy::PermitGuardedStream::new(byte_stream, permit);
-    // 再包一层 CountingStream 做输出 token 统计
-    let counting_stream = CountingStream::new(guarded_stream, claims.sub.clone());
-    let stream_body = Body::from_stream(counting_stream);
+    // 7. 按配置的顺序串联流式变换；许可证作为第一个变换随管道存活，确保 permit 在整个流的生命周期内被持有
+    let mut transforms: Vec<Box<dyn StreamTransform>> =
+        vec![Box::new(permit), Box::new(charge_guard), Box::new(crate::shutdown_report::ActiveStream::track())];
+    transforms.extend(build_pipeline(&TransformContext {
+        config: &state.config,
+        username: &claims.sub,
+        tier: user_tier.as_deref(),
+        sandbox,
+        choices,
+        json_mode,
+        metadata,
+        activity_logger: &state.activity_logger,
+    }));
+    let stream_body = Body::from_stream(TransformedStream::new(byte_stream, transforms));
 
     // 8. 构建 SSE 响应头
     let mut headers = HeaderMap::new();
@@ -194,6 +351,112 @@ pub async fn proxy_chat(
         header::CONNECTION, 
         CONNECTION_KEEP_ALIVE.parse().map_err(|_| AppError::InternalError("无效的Connection头".to_string()))?
     );
+    rate_limit.insert_headers(&mut headers);
+    if !unlimited {
+        insert_quota_headers(&mut headers, quota_limit, quota_remaining.saturating_sub(charged), quota_reset_at);
+    }
+    if let Some(value) = upstream.and_then(|(name, _)| header::HeaderValue::from_str(name).ok()) {
+        headers.insert(header::HeaderName::from_static(X_UPSTREAM), value);
+    }
+    if let Some(hint) = cache_hint {
+        hint.insert_headers(&mut headers);
+    }
 
     Ok((StatusCode::OK, headers, stream_body).into_response())
 }
+
+/// 请求体须声明为 JSON（`application/json` 或 `application/*+json`），与 axum `Json` 提取器的校验一致
+fn require_json_content_type(headers: &HeaderMap) -> Result<(), AppError> {
+    let mime = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json")) {
+        return Ok(());
+    }
+    Err(AppError::UnsupportedMediaType("请求头须为 Content-Type: application/json".to_string()))
+}
+
+/// 写入配额反馈头：Limit 为本周期总额度（含预付费额度），
+/// Remaining 为扣费后剩余次数，Reset 为距离重置的秒数
+pub(crate) fn insert_quota_headers(
+    headers: &mut HeaderMap,
+    limit: u32,
+    remaining: u32,
+    reset_at: chrono::DateTime<chrono::FixedOffset>,
+) {
+    let reset_in = (reset_at.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    headers.insert(header::HeaderName::from_static(X_QUOTA_LIMIT), limit.into());
+    headers.insert(header::HeaderName::from_static(X_QUOTA_REMAINING), remaining.into());
+    headers.insert(header::HeaderName::from_static(X_QUOTA_RESET), reset_in.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_headers() {
+        let mut headers = HeaderMap::new();
+        let reset_at = (chrono::Utc::now() + chrono::Duration::seconds(120)).fixed_offset();
+        insert_quota_headers(&mut headers, 100, 42, reset_at);
+        assert_eq!(headers[X_QUOTA_LIMIT], "100");
+        assert_eq!(headers[X_QUOTA_REMAINING], "42");
+        let reset: i64 = headers[X_QUOTA_RESET].to_str().unwrap().parse().unwrap();
+        assert!((118..=120).contains(&reset));
+    }
+
+    #[test]
+    fn test_require_json_content_type() {
+        let headers = |value: &'static str| HeaderMap::from_iter([(header::CONTENT_TYPE, value.parse().unwrap())]);
+        assert!(require_json_content_type(&headers("application/json")).is_ok());
+        assert!(require_json_content_type(&headers("Application/JSON; charset=utf-8")).is_ok());
+        assert!(require_json_content_type(&headers("application/vnd.api+json")).is_ok());
+        assert!(matches!(require_json_content_type(&headers("text/plain")), Err(AppError::UnsupportedMediaType(_))));
+        assert!(require_json_content_type(&HeaderMap::new()).is_err());
+    }
+
+    fn request(body: serde_json::Value) -> ChatRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_estimate_input_tokens_text() {
+        let req = request(serde_json::json!({
+            "model": "deepseek-chat",
+            "stream": true,
+            "messages": [{"role": "user", "content": "hello world 你好"}]
+        }));
+        assert_eq!(estimate_input_tokens(&req), 5);
+    }
+
+    #[test]
+    fn test_estimate_input_tokens_images_and_tools() {
+        let req = request(serde_json::json!({
+            "model": "deepseek-chat",
+            "stream": true,
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "describe"},
+                {"type": "image_url", "image_url": {"url": "https://x/a.png", "detail": "low"}},
+                {"type": "image_url", "image_url": {"url": "https://x/b.png"}}
+            ]}],
+            "tools": [{"type": "function", "function": {"name": "f", "parameters": {}}}]
+        }));
+        let tools_tokens = estimate_json_tokens(req.extra.get("tools").unwrap());
+        assert!(tools_tokens > 0);
+        assert_eq!(estimate_input_tokens(&req), 1 + IMAGE_TOKENS_LOW + IMAGE_TOKENS_HIGH + tools_tokens);
+    }
+
+    #[test]
+    fn test_structured_content_round_trips() {
+        let body = serde_json::json!({
+            "role": "assistant",
+            "tool_calls": [{"id": "1", "type": "function", "function": {"name": "f", "arguments": "{}"}}]
+        });
+        let msg: crate::deepseek::Message = serde_json::from_value(body.clone()).unwrap();
+        assert!(msg.content.is_none());
+        assert_eq!(serde_json::to_value(&msg).unwrap(), body);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::proxy::sse::{SseEvent, SseParser};
+use crate::proxy::stream_transform::{StreamTransform, TransformOutput};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+
+/// JSON 模式（`response_format.type = "json_object"`）输出校验：流结束时检查每个候选回复拼接后的正文
+/// 能否解析为 JSON，不能时在 `data: [DONE]` 之前追加结构化错误事件，避免下游自动化处理截断的对象时崩溃
+///
+/// 流中已有错误事件（如响应字节上限截断）或上游出错中断时不再校验
+pub struct JsonValidateTransform {
+    parser: SseParser,
+    /// 候选回复下标 -> 已拼接的正文
+    contents: BTreeMap<u64, String>,
+    /// 候选回复下标 -> finish_reason
+    finish_reasons: BTreeMap<u64, String>,
+    /// 已校验（或不再需要校验），之后的数据原样透传
+    checked: bool,
+}
+
+impl JsonValidateTransform {
+    pub fn new() -> Self {
+        Self {
+            parser: SseParser::new(),
+            contents: BTreeMap::new(),
+            finish_reasons: BTreeMap::new(),
+            checked: false,
+        }
+    }
+
+    fn collect(&mut self, event: &SseEvent) {
+        let Some(chunk) = event.json() else { return };
+        if chunk.get("error").is_some() {
+            self.checked = true;
+            return;
+        }
+        let Some(choices) = chunk.get("choices").and_then(|c| c.as_array()) else { return };
+        for choice in choices {
+            let index = choice.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            if let Some(text) = choice.pointer("/delta/content").and_then(|c| c.as_str()) {
+                self.contents.entry(index).or_default().push_str(text);
+            }
+            if let Some(reason) = choice.get("finish_reason").and_then(|r| r.as_str()) {
+                self.finish_reasons.insert(index, reason.to_string());
+            }
+        }
+    }
+
+    /// 校验全部候选回复，返回需要追加的错误事件
+    fn check(&mut self) -> Vec<Bytes> {
+        if self.checked {
+            return Vec::new();
+        }
+        self.checked = true;
+        self.contents
+            .iter()
+            .filter(|(_, content)| serde_json::from_str::<serde_json::Value>(content).is_err())
+            .map(|(index, content)| {
+                let finish_reason = self.finish_reasons.get(index).map(String::as_str);
+                crate::metrics::METRICS.json_output_invalid.inc();
+                tracing::warn!(choice = index, bytes = content.len(), finish_reason, "JSON 模式的输出不是合法 JSON");
+                SseEvent::encode_json(&serde_json::json!({
+                    "error": {
+                        "code": "invalid_json_output",
+                        "message": "JSON 模式的输出不是合法 JSON（可能被截断）",
+                        "choice": index,
+                        "finish_reason": finish_reason,
+                    }
+                }))
+            })
+            .collect()
+    }
+}
+
+impl Default for JsonValidateTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamTransform for JsonValidateTransform {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        if self.checked {
+            return TransformOutput::Continue(vec![chunk]);
+        }
+        let mut out = Vec::new();
+        for event in self.parser.feed(&chunk) {
+            if self.checked {
+                out.push(event.raw);
+                continue;
+            }
+            if event.is_done() {
+                out.extend(self.check());
+            } else {
+                self.collect(&event);
+            }
+            out.push(event.raw);
+        }
+        if self.checked {
+            out.push(self.parser.take_remainder());
+            out.retain(|b| !b.is_empty());
+        }
+        TransformOutput::Continue(out)
+    }
+
+    fn on_flush(&mut self) -> Vec<Bytes> {
+        let remainder = self.parser.take_remainder();
+        if remainder.is_empty() { Vec::new() } else { vec![remainder] }
+    }
+
+    fn on_end(&mut self) -> Vec<Bytes> {
+        // 上游没有发送 [DONE] 就正常结束时在最后校验
+        let mut out = self.on_flush();
+        out.extend(self.check());
+        out
+    }
+
+    fn on_error(&mut self) {
+        self.checked = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(parts: &[&'static str]) -> String {
+        let mut t = JsonValidateTransform::new();
+        let mut out = Vec::new();
+        for part in parts {
+            if let TransformOutput::Continue(chunks) = t.on_chunk(Bytes::from_static(part.as_bytes())) {
+                out.extend(chunks);
+            }
+        }
+        out.extend(t.on_end());
+        out.iter().map(|b| String::from_utf8_lossy(b).into_owned()).collect()
+    }
+
+    #[test]
+    fn test_valid_json_passes_through() {
+        let parts = [
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"{\\\"a\\\":\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"1}\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        ];
+        assert_eq!(run(&parts), parts.concat());
+    }
+
+    #[test]
+    fn test_truncated_json_gets_error_event_before_done() {
+        let body = run(&[
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"{\\\"a\\\":[1,\"},\"finish_reason\":\"length\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ]);
+        let error = body.find("invalid_json_output").unwrap();
+        assert!(error < body.find("data: [DONE]").unwrap());
+        assert!(body.contains("\"finish_reason\":\"length\""));
+
+        // 已有错误事件（如字节上限截断）时不重复报错
+        let body = run(&["data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"{\"}}]}\n\n", "data: {\"error\":{}}\n\ndata: [DONE]\n\n"]);
+        assert!(!body.contains("invalid_json_output"));
+    }
+}
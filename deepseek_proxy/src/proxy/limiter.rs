@@ -2,51 +2,58 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, Semaphore};
-use futures::Stream;
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use bytes::Bytes;
-
 
 /// Token 许可证
 pub struct TokenPermit {
     _permit: tokio::sync::OwnedSemaphorePermit,
+    /// 计入 permits_held 指标的档次（见 track_tier）
+    tier: Option<String>,
 }
 
-/// 持有许可证的流包装器
-/// 确保许可证在整个流的生命周期内都被持有
-pub struct PermitGuardedStream<S> {
-    stream: S,
-    _permit: TokenPermit,
+impl TokenPermit {
+    /// 记录该档次从请求到达到获得许可的等待时间，并在许可存活期间计入 permits_held
+    pub fn track_tier(mut self, tier: &str, arrived: Instant) -> Self {
+        let metrics = &crate::metrics::METRICS;
+        metrics.permit_wait.with_label_values(&[tier]).observe(arrived.elapsed().as_secs_f64());
+        metrics.permits_held.with_label_values(&[tier]).inc();
+        self.tier = Some(tier.to_string());
+        self
+    }
 }
 
-impl<S> PermitGuardedStream<S> {
-    pub fn new(stream: S, permit: TokenPermit) -> Self {
-        Self {
-            stream,
-            _permit: permit,
+impl Drop for TokenPermit {
+    fn drop(&mut self) {
+        if let Some(tier) = &self.tier {
+            crate::metrics::METRICS.permits_held.with_label_values(&[tier]).dec();
         }
     }
 }
 
-impl<S> Stream for PermitGuardedStream<S>
-where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
-{
-    type Item = Result<Bytes, reqwest::Error>;
+/// 一个登录会话（一个 token）
+struct Session {
+    token: String,
+    /// 登录来源 IP；限制会话数时同一 IP 复用同一会话
+    ip: Option<String>,
+    expires_at: Instant,
+    /// 管理员代管会话：不复用给用户登录，也不计入会话数上限
+    impersonated: bool,
+}
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.stream).poll_next(cx)
-    }
+/// 用户的全部会话：按创建先后排列，共享同一个并发信号量（每个用户同时只处理 1 个请求）
+struct UserSessions {
+    semaphore: Arc<Semaphore>,
+    sessions: Vec<Session>,
 }
 
 /// 统一Token管理器 - 管理Token生命周期和并发控制
 #[derive(Clone)]
 pub struct LoginLimiter {
-    /// 用户名 -> (token, semaphore, 过期时间)
-    cache: Arc<Mutex<HashMap<String, (String, Arc<Semaphore>, Instant)>>>,
+    /// 用户名 -> 会话列表与信号量
+    cache: Arc<Mutex<HashMap<String, UserSessions>>>,
     /// token 有效期
     ttl: Duration,
+    /// 已签发的 refresh token（撤销会话时一并吊销）
+    refresh_tokens: Option<Arc<crate::auth::refresh::RefreshTokenStore>>,
 }
 
 impl LoginLimiter {
@@ -54,41 +61,88 @@ impl LoginLimiter {
         Self {
             cache: Arc::new(Mutex::new(HashMap::new())),
             ttl: Duration::from_secs(ttl_seconds), // 使用配置的值
+            refresh_tokens: None,
         }
     }
 
+    /// 撤销会话时同时吊销该用户的 refresh token
+    pub fn with_refresh_tokens(mut self, store: Arc<crate::auth::refresh::RefreshTokenStore>) -> Self {
+        self.refresh_tokens = Some(store);
+        self
+    }
+
+    /// 懒清理：移除过期会话以及没有会话的用户，返回清理的会话数
+    fn prune(cache: &mut HashMap<String, UserSessions>, now: Instant) -> usize {
+        let mut cleaned = 0;
+        cache.retain(|_, user| {
+            let before = user.sessions.len();
+            user.sessions.retain(|s| now < s.expires_at);
+            cleaned += before - user.sessions.len();
+            !user.sessions.is_empty()
+        });
+        cleaned
+    }
+
     /// 获取或生成 token
     /// 如果在有效期内已经登录过，返回缓存的 token（有效期由 ttl 参数决定，最多 60 秒）
-    pub async fn get_or_generate<F, E>(&self, username: &str, generate_fn: F) -> Result<String, E>
+    ///
+    /// 未限制会话数（max_sessions 为 None）时所有来源共用一个会话；
+    /// 限制时每个来源 IP 各持有一个会话，超过上限淘汰最早的会话（其 token 随即失效）
+    pub async fn get_or_generate<F, E>(
+        &self,
+        username: &str,
+        ip: Option<&str>,
+        max_sessions: Option<usize>,
+        generate_fn: F,
+    ) -> Result<String, E>
     where
         F: FnOnce() -> Result<String, E>,
     {
         let now = Instant::now();
         let mut cache = self.cache.lock().await;
 
-        // 懒清理：清理所有过期的缓存条目
-        let before_count = cache.len();
-        cache.retain(|_, (_, _, expires_at)| now < *expires_at);
-        let after_count = cache.len();
-        let cleaned = before_count - after_count;
-        
+        let cleaned = Self::prune(&mut cache, now);
         if cleaned > 0 {
-            tracing::debug!("LoginLimiter 清理了 {} 个过期缓存条目，剩余 {} 个", cleaned, after_count);
+            tracing::debug!("LoginLimiter 清理了 {} 个过期会话，剩余 {} 个用户", cleaned, cache.len());
         }
 
         // 检查缓存
-        if let Some((token, _, expires_at)) = cache.get(username) {
-            if now < *expires_at {
+        if let Some(user) = cache.get(username) {
+            let mut own = user.sessions.iter().filter(|s| !s.impersonated);
+            let reusable = match max_sessions {
+                None => own.next_back(),
+                Some(_) => own.find(|s| s.ip.as_deref() == ip),
+            };
+            if let Some(session) = reusable {
                 tracing::debug!("用户 {} 使用缓存 token", username);
-                return Ok(token.clone());
+                return Ok(session.token.clone());
             }
         }
 
         // 生成新 token
         let token = generate_fn()?;
-        let expires_at = now + self.ttl;
-        let semaphore = Arc::new(Semaphore::new(1)); // 新 token 创建新的信号量
-        cache.insert(username.to_string(), (token.clone(), semaphore, expires_at));
+        let user = cache.entry(username.to_string()).or_insert_with(|| UserSessions {
+            semaphore: Arc::new(Semaphore::new(1)), // 用户没有存活会话时创建新的信号量
+            sessions: Vec::new(),
+        });
+        user.sessions.push(Session {
+            token: token.clone(),
+            ip: ip.map(str::to_string),
+            expires_at: now + self.ttl,
+            impersonated: false,
+        });
+        if let Some(max) = max_sessions {
+            let mut excess = user.sessions.iter().filter(|s| !s.impersonated).count().saturating_sub(max.max(1));
+            user.sessions.retain(|s| {
+                if excess == 0 || s.impersonated {
+                    return true;
+                }
+                excess -= 1;
+                crate::metrics::METRICS.sessions_evicted.inc();
+                tracing::info!(user = %username, evicted_ip = ?s.ip, max_sessions = max, "会话数超过上限，淘汰最早的会话");
+                false
+            });
+        }
 
         tracing::debug!("用户 {} 生成新 token，有效期 {} 秒", username, self.ttl.as_secs());
 
@@ -102,79 +156,145 @@ impl LoginLimiter {
         F: FnOnce() -> Result<String, E>,
         E: From<crate::error::AppError>,
     {
-        let now = Instant::now();
+        let token = self.get_or_generate(username, None, None, generate_fn).await?;
+        let permit = self.acquire_permit(username, &token).await?;
+        Ok((token, permit))
+    }
+
+    /// 登记管理员代管 token 的会话（与用户共享并发许可，过期时间由代管有效期决定）
+    pub async fn add_impersonation_session(&self, username: &str, token: &str, ttl_seconds: u64) {
         let mut cache = self.cache.lock().await;
+        Self::prune(&mut cache, Instant::now());
+        let user = cache.entry(username.to_string()).or_insert_with(|| UserSessions {
+            semaphore: Arc::new(Semaphore::new(1)),
+            sessions: Vec::new(),
+        });
+        user.sessions.push(Session {
+            token: token.to_string(),
+            ip: None,
+            expires_at: Instant::now() + Duration::from_secs(ttl_seconds),
+            impersonated: true,
+        });
+    }
 
-        // 懒清理：清理所有过期的缓存条目
-        let before_count = cache.len();
-        cache.retain(|_, (_, _, expires_at)| now < *expires_at);
-        let after_count = cache.len();
-        let cleaned = before_count - after_count;
-        
-        if cleaned > 0 {
-            tracing::debug!("TokenManager 清理了 {} 个过期Token，剩余 {} 个", cleaned, after_count);
+    /// 撤销用户的全部会话与 refresh token（用户被擦除或归档时调用），返回被移除会话的 token
+    pub async fn revoke(&self, username: &str) -> Vec<String> {
+        let removed = self.cache.lock().await.remove(username);
+        if let Some(store) = &self.refresh_tokens {
+            if let Err(e) = store.revoke_user(username).await {
+                tracing::warn!(user = %username, error = %e, "吊销 refresh token 失败");
+            }
         }
+        removed.map(|user| user.sessions.into_iter().map(|s| s.token).collect()).unwrap_or_default()
+    }
 
-        // 检查缓存
-        if let Some((token, semaphore, expires_at)) = cache.get(username) {
-            if now < *expires_at {
-                // 尝试获取信号量许可
-                let permit = semaphore.clone()
-                    .try_acquire_owned()
-                    .map_err(|_| {
-                        tracing::warn!("用户 {} 的Token已有请求正在处理", username);
-                        crate::error::AppError::TooManyRequests
-                    })?;
-
-                tracing::debug!("用户 {} 使用缓存Token并获得处理许可", username);
-                return Ok((token.clone(), TokenPermit { _permit: permit }));
+    /// 移除单个会话（用户注销时调用），再次登录会签发新 token
+    pub async fn remove_session(&self, username: &str, token: &str) {
+        let mut cache = self.cache.lock().await;
+        if let Some(user) = cache.get_mut(username) {
+            user.sessions.retain(|s| s.token != token);
+            if user.sessions.is_empty() {
+                cache.remove(username);
             }
         }
-
-        // 生成新 token 和信号量
-        let token = generate_fn()?;
-        let expires_at = now + self.ttl;
-        let semaphore = Arc::new(Semaphore::new(1));
-        
-        // 立即获取新Token的许可
-        let permit = semaphore.clone()
-            .try_acquire_owned()
-            .map_err(|_| crate::error::AppError::InternalError("新Token信号量获取失败".to_string()))?;
-
-        cache.insert(username.to_string(), (token.clone(), semaphore, expires_at));
-
-        tracing::debug!("用户 {} 生成新Token并获得处理许可，有效期 {} 秒", username, self.ttl.as_secs());
-
-        Ok((token, TokenPermit { _permit: permit }))
     }
 
-    /// 通过用户名获取Token许可（用于已验证的请求）
-    /// 如果用户有有效Token，返回许可；否则返回错误要求重新登录
-    pub async fn acquire_permit_by_username(&self, username: &str) -> Result<TokenPermit, crate::error::AppError> {
+    /// 获取Token许可（用于已验证的请求）
+    /// token 属于用户的有效会话时返回许可；会话已过期或已被淘汰时要求重新登录
+    pub async fn acquire_permit(&self, username: &str, token: &str) -> Result<TokenPermit, crate::error::AppError> {
         let now = Instant::now();
         let mut cache = self.cache.lock().await;
 
         // 懒清理
-        cache.retain(|_, (_, _, expires_at)| now < *expires_at);
+        Self::prune(&mut cache, now);
 
-        // 查找用户的有效Token
-        if let Some((_, semaphore, expires_at)) = cache.get(username) {
-            if now < *expires_at {
+        // 查找 token 对应的会话
+        if let Some(user) = cache.get(username) {
+            if user.sessions.iter().any(|s| s.token == token) {
                 // 尝试获取许可
-                let permit = semaphore.clone()
+                let permit = user.semaphore.clone()
                     .try_acquire_owned()
                     .map_err(|_| {
                         tracing::warn!("用户 {} 已有请求正在处理", username);
-                        crate::error::AppError::TooManyRequests
+                        crate::metrics::METRICS.record_rate_limit_rejection("per_user_permit");
+                        // 同一用户只允许一个在途请求，恢复时间取决于该请求何时结束
+                        crate::error::AppError::TooManyRequests(crate::error::RateLimit { limit: 1, remaining: 0, reset_after: None })
                     })?;
 
                 tracing::debug!("用户 {} 获得请求处理许可", username);
-                return Ok(TokenPermit { _permit: permit });
+                return Ok(TokenPermit { _permit: permit, tier: None });
             }
         }
 
-        // 没有有效Token，需要重新登录
-        Err(crate::error::AppError::Unauthorized("Token已过期，请重新登录".to_string()))
+        // 没有有效会话，需要重新登录
+        Err(crate::error::AppError::Unauthorized("Token已过期或会话已被挤出，请重新登录".to_string()))
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+
+    async fn login(limiter: &LoginLimiter, ip: &str, max: Option<usize>, n: &mut u32) -> String {
+        limiter
+            .get_or_generate("alice", Some(ip), max, || -> Result<String, AppError> {
+                *n += 1;
+                Ok(format!("t{}", n))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_session_cap_evicts_oldest() {
+        let limiter = LoginLimiter::new(60);
+        let mut n = 0;
+        let a = login(&limiter, "10.0.0.1", Some(2), &mut n).await;
+        assert_eq!(login(&limiter, "10.0.0.1", Some(2), &mut n).await, a);
+        let b = login(&limiter, "10.0.0.2", Some(2), &mut n).await;
+        let c = login(&limiter, "10.0.0.3", Some(2), &mut n).await;
+
+        assert!(limiter.acquire_permit("alice", &a).await.is_err());
+        drop(limiter.acquire_permit("alice", &b).await.unwrap());
+        // 同一用户的会话共享并发许可
+        let _held = limiter.acquire_permit("alice", &c).await.unwrap();
+        assert!(matches!(limiter.acquire_permit("alice", &b).await, Err(AppError::TooManyRequests(crate::error::RateLimit { limit: 1, remaining: 0, reset_after: None }))));
+    }
+
+    #[tokio::test]
+    async fn test_tracked_permit_counts_per_tier() {
+        let limiter = LoginLimiter::new(60);
+        let mut n = 0;
+        let token = login(&limiter, "10.0.0.1", None, &mut n).await;
+        let held = || crate::metrics::METRICS.permits_held.with_label_values(&["test-fairness"]).get();
+
+        let permit = limiter.acquire_permit("alice", &token).await.unwrap().track_tier("test-fairness", Instant::now());
+        assert_eq!(held(), 1);
+        drop(permit);
+        assert_eq!(held(), 0);
+        let waits = crate::metrics::METRICS.permit_wait.with_label_values(&["test-fairness"]).get_sample_count();
+        assert_eq!(waits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_uncapped_sessions_share_token() {
+        let limiter = LoginLimiter::new(60);
+        let mut n = 0;
+        let a = login(&limiter, "10.0.0.1", None, &mut n).await;
+        assert_eq!(login(&limiter, "10.0.0.2", None, &mut n).await, a);
+    }
+
+    #[tokio::test]
+    async fn test_impersonation_session_is_never_handed_out() {
+        let limiter = LoginLimiter::new(60);
+        limiter.add_impersonation_session("alice", "admin-token", 300).await;
+        let mut n = 0;
+        let a = login(&limiter, "10.0.0.1", None, &mut n).await;
+        assert_ne!(a, "admin-token");
+        // 不计入上限，也不会被挤出
+        login(&limiter, "10.0.0.2", Some(1), &mut n).await;
+        drop(limiter.acquire_permit("alice", "admin-token").await.unwrap());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{
+    config::RequestMetadataConfig,
+    deepseek::ChatRequest,
+    error::AppError,
+    proxy::sse::{SseEvent, SseParser},
+    proxy::stream_transform::{StreamTransform, TransformOutput},
+};
+use bytes::Bytes;
+use serde_json::Value;
+
+/// 检查请求携带的 metadata 大小；未携带时返回 None
+pub fn validate(cfg: &RequestMetadataConfig, request: &ChatRequest) -> Result<Option<Value>, AppError> {
+    let Some(metadata) = &request.metadata else {
+        return Ok(None);
+    };
+    let value = Value::Object(metadata.clone());
+    let size = value.to_string().len();
+    if size > cfg.max_bytes {
+        return Err(AppError::BadRequest(format!(
+            "metadata 过大: {} 字节，上限 {} 字节",
+            size, cfg.max_bytes
+        )));
+    }
+    Ok(Some(value))
+}
+
+/// 在上游带 usage 的事件中回显请求的 metadata，便于调用方按自己的任务 ID 对账
+///
+/// 上游没有返回 usage 时不回显
+pub struct MetadataEchoTransform {
+    metadata: Value,
+    echoed: bool,
+    /// 回显前按事件下发，不完整的事件留在解析器中
+    parser: SseParser,
+}
+
+impl MetadataEchoTransform {
+    pub fn new(metadata: Value) -> Self {
+        Self { metadata, echoed: false, parser: SseParser::new() }
+    }
+
+    /// 改写带 usage 的事件；不需要改写时返回 None
+    fn rewrite(&self, event: &SseEvent) -> Option<Bytes> {
+        let mut chunk = event.json()?;
+        if chunk.get("usage").is_none_or(Value::is_null) {
+            return None;
+        }
+        chunk.as_object_mut()?.insert("metadata".to_string(), self.metadata.clone());
+        Some(SseEvent::encode_json(&chunk))
+    }
+}
+
+impl StreamTransform for MetadataEchoTransform {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        if self.echoed {
+            return TransformOutput::Continue(vec![chunk]);
+        }
+        let mut out = Vec::new();
+        for event in self.parser.feed(&chunk) {
+            let rewritten = if self.echoed { None } else { self.rewrite(&event) };
+            match rewritten {
+                Some(rewritten) => {
+                    self.echoed = true;
+                    out.push(rewritten);
+                }
+                None => out.push(event.raw),
+            }
+        }
+        if self.echoed {
+            out.push(self.parser.take_remainder());
+            out.retain(|b| !b.is_empty());
+        }
+        TransformOutput::Continue(out)
+    }
+
+    fn on_flush(&mut self) -> Vec<Bytes> {
+        let remainder = self.parser.take_remainder();
+        if remainder.is_empty() { Vec::new() } else { vec![remainder] }
+    }
+
+    fn on_end(&mut self) -> Vec<Bytes> {
+        self.on_flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_size_limit() {
+        let cfg = RequestMetadataConfig { max_bytes: 32 };
+        let mut request: ChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "deepseek-chat",
+            "messages": [],
+            "stream": true,
+            "metadata": {"job_id": "job-42"},
+        }))
+        .unwrap();
+        assert_eq!(validate(&cfg, &request).unwrap().unwrap()["job_id"], "job-42");
+        // metadata 不转发上游
+        assert!(serde_json::to_value(&request).unwrap().get("metadata").is_none());
+
+        request.metadata.as_mut().unwrap().insert("note".to_string(), Value::from("x".repeat(40)));
+        assert!(matches!(validate(&cfg, &request), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_echo_into_usage_chunk_once() {
+        let mut echo = MetadataEchoTransform::new(serde_json::json!({"job_id": "job-42"}));
+        let plain = Bytes::from_static(b"data: {\"choices\":[],\"usage\":null}\n\n");
+        let TransformOutput::Continue(out) = echo.on_chunk(plain.clone()) else { panic!() };
+        assert_eq!(out, vec![plain]);
+
+        // usage 事件跨数据块到达
+        assert!(matches!(echo.on_chunk(Bytes::from_static(b"data: {\"choices\":[],")), TransformOutput::Continue(v) if v.is_empty()));
+        let usage = Bytes::from_static(b"\"usage\":{\"total_tokens\":3}}\n\ndata: [DONE]\n\n");
+        let TransformOutput::Continue(out) = echo.on_chunk(usage.clone()) else { panic!() };
+        let text: String = out.iter().map(|b| String::from_utf8_lossy(b).into_owned()).collect();
+        let json: Value = serde_json::from_str(text.lines().next().unwrap().strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(json["metadata"]["job_id"], "job-42");
+        assert_eq!(json["usage"]["total_tokens"], 3);
+        assert!(text.ends_with("data: [DONE]\n\n"));
+
+        let TransformOutput::Continue(out) = echo.on_chunk(usage.clone()) else { panic!() };
+        assert_eq!(out, vec![usage]);
+    }
+}
@@ -1,6 +1,19 @@
+pub mod audio;
+pub mod capabilities;
+pub mod coalesce;
+pub mod compression;
 pub mod handler;
+pub mod json_validate;
 pub mod limiter;
+pub mod metadata;
+pub mod prompt_cache;
 pub mod rate_limiter;
+pub mod sampling;
+pub mod spam;
+pub mod sse;
+pub mod stream_transform;
+pub mod vision;
+pub mod watermark;
 
 pub use handler::*;
 pub use limiter::*;
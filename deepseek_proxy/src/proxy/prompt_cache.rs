@@ -0,0 +1,227 @@
+//! 重复系统提示检测：同一用户连续请求重复发送相同的大段 system 提示时，给出上游前缀缓存的对齐建议
+//!
+//! 上游（DeepSeek）按请求前缀命中缓存：system 提示只有位于消息列表最前面、且前面的内容不变时才能命中。
+//! 重复的 system 提示不在开头时每次都按未命中计费，响应头 `x-prompt-cache-advice` 提示调整顺序，
+//! 管理接口 `/admin/cache-stats` 汇总各用户的重复次数与估算的未命中成本。只保存提示的哈希，不保存原文
+
+use crate::{
+    config::PromptCacheConfig,
+    deepseek::{ChatRequest, Message, MessageContent},
+    proxy::estimate_text_tokens,
+};
+use axum::http::{header::HeaderName, HeaderMap};
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// 本次请求重复上一次 system 提示的连续次数
+const X_PROMPT_CACHE_REPEAT: &str = "x-prompt-cache-repeat";
+/// 对齐建议（当前只有 move-system-prompt-first）
+const X_PROMPT_CACHE_ADVICE: &str = "x-prompt-cache-advice";
+const ADVICE_MOVE_SYSTEM_FIRST: &str = "move-system-prompt-first";
+
+/// 一次重复 system 提示的检测结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheHint {
+    /// 连续重复次数（上一次相同记为 1）
+    pub repeats: u32,
+    /// system 提示的估算 tokens
+    pub tokens: u32,
+    /// system 提示不在消息列表开头，无法命中前缀缓存
+    pub misaligned: bool,
+}
+
+impl CacheHint {
+    /// 写入提示响应头
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(HeaderName::from_static(X_PROMPT_CACHE_REPEAT), self.repeats.into());
+        if self.misaligned {
+            headers.insert(HeaderName::from_static(X_PROMPT_CACHE_ADVICE), ADVICE_MOVE_SYSTEM_FIRST.parse().unwrap());
+        }
+    }
+}
+
+/// 用户上一次请求的 system 提示
+struct LastPrompt {
+    hash: [u8; 32],
+    seen_at: Instant,
+    repeats: u32,
+}
+
+/// 单个用户的累计统计（进程启动以来）
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UserCacheStats {
+    pub username: String,
+    /// 重复发送相同 system 提示的请求数
+    pub duplicates: u64,
+    /// 其中 system 提示不在开头的请求数
+    pub misaligned: u64,
+    /// 重复发送的 system 提示估算 tokens
+    pub repeated_tokens: u64,
+    /// 因不在开头而无法命中缓存的估算 tokens
+    pub misaligned_tokens: u64,
+}
+
+/// 管理接口 /admin/cache-stats 的响应
+#[derive(Debug, Serialize)]
+pub struct CacheStatsReport {
+    /// 今日上游返回的缓存命中 / 未命中 tokens
+    pub today_cache_hit_tokens: u64,
+    pub today_cache_miss_tokens: u64,
+    /// 今日命中率，没有数据时为 null
+    pub today_hit_ratio: Option<f64>,
+    /// 参与检测的 system 提示最小估算 tokens
+    pub min_system_tokens: u32,
+    pub duplicates: u64,
+    pub misaligned: u64,
+    pub misaligned_tokens: u64,
+    /// 未对齐的 system 提示按未命中而非命中计费多出的估算费用（按 [reconciliation] 单价）
+    pub estimated_extra_cost: f64,
+    pub currency: String,
+    /// 按未对齐 tokens 降序
+    pub users: Vec<UserCacheStats>,
+}
+
+pub struct PromptCacheAdvisor {
+    cfg: PromptCacheConfig,
+    /// 用户名 -> 上一次请求的 system 提示
+    last: DashMap<String, LastPrompt>,
+    stats: DashMap<String, UserCacheStats>,
+}
+
+impl PromptCacheAdvisor {
+    pub fn new(cfg: PromptCacheConfig) -> Self {
+        Self { cfg, last: DashMap::new(), stats: DashMap::new() }
+    }
+
+    /// 记录本次请求的 system 提示，与该用户上一次请求相同时返回提示
+    pub fn inspect(&self, username: &str, request: &ChatRequest) -> Option<CacheHint> {
+        if !self.cfg.enabled {
+            return None;
+        }
+        let system: Vec<&Message> = request.messages.iter().filter(|m| m.role == "system").collect();
+        let texts: Vec<&str> = system
+            .iter()
+            .flat_map(|m| match &m.content {
+                Some(MessageContent::Text(text)) => vec![text.as_str()],
+                Some(MessageContent::Parts(parts)) => parts.iter().filter_map(|p| p.text()).collect(),
+                None => Vec::new(),
+            })
+            .collect();
+        let tokens: u32 = texts.iter().map(|text| estimate_text_tokens(text)).sum();
+        if texts.is_empty() || tokens < self.cfg.min_system_tokens {
+            return None;
+        }
+        let mut hasher = Sha256::new();
+        for text in &texts {
+            hasher.update(text.as_bytes());
+            hasher.update([0]);
+        }
+        let hash: [u8; 32] = hasher.finalize().into();
+        // system 消息全部位于开头时前缀才可能稳定
+        let misaligned = request.messages.iter().take(system.len()).any(|m| m.role != "system");
+
+        let now = Instant::now();
+        let window = Duration::from_secs(self.cfg.window_seconds);
+        let repeats = match self.last.entry(username.to_string()) {
+            Entry::Occupied(mut entry) => {
+                let last = entry.get_mut();
+                let repeated = last.hash == hash && now.duration_since(last.seen_at) <= window;
+                *last = LastPrompt { hash, seen_at: now, repeats: if repeated { last.repeats + 1 } else { 0 } };
+                last.repeats
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(LastPrompt { hash, seen_at: now, repeats: 0 });
+                0
+            }
+        };
+        if repeats == 0 {
+            return None;
+        }
+
+        let aligned_label = if misaligned { "false" } else { "true" };
+        crate::metrics::METRICS.duplicate_system_prompts.with_label_values(&[aligned_label]).inc();
+        crate::metrics::METRICS.duplicate_system_prompt_tokens.with_label_values(&[aligned_label]).inc_by(tokens as f64);
+        let mut stats = self.stats.entry(username.to_string()).or_default();
+        stats.username = username.to_string();
+        stats.duplicates += 1;
+        stats.repeated_tokens += tokens as u64;
+        if misaligned {
+            stats.misaligned += 1;
+            stats.misaligned_tokens += tokens as u64;
+        }
+        Some(CacheHint { repeats, tokens, misaligned })
+    }
+
+    /// 汇总统计；`hit_price` / `miss_price` 为每百万 token 单价
+    pub fn report(&self, hit_price: f64, miss_price: f64, currency: &str) -> CacheStatsReport {
+        let today = crate::metrics::METRICS.today_tokens();
+        let mut users: Vec<UserCacheStats> = self.stats.iter().map(|entry| entry.value().clone()).collect();
+        users.sort_by(|a, b| b.misaligned_tokens.cmp(&a.misaligned_tokens).then(a.username.cmp(&b.username)));
+        let misaligned_tokens: u64 = users.iter().map(|u| u.misaligned_tokens).sum();
+        let total = today.cache_hit + today.cache_miss;
+        CacheStatsReport {
+            today_cache_hit_tokens: today.cache_hit,
+            today_cache_miss_tokens: today.cache_miss,
+            today_hit_ratio: (total > 0).then(|| today.cache_hit as f64 / total as f64),
+            min_system_tokens: self.cfg.min_system_tokens,
+            duplicates: users.iter().map(|u| u.duplicates).sum(),
+            misaligned: users.iter().map(|u| u.misaligned).sum(),
+            misaligned_tokens,
+            estimated_extra_cost: misaligned_tokens as f64 * (miss_price - hit_price).max(0.0) / 1_000_000.0,
+            currency: currency.to_string(),
+            users,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advisor() -> PromptCacheAdvisor {
+        PromptCacheAdvisor::new(PromptCacheConfig { enabled: true, min_system_tokens: 10, window_seconds: 60 })
+    }
+
+    fn request(messages: serde_json::Value) -> ChatRequest {
+        serde_json::from_value(serde_json::json!({"model": "deepseek-chat", "stream": true, "messages": messages})).unwrap()
+    }
+
+    #[test]
+    fn test_detects_repeated_system_prompt_and_misalignment() {
+        let advisor = advisor();
+        let system = "你是一个严谨的助教，回答前先复述题目要求";
+        let aligned = request(serde_json::json!([
+            {"role": "system", "content": system},
+            {"role": "user", "content": "第一题"}
+        ]));
+        assert_eq!(advisor.inspect("alice", &aligned), None);
+        let hint = advisor.inspect("alice", &aligned).unwrap();
+        assert_eq!((hint.repeats, hint.misaligned), (1, false));
+        // 其他用户互不影响
+        assert_eq!(advisor.inspect("bob", &aligned), None);
+
+        let misaligned = request(serde_json::json!([
+            {"role": "user", "content": "第二题"},
+            {"role": "system", "content": system}
+        ]));
+        let hint = advisor.inspect("alice", &misaligned).unwrap();
+        assert_eq!((hint.repeats, hint.misaligned), (2, true));
+        let mut headers = HeaderMap::new();
+        hint.insert_headers(&mut headers);
+        assert_eq!(headers[X_PROMPT_CACHE_REPEAT], "2");
+        assert_eq!(headers[X_PROMPT_CACHE_ADVICE], ADVICE_MOVE_SYSTEM_FIRST);
+
+        // 短提示不参与检测
+        let short = request(serde_json::json!([{"role": "system", "content": "简短"}, {"role": "user", "content": "hi"}]));
+        assert_eq!(advisor.inspect("carol", &short), None);
+        assert_eq!(advisor.inspect("carol", &short), None);
+
+        let report = advisor.report(0.5, 2.0, "CNY");
+        assert_eq!((report.duplicates, report.misaligned), (2, 1));
+        assert_eq!(report.users[0].username, "alice");
+        assert_eq!(report.misaligned_tokens, hint.tokens as u64);
+        assert!(report.estimated_extra_cost > 0.0);
+    }
+}
@@ -1,13 +1,19 @@
+use crate::error::RateLimit;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 /// 全局速率限制器 - 使用令牌桶算法
 /// 适用于小型服务器（1核1G），防止 DoS 攻击
+///
+/// 启动后可进入一段冷启动保护期（`[rate_limit] cold_start_seconds`），期间速率与突发容量按
+/// `cold_start_factor` 降低，避免上游连接池尚未预热时涌入的请求全部打到冷连接上
 #[derive(Clone)]
 pub struct GlobalRateLimiter {
     state: Arc<Mutex<TokenBucket>>,
     config: RateLimitConfig,
+    /// 冷启动保护结束的时间
+    cold_start_until: Option<Instant>,
 }
 
 #[derive(Clone)]
@@ -16,6 +22,8 @@ pub struct RateLimitConfig {
     pub requests_per_second: usize,
     /// 最大突发容量（令牌桶大小）
     pub burst_capacity: usize,
+    /// 冷启动期间的限额比例
+    pub cold_start_factor: f64,
 }
 
 struct TokenBucket {
@@ -29,32 +37,77 @@ impl GlobalRateLimiter {
     /// 创建新的全局速率限制器
     pub fn new(requests_per_second: usize) -> Self {
         // 突发容量设为 RPS 的 2 倍，允许短时间突发
-        let burst_capacity = requests_per_second * 2;
-        
+        Self::build(requests_per_second, 2.0, None, Duration::ZERO, 1.0)
+    }
+
+    /// 按 `[rate_limit]` 配置创建
+    pub fn from_config(cfg: &crate::config::RateLimitConfig) -> Self {
+        Self::build(
+            cfg.requests_per_second,
+            cfg.burst_multiplier,
+            cfg.initial_tokens,
+            Duration::from_secs(cfg.cold_start_seconds),
+            cfg.cold_start_factor,
+        )
+    }
+
+    fn build(
+        requests_per_second: usize,
+        burst_multiplier: f64,
+        initial_tokens: Option<usize>,
+        cold_start: Duration,
+        cold_start_factor: f64,
+    ) -> Self {
+        let burst_capacity = ((requests_per_second as f64 * burst_multiplier).round() as usize).max(1);
+        let tokens = initial_tokens.unwrap_or(burst_capacity).min(burst_capacity);
+        let now = Instant::now();
         Self {
             state: Arc::new(Mutex::new(TokenBucket {
-                tokens: burst_capacity as f64,
-                last_refill: Instant::now(),
+                tokens: tokens as f64,
+                last_refill: now,
             })),
             config: RateLimitConfig {
                 requests_per_second,
                 burst_capacity,
+                cold_start_factor: cold_start_factor.clamp(0.01, 1.0),
             },
+            cold_start_until: (!cold_start.is_zero()).then(|| now + cold_start),
+        }
+    }
+
+    /// 冷启动保护的剩余时间，不在保护期内时返回 None
+    pub fn cold_start_remaining(&self) -> Option<Duration> {
+        self.cold_start_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// 当前生效的 (每秒速率, 突发容量)：冷启动保护期内按比例降低
+    fn limits(&self, now: Instant) -> (f64, f64) {
+        let rps = self.config.requests_per_second as f64;
+        let burst = self.config.burst_capacity as f64;
+        match self.cold_start_until {
+            Some(until) if now < until => {
+                let factor = self.config.cold_start_factor;
+                ((rps * factor).max(f64::MIN_POSITIVE), (burst * factor).max(1.0))
+            }
+            _ => (rps, burst),
         }
     }
 
     /// 尝试获取一个令牌
-    /// 返回 Ok(()) 如果成功，返回 Err 包含重试等待时间（秒）
-    pub async fn acquire(&self) -> Result<(), f64> {
+    /// 成功返回令牌桶的当前状态（用于限流反馈头），失败返回 Err 包含重试等待时间（秒）
+    pub async fn acquire(&self) -> Result<RateLimit, f64> {
         let mut state = self.state.lock().await;
         let now = Instant::now();
+        let (requests_per_second, burst_capacity) = self.limits(now);
         
         // 计算需要补充的令牌数
         let elapsed = now.duration_since(state.last_refill).as_secs_f64();
-        let tokens_to_add = elapsed * self.config.requests_per_second as f64;
+        let tokens_to_add = elapsed * requests_per_second;
         
         // 补充令牌，但不超过桶容量
-        state.tokens = (state.tokens + tokens_to_add).min(self.config.burst_capacity as f64);
+        state.tokens = (state.tokens + tokens_to_add).min(burst_capacity);
         state.last_refill = now;
         
         // 尝试消耗一个令牌
@@ -63,12 +116,17 @@ impl GlobalRateLimiter {
             tracing::debug!(
                 "全局速率限制：通过（剩余令牌 {:.2}/{}）",
                 state.tokens,
-                self.config.burst_capacity
+                burst_capacity
             );
-            Ok(())
+            let refill_in = (burst_capacity - state.tokens) / requests_per_second;
+            Ok(RateLimit {
+                limit: burst_capacity as u32,
+                remaining: state.tokens as u32,
+                reset_after: Some(refill_in.ceil() as u64),
+            })
         } else {
             // 计算需要等待多久才能获得下一个令牌
-            let wait_time = (1.0 - state.tokens) / self.config.requests_per_second as f64;
+            let wait_time = (1.0 - state.tokens) / requests_per_second;
             tracing::warn!(
                 "全局速率限制：请求过多（剩余令牌 {:.2}），建议等待 {:.2}秒",
                 state.tokens,
@@ -78,19 +136,34 @@ impl GlobalRateLimiter {
         }
     }
 
+    /// 拒绝时的限流反馈：当前生效的突发容量与建议等待时间（向上取整到秒）
+    pub fn rate_limit(&self, wait_time: f64) -> RateLimit {
+        let (_, burst_capacity) = self.limits(Instant::now());
+        RateLimit { limit: burst_capacity as u32, remaining: 0, reset_after: Some(wait_time.ceil() as u64) }
+    }
+
     /// 获取当前配置信息（用于日志）
     pub fn info(&self) -> String {
-        format!(
+        let mut info = format!(
             "全局限流: {}/秒, 突发容量: {}",
             self.config.requests_per_second, self.config.burst_capacity
-        )
+        );
+        if let Some(remaining) = self.cold_start_remaining() {
+            info.push_str(&format!(
+                ", 冷启动保护 {} 秒（限额 ×{}）",
+                remaining.as_secs_f64().round(),
+                self.config.cold_start_factor
+            ));
+        }
+        info
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::{sleep, Duration};
+    use crate::error::AppError;
+    use tokio::time::sleep;
 
     #[tokio::test]
     async fn test_rate_limiter_allows_within_limit() {
@@ -137,4 +210,37 @@ mod tests {
         assert!(limiter.acquire().await.is_ok());
         assert!(limiter.acquire().await.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_rate_limiter_warm_start_config() {
+        let toml = "requests_per_second = 10\nburst_multiplier = 1.5\ninitial_tokens = 3\ncold_start_seconds = 60\ncold_start_factor = 0.2";
+        let cfg: crate::config::RateLimitConfig = toml::from_str(toml).unwrap();
+        let limiter = GlobalRateLimiter::from_config(&cfg);
+        assert_eq!(limiter.config.burst_capacity, 15);
+        assert!(limiter.cold_start_remaining().is_some());
+
+        // 启动时只有 3 个令牌；冷启动期间容量为 3，每秒补充 2 个
+        let status = limiter.acquire().await.unwrap();
+        assert_eq!((status.limit, status.remaining, status.reset_after), (3, 2, Some(1)));
+        for _ in 0..2 {
+            assert!(limiter.acquire().await.is_ok());
+        }
+        // 冷启动期间每秒补充 2 个
+        let wait = limiter.acquire().await.unwrap_err();
+        assert!((0.4..=0.5).contains(&wait), "{}", wait);
+        assert_eq!(limiter.rate_limit(wait), RateLimit { limit: 3, remaining: 0, reset_after: Some(1) });
+        // 429 响应的 X-RateLimit-* 来自拒绝请求的令牌桶
+        let response = axum::response::IntoResponse::into_response(AppError::TooManyRequests(limiter.rate_limit(wait)));
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers();
+        assert_eq!(headers["x-ratelimit-limit"], "3");
+        assert_eq!(headers["x-ratelimit-remaining"], "0");
+        assert_eq!(headers["x-ratelimit-reset"], "1");
+        assert_eq!(headers["retry-after"], "1");
+
+        // 保护期结束后恢复完整限额
+        let now = Instant::now();
+        assert_eq!(limiter.limits(now), (2.0, 3.0));
+        assert_eq!(limiter.limits(now + Duration::from_secs(61)), (10.0, 15.0));
+    }
 }
@@ -0,0 +1,127 @@
+use crate::{
+    config::{SamplingConfig, SamplingMode},
+    deepseek::ChatRequest,
+    error::AppError,
+};
+
+/// 按用户档次校验采样参数（temperature / top_p / frequency_penalty）
+///
+/// clamp 模式下把越界值夹到允许范围并返回被调整的参数名；
+/// reject 模式下遇到越界值直接返回 400
+pub fn enforce_sampling_limits(
+    cfg: &SamplingConfig,
+    tier: Option<&str>,
+    request: &mut ChatRequest,
+) -> Result<Vec<&'static str>, AppError> {
+    let ranges = cfg.ranges_for(tier);
+    let mut adjusted = Vec::new();
+
+    if let Some(v) = request.temperature {
+        if let Some(new) = check("temperature", v, ranges.temperature, cfg.mode)? {
+            request.temperature = Some(new);
+            adjusted.push("temperature");
+        }
+    }
+    if let Some(v) = request.top_p {
+        if let Some(new) = check("top_p", v, ranges.top_p, cfg.mode)? {
+            request.top_p = Some(new);
+            adjusted.push("top_p");
+        }
+    }
+    // frequency_penalty 通过 extra 透传
+    if let Some(obj) = request.extra.as_object_mut() {
+        if let Some(v) = obj.get("frequency_penalty").and_then(|v| v.as_f64()) {
+            if let Some(new) = check("frequency_penalty", v as f32, ranges.frequency_penalty, cfg.mode)? {
+                obj.insert("frequency_penalty".to_string(), serde_json::json!(new));
+                adjusted.push("frequency_penalty");
+            }
+        }
+    }
+
+    Ok(adjusted)
+}
+
+/// 校验候选回复数 n 并返回本次请求的计费倍数
+///
+/// n 为 0 或超过档次上限时返回 400（不做夹取：少返回候选会让客户端按下标取值时出错）
+pub fn enforce_choice_limit(max_n: Option<u32>, request: &ChatRequest) -> Result<u32, AppError> {
+    let n = request.choice_count();
+    if n == 0 {
+        return Err(AppError::BadRequest("n 必须大于 0".to_string()));
+    }
+    if let Some(max) = max_n.filter(|max| n > *max) {
+        return Err(AppError::BadRequest(format!("n 取值 {} 超过当前套餐上限 {}", n, max)));
+    }
+    Ok(n)
+}
+
+/// 检查单个参数：范围内返回 None，越界时按模式返回夹取值或错误
+fn check(name: &str, value: f32, range: Option<[f32; 2]>, mode: SamplingMode) -> Result<Option<f32>, AppError> {
+    let Some([min, max]) = range else { return Ok(None) };
+    if (min..=max).contains(&value) {
+        return Ok(None);
+    }
+    match mode {
+        SamplingMode::Clamp => Ok(Some(value.clamp(min, max))),
+        SamplingMode::Reject => Err(AppError::BadRequest(format!(
+            "{} 取值 {} 超出允许范围 [{}, {}]",
+            name, value, min, max
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SamplingRanges;
+
+    fn request(temperature: f32, frequency_penalty: f32) -> ChatRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "deepseek-chat",
+            "stream": true,
+            "messages": [],
+            "temperature": temperature,
+            "frequency_penalty": frequency_penalty
+        }))
+        .unwrap()
+    }
+
+    fn config(mode: SamplingMode) -> SamplingConfig {
+        let mut cfg = SamplingConfig { mode, ..SamplingConfig::default() };
+        cfg.tiers.insert("basic".to_string(), SamplingRanges {
+            temperature: Some([0.0, 1.0]),
+            ..SamplingRanges::default()
+        });
+        cfg
+    }
+
+    #[test]
+    fn test_clamp_uses_tier_then_default_ranges() {
+        let mut req = request(100.0, 5.0);
+        let adjusted = enforce_sampling_limits(&config(SamplingMode::Clamp), Some("basic"), &mut req).unwrap();
+        assert_eq!(adjusted, vec!["temperature", "frequency_penalty"]);
+        assert_eq!(req.temperature, Some(1.0));
+        assert_eq!(req.extra["frequency_penalty"], serde_json::json!(2.0));
+    }
+
+    #[test]
+    fn test_choice_limit() {
+        let mut req = request(1.0, 0.0);
+        assert_eq!(enforce_choice_limit(Some(2), &req).unwrap(), 1);
+        req.n = Some(2);
+        assert_eq!(enforce_choice_limit(Some(2), &req).unwrap(), 2);
+        req.n = Some(3);
+        assert!(enforce_choice_limit(Some(2), &req).is_err());
+        assert_eq!(enforce_choice_limit(None, &req).unwrap(), 3);
+        req.n = Some(0);
+        assert!(enforce_choice_limit(None, &req).is_err());
+    }
+
+    #[test]
+    fn test_reject_out_of_range() {
+        let mut req = request(1.5, 0.0);
+        assert!(enforce_sampling_limits(&config(SamplingMode::Reject), Some("basic"), &mut req).is_err());
+        // 其他档次使用默认范围 [0, 2]
+        assert!(enforce_sampling_limits(&config(SamplingMode::Reject), Some("pro"), &mut req).is_ok());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{config::SpamConfig, deepseek::ChatRequest};
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// 记录条目超过该数量时顺带清理过期条目
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// 重复提问检测结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpamVerdict {
+    Allowed,
+    /// 窗口内重复次数超过上限；first 为本窗口内首次超限（用于只记录一次行为日志）
+    Suspected { prompt_hash: String, count: usize, first: bool },
+}
+
+/// 重复提问检测：同一用户在时间窗口内提交相同内容（模型 + 消息）超过 N 次时限流
+///
+/// 只保存内容哈希，不保存提问原文
+pub struct SpamGuard {
+    cfg: SpamConfig,
+    /// "{username}:{hash}" -> 窗口内的提交时间
+    seen: DashMap<String, Vec<Instant>>,
+}
+
+impl SpamGuard {
+    pub fn new(cfg: SpamConfig) -> Self {
+        Self { cfg, seen: DashMap::new() }
+    }
+
+    /// 记录一次提交并判断是否属于重复刷量
+    pub fn check(&self, username: &str, request: &ChatRequest) -> SpamVerdict {
+        if !self.cfg.enabled {
+            return SpamVerdict::Allowed;
+        }
+        let prompt_hash = prompt_hash(request);
+        let now = Instant::now();
+        let window = Duration::from_secs(self.cfg.window_seconds);
+
+        let count = {
+            let mut times = self.seen.entry(format!("{}:{}", username, prompt_hash)).or_default();
+            times.retain(|t| now.duration_since(*t) <= window);
+            times.push(now);
+            times.len()
+        };
+        if self.seen.len() > PRUNE_THRESHOLD {
+            self.seen.retain(|_, times| times.last().is_some_and(|t| now.duration_since(*t) <= window));
+        }
+
+        if count > self.cfg.max_duplicates {
+            SpamVerdict::Suspected { prompt_hash, count, first: count == self.cfg.max_duplicates + 1 }
+        } else {
+            SpamVerdict::Allowed
+        }
+    }
+}
+
+/// 请求内容哈希（模型 + 消息列表），取前 16 位十六进制
+fn prompt_hash(request: &ChatRequest) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.model.as_bytes());
+    hasher.update(serde_json::to_vec(&request.messages).unwrap_or_default());
+    hex::encode(hasher.finalize())[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content: &str) -> ChatRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "deepseek-chat",
+            "stream": true,
+            "messages": [{"role": "user", "content": content}]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_flags_repeated_prompt_per_user() {
+        let guard = SpamGuard::new(SpamConfig { enabled: true, max_duplicates: 2, window_seconds: 60 });
+        assert_eq!(guard.check("alice", &request("hi")), SpamVerdict::Allowed);
+        assert_eq!(guard.check("alice", &request("hi")), SpamVerdict::Allowed);
+        assert!(matches!(guard.check("alice", &request("hi")), SpamVerdict::Suspected { count: 3, first: true, .. }));
+        assert!(matches!(guard.check("alice", &request("hi")), SpamVerdict::Suspected { first: false, .. }));
+
+        // 不同内容、不同用户分别计数
+        assert_eq!(guard.check("alice", &request("hello")), SpamVerdict::Allowed);
+        assert_eq!(guard.check("bob", &request("hi")), SpamVerdict::Allowed);
+    }
+}
@@ -0,0 +1,141 @@
+use bytes::{Bytes, BytesMut};
+
+/// 一个完整的 SSE 事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `event:` 字段（未指定时为 None，即默认的 message 事件）
+    pub event: Option<String>,
+    /// 各 `data:` 行以换行拼接后的内容
+    pub data: String,
+    /// 事件在流中的原始字节（含结尾空行），不改写的变换可以原样下发
+    pub raw: Bytes,
+}
+
+impl SseEvent {
+    /// 流结束标记 `data: [DONE]`
+    pub fn is_done(&self) -> bool {
+        self.data == "[DONE]"
+    }
+
+    /// 把 data 解析为 JSON 对象；不是 JSON 时返回 None
+    pub fn json(&self) -> Option<serde_json::Value> {
+        if self.data.is_empty() || self.is_done() {
+            return None;
+        }
+        serde_json::from_str(&self.data).ok()
+    }
+
+    /// 以 data 行序列化一个 JSON 事件
+    pub fn encode_json(value: &serde_json::Value) -> Bytes {
+        Bytes::from(format!("data: {}\n\n", value))
+    }
+}
+
+/// 增量 SSE 解析器：数据块可以在任意位置切分，不完整的行与事件留在缓冲中等待后续数据
+///
+/// 行尾支持 `\n` 与 `\r\n`；`:` 开头的注释行与没有 data 的事件（如心跳空行）不产生事件，
+/// 但其原始字节会并入下一个事件的 raw，保证按 raw 重新拼接时不丢字节
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buf: BytesMut,
+    /// buf 中已经扫描过的字节数（下一次从这里找换行）
+    scanned: usize,
+    /// 当前事件已解析的字段
+    event: Option<String>,
+    data: Option<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 喂入一个数据块，返回其中完成的事件
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buf[self.scanned..].iter().position(|b| *b == b'\n') {
+            let line_end = self.scanned + pos;
+            let line = &self.buf[self.scanned..line_end];
+            let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned();
+            let blank = line.is_empty();
+            if !blank {
+                self.field(&line);
+            }
+            self.scanned = line_end + 1;
+            if blank {
+                if let Some(data) = self.data.take() {
+                    let raw = self.buf.split_to(self.scanned).freeze();
+                    self.scanned = 0;
+                    events.push(SseEvent { event: self.event.take(), data, raw });
+                } else {
+                    self.event = None;
+                }
+            }
+        }
+        events
+    }
+
+    /// 取出缓冲中尚未组成完整事件的字节（流结束或出错时原样下发）
+    pub fn take_remainder(&mut self) -> Bytes {
+        self.scanned = 0;
+        self.event = None;
+        self.data = None;
+        self.buf.split().freeze()
+    }
+
+    fn field(&mut self, line: &str) {
+        if line.starts_with(':') {
+            return;
+        }
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match name {
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "event" => self.event = Some(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reassembles_events_split_across_chunks() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: {\"a\":").is_empty());
+        let events = parser.feed(b"1}\n\nevent: watermark\r\ndata: x\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].json().unwrap()["a"], 1);
+        assert_eq!(events[0].raw, Bytes::from_static(b"data: {\"a\":1}\n\n"));
+
+        let events = parser.feed(b"\r\n: keep-alive\n\ndata: line1\ndata: line2\n\ndata: [DONE]\n\n");
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].event.as_deref(), Some("watermark"));
+        assert_eq!(events[0].data, "x");
+        // 注释事件不单独产生事件，字节并入下一个事件
+        assert_eq!(events[1].data, "line1\nline2");
+        assert!(events[1].raw.starts_with(b": keep-alive\n\n"));
+        assert!(events[2].is_done());
+        assert!(parser.take_remainder().is_empty());
+    }
+
+    #[test]
+    fn test_remainder_keeps_incomplete_event() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: partial").is_empty());
+        assert_eq!(parser.take_remainder(), Bytes::from_static(b"data: partial"));
+        let events = parser.feed(b"data: next\n\n");
+        assert_eq!(events[0].data, "next");
+    }
+}
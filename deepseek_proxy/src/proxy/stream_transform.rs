@@ -0,0 +1,508 @@
+use crate::{
+    config::Config,
+    proxy::{
+        coalesce::CoalesceTransform, json_validate::JsonValidateTransform, metadata::MetadataEchoTransform, sse::SseParser,
+        watermark::WatermarkTransform,
+    },
+    user_activity::UserActivityLogger,
+};
+use bytes::Bytes;
+use futures::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::time::{Instant, Sleep};
+
+/// 响应超过档次字节上限时追加的终止事件
+const TRUNCATED_EVENT: &str = "data: {\"error\":{\"code\":\"response_too_large\",\"message\":\"响应超过当前套餐的长度上限，已截断\"}}\n\ndata: [DONE]\n\n";
+
+/// 可在 `[streaming] transforms` 中使用的变换名称
+pub const TRANSFORM_NAMES: [&str; 4] = ["counting", "watermark", "coalesce", "json_validate"];
+/// 必须出现在管道中的变换（token 统计、响应截断与断开检测依赖它）
+pub const REQUIRED_TRANSFORMS: [&str; 1] = ["counting"];
+
+/// 单个数据块的处理结果
+pub enum TransformOutput {
+    /// 下发这些数据块（可为空表示丢弃），继续处理后续数据
+    Continue(Vec<Bytes>),
+    /// 下发这些数据块后结束流，不再读取上游
+    Terminate(Vec<Bytes>),
+}
+
+/// 流式响应变换：作用在上游字节流与客户端之间，按配置顺序串联
+///
+/// 变换内部状态在整个响应期间保留，需要在客户端断开时做处理的变换可以实现 Drop
+pub trait StreamTransform: Send {
+    /// 处理一个数据块
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput;
+
+    /// 流正常结束（上游结束或下游变换终止了流），可追加收尾数据
+    fn on_end(&mut self) -> Vec<Bytes> {
+        Vec::new()
+    }
+
+    /// 上游返回错误（错误本身原样传给客户端）
+    fn on_error(&mut self) {}
+
+    /// 缓冲了数据的变换返回最晚下发时间；上游空闲到该时间时调用 on_flush
+    fn flush_deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// 下发缓冲的数据（到达 flush_deadline，或上游出错前）
+    fn on_flush(&mut self) -> Vec<Bytes> {
+        Vec::new()
+    }
+}
+
+/// 构建管道所需的请求上下文
+pub struct TransformContext<'a> {
+    pub config: &'a Config,
+    pub username: &'a str,
+    pub tier: Option<&'a str>,
+    /// 沙箱档次：即使未配置 watermark 变换也总是加水印
+    pub sandbox: bool,
+    /// 候选回复数 n，响应字节上限按此倍数放宽
+    pub choices: u32,
+    /// 请求使用 JSON 模式（response_format.type = "json_object"），json_validate 变换只对这类请求生效
+    pub json_mode: bool,
+    /// 请求携带的 metadata，在 usage 数据块中回显
+    pub metadata: Option<serde_json::Value>,
+    pub activity_logger: &'a Arc<UserActivityLogger>,
+}
+
+/// 按配置顺序构建变换管道（名称已在加载配置时校验）
+///
+/// 沙箱请求在管道未包含 watermark 时把它插在 counting 之后；
+/// 携带 metadata 的请求在 counting 之后插入回显变换（不计入响应字节上限）
+pub fn build_pipeline(ctx: &TransformContext<'_>) -> Vec<Box<dyn StreamTransform>> {
+    let configured = &ctx.config.streaming.transforms;
+    let mut names: Vec<&str> = configured.iter().map(String::as_str).collect();
+    let after_counting = names.iter().position(|n| *n == "counting").map_or(0, |i| i + 1);
+    if ctx.sandbox && !names.contains(&"watermark") {
+        names.insert(after_counting, "watermark");
+    }
+    if ctx.metadata.is_some() {
+        names.insert(after_counting, "metadata");
+    }
+    names
+        .into_iter()
+        .filter_map(|name| -> Option<Box<dyn StreamTransform>> {
+            match name {
+                "counting" => {
+                    let max_bytes = ctx
+                        .tier
+                        .and_then(|tier| ctx.config.quota.max_response_bytes.for_tier(tier))
+                        .map(|v| v as usize * ctx.choices as usize);
+                    Some(Box::new(
+                        CountingTransform::new(ctx.username.to_string(), max_bytes)
+                            .with_activity_logger(ctx.activity_logger.clone()),
+                    ))
+                }
+                "watermark" => (ctx.sandbox || WatermarkTransform::applies_to(&ctx.config.streaming.watermark, ctx.tier)).then(|| {
+                    Box::new(WatermarkTransform::new(ctx.config.streaming.watermark.clone(), ctx.username.to_string()))
+                        as Box<dyn StreamTransform>
+                }),
+                "coalesce" => Some(Box::new(CoalesceTransform::new(&ctx.config.streaming.coalesce))),
+                "json_validate" => ctx
+                    .json_mode
+                    .then(|| Box::new(JsonValidateTransform::new()) as Box<dyn StreamTransform>),
+                "metadata" => ctx
+                    .metadata
+                    .clone()
+                    .map(|metadata| Box::new(MetadataEchoTransform::new(metadata)) as Box<dyn StreamTransform>),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// 检查管道配置：名称必须已知且不重复，且包含必需的变换
+pub fn validate(transforms: &[String]) -> Result<(), String> {
+    for (i, name) in transforms.iter().enumerate() {
+        if !TRANSFORM_NAMES.contains(&name.as_str()) {
+            return Err(format!("未知的流式变换 {}，可选: {:?}", name, TRANSFORM_NAMES));
+        }
+        if transforms[..i].contains(name) {
+            return Err(format!("流式变换 {} 重复出现", name));
+        }
+    }
+    for required in REQUIRED_TRANSFORMS {
+        if !transforms.iter().any(|t| t == required) {
+            return Err(format!("流式变换管道必须包含 {}", required));
+        }
+    }
+    Ok(())
+}
+
+/// 依次经过各变换的响应流
+///
+/// 某个变换终止流后：其下游变换照常收到 on_end，上游变换的收尾输出被丢弃
+pub struct TransformedStream<S> {
+    inner: S,
+    transforms: Vec<Box<dyn StreamTransform>>,
+    ready: VecDeque<Bytes>,
+    /// 终止流的变换下标
+    terminated_at: Option<usize>,
+    done: bool,
+    /// 最早的 flush_deadline 定时器
+    flush_timer: Option<Pin<Box<Sleep>>>,
+    /// 先下发缓冲数据，再把上游错误交给客户端
+    error: Option<reqwest::Error>,
+}
+
+impl<S> TransformedStream<S> {
+    pub fn new(inner: S, transforms: Vec<Box<dyn StreamTransform>>) -> Self {
+        Self {
+            inner,
+            transforms,
+            ready: VecDeque::new(),
+            terminated_at: None,
+            done: false,
+            flush_timer: None,
+            error: None,
+        }
+    }
+
+    /// 将数据块交给第 from 个及之后的变换，输出放入待发送队列
+    fn feed(&mut self, from: usize, mut chunks: Vec<Bytes>) {
+        if self.terminated_at.is_some_and(|t| from <= t) {
+            return;
+        }
+        for i in from..self.transforms.len() {
+            let mut next = Vec::with_capacity(chunks.len());
+            for chunk in chunks {
+                // 终止后同一批中剩余的数据块不再交给该变换
+                if self.terminated_at == Some(i) {
+                    break;
+                }
+                match self.transforms[i].on_chunk(chunk) {
+                    TransformOutput::Continue(out) => next.extend(out),
+                    TransformOutput::Terminate(out) => {
+                        next.extend(out);
+                        self.terminated_at = Some(i);
+                    }
+                }
+            }
+            chunks = next;
+        }
+        self.ready.extend(chunks);
+    }
+
+    /// 让到期（或 force 时全部）的缓冲变换下发数据
+    fn flush(&mut self, force: bool) {
+        let now = Instant::now();
+        for i in 0..self.transforms.len() {
+            if force || self.transforms[i].flush_deadline().is_some_and(|d| d <= now) {
+                let out = self.transforms[i].on_flush();
+                self.feed(i + 1, out);
+            }
+        }
+    }
+
+    /// 上游暂无数据时等待最早的 flush_deadline；到期并已下发时返回 true
+    fn poll_flush_timer(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(deadline) = self.transforms.iter().filter_map(|t| t.flush_deadline()).min() else {
+            self.flush_timer = None;
+            return false;
+        };
+        match &mut self.flush_timer {
+            Some(timer) if timer.deadline() == deadline => {}
+            Some(timer) => timer.as_mut().reset(deadline),
+            None => self.flush_timer = Some(Box::pin(tokio::time::sleep_until(deadline))),
+        }
+        if self.flush_timer.as_mut().is_some_and(|timer| timer.as_mut().poll(cx).is_ready()) {
+            self.flush_timer = None;
+            self.flush(false);
+            return true;
+        }
+        false
+    }
+
+    /// 流结束：依次通知各变换并收集收尾数据
+    fn finish(&mut self) {
+        self.done = true;
+        for i in 0..self.transforms.len() {
+            let out = self.transforms[i].on_end();
+            self.feed(i + 1, out);
+        }
+    }
+}
+
+impl<S> Stream for TransformedStream<S>
+where
+    S: Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
+{
+    type Item = Result<Bytes, reqwest::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(chunk) = self.ready.pop_front() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            if let Some(e) = self.error.take() {
+                return Poll::Ready(Some(Err(e)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.feed(0, vec![chunk]);
+                    if self.terminated_at.is_some() {
+                        self.finish();
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    self.flush(true);
+                    for t in &mut self.transforms {
+                        t.on_error();
+                    }
+                    self.error = Some(e);
+                }
+                Poll::Ready(None) => self.finish(),
+                Poll::Pending => {
+                    if !self.poll_flush_timer(cx) {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 许可证随管道一起存活，确保整个流式响应期间都持有并发许可
+impl StreamTransform for crate::proxy::TokenPermit {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        TransformOutput::Continue(vec![chunk])
+    }
+}
+
+/// 随管道存活，统计进行中的流式响应（关闭报告中的排空数）
+impl StreamTransform for crate::shutdown_report::ActiveStream {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        TransformOutput::Continue(vec![chunk])
+    }
+}
+
+/// 在途扣费记录随管道存活，响应结束后才注销（进程中途退出时下次启动按策略处理）
+impl StreamTransform for crate::quota::ChargeGuard {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        TransformOutput::Continue(vec![chunk])
+    }
+}
+
+/// 统计输出 token 的变换：累计字节数，在 Drop 时估算 token 数 (粗略: 字节/4)
+/// 若配置了响应字节上限，超限时发送终止事件并结束流
+/// 若流在完成前被丢弃（客户端断开），记录 ChatAborted 行为日志
+pub struct CountingTransform {
+    bytes_acc: usize,
+    recorded: bool,
+    username: String,
+    real_output_recorded: bool,
+    real_output_tokens: u32,
+    max_bytes: Option<usize>,
+    finished: bool,
+    activity_logger: Option<Arc<UserActivityLogger>>,
+    /// 解析 SSE 事件以读取 usage（读到后不再解析）
+    parser: SseParser,
+}
+
+impl CountingTransform {
+    pub fn new(username: String, max_bytes: Option<usize>) -> Self {
+        Self {
+            bytes_acc: 0,
+            recorded: false,
+            username,
+            real_output_recorded: false,
+            real_output_tokens: 0,
+            max_bytes,
+            finished: false,
+            activity_logger: None,
+            parser: SseParser::new(),
+        }
+    }
+
+    /// 设置客户端中断时使用的行为日志记录器
+    pub fn with_activity_logger(mut self, logger: Arc<UserActivityLogger>) -> Self {
+        self.activity_logger = Some(logger);
+        self
+    }
+
+    /// 已下发的 token 数：优先使用 usage 真实值，否则按 字节/4 估算
+    fn delivered_tokens(&self) -> u32 {
+        if self.real_output_recorded { self.real_output_tokens } else { self.bytes_acc as u32 / 4 }
+    }
+
+    /// 尝试从数据块中解析 usage（usage 所在事件可能跨数据块）
+    fn record_usage(&mut self, chunk: &Bytes) {
+        for event in self.parser.feed(chunk) {
+            let Some(v) = event.json() else { continue };
+            let Some(usage) = v.get("usage").filter(|u| !u.is_null()) else { continue };
+            let completion = usage.get("completion_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+            let prompt = usage.get("prompt_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+            let cache_hit = usage.get("prompt_cache_hit_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+            let cache_miss = usage.get("prompt_cache_miss_tokens").and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+            let reasoning = usage.get("completion_tokens_details").and_then(|d| d.get("reasoning_tokens")).and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+            // 记录输出与输入
+            crate::metrics::METRICS.record_output_tokens(completion);
+            crate::metrics::METRICS.record_input_tokens(prompt); // 修正输入 gauge
+            crate::metrics::METRICS.record_prompt_cache_hit_tokens(cache_hit);
+            crate::metrics::METRICS.record_prompt_cache_miss_tokens(cache_miss);
+            tracing::debug!(user=%self.username, prompt_tokens=prompt, completion_tokens=completion, cache_hit=cache_hit, cache_miss=cache_miss, reasoning_tokens=reasoning, "使用真实 usage 字段记录 token 与缓存命中");
+            self.real_output_recorded = true;
+            self.real_output_tokens = completion;
+            return;
+        }
+    }
+}
+
+impl StreamTransform for CountingTransform {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        // 响应字节上限检查：超限则以终止事件结束流
+        if let Some(max) = self.max_bytes {
+            if self.bytes_acc + chunk.len() > max {
+                self.finished = true;
+                crate::metrics::METRICS.response_truncated.inc();
+                tracing::warn!(user = %self.username, bytes = self.bytes_acc, max_bytes = max, "响应超过档次字节上限，终止流");
+                return TransformOutput::Terminate(vec![Bytes::from_static(TRUNCATED_EVENT.as_bytes())]);
+            }
+        }
+        self.bytes_acc += chunk.len();
+        if !self.real_output_recorded {
+            self.record_usage(&chunk);
+        }
+        TransformOutput::Continue(vec![chunk])
+    }
+
+    fn on_end(&mut self) -> Vec<Bytes> {
+        self.finished = true;
+        Vec::new()
+    }
+
+    fn on_error(&mut self) {
+        // 上游中断不属于客户端主动取消
+        self.finished = true;
+    }
+}
+
+impl Drop for CountingTransform {
+    fn drop(&mut self) {
+        crate::metrics::METRICS.response_body_bytes.observe(self.bytes_acc as f64);
+        if !self.finished {
+            let tokens = self.delivered_tokens();
+            crate::metrics::METRICS.chat_aborted.inc();
+            tracing::info!(user = %self.username, bytes = self.bytes_acc, tokens = tokens, "客户端在流式响应完成前断开");
+            if let Some(logger) = &self.activity_logger {
+                logger.log_chat_aborted(&self.username, self.bytes_acc as u64, tokens);
+            }
+        }
+        // 如果已经通过 usage 记录过真实 completion，则不再估算
+        if !self.recorded && !self.real_output_recorded {
+            let bytes = self.bytes_acc as u32;
+            // 粗略估算：假设平均 4 字节一个 token
+            let tokens = bytes / 4;
+            crate::metrics::METRICS.record_output_tokens(tokens);
+            tracing::debug!(user = %self.username, bytes = bytes, tokens = tokens, "输出 token 估算");
+            self.recorded = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn chunks(parts: &[&'static str]) -> impl Stream<Item = Result<Bytes, reqwest::Error>> + Unpin {
+        futures::stream::iter(parts.iter().map(|p| Ok(Bytes::from_static(p.as_bytes()))).collect::<Vec<_>>())
+    }
+
+    fn counting(max_bytes: Option<usize>) -> Box<dyn StreamTransform> {
+        Box::new(CountingTransform::new("u".to_string(), max_bytes))
+    }
+
+    async fn collect(stream: TransformedStream<impl Stream<Item = Result<Bytes, reqwest::Error>> + Unpin>) -> Vec<Bytes> {
+        stream.map(|c| c.unwrap()).collect().await
+    }
+
+    /// 给每个数据块加前缀，结束时追加一个标记
+    struct Tag(&'static str);
+
+    impl StreamTransform for Tag {
+        fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+            TransformOutput::Continue(vec![Bytes::from(format!("{}{}", self.0, String::from_utf8_lossy(&chunk)))])
+        }
+
+        fn on_end(&mut self) -> Vec<Bytes> {
+            vec![Bytes::from(format!("{}end", self.0))]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counting_passes_through_without_limit() {
+        let out = collect(TransformedStream::new(chunks(&["data: a\n\n", "data: b\n\n"]), vec![counting(None)])).await;
+        assert_eq!(out.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_counting_truncates_over_limit() {
+        let stream = TransformedStream::new(chunks(&["data: a\n\n", "data: b\n\n", "data: c\n\n"]), vec![counting(Some(12))]);
+        let out = collect(stream).await;
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0], Bytes::from_static(b"data: a\n\n"));
+        assert_eq!(out[1], Bytes::from_static(TRUNCATED_EVENT.as_bytes()));
+    }
+
+    #[test]
+    fn test_counting_detects_client_abort() {
+        let mut complete = CountingTransform::new("u".to_string(), None);
+        complete.on_chunk(Bytes::from_static(b"data: a\n\n"));
+        complete.on_end();
+        assert!(complete.finished);
+
+        let mut aborted = CountingTransform::new("u".to_string(), None);
+        aborted.on_chunk(Bytes::from_static(b"data: a\n\n"));
+        assert!(!aborted.finished);
+        assert_eq!(aborted.delivered_tokens(), 2);
+    }
+
+    #[test]
+    fn test_counting_reads_usage_split_across_chunks() {
+        let mut t = CountingTransform::new("u".to_string(), None);
+        t.on_chunk(Bytes::from_static(b"data: {\"choices\":[],\"usage\":null}\n\ndata: {\"usage\":{\"completion"));
+        assert!(!t.real_output_recorded);
+        t.on_chunk(Bytes::from_static(b"_tokens\":42}}\n\ndata: [DONE]\n\n"));
+        assert!(t.real_output_recorded);
+        assert_eq!(t.delivered_tokens(), 42);
+        t.on_end();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_order_and_termination() {
+        // 按顺序串联：A 先处理，B 后处理
+        let stream = TransformedStream::new(chunks(&["x", "y"]), vec![Box::new(Tag("A")), Box::new(Tag("B"))]);
+        assert_eq!(collect(stream).await, vec!["BAx", "BAy", "BAend", "Bend"]);
+
+        // 截断后：下游变换照常收尾，上游变换的收尾输出被丢弃
+        let stream = TransformedStream::new(
+            chunks(&["data: a\n\n", "data: b\n\n"]),
+            vec![Box::new(Tag("A")), counting(Some(12)), Box::new(Tag("B"))],
+        );
+        let out = collect(stream).await;
+        assert_eq!(out.len(), 3);
+        assert_eq!(out[0], Bytes::from_static(b"BAdata: a\n\n"));
+        assert!(out[1].starts_with(b"B") && out[1].ends_with(TRUNCATED_EVENT.as_bytes()));
+        assert_eq!(out[2], Bytes::from_static(b"Bend"));
+    }
+
+    #[test]
+    fn test_validate_pipeline() {
+        assert!(validate(&["counting".to_string()]).is_ok());
+        assert!(validate(&[]).is_err());
+        assert!(validate(&["counting".to_string(), "nope".to_string()]).is_err());
+        assert!(validate(&["counting".to_string(), "counting".to_string()]).is_err());
+    }
+}
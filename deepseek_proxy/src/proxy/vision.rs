@@ -0,0 +1,75 @@
+use crate::config::VisionConfig;
+use crate::deepseek::{ChatRequest, MessageContent};
+use crate::error::AppError;
+use base64::Engine;
+
+/// 检查请求中的图片（`image_url` 片段）：数量与 base64 内联图片解码后的大小不超过档次上限，返回图片数
+///
+/// http(s) 链接由上游自行下载，只计入数量；其他协议或无法解码的 data URL 返回 400
+pub fn enforce_image_limits(cfg: &VisionConfig, tier: Option<&str>, request: &ChatRequest) -> Result<u32, AppError> {
+    let max_images = tier.and_then(|t| cfg.max_images.for_tier(t));
+    let max_bytes = tier.and_then(|t| cfg.max_image_bytes.for_tier(t));
+    let mut count = 0u32;
+    let images = request.messages.iter().filter_map(|m| match &m.content {
+        Some(MessageContent::Parts(parts)) => Some(parts.iter().filter(|p| p.is_image())),
+        _ => None,
+    });
+    for part in images.flatten() {
+        count += 1;
+        if let Some(max) = max_images.filter(|max| count > *max) {
+            return Err(AppError::BadRequest(format!("图片数量超过当前套餐上限 {}", max)));
+        }
+        let url = part.image_url().ok_or_else(|| AppError::BadRequest("image_url 片段缺少 url".to_string()))?;
+        if url.starts_with("https://") || url.starts_with("http://") {
+            continue;
+        }
+        let size = decoded_size(url)?;
+        if let Some(max) = max_bytes.filter(|max| size as u64 > *max) {
+            return Err(AppError::BadRequest(format!("第 {} 张图片 {} 字节，超过当前套餐上限 {} 字节", count, size, max)));
+        }
+    }
+    Ok(count)
+}
+
+/// `data:image/png;base64,....` 解码后的字节数
+fn decoded_size(url: &str) -> Result<usize, AppError> {
+    let invalid = || AppError::BadRequest("图片只支持 http(s) 链接或 base64 编码的 data URL".to_string());
+    let (meta, data) = url.strip_prefix("data:").and_then(|rest| rest.split_once(',')).ok_or_else(invalid)?;
+    if !meta.starts_with("image/") || !meta.ends_with(";base64") {
+        return Err(invalid());
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map(|bytes| bytes.len())
+        .map_err(|_| AppError::BadRequest("图片的 base64 数据无效".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(urls: &[&str]) -> ChatRequest {
+        let parts: Vec<_> = urls.iter().map(|url| serde_json::json!({"type": "image_url", "image_url": {"url": url}})).collect();
+        serde_json::from_value(serde_json::json!({
+            "model": "glm-4v",
+            "messages": [{"role": "user", "content": parts}],
+            "stream": true,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_image_count_and_size_limits() {
+        let cfg: VisionConfig = toml::from_str("[max_images]\nbasic = 2\n[max_image_bytes]\nbasic = 4\n").unwrap();
+        let small = "data:image/png;base64,AAAA"; // 3 字节
+        let large = "data:image/png;base64,AAAAAAAA"; // 6 字节
+
+        assert_eq!(enforce_image_limits(&cfg, Some("basic"), &request(&[small, "https://example.com/a.png"])).unwrap(), 2);
+        assert!(enforce_image_limits(&cfg, Some("basic"), &request(&[small, small, small])).is_err());
+        assert!(enforce_image_limits(&cfg, Some("basic"), &request(&[large])).is_err());
+        // 未配置的档次不限制，但格式仍要合法
+        assert_eq!(enforce_image_limits(&cfg, Some("pro"), &request(&[large, large, large])).unwrap(), 3);
+        assert!(enforce_image_limits(&cfg, Some("pro"), &request(&["data:image/png;base64,@@"])).is_err());
+        assert!(enforce_image_limits(&cfg, Some("pro"), &request(&["file:///etc/passwd"])).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config::{WatermarkConfig, WatermarkMode};
+use crate::proxy::sse::{SseEvent, SseParser};
+use crate::proxy::stream_transform::{StreamTransform, TransformOutput};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// 响应水印变换：在完成的响应末尾追加来源标记（内容来源合规要求）
+///
+/// 水印插在流结束标记 `data: [DONE]` 之前，保证客户端在结束前收到；上游出错中断的响应不加水印
+pub struct WatermarkTransform {
+    cfg: WatermarkConfig,
+    username: String,
+    /// 从首个事件中取得的 (id, model, created)，content 模式下用于拼接 chunk
+    chunk_meta: Option<(String, String, i64)>,
+    emitted: bool,
+    /// 加水印前按事件下发，不完整的事件留在解析器中
+    parser: SseParser,
+}
+
+impl WatermarkTransform {
+    pub fn new(cfg: WatermarkConfig, username: String) -> Self {
+        Self {
+            cfg,
+            username,
+            chunk_meta: None,
+            emitted: false,
+            parser: SseParser::new(),
+        }
+    }
+
+    /// 该档次是否需要加水印
+    pub fn applies_to(cfg: &WatermarkConfig, tier: Option<&str>) -> bool {
+        tier.is_some_and(|tier| cfg.tiers.iter().any(|t| t == tier))
+    }
+
+    fn capture_meta(&mut self, event: &SseEvent) {
+        self.chunk_meta = event.json().and_then(|v| {
+            Some((
+                v.get("id")?.as_str()?.to_string(),
+                v.get("model")?.as_str()?.to_string(),
+                v.get("created")?.as_i64()?,
+            ))
+        });
+    }
+
+    /// 生成水印 SSE 事件
+    fn event(&self) -> Bytes {
+        let issued_at = crate::utils::now_beijing_rfc3339();
+        let mut watermark = serde_json::json!({
+            "text": self.cfg.text,
+            "issued_at": issued_at,
+        });
+        if let Some(secret) = &self.cfg.secret {
+            watermark["signature"] = signature(secret, &issued_at, &self.username, &self.cfg.text).into();
+        }
+
+        let event = match self.cfg.mode {
+            WatermarkMode::Event => format!("event: watermark\ndata: {}\n\n", serde_json::json!({ "watermark": watermark })),
+            WatermarkMode::Content => {
+                let (id, model, created) = self.chunk_meta.clone().unwrap_or_default();
+                let chunk = serde_json::json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": model,
+                    "choices": [{"index": 0, "delta": {"content": format!("\n\n{}", self.cfg.text)}, "finish_reason": null}],
+                    "watermark": watermark,
+                });
+                format!("data: {}\n\n", chunk)
+            }
+        };
+        Bytes::from(event)
+    }
+}
+
+impl StreamTransform for WatermarkTransform {
+    fn on_chunk(&mut self, chunk: Bytes) -> TransformOutput {
+        if self.emitted {
+            return TransformOutput::Continue(vec![chunk]);
+        }
+        let mut out = Vec::new();
+        for event in self.parser.feed(&chunk) {
+            if self.emitted {
+                out.push(event.raw);
+                continue;
+            }
+            if self.chunk_meta.is_none() && self.cfg.mode == WatermarkMode::Content {
+                self.capture_meta(&event);
+            }
+            if event.is_done() {
+                self.emitted = true;
+                out.push(self.event());
+            }
+            out.push(event.raw);
+        }
+        if self.emitted {
+            out.push(self.parser.take_remainder());
+        }
+        out.retain(|b| !b.is_empty());
+        TransformOutput::Continue(out)
+    }
+
+    fn on_flush(&mut self) -> Vec<Bytes> {
+        let remainder = self.parser.take_remainder();
+        if remainder.is_empty() { Vec::new() } else { vec![remainder] }
+    }
+
+    fn on_end(&mut self) -> Vec<Bytes> {
+        let mut out = self.on_flush();
+        // 上游没有发送 [DONE] 就正常结束时补在最后
+        if !self.emitted {
+            self.emitted = true;
+            out.push(self.event());
+        }
+        out
+    }
+
+    fn on_error(&mut self) {
+        self.emitted = true;
+    }
+}
+
+/// 水印签名：HMAC-SHA256(secret, "{issued_at}\n{username}\n{text}")，核验时用候选用户名重新计算
+fn signature(secret: &str, issued_at: &str, username: &str, text: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC 接受任意长度密钥");
+    mac.update(format!("{}\n{}\n{}", issued_at, username, text).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(t: &mut WatermarkTransform, parts: &[&'static str]) -> String {
+        let mut out = Vec::new();
+        for part in parts {
+            if let TransformOutput::Continue(chunks) = t.on_chunk(Bytes::from_static(part.as_bytes())) {
+                out.extend(chunks);
+            }
+        }
+        out.extend(t.on_end());
+        out.iter().map(|b| String::from_utf8_lossy(b).into_owned()).collect()
+    }
+
+    #[test]
+    fn test_watermark_inserted_before_done() {
+        let cfg = WatermarkConfig { secret: Some("k".to_string()), ..WatermarkConfig::default() };
+        let mut t = WatermarkTransform::new(cfg, "alice".to_string());
+        let body = collect(&mut t, &["data: {\"id\":\"1\"}\n\n", "data: [DONE]\n\n"]);
+        let wm = body.find("event: watermark").unwrap();
+        assert!(wm < body.find("data: [DONE]").unwrap());
+        assert_eq!(body.matches("event: watermark").count(), 1);
+        assert!(body.contains("\"signature\""));
+    }
+
+    #[test]
+    fn test_content_mode_and_missing_done() {
+        let cfg = WatermarkConfig { mode: WatermarkMode::Content, ..WatermarkConfig::default() };
+        let mut t = WatermarkTransform::new(cfg, "alice".to_string());
+        let body = collect(&mut t, &["data: {\"id\":\"c1\",\"model\":\"m\",\"created\":7,\"choices\":[]}\n\n"]);
+        let last: serde_json::Value = serde_json::from_str(body.trim_end().rsplit("data: ").next().unwrap()).unwrap();
+        assert_eq!(last["id"], "c1");
+        assert_eq!(last["choices"][0]["delta"]["content"], "\n\nAI-generated content");
+    }
+
+    #[test]
+    fn test_applies_only_to_configured_tiers() {
+        let cfg = WatermarkConfig::default();
+        assert!(WatermarkTransform::applies_to(&cfg, Some("basic")));
+        assert!(!WatermarkTransform::applies_to(&cfg, Some("premium")));
+        assert!(!WatermarkTransform::applies_to(&cfg, None));
+    }
+}
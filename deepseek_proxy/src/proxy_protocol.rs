@@ -0,0 +1,123 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// v1 头部最大长度（含 CRLF），见 PROXY protocol 规范 2.1
+const V1_MAX_LEN: usize = 107;
+/// v2 头部签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// 读取连接开头的 PROXY protocol 头部（v1 文本或 v2 二进制），返回负载均衡器转发的客户端地址
+///
+/// 只读取头部本身，之后的字节留给 HTTP 解析；LOCAL / UNKNOWN（如负载均衡健康检查）返回 None，
+/// 调用方应继续使用 TCP 对端地址
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> anyhow::Result<Option<SocketAddr>> {
+    let mut prefix = [0u8; 5];
+    stream.read_exact(&mut prefix).await?;
+
+    if &prefix == b"PROXY" {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                anyhow::bail!("PROXY v1 头部过长");
+            }
+            line.push(stream.read_u8().await?);
+        }
+        return parse_v1(std::str::from_utf8(&line)?);
+    }
+
+    if prefix == V2_SIGNATURE[..5] {
+        let mut fixed = [0u8; 16];
+        fixed[..5].copy_from_slice(&prefix);
+        stream.read_exact(&mut fixed[5..]).await?;
+        if fixed[..12] != V2_SIGNATURE {
+            anyhow::bail!("PROXY v2 签名无效");
+        }
+        let len = u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).await?;
+        return parse_v2(fixed[12], fixed[13], &body);
+    }
+
+    anyhow::bail!("连接未以 PROXY protocol 头部开始")
+}
+
+/// 解析 v1：`PROXY TCP4 <src> <dst> <sport> <dport>\r\n`
+fn parse_v1(line: &str) -> anyhow::Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.get(1).copied() {
+        Some("UNKNOWN") => Ok(None),
+        Some("TCP4") | Some("TCP6") if fields.len() == 6 => {
+            let ip: IpAddr = fields[2].parse()?;
+            let port: u16 = fields[4].parse()?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => anyhow::bail!("PROXY v1 头部格式无效: {}", line.trim_end()),
+    }
+}
+
+/// 解析 v2 地址块（只关心源地址）
+fn parse_v2(version_command: u8, family: u8, body: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        anyhow::bail!("PROXY v2 版本无效");
+    }
+    // LOCAL 命令：负载均衡器自身发起的连接
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+    match family >> 4 {
+        // AF_INET
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))))
+        }
+        // AF_INET6
+        2 if body.len() >= 36 => {
+            let octets: [u8; 16] = body[..16].try_into()?;
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), u16::from_be_bytes([body[32], body[33]]))))
+        }
+        // AF_UNSPEC / AF_UNIX：没有可用的 IP
+        0 | 3 => Ok(None),
+        _ => anyhow::bail!("PROXY v2 地址块无效"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_v1_header_leaves_payload() {
+        let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 8877\r\nGET / HTTP/1.1\r\n";
+        let addr = read_header(&mut input).await.unwrap();
+        assert_eq!(addr, Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+
+        let mut unknown: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut unknown).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_v2_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x21, 0, 36]);
+        header.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend(Ipv6Addr::LOCALHOST.octets());
+        header.extend(443u16.to_be_bytes());
+        header.extend(8877u16.to_be_bytes());
+        header.extend(b"GET");
+        let mut input = header.as_slice();
+        assert_eq!(read_header(&mut input).await.unwrap(), Some("[2001:db8::1]:443".parse().unwrap()));
+        assert_eq!(input, b"GET");
+
+        // LOCAL 命令
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([0x20, 0x00, 0, 0]);
+        assert_eq!(read_header(&mut local.as_slice()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_missing_header() {
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(read_header(&mut input).await.is_err());
+    }
+}
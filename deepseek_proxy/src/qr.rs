@@ -0,0 +1,421 @@
+//! 最小化的二维码编码（ISO/IEC 18004）：字节模式、纠错等级 M、版本 1-10（最多 213 字节），输出 SVG
+//!
+//! 只用于把 TOTP 的 otpauth:// 链接交给验证器 App 扫描，不追求通用性
+
+/// 各版本（1-10）纠错等级 M 的每块纠错码字数
+const ECC_CODEWORDS_PER_BLOCK: [usize; 11] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+/// 各版本（1-10）纠错等级 M 的分块数
+const NUM_BLOCKS: [usize; 11] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+/// 纠错等级 M 在格式信息中的编码
+const ECC_FORMAT_BITS_M: u32 = 0;
+const MAX_VERSION: usize = 10;
+
+pub struct QrCode {
+    size: usize,
+    modules: Vec<Vec<bool>>,
+    is_function: Vec<Vec<bool>>,
+}
+
+impl QrCode {
+    /// 编码字节数据；超过版本 10 的容量时返回 None
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let version = (1..=MAX_VERSION).find(|&v| 4 + char_count_bits(v) + data.len() * 8 <= data_codewords(v) * 8)?;
+
+        // 模式指示符（字节模式）+ 字符数 + 数据
+        let mut bits = BitBuffer::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, char_count_bits(version));
+        for &b in data {
+            bits.push(b as u32, 8);
+        }
+        // 终止符、补齐到整字节、交替填充 0xEC / 0x11
+        let capacity = data_codewords(version) * 8;
+        bits.push(0, (capacity - bits.0.len()).min(4));
+        bits.push(0, (8 - bits.0.len() % 8) % 8);
+        let mut pad = [0xEC, 0x11].into_iter().cycle();
+        while bits.0.len() < capacity {
+            bits.push(pad.next().unwrap_or(0), 8);
+        }
+        let codewords: Vec<u8> = bits.0.chunks(8).map(|c| c.iter().fold(0u8, |acc, &b| (acc << 1) | b as u8)).collect();
+
+        let size = version * 4 + 17;
+        let mut qr = Self {
+            size,
+            modules: vec![vec![false; size]; size],
+            is_function: vec![vec![false; size]; size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(version, &codewords));
+
+        // 选择惩罚分最低的掩码
+        let mut best = (u32::MAX, 0);
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+            let penalty = qr.penalty_score();
+            if penalty < best.0 {
+                best = (penalty, mask);
+            }
+            qr.apply_mask(mask); // 异或两次即撤销
+        }
+        qr.apply_mask(best.1);
+        qr.draw_format_bits(best.1);
+        Some(qr)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn module(&self, x: usize, y: usize) -> bool {
+        self.modules[y][x]
+    }
+
+    /// 渲染为 SVG（含 4 个模块宽的静区）
+    pub fn to_svg(&self) -> String {
+        let border = 4;
+        let dim = self.size + border * 2;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.modules[y][x] {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + border, y + border));
+                }
+            }
+        }
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {dim} {dim}\" shape-rendering=\"crispEdges\">\
+<rect width=\"100%\" height=\"100%\" fill=\"#fff\"/><path d=\"{path}\" fill=\"#000\"/></svg>"
+        )
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y][x] = dark;
+        self.is_function[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        // 定时图形
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        // 三个定位图形（含分隔符）
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+        // 校正图形（避开三个定位图形所在的角）
+        let positions = alignment_positions(version);
+        let n = positions.len();
+        for (i, &ay) in positions.iter().enumerate() {
+            for (j, &ax) in positions.iter().enumerate() {
+                if (i == 0 && (j == 0 || j == n - 1)) || (i == n - 1 && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function((ax as i32 + dx) as usize, (ay as i32 + dy) as usize, dark);
+                    }
+                }
+            }
+        }
+        // 先占位格式信息，再绘制版本信息
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let mut rem = version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+            }
+            let bits = (version as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let bit = |i: u32| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i as u32));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i as u32));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i as u32));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i as u32));
+        }
+        self.set_function(8, size - 8, true); // 固定的深色模块
+    }
+
+    /// 按之字形从右下角开始填充数据位
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.is_function[y][x] && i < data.len() * 8 {
+                        self.modules[y][x] = (data[i >> 3] >> (7 - (i & 7))) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.is_function[y][x] {
+                    self.modules[y][x] = !self.modules[y][x];
+                }
+            }
+        }
+    }
+
+    /// 标准中的四项惩罚规则：连续同色、2x2 同色块、类定位图形、深浅比例
+    fn penalty_score(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+        let line = |i: usize, horizontal: bool| -> Vec<bool> {
+            (0..size).map(|k| if horizontal { self.modules[i][k] } else { self.modules[k][i] }).collect()
+        };
+        for i in 0..size {
+            for horizontal in [true, false] {
+                let cells = line(i, horizontal);
+                let mut run = 1;
+                for k in 1..=size {
+                    if k < size && cells[k] == cells[k - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+                // 1:1:3:1:1 且一侧有 4 个浅色模块
+                let pattern = [true, false, true, true, true, false, true];
+                for k in 0..size.saturating_sub(6) {
+                    if cells[k..k + 7] == pattern {
+                        let before = k >= 4 && cells[k - 4..k].iter().all(|&c| !c);
+                        let after = k + 11 <= size && cells[k + 7..k + 11].iter().all(|&c| !c);
+                        if before || after {
+                            penalty += 40;
+                        }
+                    }
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let c = self.modules[y][x];
+                if c == self.modules[y][x + 1] && c == self.modules[y + 1][x] && c == self.modules[y + 1][x + 1] {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().flatten().filter(|&&c| c).count();
+        let total = size * size;
+        let deviation = (dark * 20).abs_diff(total * 10) / total;
+        penalty as u32 + deviation as u32 * 10
+    }
+}
+
+#[derive(Default)]
+struct BitBuffer(Vec<bool>);
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, len: usize) {
+        for i in (0..len).rev() {
+            self.0.push((value >> i) & 1 == 1);
+        }
+    }
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version <= 9 { 8 } else { 16 }
+}
+
+/// 除功能图形外可用于数据与纠错的模块数
+fn raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * NUM_BLOCKS[version]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let num_align = version / 7 + 2;
+    let size = version * 4 + 17;
+    let step = (version * 8 + num_align * 3 + 5) / (num_align * 4 - 4) * 2;
+    let mut result = vec![6; num_align];
+    let mut pos = size - 7;
+    for slot in result.iter_mut().skip(1).rev() {
+        *slot = pos;
+        pos -= step;
+    }
+    result
+}
+
+/// 15 位格式信息：纠错等级与掩码，BCH(15,5) 校验后与 0x5412 异或
+fn format_bits(mask: u32) -> u32 {
+    let data = ECC_FORMAT_BITS_M << 3 | mask;
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    (data << 10 | rem) ^ 0x5412
+}
+
+/// 分块计算 Reed-Solomon 纠错码并交织
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let num_blocks = NUM_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+    let divisor = rs_divisor(ecc_len);
+
+    let mut blocks = Vec::with_capacity(num_blocks);
+    let mut k = 0;
+    for i in 0..num_blocks {
+        let len = short_block_len - ecc_len + usize::from(i >= num_short_blocks);
+        let mut block = data[k..k + len].to_vec();
+        k += len;
+        let ecc = rs_remainder(&block, &divisor);
+        if i < num_short_blocks {
+            block.push(0); // 占位，交织时跳过
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// GF(2^8) 乘法，本原多项式 0x11D
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reed_solomon_and_format_bits() {
+        // 标准中 "HELLO WORLD"（1-M）的数据码字与纠错码字
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(rs_remainder(&data, &rs_divisor(10)), [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+        // 格式信息表：M 等级、掩码 0 / 7
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(7), 0b100101010100000);
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(data_codewords(1), 16);
+        assert_eq!(data_codewords(10), 216);
+    }
+
+    #[test]
+    fn test_encode_picks_version_by_length() {
+        let qr = QrCode::encode(b"otpauth://totp/proxy:alice?secret=JBSWY3DPEHPK3PXP&issuer=proxy").unwrap();
+        assert_eq!(qr.size(), 4 * 5 + 17);
+        // 左上角定位图形
+        assert!(qr.module(0, 0) && qr.module(6, 6) && !qr.module(1, 1) && qr.module(2, 2));
+        assert!(qr.to_svg().starts_with("<svg"));
+        assert!(QrCode::encode(&[b'a'; 214]).is_none());
+        assert!(QrCode::encode(&[b'a'; 213]).is_some());
+    }
+}
@@ -0,0 +1,181 @@
+//! 配额周期归档（data/quotas/archive/YYYY-MM/<用户>.json）
+//!
+//! 周期重置时把刚结束的周期（档次、上限、用量、剩余预付费额度）追加到该周期结束所在月份的归档文件，
+//! 当前配额文件只保留本周期的状态。按日、按周重置的档次一个月内有多个周期，同一文件中按结束时间排列
+
+use super::types::QuotaState;
+use crate::error::AppError;
+use chrono::{DateTime, Duration};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// 一个已结束的配额周期
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedCycle {
+    pub tier: String,
+    pub monthly_limit: u32,
+    pub used_count: u32,
+    /// 周期结束时剩余的预付费额度
+    pub credits: u32,
+    /// 周期结束（重置）时间
+    pub reset_at: String,
+    pub archived_at: String,
+}
+
+/// 某月归档中的一个用户
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedUser {
+    pub username: String,
+    pub cycles: Vec<ArchivedCycle>,
+}
+
+pub struct QuotaArchive {
+    dir: PathBuf,
+}
+
+impl QuotaArchive {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// 归档刚结束的周期；同一周期（reset_at 相同）已归档时跳过
+    pub async fn record(&self, state: &QuotaState) -> Result<(), AppError> {
+        let month = cycle_month(&state.reset_at)?;
+        let path = self.dir.join(&month).join(format!("{}.json", state.username));
+        let mut cycles = read_cycles(&path).await?;
+        if cycles.iter().any(|c| c.reset_at == state.reset_at) {
+            return Ok(());
+        }
+        cycles.push(ArchivedCycle {
+            tier: state.tier.clone(),
+            monthly_limit: state.monthly_limit,
+            used_count: state.used_count,
+            credits: state.credits,
+            reset_at: state.reset_at.clone(),
+            archived_at: crate::utils::now_beijing_rfc3339(),
+        });
+        crate::utils::write_json_atomic(&path, &cycles).await.map_err(|e| {
+            crate::metrics::METRICS.record_persist_failure("quota");
+            AppError::InternalError(format!("写入配额归档失败: {}", e))
+        })
+    }
+
+    /// 已有归档的月份（升序）
+    pub async fn months(&self) -> Vec<String> {
+        let mut months = Vec::new();
+        let Ok(mut read_dir) = tokio::fs::read_dir(&self.dir).await else { return months };
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() && is_month(&name) {
+                months.push(name);
+            }
+        }
+        months.sort();
+        months
+    }
+
+    /// 某月的归档（按用户名排序），月份不存在时返回 None
+    pub async fn month(&self, month: &str) -> Result<Option<Vec<ArchivedUser>>, AppError> {
+        if !is_month(month) {
+            return Err(AppError::BadRequest(format!("月份格式应为 YYYY-MM: {}", month)));
+        }
+        let Ok(mut read_dir) = tokio::fs::read_dir(self.dir.join(month)).await else { return Ok(None) };
+        let mut users = Vec::new();
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let username = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+            users.push(ArchivedUser { username, cycles: read_cycles(&path).await? });
+        }
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(Some(users))
+    }
+
+    /// 擦除用户的全部归档；指定 pseudonym 时改为以假名另存。返回涉及的月份数
+    pub async fn erase_user(&self, username: &str, pseudonym: Option<&str>) -> Result<usize, AppError> {
+        let mut erased = 0;
+        for month in self.months().await {
+            let path = self.dir.join(&month).join(format!("{}.json", username));
+            let result = match pseudonym {
+                Some(pseudonym) => tokio::fs::rename(&path, self.dir.join(&month).join(format!("{}.json", pseudonym))).await,
+                None => tokio::fs::remove_file(&path).await,
+            };
+            match result {
+                Ok(()) => erased += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(AppError::InternalError(format!("擦除配额归档 {} 失败: {}", month, e))),
+            }
+        }
+        Ok(erased)
+    }
+}
+
+/// 周期所属月份：取周期最后一刻（重置时间前 1 秒）所在的月份，时区与 reset_at 一致
+fn cycle_month(reset_at: &str) -> Result<String, AppError> {
+    let reset_at = DateTime::parse_from_rfc3339(reset_at)
+        .map_err(|e| AppError::InternalError(format!("解析重置时间失败: {}", e)))?;
+    Ok((reset_at - Duration::seconds(1)).format("%Y-%m").to_string())
+}
+
+fn is_month(name: &str) -> bool {
+    chrono::NaiveDate::parse_from_str(&format!("{}-01", name), "%Y-%m-%d").is_ok() && name.len() == 7
+}
+
+async fn read_cycles(path: &Path) -> Result<Vec<ArchivedCycle>, AppError> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| AppError::InternalError(format!("解析配额归档 {} 失败: {}", path.display(), e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(AppError::InternalError(format!("读取配额归档失败: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(username: &str, used: u32, reset_at: &str) -> QuotaState {
+        QuotaState {
+            username: username.to_string(),
+            tier: "basic".to_string(),
+            monthly_limit: 100,
+            used_count: used,
+            last_saved_count: used,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 0,
+            reset_policy: None,
+            dirty: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_query_and_erase() {
+        let dir = std::env::temp_dir().join("test_quota_archive");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let archive = QuotaArchive::new(dir.clone());
+
+        // 每月 1 日重置：11 月 1 日结束的周期归入 10 月
+        archive.record(&state("alice", 42, "2026-11-01T00:00:00+08:00")).await.unwrap();
+        archive.record(&state("alice", 42, "2026-11-01T00:00:00+08:00")).await.unwrap();
+        // 按日重置：同一月份多个周期
+        archive.record(&state("bob", 3, "2026-10-20T00:00:00+08:00")).await.unwrap();
+        archive.record(&state("bob", 5, "2026-10-21T00:00:00+08:00")).await.unwrap();
+        archive.record(&state("bob", 1, "2026-09-15T00:00:00+08:00")).await.unwrap();
+
+        assert_eq!(archive.months().await, ["2026-09", "2026-10"]);
+        let october = archive.month("2026-10").await.unwrap().unwrap();
+        assert_eq!(october.iter().map(|u| (u.username.as_str(), u.cycles.len())).collect::<Vec<_>>(), [("alice", 1), ("bob", 2)]);
+        assert_eq!(october[0].cycles[0].used_count, 42);
+        assert!(archive.month("2026-08").await.unwrap().is_none());
+        assert!(matches!(archive.month("../x").await, Err(AppError::BadRequest(_))));
+
+        assert_eq!(archive.erase_user("bob", Some("user-1")).await.unwrap(), 2);
+        assert_eq!(archive.erase_user("alice", None).await.unwrap(), 1);
+        let october = archive.month("2026-10").await.unwrap().unwrap();
+        assert_eq!(october.iter().map(|u| u.username.as_str()).collect::<Vec<_>>(), ["user-1"]);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
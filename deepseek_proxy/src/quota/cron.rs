@@ -0,0 +1,129 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+
+/// 五段式 cron 表达式：分 时 日 月 周
+///
+/// 每段支持 `*`、数字、范围 `a-b`、步长 `*/n` / `a-b/n` 及逗号分隔的列表；
+/// 周取 0-7（0 与 7 均为周日）。日与周都不是 `*` 时，任一匹配即触发（与标准 cron 一致）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("cron 表达式 \"{}\" 应为 5 段（分 时 日 月 周）", expr));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // 7 与 0 都表示周日
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    /// 该分钟是否触发
+    pub fn matches(&self, at: NaiveDateTime) -> bool {
+        let bit = |mask: u64, v: u32| mask & (1 << v) != 0;
+        let day = bit(self.days, at.day());
+        let weekday = bit(self.weekdays, at.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        bit(self.minutes, at.minute()) && bit(self.hours, at.hour()) && bit(self.months, at.month()) && day_matches
+    }
+}
+
+/// 解析一段，返回按位表示的取值集合
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("无效的步长 \"{}\"", part))?;
+                if step == 0 {
+                    return Err(format!("步长不能为 0: \"{}\"", part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (parse_value(a, min, max)?, parse_value(b, min, max)?)
+        } else {
+            let v = parse_value(range, min, max)?;
+            // "5/15" 表示从 5 开始每 15 一次
+            (v, if part.contains('/') { max } else { v })
+        };
+        if start > end {
+            return Err(format!("无效的范围 \"{}\"", part));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(v) if (min..=max).contains(&v) => Ok(v),
+        _ => Err(format!("\"{}\" 不在 {}-{} 范围内", value, min, max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_weekend_schedule() {
+        // 周六、周日 0 点
+        let cron = CronSchedule::parse("0 0 * * 6,7").unwrap();
+        assert!(cron.matches(at(2026, 10, 17, 0, 0))); // 周六
+        assert!(cron.matches(at(2026, 10, 18, 0, 0))); // 周日
+        assert!(!cron.matches(at(2026, 10, 16, 0, 0))); // 周五
+        assert!(!cron.matches(at(2026, 10, 17, 0, 1)));
+    }
+
+    #[test]
+    fn test_ranges_steps_and_day_or_weekday() {
+        let cron = CronSchedule::parse("*/15 9-17 * * 1-5").unwrap();
+        assert!(cron.matches(at(2026, 10, 16, 9, 45)));
+        assert!(!cron.matches(at(2026, 10, 16, 18, 0)));
+        assert!(!cron.matches(at(2026, 10, 16, 9, 10)));
+
+        // 日与周都受限时任一匹配即可：每月 1 号或每周一
+        let cron = CronSchedule::parse("30 8 1 * 1").unwrap();
+        assert!(cron.matches(at(2026, 10, 1, 8, 30))); // 周四，但是 1 号
+        assert!(cron.matches(at(2026, 10, 12, 8, 30))); // 周一
+
+        assert!(CronSchedule::parse("0 0 * *").is_err());
+        assert!(CronSchedule::parse("60 0 * * *").is_err());
+        assert!(CronSchedule::parse("*/0 0 * * *").is_err());
+        assert!(CronSchedule::parse("0 5-2 * * *").is_err());
+    }
+}
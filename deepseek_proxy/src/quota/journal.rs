@@ -0,0 +1,232 @@
+//! 在途扣费日志（data/charge_journal.json）
+//!
+//! 流式响应在开始下发前就已扣费：扣费时登记一条记录，响应结束（正常完成、上游中断或客户端断开）后注销。
+//! 进程在响应完成前退出（崩溃、被强制终止）时记录留在文件中，下次启动时按 `quota.crash_recovery`
+//! 退还或确认，避免用户为没有收到的回复付费。
+//!
+//! `refund` 策略下登记后立即写盘（崩溃后才能退还）；注销与 `confirm` 策略下的登记只标记变化，
+//! 由后台任务每秒合并写入一次，不再每个请求写两次文件
+
+use super::types::Charge;
+use super::QuotaManager;
+use crate::config::ChargeRecovery;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 后台合并写入的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 一条未完成响应的扣费记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChargeEntry {
+    pub username: String,
+    #[serde(flatten)]
+    pub charge: Charge,
+    pub charged_at: String,
+}
+
+pub struct ChargeJournal {
+    path: PathBuf,
+    entries: Mutex<HashMap<u64, ChargeEntry>>,
+    next_id: AtomicU64,
+    /// 串行化写文件，保证最后写入的是最新的快照
+    write_lock: tokio::sync::Mutex<()>,
+    /// 有未写盘的变化
+    dirty: AtomicBool,
+    /// 未完成响应的扣费处理策略（refund 时登记需立即写盘）
+    policy: ChargeRecovery,
+}
+
+impl ChargeJournal {
+    /// 加载日志；其中的记录都是上次运行遗留的，启动时交给 [`ChargeJournal::recover`] 处理
+    pub async fn load(path: PathBuf, policy: ChargeRecovery) -> Self {
+        let entries: HashMap<u64, ChargeEntry> = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "在途扣费日志无法解析，已忽略");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let next_id = entries.keys().max().map_or(0, |id| id + 1);
+        Self {
+            path,
+            entries: Mutex::new(entries),
+            next_id: AtomicU64::new(next_id),
+            write_lock: tokio::sync::Mutex::new(()),
+            dirty: AtomicBool::new(false),
+            policy,
+        }
+    }
+
+    /// 处理上次运行遗留的记录（启动时、开始接受请求前调用）
+    pub async fn recover(&self, quota_manager: &QuotaManager) {
+        let policy = self.policy;
+        if crate::read_only::is_enabled() {
+            let pending = self.entries.lock().unwrap().len();
+            if pending > 0 {
+                tracing::warn!(pending, "只读模式：暂不处理上次运行遗留的未完成响应扣费");
+            }
+            return;
+        }
+        let leftovers = std::mem::take(&mut *self.entries.lock().unwrap());
+        if leftovers.is_empty() {
+            return;
+        }
+        tracing::warn!(count = leftovers.len(), policy = ?policy, "上次运行有未完成的流式响应，处理其扣费");
+        for entry in leftovers.into_values() {
+            let ChargeEntry { username, charge, charged_at } = entry;
+            match policy {
+                ChargeRecovery::Confirm => {
+                    tracing::info!(user = %username, charged_at = %charged_at, period = charge.period, credits = charge.credits, "未完成响应的扣费已确认");
+                }
+                ChargeRecovery::Refund => match quota_manager.refund(&username, &charge).await {
+                    Ok((period, credits)) => {
+                        tracing::info!(user = %username, charged_at = %charged_at, period, credits, "未完成响应的扣费已退还");
+                    }
+                    Err(e) => {
+                        tracing::error!(user = %username, charged_at = %charged_at, period = charge.period, credits = charge.credits, error = %e, "退还未完成响应的扣费失败，请人工核对");
+                    }
+                },
+            }
+        }
+        self.persist().await;
+    }
+
+    /// 登记一次扣费，返回的 guard 随响应流释放时注销记录
+    pub async fn open(self: &Arc<Self>, username: &str, charge: Charge) -> ChargeGuard {
+        if charge.period == 0 && charge.credits == 0 {
+            return ChargeGuard { journal: self.clone(), id: None };
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = ChargeEntry {
+            username: username.to_string(),
+            charge,
+            charged_at: crate::utils::now_beijing_rfc3339(),
+        };
+        self.entries.lock().unwrap().insert(id, entry);
+        if self.policy == ChargeRecovery::Refund {
+            self.persist().await;
+        } else {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        ChargeGuard { journal: self.clone(), id: Some(id) }
+    }
+
+    /// 写入当前记录（只读模式下只保留在内存中）
+    pub async fn persist(&self) {
+        if crate::read_only::is_enabled() {
+            return;
+        }
+        let _guard = self.write_lock.lock().await;
+        self.dirty.store(false, Ordering::Relaxed);
+        let snapshot = self.entries.lock().unwrap().clone();
+        if let Err(e) = crate::utils::write_json_atomic(&self.path, &snapshot).await {
+            self.dirty.store(true, Ordering::Relaxed);
+            crate::metrics::METRICS.record_persist_failure("charge_journal");
+            tracing::warn!(error = %e, "写入在途扣费日志失败");
+        }
+    }
+
+    /// 后台任务：记录有变化时定期写盘
+    pub fn spawn_flusher(self: Arc<Self>) {
+        crate::supervisor::spawn("charge_journal_flusher", move || {
+            let journal = self.clone();
+            async move {
+                let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if journal.dirty.load(Ordering::Relaxed) {
+                        journal.persist().await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// 在途扣费记录的持有者：释放时注销记录（响应已结束，扣费成立），由后台任务合并写盘
+pub struct ChargeGuard {
+    journal: Arc<ChargeJournal>,
+    id: Option<u64>,
+}
+
+impl Drop for ChargeGuard {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        self.journal.entries.lock().unwrap().remove(&id);
+        self.journal.dirty.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota::{QuotaState, ResetPolicy};
+
+    fn charge(period: u32, used_after: u32, reset_at: &str, credits: u32) -> Charge {
+        Charge { period, used_after, reset_at: reset_at.to_string(), credits }
+    }
+
+    #[tokio::test]
+    async fn test_recover_refunds_only_persisted_charges() {
+        let dir = std::env::temp_dir().join("test_charge_journal");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("quotas")).await.unwrap();
+        let reset_at = "2999-01-01T00:00:00+08:00";
+        let quota = QuotaState {
+            username: "u".to_string(),
+            tier: "basic".to_string(),
+            monthly_limit: 100,
+            used_count: 5,
+            last_saved_count: 5,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 2,
+            reset_policy: Some(ResetPolicy::Monthly),
+            dirty: false,
+        };
+        tokio::fs::write(dir.join("quotas/u.json"), serde_json::to_string(&quota).unwrap()).await.unwrap();
+        let entry = |charge: Charge| ChargeEntry { username: "u".to_string(), charge, charged_at: String::new() };
+        let leftovers = HashMap::from([
+            (0u64, entry(charge(2, 5, reset_at, 0))),
+            // 扣费后的用量没来得及落盘，已随进程退出丢失
+            (1, entry(charge(1, 9, reset_at, 0))),
+            // 扣费所在的周期已经重置
+            (2, entry(charge(1, 3, "2000-01-01T00:00:00+08:00", 0))),
+            (3, entry(charge(0, 0, "", 1))),
+        ]);
+        let journal_path = dir.join("charge_journal.json");
+        crate::utils::write_json_atomic(&journal_path, &leftovers).await.unwrap();
+
+        let users = crate::auth::UserManager::new(dir.join("users"), Vec::new(), crate::auth::password::params(1024, 1).unwrap()).await.unwrap();
+        let manager = QuotaManager::new(Arc::new(crate::config::test_config("")), Arc::new(users), dir.join("quotas"), 100);
+        let journal = Arc::new(ChargeJournal::load(journal_path.clone(), ChargeRecovery::Refund).await);
+        journal.recover(&manager).await;
+
+        let quota = manager.get_quota("u").await.unwrap();
+        assert_eq!((quota.used_count, quota.credits), (3, 3));
+        assert_eq!(tokio::fs::read_to_string(&journal_path).await.unwrap(), "{}");
+
+        // refund 策略：登记后立即落盘；响应结束（guard 释放）后只标记变化，等待合并写入
+        let guard = journal.open("u", charge(1, 4, reset_at, 0)).await;
+        let reloaded = ChargeJournal::load(journal_path.clone(), ChargeRecovery::Refund).await;
+        assert_eq!(reloaded.entries.lock().unwrap().len(), 1);
+        assert_eq!(reloaded.next_id.load(Ordering::Relaxed), 5);
+        drop(guard);
+        assert!(journal.dirty.load(Ordering::Relaxed));
+        journal.persist().await;
+        assert_eq!(tokio::fs::read_to_string(&journal_path).await.unwrap(), "{}");
+
+        // confirm 策略：登记也不立即写盘
+        let journal = Arc::new(ChargeJournal::load(journal_path.clone(), ChargeRecovery::Confirm).await);
+        let _guard = journal.open("u", charge(1, 4, reset_at, 0)).await;
+        assert_eq!(tokio::fs::read_to_string(&journal_path).await.unwrap(), "{}");
+        assert!(journal.dirty.load(Ordering::Relaxed));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
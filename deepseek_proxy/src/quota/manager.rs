@@ -1,9 +1,13 @@
-use super::types::{QuotaState, QuotaStateAtomic, QuotaStatus, QuotaTier};
-use crate::config::Config;
-use crate::error::AppError;
-use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use super::preview::ResetPreviewReport;
+use super::topup::TopUpRuns;
+use super::types::{Charge, QuotaState, QuotaStateAtomic, QuotaStatus, TierPlan};
+use super::policy::NEVER_RESET_AT;
+use super::{CronSchedule, QuotaArchive, ResetPolicy};
+use crate::config::{Config, TopUpSchedule};
+use crate::error::{AppError, QuotaError};
+use chrono::{DateTime, FixedOffset, Timelike, Utc};
 use dashmap::DashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// 配额管理器（优化版：使用 DashMap + 原子操作）
@@ -23,6 +27,9 @@ pub struct QuotaManager {
 
     /// 写入间隔（每N次请求写一次）
     save_interval: u32,
+
+    /// 已结束周期的归档（data_dir/archive/YYYY-MM/）
+    archive: QuotaArchive,
 }
 
 impl QuotaManager {
@@ -36,11 +43,17 @@ impl QuotaManager {
             cache: Arc::new(DashMap::new()),
             config,
             user_manager,
+            archive: QuotaArchive::new(data_dir.join("archive")),
             data_dir,
             save_interval,
         }
     }
 
+    /// 已结束周期的归档
+    pub fn archive(&self) -> &QuotaArchive {
+        &self.archive
+    }
+
     /// 懒加载用户配额（优化版：使用 DashMap 的 entry API）
     async fn load_or_init(&self, username: &str) -> Result<Arc<QuotaStateAtomic>, AppError> {
         // 1. 快速检查内存缓存
@@ -49,15 +62,11 @@ impl QuotaManager {
         }
 
         // 2. 尝试从磁盘加载（无锁 IO）
-        let file_path = self.data_dir.join(format!("{}.json", username));
-        let state = if file_path.exists() {
-            let content = tokio::fs::read_to_string(&file_path)
-                .await
-                .map_err(|e| AppError::InternalError(format!("读取配额文件失败: {}", e)))?;
-
-            let state: QuotaState = serde_json::from_str(&content)
-                .map_err(|e| AppError::InternalError(format!("解析配额数据失败: {}", e)))?;
-
+        let mut realigned = false;
+        let state = if let Some(mut state) = self.read_state_file(username).await? {
+            // 先按当前配置确定档次，再按该档次的重置策略校正重置时间
+            let retiered = self.apply_fallback_tier(&mut state);
+            realigned = self.align_reset_policy(&mut state)? || retiered;
             QuotaStateAtomic::from_state(state)
         } else {
             // 3. 首次访问，从 UserManager 获取用户信息
@@ -66,49 +75,136 @@ impl QuotaManager {
                 .await
                 .ok_or_else(|| AppError::Unauthorized(format!("用户 {} 不存在", username)))?;
 
-            let tier = QuotaTier::from_str(&user.quota_tier)
-                .ok_or_else(|| AppError::InternalError("无效的配额档次".to_string()))?;
+            let (tier, limit) = self.resolve_tier(username, &user.quota_tier)?;
 
-            tracing::info!("初始化用户 {} 的配额：档次={}, 限额={}", username, user.quota_tier, tier.limit(&self.config.quota.tiers));
+            tracing::info!("初始化用户 {} 的配额：档次={}, 限额={}", username, tier, limit);
 
-            let reset_at = Self::next_month_reset()
+            let reset_at = self.next_reset(&tier)
                 .map_err(|e| AppError::InternalError(format!("重置时间计算失败: {}", e)))?;
 
             QuotaStateAtomic::from_state(QuotaState {
+                reset_policy: Some(reset_policy(&self.config, &tier)),
                 username: username.to_string(),
-                tier: tier.as_str().to_string(),
-                monthly_limit: tier.limit(&self.config.quota.tiers),
+                tier,
+                monthly_limit: limit,
                 used_count: 0,
                 last_saved_count: 0,
                 reset_at,
                 last_saved_at: None,
+                credits: 0,
                 dirty: true,
             })
         };
 
         // 4. 使用 DashMap 的 entry API 保证原子插入（避免竞态条件）
         let state_arc = Arc::new(state);
-        self.cache
+        let state_arc = self.cache
             .entry(username.to_string())
-            .or_insert_with(|| state_arc.clone());
+            .or_insert_with(|| state_arc.clone())
+            .clone();
+        if realigned {
+            self.save_one_immediately(username, &state_arc).await?;
+        }
 
         Ok(state_arc)
     }
 
+    /// 配额文件中的档次已从 [quota.tiers] 中删除或改名时改用 fallback_tier 及其上限，返回是否修改
+    ///
+    /// 没有 fallback_tier 时保持不变，该用户的请求在 check_quota 中被拒绝
+    fn apply_fallback_tier(&self, state: &mut QuotaState) -> bool {
+        if self.config.quota.tiers.limit(&state.tier.to_lowercase()).is_some() {
+            return false;
+        }
+        let Some((tier, limit)) = configured_tier(&self.config, &state.tier) else { return false };
+        tracing::warn!(user = %state.username, tier = %state.tier, fallback = %tier, limit, "配额文件中的档次未在 [quota.tiers] 中定义，改用 fallback_tier");
+        state.tier = tier;
+        state.monthly_limit = limit;
+        true
+    }
+
+    /// 档次的重置策略改变后（如 never 改为 monthly），按新策略重新计算文件中的重置时间，返回是否修改
+    ///
+    /// 旧文件没有记录策略：重置时间与当前策略明显不符（永不重置的占位时间，或晚于当前策略的下一次重置）时才重新计算
+    fn align_reset_policy(&self, state: &mut QuotaState) -> Result<bool, AppError> {
+        let policy = reset_policy(&self.config, &state.tier);
+        let next = self.next_reset(&state.tier)
+            .map_err(|e| AppError::InternalError(format!("重置时间计算失败: {}", e)))?;
+        let stale = match state.reset_policy {
+            Some(stored) => stored != policy,
+            None => {
+                let never = state.reset_at == NEVER_RESET_AT;
+                let later = match (DateTime::parse_from_rfc3339(&state.reset_at), DateTime::parse_from_rfc3339(&next)) {
+                    (Ok(stored), Ok(next)) => stored > next,
+                    _ => false,
+                };
+                never != (policy == ResetPolicy::Never) || later
+            }
+        };
+        state.reset_policy = Some(policy);
+        if !stale {
+            return Ok(false);
+        }
+        tracing::info!(user = %state.username, tier = %state.tier, old = %state.reset_at, new = %next, policy = ?policy, "档次重置策略已变更，重新计算重置时间");
+        state.reset_at = next;
+        Ok(true)
+    }
+
+    /// 读取磁盘上的配额文件，不存在时返回 None
+    async fn read_state_file(&self, username: &str) -> Result<Option<QuotaState>, AppError> {
+        let file_path = self.data_dir.join(format!("{}.json", username));
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        let content = tokio::fs::read_to_string(&file_path)
+            .await
+            .map_err(|e| AppError::InternalError(format!("读取配额文件失败: {}", e)))?;
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| AppError::InternalError(format!("解析配额数据失败: {}", e)))
+    }
+
+    /// 预演 `at` 时刻的周期重置：列出会重置的用户、重置后的上限与异常，不修改内存或文件
+    pub async fn preview_reset(&self, at: DateTime<FixedOffset>) -> Result<ResetPreviewReport, AppError> {
+        let mut users = self.user_manager.list_users().await;
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        let mut previews = Vec::with_capacity(users.len());
+        for user in users {
+            let cached = self.cache.get(&user.username).map(|entry| entry.value().clone());
+            let state = match cached {
+                Some(state) => Some(state.to_state().await),
+                None => self.read_state_file(&user.username).await?,
+            };
+            previews.push(super::preview::preview_user(&self.config, &user.username, &user.quota_tier, state.as_ref(), at));
+        }
+        Ok(ResetPreviewReport::new(at, previews))
+    }
+
     /// 只检查配额（不扣费）- 优化版：无锁读取
     pub async fn check_quota(&self, username: &str) -> Result<QuotaStatus, AppError> {
         // 确保用户数据已加载
         let state = self.load_or_init(username).await?;
+        // 档次已删除且没有 fallback_tier 时拒绝请求（与首次初始化一致）
+        self.resolve_tier(username, &state.plan().tier)?;
+        // 已过重置时间则先重置，避免耗尽的用户在新周期仍被拒绝
+        self.reset_if_due(username, &state).await?;
 
         let reset_at_str = state.reset_at.read().await.clone();
         let reset_at = DateTime::parse_from_rfc3339(&reset_at_str)
             .map_err(|e| AppError::InternalError(format!("解析重置时间失败: {}", e)))?;
 
         let used = state.get_used();
-        let limit = state.monthly_limit;
+        let limit = state.plan().monthly_limit;
+        // 沙箱档次到达每日上限即停止，不使用预付费额度
+        let credits = if self.is_sandbox(&state) { 0 } else { state.get_credits() };
+
+        // 服务账户不受配额限制（用量仍会记录）
+        let unlimited = self.is_unlimited(username).await;
 
-        // 只检查，不递增
-        if used >= limit {
+        // 只检查，不递增；周期配额耗尽后可继续使用预付费额度
+        if used >= limit && credits == 0 && !unlimited {
             Ok(QuotaStatus::Exceeded {
                 used,
                 limit,
@@ -118,37 +214,30 @@ impl QuotaManager {
             Ok(QuotaStatus::Ok {
                 used,
                 limit,
-                remaining: limit - used,
+                remaining: limit.saturating_sub(used) + credits,
+                credits,
                 reset_at,
             })
         }
     }
 
-    /// 递增配额（在确认请求成功后调用）- 优化版：原子操作
-    pub async fn increment_quota(&self, username: &str) -> Result<(), AppError> {
+    /// 递增配额（在确认请求成功后调用）- 优化版：原子操作，返回扣费明细
+    pub async fn increment_quota(&self, username: &str) -> Result<Charge, AppError> {
         // 确保用户数据已加载
         let state = self.load_or_init(username).await?;
 
-        let now = Utc::now();
-
-        // 检查是否需要月度重置
-        let need_reset = {
-            let reset_at_str = state.reset_at.read().await.clone();
-            let reset_at = DateTime::parse_from_rfc3339(&reset_at_str)
-                .map_err(|e| AppError::InternalError(format!("解析重置时间失败: {}", e)))?;
-            now > reset_at.with_timezone(&Utc)
-        };
-
-        if need_reset {
-            tracing::info!("用户 {} 配额月度重置", username);
-
-            let new_reset_at = Self::next_month_reset()
-                .map_err(|e| AppError::InternalError(format!("重置时间计算失败: {}", e)))?;
-
-            state.reset(new_reset_at).await;
+        self.reset_if_due(username, &state).await?;
 
-            // 重置时立即保存
-            self.save_one_immediately(username, &state).await?;
+        // 周期配额已耗尽时优先扣减预付费额度（立即保存，避免重启丢失扣减）
+        // 服务账户不消耗预付费额度，只记录用量
+        if state.get_used() >= state.plan().monthly_limit
+            && !self.is_sandbox(&state)
+            && !self.is_unlimited(username).await
+            && state.consume_credit()
+        {
+            tracing::debug!("用户 {} 使用预付费额度，剩余 {}", username, state.get_credits());
+            self.save_one(username, &state).await?;
+            return Ok(Charge { credits: 1, ..Charge::default() });
         }
 
         // 原子递增计数（无锁操作）
@@ -170,17 +259,297 @@ impl QuotaManager {
             self.save_one(username, &state).await?;
         }
 
+        let reset_at = state.reset_at.read().await.clone();
+        Ok(Charge { period: 1, used_after: current_used, reset_at, credits: 0 })
+    }
+
+    fn is_sandbox(&self, state: &QuotaStateAtomic) -> bool {
+        self.config.sandbox.is_sandbox(&state.plan().tier)
+    }
+
+    /// 是否为不受配额限制的服务账户
+    async fn is_unlimited(&self, username: &str) -> bool {
+        self.user_manager
+            .get_user(username)
+            .await
+            .map(|u| u.unlimited)
+            .unwrap_or(false)
+    }
+
+    /// 按指定次数递增配额（用于按权重计费的辅助调用）
+    pub async fn increment_quota_by(&self, username: &str, times: u32) -> Result<Charge, AppError> {
+        let mut charge = Charge::default();
+        for _ in 0..times {
+            charge.merge(self.increment_quota(username).await?);
+        }
+        Ok(charge)
+    }
+
+    /// 退还一次扣费（用于启动时处理进程退出前未完成的响应），返回退还的周期配额与预付费额度
+    ///
+    /// 按磁盘上的配额计算：扣费后用量尚未落盘的部分已随进程退出丢失，不再重复退还；周期已重置时只退还预付费额度
+    pub async fn refund(&self, username: &str, charge: &Charge) -> Result<(u32, u32), AppError> {
+        let state = self.load_or_init(username).await?;
+        let period = if charge.period > 0 && *state.reset_at.read().await == charge.reset_at {
+            let lost = charge.used_after.saturating_sub(state.get_used());
+            let refunded = state.refund_used(charge.period.saturating_sub(lost));
+            state.update_last_saved(state.get_used());
+            refunded
+        } else {
+            0
+        };
+        if charge.credits > 0 {
+            state.add_credits(charge.credits);
+        }
+        if period > 0 || charge.credits > 0 {
+            self.save_one_immediately(username, &state).await?;
+        }
+        Ok((period, charge.credits))
+    }
+
+    /// 按档次的重置策略检查是否到期，到期则重置并立即保存
+    ///
+    /// 新周期按当前配置重新解析档次与上限：档次已删除时改用 fallback_tier，上限调整后生效
+    async fn reset_if_due(&self, username: &str, state: &Arc<QuotaStateAtomic>) -> Result<(), AppError> {
+        let need_reset = {
+            let reset_at_str = state.reset_at.read().await.clone();
+            let reset_at = DateTime::parse_from_rfc3339(&reset_at_str)
+                .map_err(|e| AppError::InternalError(format!("解析重置时间失败: {}", e)))?;
+            Utc::now() > reset_at.with_timezone(&Utc)
+        };
+
+        if need_reset {
+            let current = state.plan();
+            // 档次已删除且没有 fallback_tier 时沿用原档次（请求在 check_quota 中被拒绝）
+            let (tier, monthly_limit) = configured_tier(&self.config, &current.tier)
+                .unwrap_or((current.tier.clone(), current.monthly_limit));
+            let plan = TierPlan { reset_policy: reset_policy(&self.config, &tier), tier, monthly_limit };
+            if plan != current {
+                tracing::info!(user = %username, old = ?current, new = ?plan, "周期重置时按当前配置更新档次");
+            }
+            tracing::info!("用户 {} 配额周期重置（档次={}）", username, plan.tier);
+
+            let new_reset_at = self.next_reset(&plan.tier)
+                .map_err(|e| AppError::InternalError(format!("重置时间计算失败: {}", e)))?;
+
+            // 刚结束的周期移入归档，当前文件只保留新周期（归档失败不影响重置）
+            if !crate::read_only::is_enabled() {
+                if let Err(e) = self.archive.record(&state.to_state().await).await {
+                    tracing::warn!(user = %username, error = %e, "归档已结束的配额周期失败");
+                }
+            }
+            state.reset(new_reset_at, plan).await;
+
+            // 重置时立即保存
+            self.save_one_immediately(username, state).await?;
+        }
+
         Ok(())
     }
 
     /// 查询配额信息（不递增）- 优化版
     pub async fn get_quota(&self, username: &str) -> Result<QuotaState, AppError> {
         let state = self.load_or_init(username).await?;
+        self.reset_if_due(username, &state).await?;
         Ok(state.to_state().await)
     }
 
+    /// 发放预付费额度（立即保存），返回新余额
+    pub async fn grant_credits(&self, username: &str, amount: u32) -> Result<u32, AppError> {
+        let state = self.load_or_init(username).await?;
+        let balance = state.add_credits(amount);
+        self.save_one_immediately(username, &state).await?;
+        tracing::info!("用户 {} 获得 {} 次预付费额度，当前余额 {}", username, amount, balance);
+        Ok(balance)
+    }
+
+    /// 启动定时发放额度任务（[[quota.topups]]）：每分钟检查一次，执行记录保存在 runs_path
+    ///
+    /// 只在触发的那一分钟内执行，服务停机期间错过的触发不补发
+    pub fn spawn_topup_scheduler(self: Arc<Self>, runs_path: PathBuf) {
+        if crate::read_only::is_enabled() {
+            // 发放记录无法落盘，恢复正常模式后可能重复发放
+            return;
+        }
+        let schedules: Vec<(TopUpSchedule, CronSchedule)> = self
+            .config
+            .quota
+            .topups
+            .iter()
+            // 表达式已在加载配置时校验
+            .filter_map(|topup| Some((topup.clone(), CronSchedule::parse(&topup.schedule).ok()?)))
+            .collect();
+        if schedules.is_empty() {
+            return;
+        }
+        tracing::info!("定时发放额度: {} 个计划", schedules.len());
+        let schedules = Arc::new(schedules);
+        crate::supervisor::spawn("topup_scheduler", move || {
+            let (manager, schedules, runs_path) = (self.clone(), schedules.clone(), runs_path.clone());
+            async move {
+                // 重启后重新读取执行记录，同一分钟内已发放的计划不会重复发放
+                let mut runs = TopUpRuns::load(&runs_path).await;
+                loop {
+                    let now = crate::utils::now_beijing();
+                    let slot = now.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(now);
+                    let slot_key = slot.format("%Y-%m-%dT%H:%M%:z").to_string();
+                    for (topup, cron) in schedules.iter() {
+                        if cron.matches(slot.naive_local()) {
+                            manager.run_topup(topup, &slot_key, &mut runs, &runs_path).await;
+                        }
+                    }
+                    let next = slot + chrono::Duration::minutes(1);
+                    tokio::time::sleep((next - crate::utils::now_beijing()).to_std().unwrap_or_default()).await;
+                }
+            }
+        });
+    }
+
+    /// 启动后台归档任务：每小时检查一次全部配额文件，周期已结束的立即重置并归档，
+    /// 长期不活跃的用户不必等到下次请求才把上个周期移出当前文件
+    pub fn spawn_compactor(self: Arc<Self>) {
+        if crate::read_only::is_enabled() {
+            return;
+        }
+        crate::supervisor::spawn("quota_compactor", move || {
+            let manager = self.clone();
+            async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+                loop {
+                    interval.tick().await;
+                    let compacted = manager.compact_due().await;
+                    if compacted > 0 {
+                        tracing::info!(users = compacted, "已归档到期的配额周期");
+                    }
+                }
+            }
+        });
+    }
+
+    /// 重置并归档周期已结束的配额文件，返回处理的用户数
+    pub async fn compact_due(&self) -> usize {
+        let mut compacted = 0;
+        let Ok(mut read_dir) = tokio::fs::read_dir(&self.data_dir).await else { return 0 };
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(username) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else { continue };
+            // 匿名化后保留的用量记录不是在用账户，不再重置归档
+            if username.starts_with(crate::admin::PSEUDONYM_PREFIX) {
+                continue;
+            }
+            let due = match self.cache.get(&username).map(|state| state.clone()) {
+                Some(state) => state.reset_at.read().await.clone(),
+                None => match self.read_state_file(&username).await {
+                    Ok(Some(state)) => state.reset_at,
+                    _ => continue,
+                },
+            };
+            if !DateTime::parse_from_rfc3339(&due).is_ok_and(|reset_at| Utc::now() > reset_at.with_timezone(&Utc)) {
+                continue;
+            }
+            let result = match self.load_or_init(&username).await {
+                Ok(state) => self.reset_if_due(&username, &state).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => compacted += 1,
+                Err(e) => tracing::warn!(user = %username, error = %e, "归档配额周期失败"),
+            }
+        }
+        compacted
+    }
+
+    /// 执行一次发放：跳过该触发时刻已发放过的用户，每发放一个用户立即保存记录
+    async fn run_topup(&self, topup: &TopUpSchedule, slot: &str, runs: &mut TopUpRuns, runs_path: &Path) {
+        let mut granted = 0;
+        for username in self.topup_targets(topup).await {
+            if runs.is_granted(&topup.name, slot, &username) {
+                continue;
+            }
+            if let Err(e) = self.grant_credits(&username, topup.amount).await {
+                tracing::warn!(schedule = %topup.name, user = %username, error = %e, "定时发放额度失败");
+                continue;
+            }
+            runs.record(&topup.name, slot, &username);
+            if let Err(e) = runs.save(runs_path).await {
+                tracing::warn!(schedule = %topup.name, error = %e, "保存定时发放记录失败");
+            }
+            crate::metrics::METRICS.quota_topup_grants.with_label_values(&[&topup.name]).inc();
+            granted += 1;
+        }
+        if granted > 0 {
+            tracing::info!(schedule = %topup.name, slot, users = granted, amount = topup.amount, "定时发放额度完成");
+        }
+    }
+
+    /// 计划的发放对象：users 中列出的用户与 tiers 中各档次的用户，只包括启用的账户
+    async fn topup_targets(&self, topup: &TopUpSchedule) -> Vec<String> {
+        let users = self.user_manager.list_users().await;
+        for name in topup.users.iter().filter(|name| !users.iter().any(|u| &u.username == *name)) {
+            tracing::warn!(schedule = %topup.name, user = %name, "定时发放额度: 用户不存在，跳过");
+        }
+        users
+            .into_iter()
+            .filter(|u| u.is_active)
+            .filter(|u| {
+                topup.users.contains(&u.username) || topup.tiers.iter().any(|t| t.eq_ignore_ascii_case(&u.quota_tier))
+            })
+            .map(|u| u.username)
+            .collect()
+    }
+
+    /// 擦除用户配额数据，返回配额文件是否存在
+    ///
+    /// 指定 pseudonym 时改为匿名化：以假名另存用量记录（保留统计价值），再删除原文件
+    pub async fn erase_user(&self, username: &str, pseudonym: Option<&str>) -> Result<bool, AppError> {
+        let cached = self.cache.remove(username).map(|(_, state)| state);
+        let file_path = self.data_dir.join(format!("{}.json", username));
+
+        if let Some(pseudonym) = pseudonym {
+            let state = match cached {
+                Some(state) => Some(state.to_state().await),
+                None => match tokio::fs::read_to_string(&file_path).await {
+                    Ok(content) => Some(serde_json::from_str::<QuotaState>(&content)
+                        .map_err(|e| AppError::InternalError(format!("解析配额数据失败: {}", e)))?),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(AppError::InternalError(format!("读取配额文件失败: {}", e))),
+                },
+            };
+            if let Some(mut state) = state {
+                state.username = pseudonym.to_string();
+                self.save_one(pseudonym, &Arc::new(QuotaStateAtomic::from_state(state))).await?;
+            }
+        }
+
+        match tokio::fs::remove_file(&file_path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(AppError::InternalError(format!("删除配额文件失败: {}", e))),
+        }
+    }
+
+    /// 把用户配额文件移到 archive_dir（先落盘内存中尚未保存的用量），返回配额文件是否存在
+    pub async fn archive_user(&self, username: &str, archive_dir: &Path) -> Result<bool, AppError> {
+        if let Some((_, state)) = self.cache.remove(username) {
+            self.save_one(username, &state).await?;
+        }
+        let file_path = self.data_dir.join(format!("{}.json", username));
+        match tokio::fs::rename(&file_path, archive_dir.join("quota.json")).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(AppError::InternalError(format!("归档配额文件失败: {}", e))),
+        }
+    }
+
     /// 保存单个用户数据 - 优化版：直接接受 Arc<QuotaStateAtomic>
     async fn save_one(&self, username: &str, state: &Arc<QuotaStateAtomic>) -> Result<(), AppError> {
+        // 只读模式：用量只累计在内存中
+        if crate::read_only::is_enabled() {
+            return Ok(());
+        }
         // 转换为可序列化的 QuotaState
         let quota_state = state.to_state().await;
 
@@ -194,11 +563,17 @@ impl QuotaManager {
 
         tokio::fs::write(&temp_path, json)
             .await
-            .map_err(|e| AppError::InternalError(format!("写入配额文件失败: {}", e)))?;
+            .map_err(|e| {
+                crate::metrics::METRICS.record_persist_failure("quota");
+                AppError::InternalError(format!("写入配额文件失败: {}", e))
+            })?;
 
         tokio::fs::rename(temp_path, file_path)
             .await
-            .map_err(|e| AppError::InternalError(format!("重命名配额文件失败: {}", e)))?;
+            .map_err(|e| {
+                crate::metrics::METRICS.record_persist_failure("quota");
+                AppError::InternalError(format!("重命名配额文件失败: {}", e))
+            })?;
 
         Ok(())
     }
@@ -209,40 +584,209 @@ impl QuotaManager {
     }
 
     /// 保存所有数据（优雅关闭时调用）- 优化版：使用 DashMap snapshot
-    pub async fn save_all(&self) -> Result<(), AppError> {
+    pub async fn save_all(&self) -> Result<usize, AppError> {
         // DashMap 支持无锁迭代，获取所有用户的快照
         let users_snapshot: Vec<(String, Arc<QuotaStateAtomic>)> = self.cache
             .iter()
             .map(|entry| (entry.key().clone(), entry.value().clone()))
             .collect();
 
+        let count = users_snapshot.len();
         for (username, state) in users_snapshot {
             tracing::info!("保存用户 {} 的配额数据", username);
             self.save_one(&username, &state).await?;
         }
 
-        Ok(())
+        Ok(count)
     }
 
-    /// 计算下个月1号 0点（东八区 UTC+8）
-    fn next_month_reset() -> Result<String, String> {
-        let now = crate::utils::now_beijing();
-        let next_month = if now.month() == 12 {
-            NaiveDate::from_ymd_opt(now.year() + 1, 1, 1)
-                .ok_or_else(|| "下年度1月1日创建失败".to_string())?
-        } else {
-            NaiveDate::from_ymd_opt(now.year(), now.month() + 1, 1)
-                .ok_or_else(|| "下月1日创建失败".to_string())?
+    /// 解析用户档次及其配额上限：档次已从配置中删除或改名时改用 fallback_tier，未配置则拒绝
+    fn resolve_tier(&self, username: &str, tier: &str) -> Result<(String, u32), AppError> {
+        let quota = &self.config.quota;
+        let tier = tier.to_lowercase();
+        match configured_tier(&self.config, &tier) {
+            Some((resolved, limit)) => {
+                if resolved != tier {
+                    tracing::warn!(user = %username, tier = %tier, fallback = %resolved, "用户档次未在 [quota.tiers] 中定义，改用 fallback_tier");
+                }
+                Ok((resolved, limit))
+            }
+            None => {
+                tracing::error!(user = %username, tier = %tier, defined = ?quota.tiers.names(), "用户档次未在 [quota.tiers] 中定义，且未配置 fallback_tier");
+                Err(AppError::Quota(QuotaError::InvalidTier(format!(
+                    "账户的配额档次 {} 已停用，请联系管理员",
+                    tier
+                ))))
+            }
+        }
+    }
+
+    /// 按档次的重置策略计算下一次重置时间（东八区 UTC+8）
+    fn next_reset(&self, tier: &str) -> Result<String, String> {
+        next_reset_at(&self.config, tier, crate::utils::now_beijing())
+    }
+}
+
+/// 档次在 [quota.tiers] 中的名称与上限；未定义时改用 fallback_tier，两者都没有时返回 None
+pub(super) fn configured_tier(config: &Config, tier: &str) -> Option<(String, u32)> {
+    let quota = &config.quota;
+    let tier = tier.to_lowercase();
+    if let Some(limit) = quota.tiers.limit(&tier) {
+        return Some((tier, limit));
+    }
+    let fallback = quota.fallback_tier.as_deref()?;
+    quota.tiers.limit(fallback).map(|limit| (fallback.to_lowercase(), limit))
+}
+
+/// 档次的重置策略（沙箱档次每天重置）
+fn reset_policy(config: &Config, tier: &str) -> ResetPolicy {
+    if config.sandbox.is_sandbox(tier) {
+        ResetPolicy::Daily
+    } else {
+        config.quota.reset_policies.for_tier(tier)
+    }
+}
+
+/// 按档次的重置策略计算 `now` 之后的下一次重置时间
+pub(super) fn next_reset_at(config: &Config, tier: &str, now: DateTime<FixedOffset>) -> Result<String, String> {
+    reset_policy(config, tier).next_reset(now, config.quota.monthly_reset_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compact_due_archives_ended_cycles() {
+        let dir = std::env::temp_dir().join("test_quota_compaction");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("quotas")).await.unwrap();
+        let quota = |username: &str, used: u32, reset_at: &str| QuotaState {
+            username: username.to_string(),
+            tier: "basic".to_string(),
+            monthly_limit: 100,
+            used_count: used,
+            last_saved_count: used,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 0,
+            reset_policy: Some(ResetPolicy::Monthly),
+            dirty: false,
         };
+        for state in [
+            quota("idle", 7, "2026-03-01T00:00:00+08:00"),
+            quota("current", 3, "2999-01-01T00:00:00+08:00"),
+            quota("erased-0123456789ab", 5, "2026-03-01T00:00:00+08:00"),
+        ] {
+            let path = dir.join(format!("quotas/{}.json", state.username));
+            tokio::fs::write(path, serde_json::to_string(&state).unwrap()).await.unwrap();
+        }
+
+        let users = crate::auth::UserManager::new(dir.join("users"), Vec::new(), crate::auth::password::params(1024, 1).unwrap()).await.unwrap();
+        let manager = QuotaManager::new(Arc::new(crate::config::test_config("")), Arc::new(users), dir.join("quotas"), 100);
+        assert_eq!(manager.compact_due().await, 1);
+        assert_eq!(manager.compact_due().await, 0);
+
+        let february = manager.archive().month("2026-02").await.unwrap().unwrap();
+        assert_eq!((february[0].username.as_str(), february[0].cycles[0].used_count), ("idle", 7));
+        let content = tokio::fs::read_to_string(dir.join("quotas/idle.json")).await.unwrap();
+        assert_eq!(serde_json::from_str::<QuotaState>(&content).unwrap().used_count, 0);
+        assert_eq!(manager.get_quota("current").await.unwrap().used_count, 3);
+        // 匿名化的记录原样保留
+        let content = tokio::fs::read_to_string(dir.join("quotas/erased-0123456789ab.json")).await.unwrap();
+        assert_eq!(serde_json::from_str::<QuotaState>(&content).unwrap().used_count, 5);
+        assert!(manager.cache.get("erased-0123456789ab").is_none());
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
 
-        let naive_datetime = next_month.and_hms_opt(0, 0, 0)
-            .ok_or_else(|| "时间00:00:00创建失败".to_string())?;
+    #[tokio::test]
+    async fn test_tier_is_resolved_again_on_load_and_reset() {
+        let dir = std::env::temp_dir().join("test_quota_tier_removal");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("quotas")).await.unwrap();
+        let quota = |username: &str, tier: &str, limit: u32, reset_at: &str| QuotaState {
+            username: username.to_string(),
+            tier: tier.to_string(),
+            monthly_limit: limit,
+            used_count: 4,
+            last_saved_count: 4,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 0,
+            reset_policy: Some(ResetPolicy::Monthly),
+            dirty: false,
+        };
+        let next_month = next_reset_at(&crate::config::test_config(""), "basic", crate::utils::now_beijing()).unwrap();
+        for state in [
+            // gold 档次已从配置中删除
+            quota("removed", "gold", 50, &next_month),
+            // pro 的上限已调整，周期已结束
+            quota("resized", "pro", 10, "2026-03-01T00:00:00+08:00"),
+        ] {
+            let path = dir.join(format!("quotas/{}.json", state.username));
+            tokio::fs::write(path, serde_json::to_string(&state).unwrap()).await.unwrap();
+        }
+        let users = Arc::new(
+            crate::auth::UserManager::new(dir.join("users"), Vec::new(), crate::auth::password::params(1024, 1).unwrap()).await.unwrap(),
+        );
+
+        // 没有 fallback_tier：请求被拒绝，配额文件不变
+        let manager = QuotaManager::new(Arc::new(crate::config::test_config("")), users.clone(), dir.join("quotas"), 100);
+        assert!(matches!(manager.check_quota("removed").await, Err(AppError::Quota(QuotaError::InvalidTier(_)))));
+
+        let config = Arc::new(crate::config::test_config("[quota]\nfallback_tier = \"basic\"\n"));
+        let basic = config.quota.tiers.limit("basic").unwrap();
+        let pro = config.quota.tiers.limit("pro").unwrap();
+        let manager = QuotaManager::new(config, users, dir.join("quotas"), 100);
+        // 加载时改用 fallback_tier 及其上限并写回，本周期用量保留
+        assert!(matches!(manager.check_quota("removed").await, Ok(QuotaStatus::Ok { used: 4, limit, .. }) if limit == basic));
+        let content = tokio::fs::read_to_string(dir.join("quotas/removed.json")).await.unwrap();
+        let saved: QuotaState = serde_json::from_str(&content).unwrap();
+        assert_eq!((saved.tier.as_str(), saved.monthly_limit), ("basic", basic));
+        // 周期重置后改用配置中的上限
+        let resized = manager.get_quota("resized").await.unwrap();
+        assert_eq!((resized.used_count, resized.monthly_limit), (0, pro));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
 
-        // 创建东八区时间
-        let beijing_offset = chrono::FixedOffset::east_opt(8 * 3600)
-            .ok_or_else(|| "时区创建失败".to_string())?;
-        let datetime: DateTime<chrono::FixedOffset> = DateTime::from_naive_utc_and_offset(naive_datetime, beijing_offset);
+    #[tokio::test]
+    async fn test_reset_at_follows_policy_change() {
+        let dir = std::env::temp_dir().join("test_quota_policy_change");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("quotas")).await.unwrap();
+        let quota = |username: &str, reset_at: &str, reset_policy: Option<ResetPolicy>| QuotaState {
+            username: username.to_string(),
+            tier: "basic".to_string(),
+            monthly_limit: 100,
+            used_count: 4,
+            last_saved_count: 4,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 0,
+            reset_policy,
+            dirty: false,
+        };
+        let next_month = next_reset_at(&crate::config::test_config(""), "basic", crate::utils::now_beijing()).unwrap();
+        for state in [
+            // 档次原来是 never，旧文件没有记录策略
+            quota("legacy", NEVER_RESET_AT, None),
+            quota("weekly", &next_month, Some(ResetPolicy::Weekly)),
+            quota("current", &next_month, Some(ResetPolicy::Monthly)),
+        ] {
+            let path = dir.join(format!("quotas/{}.json", state.username));
+            tokio::fs::write(path, serde_json::to_string(&state).unwrap()).await.unwrap();
+        }
 
-        Ok(datetime.to_rfc3339())
+        let users = crate::auth::UserManager::new(dir.join("users"), Vec::new(), crate::auth::password::params(1024, 1).unwrap()).await.unwrap();
+        let manager = QuotaManager::new(Arc::new(crate::config::test_config("")), Arc::new(users), dir.join("quotas"), 100);
+        let legacy = manager.get_quota("legacy").await.unwrap();
+        assert_eq!((legacy.reset_at.as_str(), legacy.used_count), (next_month.as_str(), 4));
+        // 重新计算后立即写回文件，并记录当前策略
+        let content = tokio::fs::read_to_string(dir.join("quotas/legacy.json")).await.unwrap();
+        let saved: QuotaState = serde_json::from_str(&content).unwrap();
+        assert_eq!((saved.reset_at, saved.reset_policy), (next_month.clone(), Some(ResetPolicy::Monthly)));
+        assert_eq!(manager.get_quota("weekly").await.unwrap().reset_at, next_month);
+        assert_eq!(manager.get_quota("current").await.unwrap().reset_at, next_month);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
     }
 }
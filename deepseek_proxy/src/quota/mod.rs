@@ -1,5 +1,16 @@
+mod archive;
+mod cron;
+mod journal;
 mod manager;
+mod policy;
+mod preview;
+mod topup;
 mod types;
 
+pub use archive::{ArchivedCycle, ArchivedUser, QuotaArchive};
+pub use cron::CronSchedule;
+pub use journal::{ChargeGuard, ChargeJournal};
 pub use manager::QuotaManager;
-pub use types::QuotaStatus;
+pub use policy::ResetPolicy;
+pub use preview::{ResetAnomaly, ResetPreviewReport, UserResetPreview};
+pub use types::{Charge, QuotaState, QuotaStatus};
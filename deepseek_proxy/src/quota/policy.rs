@@ -0,0 +1,97 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, TimeZone};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// 永不重置时使用的占位重置时间
+pub const NEVER_RESET_AT: &str = "9999-12-31T23:59:59+08:00";
+
+/// 配额重置策略（按档次配置）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ResetPolicy {
+    /// 每月 monthly_reset_day 号 0 点重置
+    #[default]
+    #[serde(rename = "monthly")]
+    Monthly,
+    /// 从本周期开始起滚动 30 天
+    #[serde(rename = "rolling_30d")]
+    Rolling30Days,
+    /// 每天 0 点重置
+    #[serde(rename = "daily")]
+    Daily,
+    /// 每周一 0 点重置
+    #[serde(rename = "weekly")]
+    Weekly,
+    /// 永不重置（预付费额度包）
+    #[serde(rename = "never")]
+    Never,
+}
+
+impl ResetPolicy {
+    /// 计算下一次重置时间（东八区 RFC3339）
+    pub fn next_reset(&self, now: DateTime<FixedOffset>, monthly_reset_day: u32) -> Result<String, String> {
+        let tz = *now.offset();
+        let next = match self {
+            ResetPolicy::Monthly => {
+                // 超过 28 号的月份不一定存在，统一夹到 1-28
+                let day = monthly_reset_day.clamp(1, 28);
+                let (year, month) = if now.day() < day {
+                    (now.year(), now.month())
+                } else if now.month() == 12 {
+                    (now.year() + 1, 1)
+                } else {
+                    (now.year(), now.month() + 1)
+                };
+                NaiveDate::from_ymd_opt(year, month, day)
+                    .ok_or_else(|| format!("{}-{}-{} 创建失败", year, month, day))?
+            }
+            ResetPolicy::Rolling30Days => return Ok((now + Duration::days(30)).to_rfc3339()),
+            ResetPolicy::Daily => now.date_naive() + Duration::days(1),
+            ResetPolicy::Weekly => {
+                let days_until_monday = 7 - now.weekday().num_days_from_monday() as i64;
+                now.date_naive() + Duration::days(days_until_monday)
+            }
+            ResetPolicy::Never => return Ok(NEVER_RESET_AT.to_string()),
+        };
+
+        let naive_datetime = next.and_hms_opt(0, 0, 0)
+            .ok_or_else(|| "时间00:00:00创建失败".to_string())?;
+        tz.from_local_datetime(&naive_datetime)
+            .single()
+            .map(|dt| dt.to_rfc3339())
+            .ok_or_else(|| "重置时间创建失败".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_monthly_reset() {
+        let now = at("2025-12-15T10:00:00+08:00");
+        assert_eq!(ResetPolicy::Monthly.next_reset(now, 1).unwrap(), "2026-01-01T00:00:00+08:00");
+        assert_eq!(ResetPolicy::Monthly.next_reset(now, 20).unwrap(), "2025-12-20T00:00:00+08:00");
+    }
+
+    #[test]
+    fn test_weekly_reset() {
+        // 2025-11-05 是周三
+        let now = at("2025-11-05T10:00:00+08:00");
+        assert_eq!(ResetPolicy::Weekly.next_reset(now, 1).unwrap(), "2025-11-10T00:00:00+08:00");
+        // 周一当天也是下周一重置
+        let monday = at("2025-11-10T00:00:00+08:00");
+        assert_eq!(ResetPolicy::Weekly.next_reset(monday, 1).unwrap(), "2025-11-17T00:00:00+08:00");
+    }
+
+    #[test]
+    fn test_rolling_and_never() {
+        let now = at("2025-11-05T10:00:00+08:00");
+        assert_eq!(ResetPolicy::Rolling30Days.next_reset(now, 1).unwrap(), "2025-12-05T10:00:00+08:00");
+        assert_eq!(ResetPolicy::Never.next_reset(now, 1).unwrap(), NEVER_RESET_AT);
+        assert_eq!(ResetPolicy::Daily.next_reset(now, 1).unwrap(), "2025-11-06T00:00:00+08:00");
+    }
+}
@@ -0,0 +1,184 @@
+use super::types::QuotaState;
+use crate::config::Config;
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+/// 配额重置预演报告（GET /admin/quotas/preview-reset），不修改任何数据
+#[derive(Debug, Clone, Serialize)]
+pub struct ResetPreviewReport {
+    /// 模拟的时刻（指定日期当天 23:59:59，东八区）
+    pub simulated_at: String,
+    /// 到该时刻会重置的用户数
+    pub would_reset: usize,
+    /// 存在异常的用户数
+    pub anomalies: usize,
+    pub users: Vec<UserResetPreview>,
+}
+
+impl ResetPreviewReport {
+    pub fn new(simulated_at: DateTime<FixedOffset>, users: Vec<UserResetPreview>) -> Self {
+        Self {
+            simulated_at: simulated_at.to_rfc3339(),
+            would_reset: users.iter().filter(|u| u.would_reset).count(),
+            anomalies: users.iter().filter(|u| !u.anomalies.is_empty()).count(),
+            users,
+        }
+    }
+}
+
+/// 单个用户的预演结果
+#[derive(Debug, Clone, Serialize)]
+pub struct UserResetPreview {
+    pub username: String,
+    /// 用户文件中的档次
+    pub tier: String,
+    /// 是否已有配额文件（没有的用户在首次请求时按当前配置初始化）
+    pub has_quota: bool,
+    pub would_reset: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_at: Option<String>,
+    pub used: u32,
+    /// 当前周期上限（配额文件中记录的值）
+    pub limit: u32,
+    /// 上限减去已用次数，超用时为负
+    pub remaining: i64,
+    /// 按当前 [quota.tiers] 配置解析出的上限；档次未定义且没有 fallback_tier 时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub configured_limit: Option<u32>,
+    /// 重置后新周期的结束时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_reset_at: Option<String>,
+    pub anomalies: Vec<ResetAnomaly>,
+}
+
+/// 预演发现的异常
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetAnomaly {
+    /// 已用次数超过上限（剩余为负）
+    NegativeRemaining,
+    /// 档次未在 [quota.tiers] 中定义，且没有可用的 fallback_tier（请求返回 403 invalid_quota_tier）
+    MissingTier,
+    /// 档次未定义，下次加载配额时改用 fallback_tier 及其上限
+    FallbackTier,
+    /// 配额文件中的上限与当前配置不一致：本周期沿用配额文件中的上限，重置后改用配置中的上限
+    LimitMismatch,
+    /// 配额文件中的重置时间无法解析
+    InvalidResetAt,
+}
+
+/// 按当前配置预演一个用户在 `at` 时刻的重置结果
+pub(super) fn preview_user(
+    config: &Config,
+    username: &str,
+    tier: &str,
+    state: Option<&QuotaState>,
+    at: DateTime<FixedOffset>,
+) -> UserResetPreview {
+    let mut anomalies = Vec::new();
+    let configured = super::manager::configured_tier(config, tier);
+    if config.quota.tiers.limit(&tier.to_lowercase()).is_none() {
+        anomalies.push(if configured.is_some() { ResetAnomaly::FallbackTier } else { ResetAnomaly::MissingTier });
+    }
+    let configured_limit = configured.map(|(_, limit)| limit);
+
+    let Some(state) = state else {
+        return UserResetPreview {
+            username: username.to_string(),
+            tier: tier.to_string(),
+            has_quota: false,
+            would_reset: false,
+            reset_at: None,
+            used: 0,
+            limit: configured_limit.unwrap_or(0),
+            remaining: configured_limit.unwrap_or(0) as i64,
+            configured_limit,
+            next_reset_at: None,
+            anomalies,
+        };
+    };
+
+    let remaining = state.monthly_limit as i64 - state.used_count as i64;
+    if remaining < 0 {
+        anomalies.push(ResetAnomaly::NegativeRemaining);
+    }
+    if configured_limit.is_some_and(|limit| limit != state.monthly_limit) {
+        anomalies.push(ResetAnomaly::LimitMismatch);
+    }
+    let would_reset = match DateTime::parse_from_rfc3339(&state.reset_at) {
+        Ok(reset_at) => at > reset_at,
+        Err(_) => {
+            anomalies.push(ResetAnomaly::InvalidResetAt);
+            false
+        }
+    };
+    // 与真实重置一致：按配额文件中的档次重新解析（已删除时改用 fallback_tier）后计算下一周期
+    let next_tier = super::manager::configured_tier(config, &state.tier).map_or_else(|| state.tier.clone(), |(tier, _)| tier);
+    let next_reset_at = would_reset.then(|| super::manager::next_reset_at(config, &next_tier, at).ok()).flatten();
+
+    UserResetPreview {
+        username: username.to_string(),
+        tier: tier.to_string(),
+        has_quota: true,
+        would_reset,
+        reset_at: Some(state.reset_at.clone()),
+        used: state.used_count,
+        limit: state.monthly_limit,
+        remaining,
+        configured_limit,
+        next_reset_at,
+        anomalies,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        crate::config::test_config("[quota.tiers]\nbasic = 100\npro = 1000\n")
+    }
+
+    fn state(tier: &str, limit: u32, used: u32, reset_at: &str) -> QuotaState {
+        QuotaState {
+            username: "u".to_string(),
+            tier: tier.to_string(),
+            monthly_limit: limit,
+            used_count: used,
+            last_saved_count: used,
+            reset_at: reset_at.to_string(),
+            last_saved_at: None,
+            credits: 0,
+            reset_policy: None,
+            dirty: false,
+        }
+    }
+
+    #[test]
+    fn test_preview_reset_flags_anomalies() {
+        let config = config();
+        let at = DateTime::parse_from_rfc3339("2025-12-01T23:59:59+08:00").unwrap();
+
+        let due = preview_user(&config, "a", "basic", Some(&state("basic", 100, 40, "2025-12-01T00:00:00+08:00")), at);
+        assert!(due.would_reset && due.anomalies.is_empty());
+        assert_eq!(due.next_reset_at.as_deref(), Some("2026-01-01T00:00:00+08:00"));
+
+        let later = preview_user(&config, "b", "basic", Some(&state("basic", 100, 40, "2026-01-01T00:00:00+08:00")), at);
+        assert!(!later.would_reset && later.next_reset_at.is_none());
+
+        let odd = preview_user(&config, "c", "gold", Some(&state("gold", 50, 60, "bad")), at);
+        assert_eq!(odd.remaining, -10);
+        assert_eq!(
+            odd.anomalies,
+            [ResetAnomaly::MissingTier, ResetAnomaly::NegativeRemaining, ResetAnomaly::InvalidResetAt]
+        );
+
+        let changed = preview_user(&config, "d", "pro", Some(&state("pro", 500, 0, "2025-12-01T00:00:00+08:00")), at);
+        assert_eq!(changed.anomalies, [ResetAnomaly::LimitMismatch]);
+        assert_eq!(changed.configured_limit, Some(1000));
+
+        let fresh = preview_user(&config, "e", "pro", None, at);
+        assert!(!fresh.has_quota && !fresh.would_reset);
+        assert_eq!(fresh.limit, 1000);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// 定时发放额度的执行记录（计划名 -> 最近一次触发），保证同一触发时刻每个用户只发放一次
+///
+/// 进程在同一分钟内重启、或发放到一半时崩溃，都不会重复发放
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TopUpRuns(HashMap<String, TopUpRun>);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TopUpRun {
+    /// 触发时刻（北京时间，精确到分钟）
+    slot: String,
+    /// 该时刻已发放的用户
+    granted: Vec<String>,
+}
+
+impl TopUpRuns {
+    /// 读取执行记录；文件不存在或无法解析时从空记录开始
+    pub async fn load(path: &Path) -> Self {
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "定时发放记录无法解析，从空记录开始");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), AppError> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| AppError::InternalError(format!("创建定时发放记录目录失败: {}", e)))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::InternalError(format!("序列化定时发放记录失败: {}", e)))?;
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, json)
+            .await
+            .map_err(|e| AppError::InternalError(format!("写入定时发放记录失败: {}", e)))?;
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(|e| AppError::InternalError(format!("重命名定时发放记录失败: {}", e)))
+    }
+
+    pub fn is_granted(&self, schedule: &str, slot: &str, username: &str) -> bool {
+        self.0
+            .get(schedule)
+            .is_some_and(|run| run.slot == slot && run.granted.iter().any(|u| u == username))
+    }
+
+    /// 记录一次发放；新的触发时刻会替换该计划之前的记录
+    pub fn record(&mut self, schedule: &str, slot: &str, username: &str) {
+        let run = self.0.entry(schedule.to_string()).or_insert_with(|| TopUpRun {
+            slot: slot.to_string(),
+            granted: Vec::new(),
+        });
+        if run.slot != slot {
+            run.slot = slot.to_string();
+            run.granted.clear();
+        }
+        run.granted.push(username.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_runs_are_tracked_per_slot() {
+        let path = std::env::temp_dir().join("test_topup_runs/runs.json");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let mut runs = TopUpRuns::load(&path).await;
+        runs.record("weekend", "2026-10-17T00:00+08:00", "alice");
+        runs.save(&path).await.unwrap();
+
+        let mut runs = TopUpRuns::load(&path).await;
+        assert!(runs.is_granted("weekend", "2026-10-17T00:00+08:00", "alice"));
+        assert!(!runs.is_granted("weekend", "2026-10-17T00:00+08:00", "bob"));
+        // 下一次触发重新计
+        runs.record("weekend", "2026-10-18T00:00+08:00", "bob");
+        assert!(!runs.is_granted("weekend", "2026-10-18T00:00+08:00", "alice"));
+        assert!(runs.is_granted("weekend", "2026-10-18T00:00+08:00", "bob"));
+        let _ = tokio::fs::remove_dir_all(path.parent().unwrap()).await;
+    }
+}
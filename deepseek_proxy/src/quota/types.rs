@@ -1,48 +1,10 @@
+use super::ResetPolicy;
 use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-/// 配额档次
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum QuotaTier {
-    Basic,    // 500次/月
-    Pro,      // 1000次/月
-    Premium,  // 1500次/月
-}
-
-impl QuotaTier {
-    /// 获取配额上限（从配置中读取）
-    pub fn limit(&self, config: &crate::config::QuotaTiersConfig) -> u32 {
-        match self {
-            QuotaTier::Basic => config.basic,
-            QuotaTier::Pro => config.pro,
-            QuotaTier::Premium => config.premium,
-        }
-    }
-
-    /// 从字符串解析
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "basic" => Some(QuotaTier::Basic),
-            "pro" => Some(QuotaTier::Pro),
-            "premium" => Some(QuotaTier::Premium),
-            _ => None,
-        }
-    }
-
-    /// 转换为字符串
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            QuotaTier::Basic => "basic",
-            QuotaTier::Pro => "pro",
-            QuotaTier::Premium => "premium",
-        }
-    }
-}
-
 /// 配额检查结果
 #[derive(Debug)]
 pub enum QuotaStatus {
@@ -50,7 +12,8 @@ pub enum QuotaStatus {
     Ok {
         used: u32,
         limit: u32,
-        remaining: u32,
+        remaining: u32,  // 含预付费额度
+        credits: u32,
         reset_at: DateTime<FixedOffset>,  // 支持任意时区（东八区）
     },
     /// 配额已耗尽，需要付费
@@ -61,6 +24,31 @@ pub enum QuotaStatus {
     },
 }
 
+/// 一次扣费的明细，用于在途扣费日志与退还
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Charge {
+    /// 扣减的周期配额次数
+    pub period: u32,
+    /// 扣费后的周期用量，用于判断扣费是否已经落盘
+    pub used_after: u32,
+    /// 扣费时所在周期的重置时间；周期已重置时不再退还周期配额
+    pub reset_at: String,
+    /// 扣减的预付费额度
+    pub credits: u32,
+}
+
+impl Charge {
+    /// 合并同一请求的多次扣费
+    pub fn merge(&mut self, other: Charge) {
+        self.period += other.period;
+        self.credits += other.credits;
+        if other.period > 0 {
+            self.used_after = self.used_after.max(other.used_after);
+            self.reset_at = other.reset_at;
+        }
+    }
+}
+
 /// 配额状态（用于持久化）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuotaState {
@@ -72,16 +60,33 @@ pub struct QuotaState {
     pub reset_at: String,  // ISO 8601 格式
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_saved_at: Option<String>,
-    
+    /// 预付费额度（次数），不随周期重置
+    #[serde(default)]
+    pub credits: u32,
+    /// 计算 reset_at 所用的重置策略；与档次当前的策略不同时加载后重新计算（旧文件没有此字段）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_policy: Option<ResetPolicy>,
+
     #[serde(skip)]
     pub dirty: bool,  // 是否有未保存的修改
 }
 
+/// 用户所在档次及其决定的周期上限与重置策略
+///
+/// 档次被删除、改名或上限调整后，周期重置时按当前配置重新解析
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierPlan {
+    pub tier: String,
+    pub monthly_limit: u32,
+    /// 计算 reset_at 所用的重置策略
+    pub reset_policy: ResetPolicy,
+}
+
 /// 配额状态（原子版本，用于高并发场景）
 pub struct QuotaStateAtomic {
     pub username: String,
-    pub tier: String,
-    pub monthly_limit: u32,
+    /// 档次、上限与重置策略（只在周期重置时修改）
+    plan: std::sync::RwLock<TierPlan>,
     /// 原子计数器，支持无锁并发递增
     pub used_count: Arc<AtomicU32>,
     /// 上次保存时的计数
@@ -90,6 +95,8 @@ pub struct QuotaStateAtomic {
     pub reset_at: Arc<RwLock<String>>,
     /// 上次保存时间
     pub last_saved_at: Arc<RwLock<Option<String>>>,
+    /// 预付费额度（次数），周期配额耗尽后按次扣减
+    pub credits: Arc<AtomicU32>,
 }
 
 impl QuotaStateAtomic {
@@ -97,29 +104,41 @@ impl QuotaStateAtomic {
     pub fn from_state(state: QuotaState) -> Self {
         Self {
             username: state.username,
-            tier: state.tier,
-            monthly_limit: state.monthly_limit,
+            plan: std::sync::RwLock::new(TierPlan {
+                tier: state.tier,
+                monthly_limit: state.monthly_limit,
+                reset_policy: state.reset_policy.unwrap_or_default(),
+            }),
             used_count: Arc::new(AtomicU32::new(state.used_count)),
             last_saved_count: Arc::new(AtomicU32::new(state.last_saved_count)),
             reset_at: Arc::new(RwLock::new(state.reset_at)),
             last_saved_at: Arc::new(RwLock::new(state.last_saved_at)),
+            credits: Arc::new(AtomicU32::new(state.credits)),
         }
     }
 
     /// 转换为普通 QuotaState（用于序列化）
     pub async fn to_state(&self) -> QuotaState {
+        let plan = self.plan();
         QuotaState {
             username: self.username.clone(),
-            tier: self.tier.clone(),
-            monthly_limit: self.monthly_limit,
+            tier: plan.tier,
+            monthly_limit: plan.monthly_limit,
             used_count: self.used_count.load(Ordering::Relaxed),
             last_saved_count: self.last_saved_count.load(Ordering::Relaxed),
             reset_at: self.reset_at.read().await.clone(),
             last_saved_at: self.last_saved_at.read().await.clone(),
+            credits: self.credits.load(Ordering::Relaxed),
+            reset_policy: Some(plan.reset_policy),
             dirty: false,
         }
     }
 
+    /// 当前的档次、上限与重置策略
+    pub fn plan(&self) -> TierPlan {
+        self.plan.read().unwrap().clone()
+    }
+
     /// 原子递增使用计数
     pub fn increment(&self) -> u32 {
         self.used_count.fetch_add(1, Ordering::Relaxed) + 1
@@ -130,6 +149,26 @@ impl QuotaStateAtomic {
         self.used_count.load(Ordering::Relaxed)
     }
 
+    /// 获取剩余预付费额度
+    pub fn get_credits(&self) -> u32 {
+        self.credits.load(Ordering::Relaxed)
+    }
+
+    /// 增加预付费额度，返回新余额
+    pub fn add_credits(&self, amount: u32) -> u32 {
+        self.credits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c.saturating_add(amount)))
+            .map(|prev| prev.saturating_add(amount))
+            .unwrap_or(0)
+    }
+
+    /// 扣减一次预付费额度，余额不足时返回 false
+    pub fn consume_credit(&self) -> bool {
+        self.credits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_sub(1))
+            .is_ok()
+    }
+
     /// 获取上次保存的计数
     pub fn get_last_saved(&self) -> u32 {
         self.last_saved_count.load(Ordering::Relaxed)
@@ -140,10 +179,59 @@ impl QuotaStateAtomic {
         self.last_saved_count.store(count, Ordering::Relaxed);
     }
 
-    /// 重置配额（月度重置）
-    pub async fn reset(&self, new_reset_at: String) {
+    /// 退还周期用量，返回实际退还的次数
+    pub fn refund_used(&self, times: u32) -> u32 {
+        self.used_count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| Some(used.saturating_sub(times)))
+            .map(|prev| prev.min(times))
+            .unwrap_or(0)
+    }
+
+    /// 重置配额（周期重置），新周期按重新解析的档次计
+    pub async fn reset(&self, new_reset_at: String, plan: TierPlan) {
         self.used_count.store(0, Ordering::Relaxed);
         self.last_saved_count.store(0, Ordering::Relaxed);
+        *self.plan.write().unwrap() = plan;
         *self.reset_at.write().await = new_reset_at;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(credits: u32) -> QuotaStateAtomic {
+        QuotaStateAtomic::from_state(QuotaState {
+            username: "u".to_string(),
+            tier: "basic".to_string(),
+            monthly_limit: 10,
+            used_count: 10,
+            last_saved_count: 10,
+            reset_at: "2025-12-01T00:00:00+08:00".to_string(),
+            last_saved_at: None,
+            credits,
+            reset_policy: None,
+            dirty: false,
+        })
+    }
+
+    #[test]
+    fn test_consume_credit_until_empty() {
+        let s = state(2);
+        assert!(s.consume_credit());
+        assert!(s.consume_credit());
+        assert!(!s.consume_credit());
+        assert_eq!(s.get_credits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_credits_survive_reset() {
+        let s = state(0);
+        assert_eq!(s.add_credits(5), 5);
+        let plan = TierPlan { tier: "pro".to_string(), monthly_limit: 20, reset_policy: ResetPolicy::Weekly };
+        s.reset("2026-01-01T00:00:00+08:00".to_string(), plan.clone()).await;
+        assert_eq!(s.get_used(), 0);
+        assert_eq!(s.get_credits(), 5);
+        assert_eq!(s.plan(), plan);
+    }
+}
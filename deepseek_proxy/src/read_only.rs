@@ -0,0 +1,28 @@
+//! 只读模式（灾难恢复）：从恢复的快照运行、主磁盘仍在修复时使用
+//!
+//! 登录与聊天照常服务，但不写 data/ 目录：配额用量只累计在内存中，用户文件的更新只改内存，
+//! 指标快照、SLO 采样、对账记录、定时任务与备份全部停止落盘；修改数据的管理接口与数据导出返回 503 `read_only`。
+//! 用户行为日志与服务日志仍写入 logs/
+
+use crate::error::AppError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 与 METRICS 一样做成全局：写盘的组件分散在各模块（含拿不到 AppState 的后台任务）
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// 开启只读模式（启动时由 `--read-only` 或 `[server] read_only` 设置，运行中不能关闭）
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// 只读模式下拒绝修改数据的操作
+pub fn ensure_writable() -> Result<(), AppError> {
+    if is_enabled() {
+        return Err(AppError::ReadOnly);
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use crate::config::ReconciliationConfig;
+use crate::deepseek::DeepSeekClient;
+use crate::error::AppError;
+use crate::metrics::{DayTokens, METRICS};
+use chrono::{Local, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// 对账状态与历史记录目录
+const STATE_DIR: &str = "data/reconciliation";
+
+/// 上一次读取的上游余额（下一次对账的基线）
+#[derive(Debug, Serialize, Deserialize)]
+struct LastBalance {
+    /// 读取余额时的本地日期
+    day: String,
+    balance: f64,
+    checked_at: String,
+}
+
+/// 一次对账结果（追加到 data/reconciliation/history.jsonl）
+#[derive(Debug, Serialize)]
+pub struct ReconcileRecord {
+    /// 被对账的日期
+    pub day: String,
+    pub currency: String,
+    pub estimated_cost: f64,
+    pub upstream_spend: f64,
+    /// (上游扣费 - 估算费用) / 上游扣费；上游扣费为 0 时为 null
+    pub drift_ratio: Option<f64>,
+    pub tokens_input: u64,
+    pub tokens_output: u64,
+    pub checked_at: String,
+}
+
+/// 解析 HH:MM 格式的执行时间
+pub fn parse_run_at(run_at: &str) -> Result<NaiveTime, AppError> {
+    NaiveTime::parse_from_str(run_at, "%H:%M")
+        .map_err(|_| AppError::configuration_error(format!("[reconciliation] run_at = \"{}\" 格式应为 HH:MM", run_at)))
+}
+
+/// 每天在 run_at 对账一次；run_at 无效时返回错误
+pub fn spawn(cfg: ReconciliationConfig, client: Arc<DeepSeekClient>) -> Result<(), AppError> {
+    if !cfg.enabled {
+        return Ok(());
+    }
+    if crate::read_only::is_enabled() {
+        tracing::warn!("只读模式：用量对账不启动（对账需要保存余额记录）");
+        return Ok(());
+    }
+    let run_at = parse_run_at(&cfg.run_at)?;
+    let url = cfg.balance_url.clone().unwrap_or_else(|| client.default_balance_url());
+    tracing::info!("用量对账: 每天 {} 比对前一天估算费用与上游余额变化（{}）", cfg.run_at, url);
+    crate::supervisor::spawn("usage_reconciliation", move || {
+        let (cfg, client, url) = (cfg.clone(), client.clone(), url.clone());
+        async move {
+            loop {
+                tokio::time::sleep(until_next(Local::now().naive_local(), run_at)).await;
+                let result = match run_once(&cfg, &client, &url).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "用量对账失败");
+                        "error"
+                    }
+                };
+                METRICS.usage_reconciliations.with_label_values(&[result]).inc();
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 距离下一个 run_at 的时长
+fn until_next(now: chrono::NaiveDateTime, run_at: NaiveTime) -> Duration {
+    let mut next = now.date().and_time(run_at);
+    if next <= now {
+        next += chrono::Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
+
+/// 执行一次对账，返回 usage_reconciliations_total 的 result 标签
+async fn run_once(cfg: &ReconciliationConfig, client: &DeepSeekClient, url: &str) -> anyhow::Result<&'static str> {
+    // 没有流量时指标不会跨天，先保存前一天的快照
+    METRICS.rollover_if_needed();
+
+    let Some(balances) = client.fetch_balance(url).await? else {
+        tracing::info!("上游不提供余额接口，跳过用量对账");
+        return Ok("skipped");
+    };
+    let balance = balances
+        .iter()
+        .find(|b| b.currency.eq_ignore_ascii_case(&cfg.currency))
+        .ok_or_else(|| anyhow::anyhow!("上游余额中没有 {} 币种", cfg.currency))?
+        .total_balance
+        .parse::<f64>()?;
+
+    let now = Local::now();
+    let today = now.date_naive();
+    let previous = load_last_balance().await;
+    save_last_balance(&LastBalance {
+        day: today.format("%Y-%m-%d").to_string(),
+        balance,
+        checked_at: now.to_rfc3339(),
+    })
+    .await?;
+
+    let Some(yesterday) = today.pred_opt() else { return Ok("skipped") };
+    let day = yesterday.format("%Y-%m-%d").to_string();
+    let upstream_spend = match upstream_spend(previous.as_ref(), yesterday, balance) {
+        Ok(spend) => spend,
+        Err(reason) => {
+            tracing::info!(day = %day, "{}，跳过本次对账", reason);
+            return Ok("skipped");
+        }
+    };
+    let tokens = METRICS.day_tokens(&day)?.unwrap_or_default();
+    let estimated_cost = estimate_cost(&tokens, cfg);
+    let drift_ratio = drift_ratio(estimated_cost, upstream_spend);
+
+    let record = ReconcileRecord {
+        day: day.clone(),
+        currency: cfg.currency.clone(),
+        estimated_cost,
+        upstream_spend,
+        drift_ratio,
+        tokens_input: tokens.input,
+        tokens_output: tokens.output,
+        checked_at: now.to_rfc3339(),
+    };
+    append_record(&record).await?;
+
+    let Some(ratio) = drift_ratio else {
+        if estimated_cost > 0.0 {
+            tracing::warn!(day = %day, estimated_cost, "上游余额没有减少，但代理估算有费用");
+            return Ok("drift");
+        }
+        return Ok("ok");
+    };
+    METRICS.usage_drift_ratio.set(ratio);
+    if ratio.abs() > cfg.drift_threshold {
+        tracing::warn!(
+            day = %day,
+            estimated_cost,
+            upstream_spend,
+            drift_ratio = ratio,
+            threshold = cfg.drift_threshold,
+            "代理估算费用与上游扣费偏差超过阈值"
+        );
+        Ok("drift")
+    } else {
+        tracing::info!(day = %day, estimated_cost, upstream_spend, drift_ratio = ratio, "用量对账完成");
+        Ok("ok")
+    }
+}
+
+/// 由上一次余额计算前一天的上游扣费；基线不是前一天读取的或余额增加（充值）时无法对账
+fn upstream_spend(previous: Option<&LastBalance>, yesterday: NaiveDate, balance: f64) -> Result<f64, &'static str> {
+    let previous = previous.ok_or("没有前一天的余额基线")?;
+    if previous.day != yesterday.format("%Y-%m-%d").to_string() {
+        return Err("余额基线不是前一天读取的");
+    }
+    if balance > previous.balance {
+        return Err("上游余额增加（可能有充值）");
+    }
+    Ok(previous.balance - balance)
+}
+
+/// 按单价估算费用；没有缓存命中统计时输入按未命中计价
+fn estimate_cost(tokens: &DayTokens, cfg: &ReconciliationConfig) -> f64 {
+    let (hit, miss) = if tokens.cache_hit + tokens.cache_miss > 0 {
+        (tokens.cache_hit, tokens.cache_miss)
+    } else {
+        (0, tokens.input)
+    };
+    (hit as f64 * cfg.input_cache_hit_price + miss as f64 * cfg.input_cache_miss_price + tokens.output as f64 * cfg.output_price)
+        / 1_000_000.0
+}
+
+fn drift_ratio(estimated: f64, actual: f64) -> Option<f64> {
+    (actual > 0.0).then(|| (actual - estimated) / actual)
+}
+
+async fn load_last_balance() -> Option<LastBalance> {
+    let content = tokio::fs::read_to_string(Path::new(STATE_DIR).join("last_balance.json")).await.ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+async fn save_last_balance(last: &LastBalance) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(STATE_DIR).await?;
+    tokio::fs::write(Path::new(STATE_DIR).join("last_balance.json"), serde_json::to_string_pretty(last)?).await?;
+    Ok(())
+}
+
+async fn append_record(record: &ReconcileRecord) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(STATE_DIR).await?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(Path::new(STATE_DIR).join("history.jsonl"))
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_and_drift() {
+        let cfg = ReconciliationConfig::default();
+        let tokens = DayTokens { input: 3_000_000, output: 1_000_000, cache_hit: 2_000_000, cache_miss: 1_000_000 };
+        // 2M × 0.5 + 1M × 2 + 1M × 8 = 11
+        assert!((estimate_cost(&tokens, &cfg) - 11.0).abs() < 1e-9);
+        // 没有缓存统计时输入全部按未命中计价
+        let plain = DayTokens { input: 1_000_000, ..DayTokens::default() };
+        assert!((estimate_cost(&plain, &cfg) - 2.0).abs() < 1e-9);
+
+        assert!((drift_ratio(11.0, 12.1).unwrap() - 0.0909).abs() < 1e-3);
+        assert_eq!(drift_ratio(1.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_upstream_spend_needs_previous_day_baseline() {
+        let yesterday = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let last = |day: &str, balance| LastBalance { day: day.to_string(), balance, checked_at: String::new() };
+        assert_eq!(upstream_spend(Some(&last("2026-10-15", 100.0)), yesterday, 88.5), Ok(11.5));
+        assert!(upstream_spend(None, yesterday, 88.5).is_err());
+        assert!(upstream_spend(Some(&last("2026-10-13", 100.0)), yesterday, 88.5).is_err());
+        assert!(upstream_spend(Some(&last("2026-10-15", 100.0)), yesterday, 150.0).is_err());
+    }
+
+    #[test]
+    fn test_until_next() {
+        let at = parse_run_at("00:10").unwrap();
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16).unwrap().and_hms_opt(0, 5, 0).unwrap();
+        assert_eq!(until_next(now, at), Duration::from_secs(300));
+        let later = now + chrono::Duration::minutes(10);
+        assert_eq!(until_next(later, at), Duration::from_secs(24 * 3600 - 300));
+        assert!(parse_run_at("25:00").is_err());
+    }
+}
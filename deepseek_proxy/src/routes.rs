@@ -0,0 +1,291 @@
+//! 路由登记表：每个接口连同其访问策略在这里声明一次，[`crate::AppBuilder`] 按策略统一挂载中间件
+//!
+//! 新增接口必须写明鉴权方式、限制类别、所在监听地址与是否扣配额，构建时校验策略组合，
+//! 避免出现"忘了挂鉴权中间件"或"未登录即可访问上游"的接口
+
+use crate::{
+    admin,
+    auth::{auth_middleware, change_password, jwks, login, logout, me, refresh},
+    chatops, conditional, downloads, error, health, metrics,
+    proxy::{audio, capabilities, proxy_chat, proxy_models},
+    AppState,
+};
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    http::{header, Method, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post, MethodRouter},
+    BoxError,
+};
+use std::collections::HashSet;
+use std::time::Duration;
+use tower::{timeout::TimeoutLayer, ServiceBuilder};
+
+/// 鉴权方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    /// 无需鉴权
+    None,
+    /// Bearer token（auth_middleware）
+    User,
+    /// 管理员：localhost、HMAC 签名或 admin 客户端证书（admin::localhost_or_signed）
+    Admin,
+    /// 只允许 localhost（admin::localhost_only）
+    Localhost,
+    /// 由 handler 校验外部平台的请求签名（Slack / Discord）
+    PlatformSignature,
+}
+
+/// 限制类别：决定路由上挂的超时与请求体上限，以及由 handler 执行的速率限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitClass {
+    /// 登录：独立超时与请求体上限（`auth.login_timeout_seconds` / `login_max_body_bytes`），失败次数由防爆破模块限制
+    Login,
+    /// 访问上游：全局速率限制与每用户并发许可（handler 内执行），不设整体超时（流式响应）；
+    /// 请求体上限为 `vision.max_request_body_bytes`
+    Upstream,
+    /// 上传文件并访问上游：同 Upstream，请求体上限为 `audio.max_upload_bytes`
+    Upload,
+    /// 外部平台回调：64KB 请求体上限
+    Webhook,
+    /// 管理操作：低频，签名请求体上限 1MB（admin 中间件）
+    Operator,
+    /// 只读的轻量接口（健康检查、指标）
+    Light,
+}
+
+/// 路由所在的监听地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// 只在公开地址
+    Public,
+    /// 管理面：配置 `server.internal_listen` 时只在内部地址，否则与公开接口共用
+    Internal,
+    /// 公开地址与内部地址都提供
+    Both,
+}
+
+/// 一条路由及其访问策略
+pub struct RouteSpec {
+    pub method: Method,
+    pub path: &'static str,
+    pub auth: Auth,
+    pub limit: LimitClass,
+    pub scope: Scope,
+    /// 是否扣减用户配额
+    pub consumes_quota: bool,
+    handler: MethodRouter<AppState>,
+}
+
+impl RouteSpec {
+    fn new(method: Method, path: &'static str, handler: MethodRouter<AppState>) -> Self {
+        Self { method, path, auth: Auth::None, limit: LimitClass::Light, scope: Scope::Public, consumes_quota: false, handler }
+    }
+
+    fn auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn limit(mut self, limit: LimitClass) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    fn consumes_quota(mut self) -> Self {
+        self.consumes_quota = true;
+        self
+    }
+
+    /// 策略组合是否合理（构建时检查）
+    fn validate(&self) -> Result<(), String> {
+        let name = format!("{} {}", self.method, self.path);
+        let upstream = matches!(self.limit, LimitClass::Upstream | LimitClass::Upload);
+        if self.auth == Auth::None && (upstream || self.consumes_quota) {
+            return Err(format!("{} 未鉴权却会访问上游或扣配额", name));
+        }
+        if self.consumes_quota && !upstream {
+            return Err(format!("{} 扣配额的接口必须使用 upstream 或 upload 限制类别", name));
+        }
+        if self.path.starts_with("/admin/") && (self.auth != Auth::Admin || self.scope != Scope::Internal) {
+            return Err(format!("{} 管理接口必须使用 admin 鉴权并放在管理面", name));
+        }
+        if self.auth == Auth::None && self.method != Method::GET && self.limit != LimitClass::Login {
+            return Err(format!("{} 未鉴权的写接口只允许登录", name));
+        }
+        Ok(())
+    }
+
+    /// 按策略挂载中间件（鉴权在外层，先于超时与请求体限制执行）
+    fn into_method_router(self, state: &AppState) -> MethodRouter<AppState> {
+        let handler = match self.limit {
+            LimitClass::Login => self.handler.layer(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|err: BoxError| async move {
+                        tracing::warn!(error = %err, "登录请求超时");
+                        error::AppError::RequestTimeout
+                    }))
+                    .layer(TimeoutLayer::new(Duration::from_secs(state.config.auth.login_timeout_seconds)))
+                    .layer(DefaultBodyLimit::max(state.config.auth.login_max_body_bytes)),
+            ),
+            LimitClass::Upstream => self.handler.layer(DefaultBodyLimit::max(state.config.vision.max_request_body_bytes)),
+            LimitClass::Upload => self.handler.layer(DefaultBodyLimit::max(state.config.audio.max_upload_bytes)),
+            LimitClass::Webhook => self.handler.layer(DefaultBodyLimit::max(64 * 1024)),
+            LimitClass::Operator | LimitClass::Light => self.handler,
+        };
+        match self.auth {
+            Auth::None | Auth::PlatformSignature => handler,
+            Auth::User => handler.layer(middleware::from_fn_with_state(state.clone(), auth_middleware)),
+            Auth::Admin => handler.layer(middleware::from_fn_with_state(state.clone(), admin::localhost_or_signed)),
+            Auth::Localhost => handler.layer(middleware::from_fn(admin::localhost_only)),
+        }
+    }
+}
+
+/// 全部路由；聊天运维回调只登记已配置密钥的平台
+pub fn registry(config: &crate::config::Config) -> Vec<RouteSpec> {
+    let mut routes = vec![
+        RouteSpec::new(Method::POST, "/auth/login", post(login)).limit(LimitClass::Login),
+        RouteSpec::new(Method::POST, "/chat/completions", post(proxy_chat))
+            .auth(Auth::User)
+            .limit(LimitClass::Upstream)
+            .consumes_quota(),
+        RouteSpec::new(Method::POST, "/auth/logout", post(logout)).auth(Auth::User).limit(LimitClass::Login),
+        RouteSpec::new(Method::GET, "/me", get(me)).auth(Auth::User),
+        RouteSpec::new(Method::POST, "/me/password", post(change_password)).auth(Auth::User).limit(LimitClass::Login),
+        RouteSpec::new(Method::GET, "/models", get(proxy_models)).auth(Auth::User).limit(LimitClass::Upstream),
+        RouteSpec::new(Method::GET, "/models/:name/capabilities", get(capabilities::model_capabilities))
+            .auth(Auth::User)
+            .limit(LimitClass::Light),
+        RouteSpec::new(Method::GET, "/readyz", get(health::readyz)).scope(Scope::Both),
+        // 签名链接本身即凭证
+        RouteSpec::new(Method::GET, "/downloads/:token", get(downloads::download)).scope(Scope::Both),
+        RouteSpec::new(Method::GET, "/metrics", get(render_metrics).layer(middleware::from_fn(conditional::conditional_get)))
+            .scope(Scope::Internal),
+        // 探针用户的用量照常记录（建议设为 unlimited）
+        RouteSpec::new(Method::GET, "/probe/chat", get(admin::probe_chat))
+            .auth(Auth::Localhost)
+            .limit(LimitClass::Upstream)
+            .scope(Scope::Internal)
+            .consumes_quota(),
+    ];
+    if config.auth.refresh_token_ttl_seconds > 0 {
+        routes.push(RouteSpec::new(Method::POST, "/auth/refresh", post(refresh)).limit(LimitClass::Login));
+    }
+    if config.auth.jwt_algorithm.is_asymmetric() {
+        routes.push(RouteSpec::new(Method::GET, "/.well-known/jwks.json", get(jwks)).limit(LimitClass::Light).scope(Scope::Both));
+    }
+    if config.audio.enabled {
+        routes.push(
+            RouteSpec::new(Method::POST, "/audio/transcriptions", post(audio::proxy_transcription))
+                .auth(Auth::User)
+                .limit(LimitClass::Upload)
+                .consumes_quota(),
+        );
+        routes.push(
+            RouteSpec::new(Method::POST, "/audio/speech", post(audio::proxy_speech))
+                .auth(Auth::User)
+                .limit(LimitClass::Upstream)
+                .consumes_quota(),
+        );
+    }
+    if config.chatops.slack_signing_secret.is_some() {
+        routes.push(
+            RouteSpec::new(Method::POST, "/chatops/slack", post(chatops::slack))
+                .auth(Auth::PlatformSignature)
+                .limit(LimitClass::Webhook),
+        );
+    }
+    if config.chatops.discord_public_key.is_some() {
+        routes.push(
+            RouteSpec::new(Method::POST, "/chatops/discord", post(chatops::discord))
+                .auth(Auth::PlatformSignature)
+                .limit(LimitClass::Webhook),
+        );
+    }
+    routes.extend(admin::registry().into_iter().map(|route| {
+        RouteSpec::new(route.method.clone(), route.path, route.into_handler())
+            .auth(Auth::Admin)
+            .limit(LimitClass::Operator)
+            .scope(Scope::Internal)
+    }));
+    routes
+}
+
+/// 检查登记表：策略组合不合理或同一方法与路径重复登记时返回错误
+pub fn validate(routes: &[RouteSpec]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for route in routes {
+        route.validate()?;
+        if !seen.insert((route.method.clone(), route.path)) {
+            return Err(format!("{} {} 重复登记", route.method, route.path));
+        }
+    }
+    Ok(())
+}
+
+/// 按监听地址分组并挂载策略中间件，返回 (公开路由, 管理面路由, 两处都提供的路由)
+pub fn build(
+    routes: Vec<RouteSpec>,
+    state: &AppState,
+) -> (axum::Router<AppState>, axum::Router<AppState>, axum::Router<AppState>) {
+    let (mut public, mut internal, mut both) = (axum::Router::new(), axum::Router::new(), axum::Router::new());
+    for route in routes {
+        let (path, scope) = (route.path, route.scope);
+        let handler = route.into_method_router(state);
+        match scope {
+            Scope::Public => public = public.route(path, handler),
+            Scope::Internal => internal = internal.route(path, handler),
+            Scope::Both => both = both.route(path, handler),
+        }
+    }
+    (public, internal, both)
+}
+
+async fn render_metrics() -> Response {
+    match metrics::METRICS.render() {
+        Ok(body) => (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("metrics render error: {}", e)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(method: Method, path: &'static str) -> RouteSpec {
+        RouteSpec::new(method, path, get(|| async {}))
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        assert!(spec(Method::GET, "/x").validate().is_ok());
+        assert!(spec(Method::POST, "/x").validate().is_err()); // 未鉴权的写接口
+        assert!(spec(Method::POST, "/x").auth(Auth::User).limit(LimitClass::Upstream).consumes_quota().validate().is_ok());
+        assert!(spec(Method::GET, "/x").limit(LimitClass::Upstream).validate().is_err());
+        assert!(spec(Method::GET, "/x").auth(Auth::User).consumes_quota().validate().is_err());
+        assert!(spec(Method::GET, "/admin/x").auth(Auth::Admin).validate().is_err()); // 不在管理面
+        assert!(spec(Method::GET, "/admin/x").auth(Auth::Admin).scope(Scope::Internal).validate().is_ok());
+
+        let duplicated = [spec(Method::GET, "/x"), spec(Method::GET, "/x")];
+        assert!(validate(&duplicated).is_err());
+    }
+
+    #[test]
+    fn test_registry_is_valid() {
+        let config = crate::config::test_config(
+            "[chatops]\nslack_signing_secret = \"s\"\ndiscord_public_key = \"k\"\n[audio]\nenabled = true\n",
+        );
+        let routes = registry(&config);
+        assert_eq!(validate(&routes), Ok(()));
+        assert!(routes.iter().any(|r| r.path == "/chatops/discord"));
+        assert!(routes.iter().any(|r| r.path == "/audio/transcriptions" && r.limit == LimitClass::Upload));
+    }
+}
@@ -0,0 +1,187 @@
+//! 关闭报告：优雅关闭时汇总本次运行处理的请求数、排空的流式响应、落盘的配额与行为日志，
+//! 写入日志与 `data/last_shutdown.json`
+//!
+//! 启动时写入运行标记 `data/running.json`，正常关闭后删除；下次启动时标记仍在，说明上次是崩溃或被强制终止
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+const REPORT_FILE: &str = "last_shutdown.json";
+const MARKER_FILE: &str = "running.json";
+
+/// 本次运行处理完成的请求数
+static REQUESTS_SERVED: AtomicU64 = AtomicU64::new(0);
+/// 进行中的流式响应数
+static ACTIVE_STREAMS: AtomicU64 = AtomicU64::new(0);
+
+/// 中间件：统计处理完成的请求数
+pub async fn count_requests(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    REQUESTS_SERVED.fetch_add(1, Ordering::Relaxed);
+    response
+}
+
+/// 进行中的流式响应，随响应流一起释放
+pub struct ActiveStream(());
+
+impl ActiveStream {
+    pub fn track() -> Self {
+        ACTIVE_STREAMS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        ACTIVE_STREAMS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 运行标记：记录本次运行的启动时间
+#[derive(Debug, Serialize, Deserialize)]
+struct RunMarker {
+    pid: u32,
+    started_at: String,
+}
+
+/// 关闭报告（data/last_shutdown.json）
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShutdownReport {
+    pub started_at: String,
+    pub shutdown_at: String,
+    pub uptime_seconds: u64,
+    pub requests_served: u64,
+    /// 开始关闭时仍在进行中的流式响应
+    pub streams_in_flight: u64,
+    /// 其中在排空期间结束的
+    pub streams_drained: u64,
+    pub quota_entries_flushed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_flush_error: Option<String>,
+    pub activity_events_flushed: usize,
+    /// 本次运行中因缓冲通道写满而丢弃的行为日志
+    pub activity_events_dropped: u64,
+    /// 从收到关闭信号到在途请求全部结束的耗时
+    pub drain_duration_ms: u64,
+}
+
+/// 关闭开始时的快照
+pub struct Drain {
+    started: Instant,
+    streams_in_flight: u64,
+}
+
+pub struct ShutdownReporter {
+    data_dir: PathBuf,
+    started_at: String,
+    started: Instant,
+}
+
+impl ShutdownReporter {
+    /// 检查上次运行是否正常关闭，并写入本次运行标记（只读模式下只检查）
+    pub async fn start(data_dir: impl Into<PathBuf>) -> Self {
+        let reporter = Self {
+            data_dir: data_dir.into(),
+            started_at: crate::utils::now_beijing_rfc3339(),
+            started: Instant::now(),
+        };
+        reporter.check_previous().await;
+        if !crate::read_only::is_enabled() {
+            let marker = RunMarker { pid: std::process::id(), started_at: reporter.started_at.clone() };
+            if let Err(e) = write_json(&reporter.data_dir.join(MARKER_FILE), &marker).await {
+                tracing::warn!(error = %e, "写入运行标记失败，下次启动将无法判断本次是否正常关闭");
+            }
+        }
+        reporter
+    }
+
+    /// 上次运行的结束方式：运行标记仍在为异常退出，否则输出上次的关闭报告
+    async fn check_previous(&self) {
+        if let Some(marker) = read_json::<RunMarker>(&self.data_dir.join(MARKER_FILE)).await {
+            tracing::warn!(
+                pid = marker.pid,
+                started_at = %marker.started_at,
+                "上次运行未正常关闭（崩溃或被强制终止），配额与行为日志可能丢失最后一次保存后的变更"
+            );
+        } else if let Some(report) = read_json::<ShutdownReport>(&self.data_dir.join(REPORT_FILE)).await {
+            tracing::info!(
+                shutdown_at = %report.shutdown_at,
+                requests_served = report.requests_served,
+                drain_duration_ms = report.drain_duration_ms,
+                "上次运行正常关闭"
+            );
+        }
+    }
+
+    /// 收到关闭信号时调用
+    pub fn begin_drain(&self) -> Drain {
+        Drain { started: Instant::now(), streams_in_flight: ACTIVE_STREAMS.load(Ordering::Relaxed) }
+    }
+
+    /// 在途请求全部结束后调用：写出剩余行为日志，输出并保存关闭报告，删除运行标记
+    pub async fn finish(
+        &self,
+        drain: Drain,
+        quota_flush: Result<usize, String>,
+        activity_logger: &crate::user_activity::UserActivityLogger,
+    ) -> ShutdownReport {
+        let drain_duration_ms = drain.started.elapsed().as_millis() as u64;
+        let remaining = ACTIVE_STREAMS.load(Ordering::Relaxed);
+        let (quota_entries_flushed, quota_flush_error) = match quota_flush {
+            Ok(n) => (n, None),
+            Err(e) => (0, Some(e)),
+        };
+        let report = ShutdownReport {
+            started_at: self.started_at.clone(),
+            shutdown_at: crate::utils::now_beijing_rfc3339(),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            requests_served: REQUESTS_SERVED.load(Ordering::Relaxed),
+            streams_in_flight: drain.streams_in_flight,
+            streams_drained: drain.streams_in_flight.saturating_sub(remaining),
+            quota_entries_flushed,
+            quota_flush_error,
+            activity_events_flushed: activity_logger.flush().await,
+            activity_events_dropped: activity_logger.dropped(),
+            drain_duration_ms,
+        };
+        tracing::info!(
+            uptime_seconds = report.uptime_seconds,
+            requests_served = report.requests_served,
+            streams_in_flight = report.streams_in_flight,
+            streams_drained = report.streams_drained,
+            quota_entries_flushed = report.quota_entries_flushed,
+            activity_events_flushed = report.activity_events_flushed,
+            activity_events_dropped = report.activity_events_dropped,
+            drain_duration_ms = report.drain_duration_ms,
+            "关闭报告"
+        );
+
+        if !crate::read_only::is_enabled() {
+            if let Err(e) = write_json(&self.data_dir.join(REPORT_FILE), &report).await {
+                tracing::warn!(error = %e, "写入关闭报告失败");
+            }
+            let _ = tokio::fs::remove_file(self.data_dir.join(MARKER_FILE)).await;
+        }
+        report
+    }
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&content)
+        .map_err(|e| tracing::warn!(path = %path.display(), error = %e, "文件无法解析，已忽略"))
+        .ok()
+}
+
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let json = serde_json::to_string_pretty(value)?;
+    let temp_path = path.with_extension("tmp");
+    tokio::fs::write(&temp_path, json).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
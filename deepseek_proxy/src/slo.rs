@@ -0,0 +1,277 @@
+use crate::config::SloConfig;
+use crate::metrics::{METRICS, UPSTREAM_LATENCY_BUCKETS};
+use prometheus::core::Metric;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// 采样文件目录：每天一个 JSONL 文件，每行是一个采样区间内的增量
+const SAMPLE_DIR: &str = "data/metrics/slo";
+/// 报告的滚动窗口
+const WINDOWS: [(&str, i64); 2] = [("24h", 24 * 3600), ("7d", 7 * 24 * 3600)];
+/// 采样文件保留天数（覆盖最长窗口）
+const KEEP_DAYS: i64 = 8;
+
+/// 一个采样区间内的增量
+#[derive(Debug, Serialize, Deserialize)]
+struct Sample {
+    /// 区间结束时间（Unix 秒）
+    ts: i64,
+    success: u64,
+    failure: u64,
+    /// 上游首包延迟各区间的请求数（非累计），最后一项为超过最大边界的请求
+    latency_buckets: Vec<u64>,
+}
+
+/// 进程内计数器的累计值
+#[derive(Clone, Default)]
+struct Totals {
+    success: u64,
+    failure: u64,
+    latency_cumulative: Vec<u64>,
+}
+
+impl Totals {
+    fn read() -> Self {
+        let success = METRICS.chat_requests.with_label_values(&["success"]).get() as u64;
+        let failure = ["network", "api"]
+            .iter()
+            .map(|kind| METRICS.upstream_errors.with_label_values(&[kind]).get() as u64)
+            .sum();
+        let proto = METRICS.upstream_latency.metric();
+        let histogram = proto.get_histogram();
+        let mut latency_cumulative: Vec<u64> = histogram.get_bucket().iter().map(|b| b.get_cumulative_count()).collect();
+        latency_cumulative.push(histogram.get_sample_count());
+        Self { success, failure, latency_cumulative }
+    }
+
+    /// 相对上一次读数的增量
+    fn delta(&self, prev: &Totals, ts: i64) -> Sample {
+        let cumulative_delta: Vec<u64> = self
+            .latency_cumulative
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v.saturating_sub(prev.latency_cumulative.get(i).copied().unwrap_or(0)))
+            .collect();
+        let latency_buckets = cumulative_delta
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v.saturating_sub(if i == 0 { 0 } else { cumulative_delta[i - 1] }))
+            .collect();
+        Sample {
+            ts,
+            success: self.success.saturating_sub(prev.success),
+            failure: self.failure.saturating_sub(prev.failure),
+            latency_buckets,
+        }
+    }
+}
+
+/// 定期把请求成功 / 失败数与上游延迟分布的增量写入采样文件，供 /admin/slo 计算滚动窗口
+///
+/// 以启动时（已恢复今日快照后）的计数为基线；关闭前最后一个未满的采样区间不会写入。只读模式下不启动
+pub fn spawn_recorder(cfg: SloConfig) {
+    if crate::read_only::is_enabled() {
+        return;
+    }
+    let interval = Duration::from_secs(cfg.sample_interval_seconds.max(1));
+    crate::supervisor::spawn("slo_recorder", move || async move {
+        // 重启后以当时的计数为新基线，重启前未满的采样区间不计入
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        let mut prev = Totals::read();
+        let mut cleaned_day = String::new();
+        loop {
+            ticker.tick().await;
+            let now = crate::utils::now_beijing();
+            let current = Totals::read();
+            let sample = current.delta(&prev, now.timestamp());
+            prev = current;
+
+            let day = now.format("%Y-%m-%d").to_string();
+            if let Err(e) = append_sample(&day, &sample).await {
+                tracing::warn!(error = %e, "写入 SLO 采样失败");
+            }
+            if cleaned_day != day {
+                cleanup_old_files(now.date_naive()).await;
+                cleaned_day = day;
+            }
+        }
+    });
+}
+
+async fn append_sample(day: &str, sample: &Sample) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(SAMPLE_DIR).await?;
+    let mut line = serde_json::to_string(sample)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(Path::new(SAMPLE_DIR).join(format!("{}.jsonl", day)))
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+async fn cleanup_old_files(today: chrono::NaiveDate) {
+    let Ok(mut entries) = tokio::fs::read_dir(SAMPLE_DIR).await else { return };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Some(date) = file_date(&path) else { continue };
+        if (today - date).num_days() > KEEP_DAYS {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+    }
+}
+
+fn file_date(path: &Path) -> Option<chrono::NaiveDate> {
+    let stem = path.file_stem()?.to_str()?;
+    chrono::NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok()
+}
+
+/// 可用性报告
+#[derive(Debug, Serialize)]
+pub struct SloReport {
+    /// 目标成功率
+    pub target: f64,
+    pub windows: Vec<WindowReport>,
+    pub generated_at: String,
+}
+
+/// 单个滚动窗口的统计
+#[derive(Debug, Serialize)]
+pub struct WindowReport {
+    pub window: &'static str,
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// 没有请求时为 null
+    pub success_rate: Option<f64>,
+    /// 上游首包延迟 p95（按直方图区间线性插值）
+    pub p95_latency_seconds: Option<f64>,
+    /// 剩余错误预算比例：1 - 失败数 / (请求数 × (1 - 目标))，可为负
+    pub error_budget_remaining: Option<f64>,
+    /// 窗口内采样覆盖的时长（服务停机期间没有采样）
+    pub covered_seconds: i64,
+}
+
+/// 读取采样文件并计算各滚动窗口的成功率、p95 延迟与剩余错误预算
+pub async fn report(cfg: &SloConfig) -> anyhow::Result<SloReport> {
+    let now = crate::utils::now_beijing();
+    let samples = load_samples(now.date_naive()).await?;
+    let windows = WINDOWS
+        .iter()
+        .map(|(name, secs)| summarize(name, &samples, now.timestamp() - secs, cfg.target, cfg.sample_interval_seconds))
+        .collect();
+    Ok(SloReport {
+        target: cfg.target,
+        windows,
+        generated_at: now.to_rfc3339(),
+    })
+}
+
+async fn load_samples(today: chrono::NaiveDate) -> anyhow::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for days_ago in (0..KEEP_DAYS).rev() {
+        let day = today - chrono::Duration::days(days_ago);
+        let path: PathBuf = Path::new(SAMPLE_DIR).join(format!("{}.jsonl", day.format("%Y-%m-%d")));
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // 跳过写入中断产生的残缺行
+        samples.extend(content.lines().filter_map(|line| serde_json::from_str::<Sample>(line).ok()));
+    }
+    Ok(samples)
+}
+
+fn summarize(window: &'static str, samples: &[Sample], since: i64, target: f64, interval: u64) -> WindowReport {
+    let mut successes = 0;
+    let mut failures = 0;
+    let mut latency = vec![0u64; UPSTREAM_LATENCY_BUCKETS.len() + 1];
+    let mut covered = 0;
+    for sample in samples.iter().filter(|s| s.ts > since) {
+        successes += sample.success;
+        failures += sample.failure;
+        for (acc, v) in latency.iter_mut().zip(&sample.latency_buckets) {
+            *acc += v;
+        }
+        covered += interval as i64;
+    }
+    let requests = successes + failures;
+    let allowed_failures = requests as f64 * (1.0 - target);
+    WindowReport {
+        window,
+        requests,
+        successes,
+        failures,
+        success_rate: (requests > 0).then(|| successes as f64 / requests as f64),
+        p95_latency_seconds: quantile(0.95, &latency),
+        error_budget_remaining: (allowed_failures > 0.0).then(|| 1.0 - failures as f64 / allowed_failures),
+        covered_seconds: covered,
+    }
+}
+
+/// 按直方图区间估算分位数（与 Prometheus histogram_quantile 相同：区间内线性插值，落在最后一个区间时取最大边界）
+fn quantile(q: f64, buckets: &[u64]) -> Option<f64> {
+    let total: u64 = buckets.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = q * total as f64;
+    let mut cumulative = 0u64;
+    for (i, count) in buckets.iter().enumerate() {
+        let prev = cumulative;
+        cumulative += count;
+        if (cumulative as f64) < rank || *count == 0 {
+            continue;
+        }
+        let Some(upper) = UPSTREAM_LATENCY_BUCKETS.get(i) else {
+            return UPSTREAM_LATENCY_BUCKETS.last().copied();
+        };
+        let lower = if i == 0 { 0.0 } else { UPSTREAM_LATENCY_BUCKETS[i - 1] };
+        return Some(lower + (upper - lower) * (rank - prev as f64) / *count as f64);
+    }
+    UPSTREAM_LATENCY_BUCKETS.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ts: i64, success: u64, failure: u64, latency_buckets: Vec<u64>) -> Sample {
+        Sample { ts, success, failure, latency_buckets }
+    }
+
+    #[test]
+    fn test_summarize_window() {
+        // 边界 [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0]
+        let samples = vec![
+            sample(100, 50, 50, vec![0, 0, 0, 0, 0, 0, 0, 100]),
+            sample(200, 95, 5, vec![0, 0, 0, 0, 100, 0, 0, 0]),
+        ];
+        let report = summarize("24h", &samples, 150, 0.99, 60);
+        assert_eq!((report.requests, report.failures), (100, 5));
+        assert_eq!(report.success_rate, Some(0.95));
+        // p95 落在 (0.5, 1.0] 区间内
+        assert!((report.p95_latency_seconds.unwrap() - 0.975).abs() < 1e-9);
+        // 允许 1 次失败，实际 5 次
+        assert!((report.error_budget_remaining.unwrap() + 4.0).abs() < 1e-9);
+        assert_eq!(report.covered_seconds, 60);
+
+        let empty = summarize("24h", &samples, 300, 0.99, 60);
+        assert_eq!(empty.success_rate, None);
+        assert_eq!(empty.p95_latency_seconds, None);
+    }
+
+    #[test]
+    fn test_delta_handles_histogram_buckets() {
+        let prev = Totals { success: 10, failure: 1, latency_cumulative: vec![1, 2, 2, 2, 2, 2, 2, 3] };
+        let cur = Totals { success: 12, failure: 1, latency_cumulative: vec![2, 4, 4, 4, 4, 4, 4, 6] };
+        let s = cur.delta(&prev, 1);
+        assert_eq!((s.success, s.failure), (2, 0));
+        assert_eq!(s.latency_buckets, vec![1, 1, 0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{config::StatsdConfig, metrics::METRICS};
+use prometheus::proto::{MetricFamily, MetricType};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// 单个 UDP 包的最大负载（避免超过常见 MTU 被分片）
+const MAX_PACKET_BYTES: usize = 1432;
+
+/// 启动 StatsD 推送任务：每隔 interval_seconds 采集一次 Prometheus 注册表并通过 UDP 发送
+///
+/// Counter 与 Histogram 的 count/sum 以增量（`|c`）发送，Gauge 以当前值（`|g`）发送；
+/// 发送失败只记录日志，不影响服务
+pub fn spawn_exporter(cfg: StatsdConfig) {
+    crate::supervisor::spawn("statsd_exporter", move || {
+        let cfg = cfg.clone();
+        async move {
+            let socket = match UdpSocket::bind("0.0.0.0:0").await {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::error!("StatsD 推送任务启动失败: {}", e);
+                    return;
+                }
+            };
+            let mut previous = HashMap::new();
+            let mut interval = tokio::time::interval(Duration::from_secs(cfg.interval_seconds.max(1)));
+            loop {
+                interval.tick().await;
+                let lines = render_lines(&METRICS.registry.gather(), &cfg, &mut previous);
+                for packet in pack_lines(&lines) {
+                    if let Err(e) = socket.send_to(packet.as_bytes(), &cfg.addr).await {
+                        tracing::warn!("StatsD 推送失败: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// 将指标族渲染为 StatsD 行；previous 记录上次的累计值以计算增量
+fn render_lines(families: &[MetricFamily], cfg: &StatsdConfig, previous: &mut HashMap<String, f64>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for family in families {
+        for metric in family.get_metric() {
+            let labels: Vec<(&str, &str)> = metric
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name(), l.get_value()))
+                .collect();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let name = metric_name(cfg, family.get_name(), &labels);
+                    let delta = counter_delta(previous, &name, metric.get_counter().get_value());
+                    if delta > 0.0 {
+                        lines.push(format_line(cfg, &name, delta, "c", &labels));
+                    }
+                }
+                MetricType::GAUGE => {
+                    let name = metric_name(cfg, family.get_name(), &labels);
+                    lines.push(format_line(cfg, &name, metric.get_gauge().get_value(), "g", &labels));
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for (suffix, value) in [
+                        ("count", histogram.get_sample_count() as f64),
+                        ("sum", histogram.get_sample_sum()),
+                    ] {
+                        let name = metric_name(cfg, &format!("{}.{}", family.get_name(), suffix), &labels);
+                        let delta = counter_delta(previous, &name, value);
+                        if delta > 0.0 {
+                            lines.push(format_line(cfg, &name, delta, "c", &labels));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    lines
+}
+
+/// 计算累计值的增量（进程内计数器不会回退，回退时按 0 处理）
+fn counter_delta(previous: &mut HashMap<String, f64>, key: &str, value: f64) -> f64 {
+    let last = previous.insert(key.to_string(), value).unwrap_or(0.0);
+    (value - last).max(0.0)
+}
+
+/// 指标名：`prefix.name`；非 DogStatsD 模式下 label 值拼接到名称后
+fn metric_name(cfg: &StatsdConfig, name: &str, labels: &[(&str, &str)]) -> String {
+    let mut full = if cfg.prefix.is_empty() { name.to_string() } else { format!("{}.{}", cfg.prefix, name) };
+    if !cfg.dogstatsd {
+        for (_, value) in labels {
+            full.push('.');
+            full.push_str(&sanitize(value));
+        }
+    }
+    full
+}
+
+fn format_line(cfg: &StatsdConfig, name: &str, value: f64, kind: &str, labels: &[(&str, &str)]) -> String {
+    let mut line = format!("{}:{}|{}", name, value, kind);
+    if cfg.dogstatsd && !labels.is_empty() {
+        let tags: Vec<String> = labels.iter().map(|(k, v)| format!("{}:{}", k, sanitize(v))).collect();
+        line.push_str("|#");
+        line.push_str(&tags.join(","));
+    }
+    line
+}
+
+/// 替换 StatsD 协议中的保留字符
+fn sanitize(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, ':' | '|' | '@' | ',' | '#' | ' ' | '\n') { '_' } else { c }).collect()
+}
+
+/// 按最大包长将多行合并为若干个 UDP 包
+fn pack_lines(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_PACKET_BYTES {
+            packets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        packets.push(current);
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, IntGauge, Opts, Registry};
+
+    fn config(dogstatsd: bool) -> StatsdConfig {
+        StatsdConfig { dogstatsd, ..StatsdConfig::default() }
+    }
+
+    #[test]
+    fn test_render_counter_deltas_and_gauges() {
+        let registry = Registry::new();
+        let counter = CounterVec::new(Opts::new("chat_requests_total", "x"), &["status"]).unwrap();
+        let gauge = IntGauge::new("today_input_tokens", "x").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+
+        counter.with_label_values(&["success"]).inc_by(3.0);
+        gauge.set(42);
+        let mut previous = HashMap::new();
+        let lines = render_lines(&registry.gather(), &config(true), &mut previous);
+        assert_eq!(lines, vec![
+            "deepseek_proxy.chat_requests_total:3|c|#status:success",
+            "deepseek_proxy.today_input_tokens:42|g",
+        ]);
+
+        // 第二次只发送增量，未变化的 counter 不发送
+        counter.with_label_values(&["success"]).inc();
+        let lines = render_lines(&registry.gather(), &config(false), &mut HashMap::from([
+            ("deepseek_proxy.chat_requests_total.success".to_string(), 3.0),
+        ]));
+        assert_eq!(lines[0], "deepseek_proxy.chat_requests_total.success:1|c");
+    }
+
+    #[test]
+    fn test_pack_lines_respects_packet_size() {
+        let lines: Vec<String> = (0..100).map(|i| format!("deepseek_proxy.metric_{}:1|c", i)).collect();
+        let packets = pack_lines(&lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_PACKET_BYTES));
+        assert_eq!(packets.iter().map(|p| p.lines().count()).sum::<usize>(), 100);
+    }
+}
@@ -0,0 +1,136 @@
+//! 后台任务监管：常驻的后台循环（行为日志写入、日志滚动、定时任务、快照保存等）都经 [`spawn`] 启动
+//!
+//! 任务 panic 后按退避重启（1 秒起逐次翻倍，最长 60 秒；重启后稳定运行 60 秒则退避清零），
+//! 正常返回视为任务已完成，不再重启。运行状态在 /readyz 的 `background_tasks` 中列出，
+//! 并导出 background_task_up 与 background_task_restarts_total 指标
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// 重启后连续运行超过这么久再 panic，退避从头开始
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// 任务 ID -> 运行状态（已完成的任务移除）
+static TASKS: Lazy<DashMap<u64, TaskStatus>> = Lazy::new(DashMap::new);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// panic 后等待重启
+    Restarting,
+}
+
+/// 一个后台任务的运行状态
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub state: TaskState,
+    /// 启动以来因 panic 重启的次数
+    pub restarts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_panic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_panic_at: Option<String>,
+}
+
+/// 启动受监管的后台任务；`task` 每次（重新）启动时调用一次，生成新的任务 future
+pub fn spawn<F, Fut>(name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    TASKS.insert(id, TaskStatus { name, state: TaskState::Running, restarts: 0, last_panic: None, last_panic_at: None });
+    let up = crate::metrics::METRICS.background_task_up.with_label_values(&[name]);
+    up.set(1);
+
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let error = match tokio::spawn(task()).await {
+                Ok(()) => {
+                    tracing::info!(task = name, "后台任务已结束");
+                    break;
+                }
+                // 运行时关闭
+                Err(e) if e.is_cancelled() => break,
+                Err(e) => e,
+            };
+            let message = crate::panic_guard::panic_message(error.into_panic().as_ref());
+            if started.elapsed() >= STABLE_AFTER {
+                backoff = INITIAL_BACKOFF;
+            }
+            tracing::error!(task = name, panic = %message, retry_in_seconds = backoff.as_secs(), "后台任务 panic，稍后重启");
+            crate::metrics::METRICS.background_task_restarts.with_label_values(&[name]).inc();
+            up.set(0);
+            if let Some(mut status) = TASKS.get_mut(&id) {
+                status.state = TaskState::Restarting;
+                status.restarts += 1;
+                status.last_panic = Some(message);
+                status.last_panic_at = Some(crate::utils::now_beijing_rfc3339());
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            if let Some(mut status) = TASKS.get_mut(&id) {
+                status.state = TaskState::Running;
+            }
+            up.set(1);
+        }
+        TASKS.remove(&id);
+        up.set(0);
+    });
+}
+
+/// 当前的后台任务，按名称排序
+pub fn report() -> Vec<TaskStatus> {
+    let mut tasks: Vec<TaskStatus> = TASKS.iter().map(|entry| entry.value().clone()).collect();
+    tasks.sort_by(|a, b| a.name.cmp(b.name));
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn status(name: &str) -> Option<TaskStatus> {
+        report().into_iter().find(|task| task.name == name)
+    }
+
+    #[tokio::test]
+    async fn test_restarts_after_panic_and_drops_finished_tasks() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let counter = runs.clone();
+        spawn("test_flaky", move || {
+            let runs = counter.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("first run fails");
+                }
+                std::future::pending::<()>().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let flaky = status("test_flaky").unwrap();
+        assert_eq!((flaky.state, flaky.restarts), (TaskState::Restarting, 1));
+        assert_eq!(flaky.last_panic.as_deref(), Some("first run fails"));
+
+        tokio::time::sleep(INITIAL_BACKOFF + Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(status("test_flaky").unwrap().state, TaskState::Running);
+
+        spawn("test_oneshot", || async {});
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(status("test_oneshot").is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::config::TailSamplingConfig;
+use once_cell::sync::Lazy;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{field::Field, span, Event, Level, Subscriber};
+use tracing_subscriber::{fmt::MakeWriter, layer::Context, registry::LookupSpan, Layer};
+
+/// 尾部采样设置（日志系统先于配置初始化，加载配置后再通过 `configure` 生效）
+#[derive(Debug, Default)]
+pub struct TailSamplingSettings {
+    enabled: AtomicBool,
+    latency_threshold_ms: AtomicU64,
+    max_events_per_request: AtomicUsize,
+}
+
+impl TailSamplingSettings {
+    pub fn apply(&self, cfg: &TailSamplingConfig) {
+        self.latency_threshold_ms.store(cfg.latency_threshold_ms, Ordering::Relaxed);
+        self.max_events_per_request.store(cfg.max_events_per_request, Ordering::Relaxed);
+        self.enabled.store(cfg.enabled, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+/// 全局设置，日志文件层的过滤器与采样层共用
+pub static SETTINGS: Lazy<Arc<TailSamplingSettings>> = Lazy::new(|| Arc::new(TailSamplingSettings::default()));
+
+/// 按配置启用尾部采样
+pub fn configure(cfg: &TailSamplingConfig) {
+    SETTINGS.apply(cfg);
+    if cfg.enabled {
+        tracing::info!(
+            "尾部采样已启用：仅为出错或耗时超过 {}ms 的请求保留 debug 日志",
+            cfg.latency_threshold_ms
+        );
+    }
+}
+
+/// 启用尾部采样时，文件层只直接写入 INFO 及以上的事件，debug 事件交给采样层决定去留
+pub fn file_filter(meta: &tracing::Metadata<'_>) -> bool {
+    !SETTINGS.enabled() || meta.is_span() || *meta.level() <= Level::INFO
+}
+
+/// 每个请求（根 span）的缓冲区
+struct RequestBuffer {
+    started: Instant,
+    lines: Vec<String>,
+    dropped: usize,
+    errored: bool,
+}
+
+/// 尾部采样层：按根 span（TraceLayer 为每个请求创建的 span）缓冲 debug 事件，
+/// 请求结束时若出现过 WARN/ERROR 或耗时超过阈值则写出，否则丢弃
+pub struct TailSamplingLayer<W> {
+    settings: Arc<TailSamplingSettings>,
+    writer: W,
+}
+
+impl<W> TailSamplingLayer<W> {
+    pub fn new(settings: Arc<TailSamplingSettings>, writer: W) -> Self {
+        Self { settings, writer }
+    }
+}
+
+impl<S, W> Layer<S> for TailSamplingLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if !self.settings.enabled() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            if span.parent().is_none() {
+                span.extensions_mut().insert(RequestBuffer {
+                    started: Instant::now(),
+                    lines: Vec::new(),
+                    dropped: 0,
+                    errored: false,
+                });
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !self.settings.enabled() {
+            return;
+        }
+        let Some(root) = ctx.event_scope(event).and_then(|scope| scope.from_root().next()) else {
+            return;
+        };
+        let mut extensions = root.extensions_mut();
+        let Some(buffer) = extensions.get_mut::<RequestBuffer>() else {
+            return;
+        };
+
+        let level = *event.metadata().level();
+        if level <= Level::WARN {
+            buffer.errored = true;
+        }
+        if level > Level::INFO {
+            if buffer.lines.len() < self.settings.max_events_per_request.load(Ordering::Relaxed) {
+                buffer.lines.push(format_event(event));
+            } else {
+                buffer.dropped += 1;
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(buffer) = span.extensions_mut().remove::<RequestBuffer>() else { return };
+        if buffer.lines.is_empty() {
+            return;
+        }
+
+        let latency_ms = buffer.started.elapsed().as_millis() as u64;
+        let slow = latency_ms >= self.settings.latency_threshold_ms.load(Ordering::Relaxed);
+        if !buffer.errored && !slow {
+            return;
+        }
+
+        let reason = if buffer.errored { "error" } else { "slow" };
+        let mut out = format!(
+            "{} TAIL-SAMPLED span={} reason={} latency_ms={} events={} dropped={}\n",
+            crate::utils::now_beijing_rfc3339(),
+            span.name(),
+            reason,
+            latency_ms,
+            buffer.lines.len(),
+            buffer.dropped
+        );
+        for line in &buffer.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        let _ = self.writer.make_writer().write_all(out.as_bytes());
+    }
+}
+
+/// 将事件格式化为一行文本：时间 级别 target: message k=v ...
+fn format_event(event: &Event<'_>) -> String {
+    let meta = event.metadata();
+    let mut visitor = LineVisitor::default();
+    event.record(&mut visitor);
+    format!(
+        "{} {:>5} {}: {}{}",
+        crate::utils::now_beijing_rfc3339(),
+        meta.level(),
+        meta.target(),
+        visitor.message,
+        visitor.fields
+    )
+}
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl tracing::field::Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_keeps_debug_only_for_failed_requests() {
+        let settings = Arc::new(TailSamplingSettings::default());
+        settings.apply(&TailSamplingConfig {
+            enabled: true,
+            latency_threshold_ms: 60_000,
+            max_events_per_request: 1,
+        });
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TailSamplingLayer::new(settings, captured.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug_span!("request", uri = "/ok").in_scope(|| {
+                tracing::debug!("快速成功的请求");
+            });
+            tracing::debug_span!("request", uri = "/bad").in_scope(|| {
+                tracing::debug!(user = "alice", "上游返回异常");
+                tracing::debug!("超出缓冲上限");
+                tracing::warn!("请求失败");
+            });
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("快速成功的请求"));
+        assert!(output.contains("reason=error"));
+        assert!(output.contains("dropped=1"));
+        assert!(output.contains("上游返回异常 user=alice"));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::config::{ClientAuthScope, ClientRole, TlsConfig};
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    self,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+use tokio_rustls::TlsAcceptor;
+
+/// 通过 mTLS 认证的客户端身份（按连接注入到请求 extensions）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertIdentity {
+    /// 证书 Subject 中的 CN
+    pub cn: String,
+    /// 映射到的用户名（未配置映射时为 None）
+    pub username: Option<String>,
+    pub role: ClientRole,
+}
+
+impl ClientCertIdentity {
+    pub fn is_admin(&self) -> bool {
+        self.role == ClientRole::Admin
+    }
+}
+
+/// 根据配置构建 TLS acceptor
+pub fn build_acceptor(cfg: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = load_certs(&cfg.cert)?;
+    let key = load_key(&cfg.key)?;
+
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let mut server_config = match &cfg.client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match cfg.client_auth {
+                ClientAuthScope::All => verifier.build()?,
+                // 证书可选，管理接口再校验角色
+                ClientAuthScope::Admin => verifier.allow_unauthenticated().build()?,
+            };
+            builder.with_client_cert_verifier(verifier).with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// 从客户端证书解析身份；CN 不在映射表中的证书仅保留 CN，角色为 user
+pub fn identity_from_cert(
+    cert: &CertificateDer<'_>,
+    identities: &HashMap<String, crate::config::ClientIdentityConfig>,
+) -> Option<ClientCertIdentity> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let cn = parsed.subject().iter_common_name().next()?.as_str().ok()?.to_string();
+    let mapped = identities.get(&cn);
+    Some(ClientCertIdentity {
+        username: mapped.and_then(|m| m.username.clone()),
+        role: mapped.map(|m| m.role).unwrap_or_default(),
+        cn,
+    })
+}
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("读取证书 {} 失败: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        anyhow::bail!("证书文件 {} 中没有证书", path);
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("读取私钥 {} 失败: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))?
+        .ok_or_else(|| anyhow::anyhow!("私钥文件 {} 中没有私钥", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientIdentityConfig;
+
+    fn cert_with_cn(cn: &str) -> CertificateDer<'static> {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, cn);
+        let key = rcgen::KeyPair::generate().unwrap();
+        params.self_signed(&key).unwrap().der().clone()
+    }
+
+    #[test]
+    fn test_identity_from_cert_maps_cn() {
+        let identities = HashMap::from([(
+            "ops-bot".to_string(),
+            ClientIdentityConfig { username: Some("admin".to_string()), role: ClientRole::Admin },
+        )]);
+
+        let identity = identity_from_cert(&cert_with_cn("ops-bot"), &identities).unwrap();
+        assert_eq!(identity.username.as_deref(), Some("admin"));
+        assert!(identity.is_admin());
+
+        let unknown = identity_from_cert(&cert_with_cn("stranger"), &identities).unwrap();
+        assert_eq!(unknown.cn, "stranger");
+        assert_eq!(unknown.username, None);
+        assert!(!unknown.is_admin());
+    }
+}
@@ -1,11 +1,13 @@
+use crate::config::ActivityLogConfig;
+use crate::object_storage::ObjectStorage;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, mpsc, oneshot};
 use std::collections::HashMap;
-use tokio::task::JoinHandle;
 
 /// 用户行为类型
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +22,22 @@ pub enum UserAction {
         model: String,
         message_count: usize,
         tokens_estimated: Option<u32>,
+        /// 调用方随请求提交的 metadata（schema 版本 3 起）
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<serde_json::Value>,
+    },
+    /// 语音请求（schema 版本 4 起）
+    AudioRequest {
+        /// transcription（语音识别）或 speech（语音合成）
+        endpoint: String,
+        model: String,
+        /// 上传的请求体字节数
+        request_bytes: usize,
+    },
+    /// 客户端在流式响应完成前断开
+    ChatAborted {
+        bytes_delivered: u64,
+        tokens_delivered: u32,
     },
     /// 配额检查
     QuotaCheck {
@@ -33,8 +51,38 @@ pub enum UserAction {
     },
     /// 速率限制触发
     RateLimited,
+    /// 疑似重复提问刷量（窗口内相同内容超过上限）
+    SpamSuspected {
+        prompt_hash: String,
+        count: usize,
+    },
+    /// 来源 IP 不在账户允许范围内（登录或调用接口被拒绝）
+    IpRestricted,
+    /// 用户修改了自己的密码（旧会话已失效）
+    PasswordChanged,
+    /// 管理员重置了该用户的密码（审计用）
+    PasswordReset {
+        actor: String,
+    },
+    /// 管理员强制该用户下线（审计用）
+    ForcedLogout {
+        actor: String,
+        revoked_tokens: usize,
+    },
     /// 账户被停用
     AccountDisabled,
+    /// 管理员为该用户签发了代管 token
+    Impersonated {
+        actor: String,
+        reason: String,
+        expires_in: u64,
+    },
+    /// 长期不活跃，账户被自动停用并归档
+    AccountExpired {
+        inactive_days: i64,
+        last_login_at: Option<String>,
+        archive_dir: String,
+    },
     /// 错误
     Error {
         error_type: String,
@@ -45,6 +93,9 @@ pub enum UserAction {
 /// 用户行为日志记录
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserActivityLog {
+    /// 日志结构版本（旧记录没有该字段，读取时见 activity_schema）
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     /// 时间戳 (RFC3339)
     pub timestamp: String,
     /// 用户名
@@ -62,17 +113,20 @@ pub struct UserActivityLog {
     pub extra: Option<serde_json::Value>,
 }
 
+fn legacy_schema_version() -> u32 {
+    crate::activity_schema::LEGACY_SCHEMA_VERSION
+}
+
 /// 用户行为日志记录器
 #[derive(Clone)]
 pub struct UserActivityLogger {
-    #[allow(dead_code)]
     base_dir: PathBuf,
     #[allow(dead_code)]
     max_file_size: u64,
-    #[allow(dead_code)]
     file_handles: Arc<Mutex<HashMap<String, (tokio::fs::File, u64)>>>, // log_key -> (file, current_size)
     tx: mpsc::Sender<UserActivityLog>,            // 异步发送日志
-    _bg_handle: Arc<JoinHandle<()>>,              // 后台写任务，保持生命周期
+    dropped: Arc<AtomicU64>,                      // 通道写满而丢弃的记录数
+    flush_tx: mpsc::Sender<oneshot::Sender<usize>>, // 立即写出缓冲（关闭前），回复写出的条数
 }
 
 impl UserActivityLogger {
@@ -82,47 +136,93 @@ impl UserActivityLogger {
     /// - 每个用户独立文件夹：logs/users/{username}/
     /// - 按日期自动滚动：{username}.2025-11-01.log
     /// - 按大小自动滚动：单个文件最大 5MB
-    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+    /// - 缓冲通道写满时丢弃新记录并计数，按 drop_warn_interval 汇总告警，绝不阻塞请求
+    /// - 配置了对象存储时，滚动完成的文件（按大小归档或跨天）在后台转存
+    pub fn new(base_dir: impl Into<PathBuf>, cfg: &ActivityLogConfig, storage: Option<Arc<ObjectStorage>>) -> Self {
         let base_dir = base_dir.into();
         let max_file_size = 5 * 1024 * 1024; // 5MB 默认
-        let (tx, mut rx) = mpsc::channel::<UserActivityLog>(10_000); // 足够大的缓冲，避免高峰阻塞
+        let (tx, rx) = mpsc::channel::<UserActivityLog>(cfg.channel_capacity.max(1));
+        let (flush_tx, flush_rx) = mpsc::channel::<oneshot::Sender<usize>>(1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let dropped_clone = dropped.clone();
+        let drop_warn_interval = cfg.drop_warn_interval_seconds.max(1);
         let file_handles = Arc::new(Mutex::new(HashMap::new()));
         let base_dir_clone = base_dir.clone();
         let fh_clone = file_handles.clone();
         let max_size_clone = max_file_size;
-        // 后台批量写任务
-        let handle = tokio::spawn(async move {
-            use tokio::time::{interval, Duration};
-            let mut flush_tick = interval(Duration::from_millis(500)); // 500ms 尝试刷新一次
-            // 缓冲队列
-            let mut pending: Vec<UserActivityLog> = Vec::with_capacity(1024);
-            loop {
-                tokio::select! {
-                    biased;
-                    _ = flush_tick.tick() => {
-                        if !pending.is_empty() {
-                            if let Err(e) = write_batch(&base_dir_clone, max_size_clone, &fh_clone, &mut pending).await {
-                                tracing::error!(error = %e, "批量写入用户行为日志失败");
+        // 后台批量写任务：panic 后由 supervisor 重启，通道接收端保留在 channels 中（已取出未写出的记录丢失）
+        let channels = Arc::new(Mutex::new((rx, flush_rx)));
+        crate::supervisor::spawn("activity_writer", move || {
+            let channels = channels.clone();
+            let (base_dir_clone, fh_clone, storage, dropped_clone) =
+                (base_dir_clone.clone(), fh_clone.clone(), storage.clone(), dropped_clone.clone());
+            async move {
+                use tokio::time::{interval, Duration};
+                let mut channels = channels.lock().await;
+                let (rx, flush_rx) = &mut *channels;
+                let mut flush_tick = interval(Duration::from_millis(500)); // 500ms 尝试刷新一次
+                let mut drop_tick = interval(Duration::from_secs(drop_warn_interval));
+                let mut reported_dropped = 0u64;
+                // 缓冲队列
+                let mut pending: Vec<UserActivityLog> = Vec::with_capacity(1024);
+                loop {
+                    tokio::select! {
+                        biased;
+                        _ = flush_tick.tick() => {
+                            if !pending.is_empty() {
+                                if let Err(e) = write_batch(&base_dir_clone, max_size_clone, &fh_clone, storage.as_ref(), &mut pending).await {
+                                    crate::metrics::METRICS.record_persist_failure("activity_log");
+                                    tracing::error!(error = %e, "批量写入用户行为日志失败");
+                                }
                             }
                         }
-                    }
-                    msg = rx.recv() => {
-                        match msg {
-                            Some(log) => {
+                        Some(reply) = flush_rx.recv() => {
+                            while let Ok(log) = rx.try_recv() {
                                 pending.push(log);
-                                // 达到批量阈值立即写
-                                if pending.len() >= 1024 { // 批量大小阈值
-                                    if let Err(e) = write_batch(&base_dir_clone, max_size_clone, &fh_clone, &mut pending).await {
-                                        tracing::error!(error = %e, "批量写入用户行为日志失败");
-                                    }
+                            }
+                            let count = pending.len();
+                            if count > 0 {
+                                if let Err(e) = write_batch(&base_dir_clone, max_size_clone, &fh_clone, storage.as_ref(), &mut pending).await {
+                                    crate::metrics::METRICS.record_persist_failure("activity_log");
+                                    tracing::error!(error = %e, "批量写入用户行为日志失败");
                                 }
                             }
-                            None => {
-                                // 通道关闭，尝试写出剩余日志后退出
-                                if !pending.is_empty() {
-                                    let _ = write_batch(&base_dir_clone, max_size_clone, &fh_clone, &mut pending).await;
+                            // tokio 的文件写入可能仍在后台缓冲中，关闭前逐个 flush
+                            for (file, _) in fh_clone.lock().await.values_mut() {
+                                let _ = file.flush().await;
+                            }
+                            let _ = reply.send(count);
+                        }
+                        _ = drop_tick.tick() => {
+                            let total = dropped_clone.load(Ordering::Relaxed);
+                            if total > reported_dropped {
+                                tracing::warn!(
+                                    dropped = total - reported_dropped,
+                                    total,
+                                    "用户行为日志缓冲通道已满，期间丢弃了部分记录"
+                                );
+                                reported_dropped = total;
+                            }
+                        }
+                        msg = rx.recv() => {
+                            match msg {
+                                Some(log) => {
+                                    pending.push(log);
+                                    // 达到批量阈值立即写
+                                    if pending.len() >= 1024 { // 批量大小阈值
+                                        if let Err(e) = write_batch(&base_dir_clone, max_size_clone, &fh_clone, storage.as_ref(), &mut pending).await {
+                                            crate::metrics::METRICS.record_persist_failure("activity_log");
+                                            tracing::error!(error = %e, "批量写入用户行为日志失败");
+                                        }
+                                    }
+                                }
+                                None => {
+                                    // 通道关闭，尝试写出剩余日志后退出
+                                    if !pending.is_empty() {
+                                        let _ = write_batch(&base_dir_clone, max_size_clone, &fh_clone, storage.as_ref(), &mut pending).await;
+                                    }
+                                    break;
                                 }
-                                break;
                             }
                         }
                     }
@@ -135,17 +235,44 @@ impl UserActivityLogger {
             max_file_size,
             file_handles,
             tx,
-            _bg_handle: Arc::new(handle),
+            dropped,
+            flush_tx,
         }
     }
 
-    /// 记录用户行为（异步投递，不做磁盘 IO）
+    /// 记录用户行为（非阻塞投递，不做磁盘 IO；保留 async 签名以兼容调用方）
     pub async fn log(&self, log: UserActivityLog) {
-        if let Err(e) = self.tx.send(log).await {
-            tracing::error!(error = %e, "发送用户行为日志到缓冲通道失败");
+        self.try_log(log);
+    }
+
+    /// 非阻塞投递：通道满时丢弃并计数（由后台任务汇总告警），不阻塞请求路径
+    pub fn try_log(&self, log: UserActivityLog) {
+        match self.tx.try_send(log) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                crate::metrics::METRICS.activity_log_dropped.inc();
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::error!("用户行为日志缓冲通道已关闭，丢弃记录");
+            }
         }
     }
 
+    /// 立即写出缓冲中的记录（优雅关闭时调用），返回写出的条数
+    pub async fn flush(&self) -> usize {
+        let (reply, done) = oneshot::channel();
+        if self.flush_tx.send(reply).await.is_err() {
+            return 0;
+        }
+        done.await.unwrap_or(0)
+    }
+
+    /// 本次运行中因缓冲通道写满而丢弃的记录数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
     /// 旧的直接写方法保留为内部工具（可用于测试或紧急 flush）
     #[allow(dead_code)]
     async fn write_log_direct(&self, log: &UserActivityLog) -> anyhow::Result<()> {
@@ -221,9 +348,79 @@ impl UserActivityLogger {
         Ok(())
     }
 
+    /// 读取用户的全部行为日志（按时间排序），同时返回无法解析而跳过的行数
+    ///
+    /// 仍在缓冲通道中、尚未落盘的记录（最多约 500ms）不包含在内
+    pub async fn read_user_logs(&self, username: &str) -> anyhow::Result<(Vec<UserActivityLog>, usize)> {
+        let user_log_dir = self.base_dir.join(sanitize_username(username));
+        if !tokio::fs::try_exists(&user_log_dir).await? {
+            return Ok((Vec::new(), 0));
+        }
+
+        let mut paths = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&user_log_dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("log") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut logs = Vec::new();
+        let mut skipped = 0;
+        for path in paths {
+            let (mut file_logs, file_skipped) = crate::activity_schema::read_log_file(&path).await?;
+            logs.append(&mut file_logs);
+            skipped += file_skipped;
+        }
+        logs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok((logs, skipped))
+    }
+
+    /// 擦除用户的全部行为日志，返回处理的日志文件数
+    ///
+    /// 指定 pseudonym 时改为匿名化：逐行替换用户名并去掉 IP、请求 ID 与额外信息，
+    /// 写入假名目录后删除原目录
+    pub async fn erase_user(&self, username: &str, pseudonym: Option<&str>) -> anyhow::Result<usize> {
+        let username = sanitize_username(username);
+        let user_log_dir = self.base_dir.join(&username);
+
+        // 持有句柄锁，避免后台批量写任务在擦除期间继续追加
+        let mut handles = self.file_handles.lock().await;
+        handles.retain(|key, _| key.split(':').next() != Some(username.as_str()));
+
+        if !tokio::fs::try_exists(&user_log_dir).await? {
+            return Ok(0);
+        }
+
+        let mut files = 0;
+        let mut read_dir = tokio::fs::read_dir(&user_log_dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+            if !file_name.ends_with(".log") {
+                continue;
+            }
+            files += 1;
+
+            if let Some(pseudonym) = pseudonym {
+                let anon_dir = self.base_dir.join(pseudonym);
+                tokio::fs::create_dir_all(&anon_dir).await?;
+                let content = tokio::fs::read_to_string(&path).await?;
+                let anon_name = file_name.replacen(username.as_str(), pseudonym, 1);
+                tokio::fs::write(anon_dir.join(anon_name), anonymize_lines(&content, pseudonym)).await?;
+            }
+        }
+
+        tokio::fs::remove_dir_all(&user_log_dir).await?;
+        Ok(files)
+    }
+
     /// 快捷方法：记录登录
     pub async fn log_login(&self, username: &str, ip: Option<String>) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::Login,
@@ -241,14 +438,17 @@ impl UserActivityLogger {
         model: &str,
         message_count: usize,
         tokens_estimated: Option<u32>,
+        metadata: Option<serde_json::Value>,
     ) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::ChatRequest {
                 model: model.to_string(),
                 message_count,
                 tokens_estimated,
+                metadata,
             },
             ip_address: None,
             request_id: None,
@@ -257,9 +457,44 @@ impl UserActivityLogger {
         .await;
     }
 
+    /// 快捷方法：记录语音请求
+    pub async fn log_audio_request(&self, username: &str, endpoint: &str, model: &str, request_bytes: usize) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::AudioRequest {
+                endpoint: endpoint.to_string(),
+                model: model.to_string(),
+                request_bytes,
+            },
+            ip_address: None,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录客户端中断流式响应（同步投递）
+    pub fn log_chat_aborted(&self, username: &str, bytes_delivered: u64, tokens_delivered: u32) {
+        self.try_log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::ChatAborted {
+                bytes_delivered,
+                tokens_delivered,
+            },
+            ip_address: None,
+            request_id: None,
+            extra: None,
+        });
+    }
+
     /// 快捷方法：记录配额检查
     pub async fn log_quota_check(&self, username: &str, used: u32, remaining: u32) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::QuotaCheck { used, remaining },
@@ -273,6 +508,7 @@ impl UserActivityLogger {
     /// 快捷方法：记录配额耗尽
     pub async fn log_quota_exceeded(&self, username: &str, used: u32, limit: u32) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::QuotaExceeded { used, limit },
@@ -283,9 +519,96 @@ impl UserActivityLogger {
         .await;
     }
 
+    /// 快捷方法：记录疑似重复提问刷量
+    pub async fn log_spam_suspected(&self, username: &str, prompt_hash: &str, count: usize) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::SpamSuspected {
+                prompt_hash: prompt_hash.to_string(),
+                count,
+            },
+            ip_address: None,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录管理员代管（审计用）
+    pub async fn log_impersonation(&self, username: &str, actor: &str, reason: &str, expires_in: u64, ip: Option<String>) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::Impersonated {
+                actor: actor.to_string(),
+                reason: reason.to_string(),
+                expires_in,
+            },
+            ip_address: ip,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录管理员重置密码（审计用）
+    pub async fn log_password_reset(&self, username: &str, actor: &str, ip: Option<String>) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::PasswordReset {
+                actor: actor.to_string(),
+            },
+            ip_address: ip,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录管理员强制下线（审计用）
+    pub async fn log_forced_logout(&self, username: &str, actor: &str, revoked_tokens: usize, ip: Option<String>) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::ForcedLogout {
+                actor: actor.to_string(),
+                revoked_tokens,
+            },
+            ip_address: ip,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录不活跃账户自动过期（审计用）
+    pub async fn log_account_expired(&self, username: &str, inactive_days: i64, last_login_at: Option<String>, archive_dir: &str) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::AccountExpired {
+                inactive_days,
+                last_login_at,
+                archive_dir: archive_dir.to_string(),
+            },
+            ip_address: None,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
     /// 快捷方法：记录速率限制
     pub async fn log_rate_limited(&self, username: &str) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::RateLimited,
@@ -296,9 +619,38 @@ impl UserActivityLogger {
         .await;
     }
 
+    /// 快捷方法：记录来源 IP 不在账户允许范围内
+    pub async fn log_ip_restricted(&self, username: &str, ip: Option<String>) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::IpRestricted,
+            ip_address: ip,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
+    /// 快捷方法：记录用户修改密码
+    pub async fn log_password_changed(&self, username: &str, ip: Option<String>) {
+        self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            username: username.to_string(),
+            action: UserAction::PasswordChanged,
+            ip_address: ip,
+            request_id: None,
+            extra: None,
+        })
+        .await;
+    }
+
     /// 快捷方法：记录错误
     pub async fn log_error(&self, username: &str, error_type: &str, message: &str) {
         self.log(UserActivityLog {
+            schema_version: crate::activity_schema::CURRENT_SCHEMA_VERSION,
             timestamp: chrono::Utc::now().to_rfc3339(),
             username: username.to_string(),
             action: UserAction::Error {
@@ -314,7 +666,7 @@ impl UserActivityLogger {
 }
 
 /// 批量写入：对 pending 中的日志按照 log_key 分组写入，提高 IO 效率
-async fn write_batch(base_dir: &PathBuf, max_file_size: u64, file_handles: &Arc<Mutex<HashMap<String, (tokio::fs::File, u64)>>>, pending: &mut Vec<UserActivityLog>) -> anyhow::Result<()> {
+async fn write_batch(base_dir: &std::path::Path, max_file_size: u64, file_handles: &Arc<Mutex<HashMap<String, (tokio::fs::File, u64)>>>, storage: Option<&Arc<ObjectStorage>>, pending: &mut Vec<UserActivityLog>) -> anyhow::Result<()> {
     if pending.is_empty() { return Ok(()); }
     // 交换出批次，避免长期持锁
     let mut current = Vec::new();
@@ -341,7 +693,10 @@ async fn write_batch(base_dir: &PathBuf, max_file_size: u64, file_handles: &Arc<
             let timestamp = chrono::Local::now().format("%H%M%S").to_string();
             let archived_name = format!("{}.{}.{}.log", username, today, timestamp);
             let archived_path = user_log_dir.join(&archived_name);
-            if let Err(e) = tokio::fs::rename(&log_file_path, &archived_path).await { tracing::warn!(error=%e, "日志文件重命名失败"); } else { tracing::info!("用户日志文件滚动: {} -> {}", log_file_path.display(), archived_path.display()); }
+            if let Err(e) = tokio::fs::rename(&log_file_path, &archived_path).await { tracing::warn!(error=%e, "日志文件重命名失败"); } else {
+                tracing::info!("用户日志文件滚动: {} -> {}", log_file_path.display(), archived_path.display());
+                if let Some(storage) = storage { storage.spawn_upload(format!("activity-logs/{}/{}", username, archived_name), archived_path.clone()); }
+            }
             handles.remove(&cache_key);
             // 异步清理旧文件
             let user_log_dir_clone = user_log_dir.clone();
@@ -352,6 +707,16 @@ async fn write_batch(base_dir: &PathBuf, max_file_size: u64, file_handles: &Arc<
         // 获取或创建文件句柄
         let (file, sz_ptr) = if let Some((f, sz)) = handles.get_mut(&cache_key) { (f, sz) } else {
             need_open = true;
+            // 跨天：关闭该用户前一天的句柄，前一天的文件已写完，可以转存
+            let user_prefix = format!("{}:", username);
+            let stale: Vec<String> = handles.keys().filter(|k| k.starts_with(&user_prefix) && **k != cache_key).cloned().collect();
+            for key in stale {
+                handles.remove(&key);
+                if let (Some(storage), Some(date)) = (storage, key.strip_prefix(&user_prefix)) {
+                    let name = format!("{}.{}.log", username, date);
+                    storage.spawn_upload(format!("activity-logs/{}/{}", username, name), user_log_dir.join(&name));
+                }
+            }
             let f = OpenOptions::new().create(true).append(true).open(&log_file_path).await?;
             handles.insert(cache_key.clone(), (f, 0));
             let (f2, sz2) = handles.get_mut(&cache_key).unwrap();
@@ -368,6 +733,22 @@ async fn write_batch(base_dir: &PathBuf, max_file_size: u64, file_handles: &Arc<
     Ok(())
 }
 
+/// 将日志内容逐行匿名化；无法解析的行直接丢弃（其中可能含有身份信息）
+fn anonymize_lines(content: &str, pseudonym: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    for mut log in crate::activity_schema::parse_lines(content).0 {
+        log.username = pseudonym.to_string();
+        log.ip_address = None;
+        log.request_id = None;
+        log.extra = None;
+        if let Ok(json) = serde_json::to_string(&log) {
+            out.push_str(&json);
+            out.push('\n');
+        }
+    }
+    out
+}
+
 /// 清理用户名中的非法字符，防止路径穿越
 fn sanitize_username(username: &str) -> String {
     username
@@ -402,7 +783,7 @@ async fn cleanup_old_logs(user_log_dir: &PathBuf, username: &str) -> anyhow::Res
     }
 
     // 按修改时间排序（最新的在前）
-    entries.sort_by(|a, b| b.1.cmp(&a.1));
+    entries.sort_by_key(|e| std::cmp::Reverse(e.1));
 
     // 保留最近 10 个文件，删除其余的
     const MAX_FILES: usize = 10;
@@ -433,12 +814,62 @@ mod tests {
         assert_eq!(sanitize_username("user@example.com"), "user_example_com");
     }
 
+    #[test]
+    fn test_anonymize_lines() {
+        let line = r#"{"timestamp":"2025-11-01T00:00:00Z","username":"alice","action":"login","ip_address":"10.0.0.1"}"#;
+        let out = anonymize_lines(&format!("{}\nnot json\n", line), "erased-1234");
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.contains("erased-1234"));
+        assert!(!out.contains("alice"));
+        assert!(!out.contains("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_read_user_logs_sorted_and_skips_bad_lines() {
+        let temp_dir = std::env::temp_dir().join("test_user_logs_read");
+        let _ = tokio::fs::remove_dir_all(&temp_dir).await;
+        let user_dir = temp_dir.join("alice");
+        tokio::fs::create_dir_all(&user_dir).await.unwrap();
+        tokio::fs::write(
+            user_dir.join("alice.2025-11-02.log"),
+            "{\"timestamp\":\"2025-11-02T00:00:00Z\",\"username\":\"alice\",\"action\":\"logout\"}\nbroken\n",
+        ).await.unwrap();
+        tokio::fs::write(
+            user_dir.join("alice.2025-11-01.log"),
+            "{\"timestamp\":\"2025-11-01T00:00:00Z\",\"username\":\"alice\",\"action\":\"login\"}\n",
+        ).await.unwrap();
+
+        let logger = UserActivityLogger::new(&temp_dir, &ActivityLogConfig::default(), None);
+        let (logs, skipped) = logger.read_user_logs("alice").await.unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(matches!(logs[0].action, UserAction::Login));
+        assert_eq!(skipped, 1);
+        assert!(logger.read_user_logs("nobody").await.unwrap().0.is_empty());
+
+        let _ = tokio::fs::remove_dir_all(&temp_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_full_channel_drops_instead_of_blocking() {
+        let temp_dir = std::env::temp_dir().join("test_user_logs_drop");
+        let cfg = ActivityLogConfig { channel_capacity: 1, ..ActivityLogConfig::default() };
+        let logger = UserActivityLogger::new(&temp_dir, &cfg, None);
+
+        // 单线程运行时下后台任务尚未运行，第 2、3 条必然因通道已满被丢弃
+        for _ in 0..3 {
+            logger.log_rate_limited("alice").await;
+        }
+        assert_eq!(logger.dropped.load(Ordering::Relaxed), 2);
+
+        let _ = tokio::fs::remove_dir_all(&temp_dir).await;
+    }
+
     #[tokio::test]
     async fn test_log_creation() {
         let temp_dir = std::env::temp_dir().join("test_user_logs");
         let _ = tokio::fs::remove_dir_all(&temp_dir).await;
 
-        let logger = UserActivityLogger::new(&temp_dir);
+        let logger = UserActivityLogger::new(&temp_dir, &ActivityLogConfig::default(), None);
         logger.log_login("test_user", Some("127.0.0.1".to_string())).await;
 
     // 等待后台异步批量写任务 flush
@@ -10,3 +10,14 @@ pub fn now_beijing() -> DateTime<FixedOffset> {
 pub fn now_beijing_rfc3339() -> String {
     now_beijing().to_rfc3339()
 }
+
+/// 以 JSON 写入文件：先写临时文件再 rename，避免崩溃时留下写了一半的文件（自动创建父目录）
+pub async fn write_json_atomic<T: serde::Serialize + ?Sized>(path: &std::path::Path, value: &T) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let json = serde_json::to_string_pretty(value)?;
+    let temp_path = path.with_extension("tmp");
+    tokio::fs::write(&temp_path, json).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
@@ -0,0 +1,362 @@
+//! 端到端测试工具：进程内的模拟上游 + 以独立工作目录启动的代理进程
+//!
+//! 代理是二进制 crate，测试通过 `CARGO_BIN_EXE_deepseek_proxy` 启动真实进程，
+//! 每个 `TestServer` 使用独立的临时目录（config.toml、data/、logs/）与端口，测试之间互不影响
+
+#![allow(dead_code)]
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const PASSWORD: &str = "secret123";
+
+/// 模拟上游：/chat/completions 流式请求返回固定的 SSE 流、非流式请求返回 [`MOCK_SUMMARY`]，
+/// /models 返回模型列表，/audio/transcriptions 返回识别文本，/audio/speech 返回 [`MOCK_AUDIO`]
+pub struct MockUpstream {
+    pub base_url: String,
+    chat_requests: Arc<AtomicUsize>,
+    fail_streams: Arc<AtomicBool>,
+}
+
+/// 模拟上游对非流式请求（如历史压缩的摘要调用）的回复内容
+pub const MOCK_SUMMARY: &str = "用户在问好";
+
+/// 模拟上游语音合成返回的音频
+pub const MOCK_AUDIO: &[u8] = b"ID3mock-audio";
+
+/// 模拟上游推送的内容分片，拼起来是 "你好，世界"
+pub const MOCK_DELTAS: [&str; 3] = ["你好", "，", "世界"];
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let chat_requests = Arc::new(AtomicUsize::new(0));
+        let fail_streams = Arc::new(AtomicBool::new(false));
+        let counter = chat_requests.clone();
+        let failing = fail_streams.clone();
+        let app = Router::new()
+            .route(
+                "/chat/completions",
+                post(move |Json(body): Json<Value>| {
+                    let counter = counter.clone();
+                    let failing = failing.clone();
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let model = body["model"].as_str().unwrap_or("mock").to_string();
+                        if body["stream"] == false {
+                            let reply = json!({"choices": [{"index": 0, "message": {"role": "assistant", "content": MOCK_SUMMARY}}]});
+                            return Json(reply).into_response();
+                        }
+                        if failing.load(Ordering::SeqCst) {
+                            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": {"message": "mock failure"}}))).into_response();
+                        }
+                        (StatusCode::OK, [(header::CONTENT_TYPE, "text/event-stream")], sse_body(&model)).into_response()
+                    }
+                }),
+            )
+            .route(
+                "/models",
+                get(|| async { Json(json!({"object": "list", "data": [{"id": "deepseek-chat", "object": "model"}]})) }),
+            )
+            .route(
+                "/audio/transcriptions",
+                post(|body: axum::body::Bytes| async move { Json(json!({"text": "你好", "bytes": body.len()})) }),
+            )
+            .route(
+                "/audio/speech",
+                post(|| async { (StatusCode::OK, [(header::CONTENT_TYPE, "audio/mpeg")], MOCK_AUDIO) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self {
+            base_url: format!("http://{}", addr),
+            chat_requests,
+            fail_streams,
+        }
+    }
+
+    /// 之后的流式聊天请求一律返回 500（非流式请求不受影响）
+    pub fn fail_chat_streams(&self) {
+        self.fail_streams.store(true, Ordering::SeqCst);
+    }
+
+    /// 上游实际收到的聊天请求数
+    pub fn chat_requests(&self) -> usize {
+        self.chat_requests.load(Ordering::SeqCst)
+    }
+}
+
+fn sse_body(model: &str) -> String {
+    let mut body = String::new();
+    for delta in MOCK_DELTAS {
+        let chunk = json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion.chunk",
+            "model": model,
+            "choices": [{"index": 0, "delta": {"content": delta}, "finish_reason": null}],
+        });
+        body.push_str(&format!("data: {}\n\n", chunk));
+    }
+    let last = json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion.chunk",
+        "model": model,
+        "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8},
+    });
+    body.push_str(&format!("data: {}\n\ndata: [DONE]\n\n", last));
+    body
+}
+
+/// 以独立工作目录启动的代理进程，drop 时结束进程并删除目录
+pub struct TestServer {
+    pub base_url: String,
+    /// `internal_listen` 的地址（ServerOptions::internal_listen 时）
+    pub internal_url: Option<String>,
+    pub dir: PathBuf,
+    child: Option<Child>,
+    client: reqwest::Client,
+}
+
+/// 代理配置：`extra` 追加到生成的 config.toml 末尾（可覆盖 [quota.tiers] 之外的任意可选表）
+pub struct ServerOptions<'a> {
+    /// (用户名, 档次)，密码统一为 PASSWORD
+    pub users: &'a [(&'a str, &'a str)],
+    /// basic 档次的月度配额
+    pub basic_quota: u32,
+    /// 登录失败多少次后阻断
+    pub login_fail_threshold: usize,
+    /// 追加到 [quota.tiers] 的档次定义，如 `sandbox = 2`
+    pub extra_tiers: &'a str,
+    pub extra: &'a str,
+    /// 代理进程的命令行参数
+    pub args: &'a [&'a str],
+    /// 另开内部监听（`server.internal_listen`），就绪检查改走内部地址
+    pub internal_listen: bool,
+}
+
+impl Default for ServerOptions<'_> {
+    fn default() -> Self {
+        Self {
+            users: &[("alice", "basic")],
+            basic_quota: 100,
+            login_fail_threshold: 5,
+            extra_tiers: "",
+            extra: "",
+            args: &[],
+            internal_listen: false,
+        }
+    }
+}
+
+impl TestServer {
+    pub async fn start(upstream: &MockUpstream, opts: ServerOptions<'_>) -> Self {
+        let port = free_port();
+        let internal_port = opts.internal_listen.then(free_port);
+        let dir = unique_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.toml"), config_toml(port, internal_port, &upstream.base_url, &opts)).unwrap();
+
+        let log = std::fs::File::create(dir.join("proxy.log")).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_deepseek_proxy"))
+            .args(opts.args)
+            .current_dir(&dir)
+            .env("OPENAI_API_KEY", "test-key")
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::from(log.try_clone().unwrap()))
+            .stderr(Stdio::from(log))
+            .spawn()
+            .expect("启动代理进程失败");
+
+        let server = Self {
+            base_url: format!("http://127.0.0.1:{}", port),
+            internal_url: internal_port.map(|port| format!("http://127.0.0.1:{}", port)),
+            dir,
+            child: Some(child),
+            client: reqwest::Client::new(),
+        };
+        server.wait_ready().await;
+        server
+    }
+
+    async fn wait_ready(&self) {
+        // 公开端口可能开启了 HTTPS，有内部监听时从内部地址检查
+        let url = self.internal_url.as_ref().unwrap_or(&self.base_url);
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while Instant::now() < deadline {
+            if let Ok(resp) = self.client.get(format!("{}/readyz", url)).send().await {
+                if resp.status().is_success() {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("代理未在 15 秒内就绪，日志：\n{}", self.log());
+    }
+
+    /// 代理进程的标准输出与日志
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("proxy.log")).unwrap_or_default()
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> reqwest::Response {
+        self.client
+            .post(format!("{}/auth/login", self.base_url))
+            .json(&json!({"username": username, "password": password}))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// 登录并返回 token（失败时 panic）
+    pub async fn token(&self, username: &str) -> String {
+        let resp = self.login(username, PASSWORD).await;
+        assert_eq!(resp.status(), StatusCode::OK, "登录失败");
+        let body: Value = resp.json().await.unwrap();
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    /// 发送一次流式聊天请求，返回状态码与完整响应体
+    ///
+    /// 每个用户同时只允许 1 个请求，上一个流结束后许可的释放可能略晚于客户端读完响应，
+    /// 因此遇到 429 时稍等重试
+    pub async fn chat(&self, token: &str) -> (StatusCode, String) {
+        for _ in 0..20 {
+            let resp = self
+                .client
+                .post(format!("{}/chat/completions", self.base_url))
+                .bearer_auth(token)
+                .json(&json!({
+                    "model": "deepseek-chat",
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "hi"}],
+                }))
+                .send()
+                .await
+                .unwrap();
+            let status = resp.status();
+            let body = resp.text().await.unwrap();
+            if status != StatusCode::TOO_MANY_REQUESTS {
+                return (status, body);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("聊天请求持续返回 429");
+    }
+
+    /// 发送 SIGTERM 并等待进程退出
+    #[cfg(unix)]
+    pub async fn terminate(&mut self) -> ExitStatus {
+        let mut child = self.child.take().expect("进程已结束");
+        // SAFETY: 向自己启动的子进程发送信号
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                return status;
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                panic!("代理未在 10 秒内退出，日志：\n{}", self.log());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    pub fn path(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(relative)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn unique_dir() -> PathBuf {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "deepseek_proxy_e2e_{}_{}",
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+fn config_toml(port: u16, internal_port: Option<u16>, upstream: &str, opts: &ServerOptions<'_>) -> String {
+    let mut users = String::new();
+    for (username, tier) in opts.users {
+        users.push_str(&format!(
+            "[[auth.users]]\nusername = \"{}\"\npassword = \"{}\"\nquota_tier = \"{}\"\n\n",
+            username, PASSWORD, tier
+        ));
+    }
+    let internal = internal_port
+        .map(|port| format!("internal_listen = \"127.0.0.1:{}\"\n", port))
+        .unwrap_or_default();
+    format!(
+        r#"[server]
+host = "127.0.0.1"
+port = {port}
+{internal}
+[auth]
+jwt_secret = "e2e-test-secret"
+token_ttl_seconds = 60
+password_hash_memory_kib = 1024
+password_hash_time_cost = 1
+
+{users}
+[deepseek]
+api_key = ""
+base_url = "{upstream}"
+timeout_seconds = 10
+
+[deepseek.http_client]
+warmup = false
+
+[quota]
+save_interval = 1000
+
+[quota.tiers]
+basic = {basic}
+pro = 1000
+premium = 1500
+{extra_tiers}
+
+[rate_limit]
+requests_per_second = 1000
+
+[security]
+login_fail_window_seconds = 60
+login_fail_threshold = {threshold}
+
+{extra}
+"#,
+        basic = opts.basic_quota,
+        threshold = opts.login_fail_threshold,
+        extra_tiers = opts.extra_tiers,
+        extra = opts.extra,
+    )
+}
@@ -0,0 +1,959 @@
+//! 端到端测试：启动完整的代理进程，上游为进程内的模拟服务（见 tests/common）
+
+mod common;
+
+use common::{MockUpstream, ServerOptions, TestServer, MOCK_AUDIO, MOCK_DELTAS, PASSWORD};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+#[tokio::test]
+async fn test_login() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+
+    let resp = server.login("alice", PASSWORD).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["token"].as_str().is_some_and(|t| !t.is_empty()));
+    assert_eq!(body["expires_in"], 60);
+
+    assert_eq!(server.login("alice", "wrong").await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.login("nobody", PASSWORD).await.status(), StatusCode::UNAUTHORIZED);
+
+    // 用户文件只保存密码哈希
+    let stored = std::fs::read_to_string(server.path("data/users/alice.toml")).unwrap();
+    assert!(stored.contains("password_hash = \"$argon2id$"));
+    assert!(!stored.contains(PASSWORD));
+
+    // 没有 token 不能调用受保护接口
+    let resp = reqwest::get(format!("{}/me", server.base_url)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_refresh_token_rotation() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let me = |token: String| client.get(format!("{}/me", server.base_url)).bearer_auth(token).send();
+    let refresh = |token: String| {
+        client
+            .post(format!("{}/auth/refresh", server.base_url))
+            .json(&serde_json::json!({"refresh_token": token}))
+            .send()
+    };
+
+    let body: Value = server.login("alice", PASSWORD).await.json().await.unwrap();
+    let first = body["refresh_token"].as_str().unwrap().to_string();
+    assert_eq!(body["refresh_expires_in"], 604800);
+
+    // refresh token 不能当作访问 token 使用
+    assert_eq!(me(first.clone()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    let resp = refresh(first.clone()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    let second = body["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(first, second);
+    let token = body["token"].as_str().unwrap().to_string();
+    assert_eq!(me(token).await.unwrap().status(), StatusCode::OK);
+
+    // 旧 token 被重复使用：拒绝，并连带吊销新签发的 refresh token
+    assert_eq!(refresh(first).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(refresh(second).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(refresh("garbage".to_string()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_logout_revokes_token() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let me = |token: String| client.get(format!("{}/me", server.base_url)).bearer_auth(token).send();
+
+    let body: Value = server.login("alice", PASSWORD).await.json().await.unwrap();
+    let token = body["token"].as_str().unwrap().to_string();
+    let refresh_token = body["refresh_token"].as_str().unwrap().to_string();
+    assert_eq!(me(token.clone()).await.unwrap().status(), StatusCode::OK);
+
+    let resp = client
+        .post(format!("{}/auth/logout", server.base_url))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"refresh_token": refresh_token}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(me(token.clone()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    // 一并提交的 refresh token 已作废
+    let resp = client
+        .post(format!("{}/auth/refresh", server.base_url))
+        .json(&serde_json::json!({"refresh_token": refresh_token}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // 重新登录签发新 token
+    let fresh = server.token("alice").await;
+    assert_ne!(fresh, token);
+    assert_eq!(me(fresh).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_change_password() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let token = server.token("alice").await;
+    let change = |token: &str, old: &str, new: &str| {
+        client
+            .post(format!("{}/me/password", server.base_url))
+            .bearer_auth(token)
+            .json(&serde_json::json!({"old_password": old, "new_password": new}))
+            .send()
+    };
+
+    assert_eq!(change(&token, "wrong", "N3w-passw0rd").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let resp = change(&token, PASSWORD, "short").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "weak_password");
+
+    assert_eq!(change(&token, PASSWORD, "N3w-passw0rd").await.unwrap().status(), StatusCode::OK);
+    // 旧 token 失效，旧密码不能再登录
+    let resp = client.get(format!("{}/me", server.base_url)).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = server.login("alice", "N3w-passw0rd").await.json().await.unwrap();
+    assert_ne!(body["token"].as_str(), Some(token.as_str()));
+}
+
+#[tokio::test]
+async fn test_admin_reset_password() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let token = server.token("alice").await;
+    let reset = |password: &str| {
+        client
+            .post(format!("{}/admin/users/alice/password", server.base_url))
+            .json(&serde_json::json!({"password": password, "operator": "ops"}))
+            .send()
+    };
+
+    assert_eq!(reset("short").await.unwrap().status(), StatusCode::BAD_REQUEST);
+    let resp = client
+        .post(format!("{}/admin/users/nobody/password", server.base_url))
+        .json(&serde_json::json!({"password": "N3w-passw0rd"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = reset("N3w-passw0rd").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let saved = std::fs::read_to_string(server.path("data/users/alice.toml")).unwrap();
+    assert!(saved.contains("password_hash") && saved.contains("updated_at"), "{}", saved);
+    // 旧 token 已注销，需重新登录
+    let resp = client.get(format!("{}/me", server.base_url)).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.login("alice", "N3w-passw0rd").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_force_logout() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let me = |token: String| client.get(format!("{}/me", server.base_url)).bearer_auth(token).send();
+    let logout = |username: &str| {
+        client
+            .post(format!("{}/admin/users/{}/logout", server.base_url, username))
+            .json(&serde_json::json!({"operator": "ops"}))
+            .send()
+    };
+
+    let token = server.token("alice").await;
+    assert_eq!(me(token.clone()).await.unwrap().status(), StatusCode::OK);
+    let resp = logout("alice").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["revoked_tokens"], 1);
+    assert_eq!(me(token.clone()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.chat(&token).await.0, StatusCode::UNAUTHORIZED);
+    // 下线记入该用户的行为日志（异步写入），带操作人
+    let audited = async {
+        loop {
+            let logs = std::fs::read_dir(server.path("logs/users/alice")).into_iter().flatten().flatten();
+            if logs.filter_map(|entry| std::fs::read_to_string(entry.path()).ok()).any(|log| log.contains("forced_logout") && log.contains("\"ops\"")) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), audited).await.expect("强制下线未写入行为日志");
+
+    // 账户仍可重新登录
+    let fresh = server.token("alice").await;
+    assert_ne!(fresh, token);
+    assert_eq!(me(fresh).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(logout("nobody").await.unwrap().status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_duplicate_system_prompt_hints() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions { extra: "[prompt_cache]\nmin_system_tokens = 5\n", ..ServerOptions::default() },
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let token = server.token("alice").await;
+    let system = "你是严谨的助教，先复述题目再回答";
+    // system 提示放在用户消息之后，无法命中上游前缀缓存；上一个流式响应的许可可能尚未释放，429 时重试
+    let chat = |question: &str| {
+        let body = serde_json::json!({"model": "deepseek-chat", "stream": true, "messages": [
+            {"role": "user", "content": question},
+            {"role": "system", "content": system}
+        ]});
+        let request = client.post(format!("{}/chat/completions", server.base_url)).bearer_auth(&token).json(&body);
+        async move {
+            for _ in 0..20 {
+                let resp = request.try_clone().unwrap().send().await.unwrap();
+                if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                    let headers = resp.headers().clone();
+                    resp.text().await.unwrap();
+                    return headers;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            panic!("聊天请求持续返回 429");
+        }
+    };
+
+    assert!(chat("第一题").await.get("x-prompt-cache-repeat").is_none());
+    let headers = chat("第二题").await;
+    assert_eq!(headers["x-prompt-cache-repeat"], "1");
+    assert_eq!(headers["x-prompt-cache-advice"], "move-system-prompt-first");
+
+    let stats: Value = client.get(format!("{}/admin/cache-stats", server.base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!((stats["duplicates"].as_u64(), stats["misaligned"].as_u64()), (Some(1), Some(1)));
+    assert_eq!(stats["users"][0]["username"], "alice");
+    assert!(stats["estimated_extra_cost"].as_f64().unwrap() > 0.0);
+}
+
+#[tokio::test]
+async fn test_export_download_link() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+
+    let resp = client.get(format!("{}/admin/users/alice/export", server.base_url)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let link: Value = resp.json().await.unwrap();
+    assert_eq!(link["filename"], "alice-export.json");
+    let url = format!("{}{}", server.base_url, link["url"].as_str().unwrap());
+
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers()["content-disposition"].to_str().unwrap().contains("alice-export.json"));
+    let body = resp.bytes().await.unwrap();
+    assert_eq!(link["bytes"].as_u64(), Some(body.len() as u64));
+    let export: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(export["user"]["username"], "alice");
+
+    // 篡改签名的链接
+    let tampered = format!("{}0", url.trim_end_matches(|c: char| c.is_ascii_hexdigit()));
+    assert_eq!(client.get(&tampered).send().await.unwrap().status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_totp_login() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let totp_url = format!("{}/admin/users/alice/totp", server.base_url);
+    let login = |totp_code: &str| {
+        client
+            .post(format!("{}/auth/login", server.base_url))
+            .json(&serde_json::json!({"username": "alice", "password": PASSWORD, "totp_code": totp_code}))
+            .send()
+    };
+
+    let body: Value = client.post(&totp_url).send().await.unwrap().json().await.unwrap();
+    let secret = body["secret"].as_str().unwrap();
+    assert!(body["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/"));
+    assert!(body["qr_svg"].as_str().unwrap().starts_with("<svg"));
+
+    let resp = server.login("alice", PASSWORD).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(resp.text().await.unwrap().contains("totp_required"));
+    let resp = login("000000x").await.unwrap();
+    assert!(resp.text().await.unwrap().contains("invalid_totp"));
+
+    let code = deepseek_proxy::auth::totp::code_at_time(secret, chrono::Utc::now().timestamp()).unwrap();
+    let resp = login(&code).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // 同一验证码不能重复使用
+    let resp = login(&code).await.unwrap();
+    assert!(resp.text().await.unwrap().contains("invalid_totp"));
+
+    let body: Value = client.delete(&totp_url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["totp_enabled"], false);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_streaming_passthrough() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let token = server.token("alice").await;
+
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::OK);
+    let content: String = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+        .collect();
+    assert_eq!(content, MOCK_DELTAS.concat());
+    assert!(body.contains("data: [DONE]"));
+    assert_eq!(upstream.chat_requests(), 1);
+}
+
+#[tokio::test]
+async fn test_quota_exhaustion() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions {
+            basic_quota: 2,
+            extra: "[branding]\nupgrade_url = \"https://acme.example/upgrade\"\n",
+            ..ServerOptions::default()
+        },
+    )
+    .await;
+    let token = server.token("alice").await;
+
+    for _ in 0..2 {
+        assert_eq!(server.chat(&token).await.0, StatusCode::OK);
+    }
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    assert!(body.contains("https://acme.example/upgrade"), "{}", body);
+    // 配额耗尽的请求不转发到上游
+    assert_eq!(upstream.chat_requests(), 2);
+}
+
+#[tokio::test]
+async fn test_model_capabilities() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions {
+            extra: "[models.\"deepseek-chat\"]\ncontext_window = 1000\nmax_output_tokens = 100\nsupports_tools = false\n",
+            ..ServerOptions::default()
+        },
+    )
+    .await;
+    let token = server.token("alice").await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .get(format!("{}/models/deepseek-chat/capabilities", server.base_url))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["context_window"], 1000);
+    assert_eq!(body["supports_tools"], false);
+    assert_eq!(body["supports_vision"], false);
+    let resp = client
+        .get(format!("{}/models/unknown/capabilities", server.base_url))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // 超出模型能力的请求不转发上游、不扣费
+    for extra in [
+        serde_json::json!({"tools": [{"type": "function", "function": {"name": "f", "parameters": {}}}]}),
+        serde_json::json!({"max_tokens": 101}),
+    ] {
+        let mut body = serde_json::json!({"model": "deepseek-chat", "stream": true, "messages": [{"role": "user", "content": "hi"}]});
+        body.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let resp = client
+            .post(format!("{}/chat/completions", server.base_url))
+            .bearer_auth(&token)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+    assert_eq!(upstream.chat_requests(), 0);
+    let (status, _) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_audio_endpoints_are_metered() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions {
+            basic_quota: 3,
+            extra: "[audio]\nenabled = true\nspeech_quota_weight = 2\n",
+            ..ServerOptions::default()
+        },
+    )
+    .await;
+    let token = server.token("alice").await;
+    let client = reqwest::Client::new();
+
+    let form = "--b1\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+--b1\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\r\nRIFF\r\n--b1--\r\n";
+    let transcribe = |body: &'static str| {
+        client
+            .post(format!("{}/audio/transcriptions", server.base_url))
+            .bearer_auth(&token)
+            .header("Content-Type", "multipart/form-data; boundary=b1")
+            .body(body)
+            .send()
+    };
+    let resp = transcribe(form).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["text"], "你好");
+    assert_eq!(body["bytes"], form.len());
+    // 缺少音频文件时不转发、不扣费
+    let resp = transcribe("--b1\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n--b1--\r\n").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let speech = || {
+        client
+            .post(format!("{}/audio/speech", server.base_url))
+            .bearer_auth(&token)
+            .json(&serde_json::json!({"model": "tts-1", "input": "你好", "voice": "alloy"}))
+            .send()
+    };
+    let resp = speech().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "audio/mpeg");
+    assert_eq!(resp.headers()["x-quota-remaining"], "0");
+    // 限流反馈头来自全局令牌桶（1000/秒，突发容量 2000），与配额分开
+    assert_eq!(resp.headers()["x-ratelimit-limit"], "2000");
+    assert_eq!(resp.bytes().await.unwrap(), MOCK_AUDIO);
+    // 1 + 2 次已用完配额
+    let resp = speech().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(resp.headers()["x-quota-remaining"], "0");
+    assert_eq!(resp.headers()["x-ratelimit-limit"], "2000");
+}
+
+#[tokio::test]
+async fn test_undefined_tier_is_rejected_clearly() {
+    let upstream = MockUpstream::start().await;
+    // gold 档次不在 [quota.tiers] 中（已被删除或改名），且未配置 fallback_tier
+    let server = TestServer::start(&upstream, ServerOptions { users: &[("carol", "gold")], ..ServerOptions::default() }).await;
+    let token = server.token("carol").await;
+
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.contains("invalid_quota_tier"), "{}", body);
+    assert_eq!(upstream.chat_requests(), 0);
+}
+
+#[tokio::test]
+async fn test_access_schedule_denies_login() {
+    let upstream = MockUpstream::start().await;
+    // basic 档次的访问期限早已结束
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions { extra: "[access_schedules.basic]\nuntil = \"2000-01-01\"\n", ..ServerOptions::default() },
+    )
+    .await;
+
+    let resp = server.login("alice", PASSWORD).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("outside_access_hours"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_allowed_ips_restrict_login_and_requests() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let set_allowed_ips = |allowed_ips: &'static [&'static str]| {
+        client
+            .post(format!("{}/admin/users/alice/allowed_ips", server.base_url))
+            .json(&serde_json::json!({"allowed_ips": allowed_ips}))
+            .send()
+    };
+    let token = server.token("alice").await;
+
+    assert_eq!(set_allowed_ips(&["203.0.113.0/24"]).await.unwrap().status(), StatusCode::OK);
+    // 允许范围外的登录与密码错误无法区分，不能借此确认密码
+    let resp = server.login("alice", PASSWORD).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.text().await.unwrap(), server.login("alice", "wrong-password").await.text().await.unwrap());
+    // 限制之前签发的 token 同样被拒绝
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(upstream.chat_requests(), 0);
+
+    assert_eq!(set_allowed_ips(&["127.0.0.1/32"]).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_inactive_user_is_expired_and_archived() {
+    let upstream = MockUpstream::start().await;
+    let extra = format!(
+        "[user_expiry]\ninactive_days = 30\n\n[[auth.users]]\nusername = \"dormant\"\npassword = \"{}\"\nlast_login_at = \"2020-01-01T09:00:00+08:00\"\n",
+        PASSWORD
+    );
+    let server = TestServer::start(&upstream, ServerOptions { extra: &extra, ..ServerOptions::default() }).await;
+    // 第一次检查在启动时进行
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    assert_eq!(server.login("dormant", PASSWORD).await.status(), StatusCode::UNAUTHORIZED);
+    // 没有登录记录的用户从第一次检查开始计时，不会被过期
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::OK);
+
+    let archived: Vec<_> = std::fs::read_dir(server.path("data/archive")).unwrap().flatten().collect();
+    assert_eq!(archived.len(), 1);
+    assert!(archived[0].file_name().to_string_lossy().starts_with("dormant."));
+    assert!(archived[0].path().join("user.toml").exists());
+    assert!(!server.path("data/users/dormant.toml").exists());
+}
+
+#[tokio::test]
+async fn test_sandbox_tier() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions {
+            users: &[("demo", "sandbox")],
+            extra_tiers: "sandbox = 2",
+            extra: "[sandbox]\ntiers = [\"sandbox\"]\nmodel = \"deepseek-cheap\"\n",
+            ..ServerOptions::default()
+        },
+    )
+    .await;
+    let token = server.token("demo").await;
+
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    // 模拟上游在响应中回显请求的模型
+    assert!(body.contains("\"model\":\"deepseek-cheap\""), "{}", body);
+    // 未配置 watermark 变换也会加水印
+    assert!(body.contains("event: watermark"), "{}", body);
+
+    assert_eq!(server.chat(&token).await.0, StatusCode::OK);
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    // 沙箱配额在次日零点（东八区）重置
+    let tomorrow = (chrono::Utc::now() + chrono::Duration::hours(8)).date_naive().succ_opt().unwrap();
+    assert!(body.contains(&format!("\"reset_at\":\"{}T00:00:00+08:00\"", tomorrow)), "{}", body);
+}
+
+#[tokio::test]
+async fn test_pinned_model_overrides_request() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let pin = |body: Value| client.post(format!("{}/admin/users/alice/model", server.base_url)).json(&body).send();
+    let token = server.token("alice").await;
+
+    let resp = pin(serde_json::json!({"pinned_model": "deepseek-pinned"})).await.unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!((body["pinned_model"].as_str(), body["model_override"].as_bool()), (Some("deepseek-pinned"), Some(false)));
+    // 模拟上游在响应中回显请求的模型
+    let (status, body) = server.chat(&token).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body.contains("\"model\":\"deepseek-pinned\""), "{}", body);
+
+    // 允许自选模型时，固定模型只作为未指定 model 时的默认值
+    pin(serde_json::json!({"pinned_model": "deepseek-pinned", "model_override": true})).await.unwrap();
+    let (_, body) = server.chat(&token).await;
+    assert!(body.contains("\"model\":\"deepseek-chat\""), "{}", body);
+    // 上一个流式响应的许可可能尚未释放，429 时重试
+    for _ in 0..20 {
+        let resp = client
+            .post(format!("{}/chat/completions", server.base_url))
+            .bearer_auth(&token)
+            .json(&serde_json::json!({"stream": true, "messages": [{"role": "user", "content": "hi"}]}))
+            .send()
+            .await
+            .unwrap();
+        if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+            let body = resp.text().await.unwrap();
+            assert!(body.contains("\"model\":\"deepseek-pinned\""), "{}", body);
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("聊天请求持续返回 429");
+}
+
+#[tokio::test]
+async fn test_upstream_override_for_trusted_tokens() {
+    let upstream = MockUpstream::start().await;
+    let staging = MockUpstream::start().await;
+    let extra = format!("[deepseek.upstreams.staging]\nbase_url = \"{}\"\n", staging.base_url);
+    let server = TestServer::start(&upstream, ServerOptions { extra: &extra, ..ServerOptions::default() }).await;
+    let client = reqwest::Client::new();
+    let chat = |token: String, target: &'static str| {
+        let client = client.clone();
+        let url = format!("{}/chat/completions", server.base_url);
+        async move {
+            let body = serde_json::json!({"model": "deepseek-chat", "stream": true, "messages": [{"role": "user", "content": "hi"}]});
+            let resp = client.post(url).bearer_auth(token).header("X-Upstream", target).json(&body).send().await.unwrap();
+            let status = resp.status();
+            let selected = resp.headers().get("x-upstream").map(|v| v.to_str().unwrap().to_string());
+            let _ = resp.text().await;
+            (status, selected)
+        }
+    };
+
+    // 普通账户不能选择上游
+    let token = server.token("alice").await;
+    assert_eq!(chat(token, "staging").await.0, StatusCode::FORBIDDEN);
+
+    // 管理员代管的 token 可以
+    let resp: Value = client
+        .post(format!("{}/admin/impersonate/alice", server.base_url))
+        .json(&serde_json::json!({"reason": "验证预发布上游"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = resp["token"].as_str().unwrap().to_string();
+    assert_eq!(chat(token.clone(), "staging").await, (StatusCode::OK, Some("staging".to_string())));
+    assert_eq!((staging.chat_requests(), upstream.chat_requests()), (1, 0));
+    assert_eq!(chat(token, "unknown").await.0, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_bruteforce_lockout() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions { login_fail_threshold: 3, ..ServerOptions::default() }).await;
+
+    for _ in 0..2 {
+        assert_eq!(server.login("alice", "wrong").await.status(), StatusCode::UNAUTHORIZED);
+    }
+    // 第 3 次失败达到阈值，进入第一级阻断（60 秒）
+    let resp = server.login("alice", "wrong").await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = resp.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "login_locked");
+    assert_eq!(body["error"]["retry_after_seconds"], retry_after);
+    // 阻断期间正确的密码也被拒绝
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // 管理员查看并解除阻断
+    let client = reqwest::Client::new();
+    let lockouts_url = format!("{}/admin/security/lockouts", server.base_url);
+    let body: Value = client.get(&lockouts_url).send().await.unwrap().json().await.unwrap();
+    let lockout = &body["lockouts"][0];
+    assert_eq!((lockout["username"].as_str(), lockout["level"].as_u64()), (Some("alice"), Some(1)));
+    let key = lockout["key"].as_str().unwrap();
+    let resp = client.delete(format!("{}/{}", lockouts_url, key)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::OK);
+    let resp = client.delete(format!("{}/{}", lockouts_url, key)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(server.path("data/login_attempts.json").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_graceful_shutdown_persists_quota() {
+    let upstream = MockUpstream::start().await;
+    let mut server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let token = server.token("alice").await;
+    assert_eq!(server.chat(&token).await.0, StatusCode::OK);
+    assert!(server.path("data/running.json").exists());
+
+    let status = server.terminate().await;
+    assert!(status.success(), "退出状态 {:?}，日志：\n{}", status, server.log());
+
+    // save_interval 很大，配额只在关闭时落盘
+    let quota: Value =
+        serde_json::from_str(&std::fs::read_to_string(server.path("data/quotas/alice.json")).unwrap()).unwrap();
+    assert_eq!(quota["used_count"], 1);
+
+    // 正常关闭：写出关闭报告并删除运行标记
+    let report: Value =
+        serde_json::from_str(&std::fs::read_to_string(server.path("data/last_shutdown.json")).unwrap()).unwrap();
+    assert!(report["requests_served"].as_u64().unwrap() >= 2);
+    assert!(report["quota_entries_flushed"].as_u64().unwrap() >= 1);
+    assert_eq!(report["streams_in_flight"], 0);
+    assert!(!server.path("data/running.json").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_read_only_mode_never_writes_data() {
+    let upstream = MockUpstream::start().await;
+    let mut server = TestServer::start(&upstream, ServerOptions { args: &["--read-only"], ..ServerOptions::default() }).await;
+    let token = server.token("alice").await;
+    assert_eq!(server.chat(&token).await.0, StatusCode::OK);
+
+    let resp = reqwest::Client::new()
+        .post(format!("{}/admin/users/alice/credits", server.base_url))
+        .json(&serde_json::json!({"amount": 10}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(resp.text().await.unwrap().contains("read_only"));
+
+    let status = server.terminate().await;
+    assert!(status.success(), "退出状态 {:?}，日志：\n{}", status, server.log());
+    // 用户从配置导入、配额用量都只保存在内存中
+    assert!(!server.path("data/users").exists());
+    assert!(!server.path("data/quotas/alice.json").exists());
+}
+
+#[tokio::test]
+async fn test_global_ip_rules() {
+    let upstream = MockUpstream::start().await;
+    // 信任本机代理，用 X-Forwarded-For 模拟外部来源
+    let extra = "[server.trusted_proxies]\ncidrs = [\"127.0.0.1\"]\n\n[security.ip_rules]\ndeny = [\"198.51.100.0/24\"]\n";
+    let server = TestServer::start(&upstream, ServerOptions { extra, ..ServerOptions::default() }).await;
+    let client = reqwest::Client::new();
+    let login_from = |ip: &'static str| {
+        client
+            .post(format!("{}/auth/login", server.base_url))
+            .header("x-forwarded-for", ip)
+            .json(&serde_json::json!({"username": "alice", "password": PASSWORD}))
+            .send()
+    };
+
+    let resp = login_from("198.51.100.7").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(resp.text().await.unwrap().contains("ip_blocked"));
+    assert_eq!(login_from("203.0.113.5").await.unwrap().status(), StatusCode::OK);
+
+    // 运行时改为只允许 203.0.113.0/24；本机请求始终放行
+    let resp = client
+        .post(format!("{}/admin/ip_rules", server.base_url))
+        .json(&serde_json::json!({"allow": ["203.0.113.0/24"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(login_from("198.51.100.7").await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(login_from("192.0.2.1").await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(login_from("203.0.113.5").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(server.login("alice", PASSWORD).await.status(), StatusCode::OK);
+    assert!(server.path("data/ip_rules.json").exists());
+
+    let metrics = client.get(format!("{}/metrics", server.base_url)).send().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("ip_rule_rejections_total{reason=\"denylist\"} 1"), "{}", metrics);
+    assert!(metrics.contains("ip_rule_rejections_total{reason=\"not_allowlisted\"} 2"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn test_admin_listings_support_conditional_get() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let users_url = format!("{}/admin/users", server.base_url);
+
+    let resp = client.get(&users_url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = resp.headers()["last-modified"].to_str().unwrap().to_string();
+
+    let resp = client.get(&users_url).header("if-none-match", &etag).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["etag"], etag.as_str());
+    assert!(resp.text().await.unwrap().is_empty());
+    let resp = client.get(&users_url).header("if-modified-since", &last_modified).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // 内容变化后返回新内容与新的 ETag
+    let resp = client
+        .post(&users_url)
+        .json(&serde_json::json!({"username": "carol", "password": PASSWORD}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = client.get(&users_url).header("if-none-match", &etag).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers()["etag"], etag.as_str());
+    assert!(resp.text().await.unwrap().contains("carol"));
+
+    let resp = client.get(format!("{}/admin/quotas", server.base_url)).send().await.unwrap();
+    let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["quotas"].as_array().unwrap().iter().any(|q| q["username"] == "carol"));
+    let resp = client.get(format!("{}/admin/quotas", server.base_url)).header("if-none-match", &etag).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = client.get(format!("{}/metrics", server.base_url)).send().await.unwrap();
+    assert!(resp.headers().contains_key("etag"));
+}
+
+#[tokio::test]
+async fn test_admin_user_listing_pagination() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions::default()).await;
+    let client = reqwest::Client::new();
+    let users_url = format!("{}/admin/users", server.base_url);
+    for (username, tier) in [("team-b", "pro"), ("team-a", "basic"), ("team-c", "pro"), ("solo", "basic")] {
+        let body = serde_json::json!({"username": username, "password": PASSWORD, "quota_tier": tier});
+        assert_eq!(client.post(&users_url).json(&body).send().await.unwrap().status(), StatusCode::OK);
+    }
+    client
+        .post(format!("{}/admin/users/team-c/active", server.base_url))
+        .json(&serde_json::json!({"is_active": false}))
+        .send()
+        .await
+        .unwrap();
+    let list = |query: &'static str| {
+        let url = format!("{}?{}", users_url, query);
+        let client = client.clone();
+        async move {
+            let body: Value = client.get(url).send().await.unwrap().json().await.unwrap();
+            let names: Vec<String> = body["users"].as_array().unwrap().iter().map(|u| u["username"].as_str().unwrap().to_string()).collect();
+            (names, body["total"].as_u64().unwrap())
+        }
+    };
+
+    assert_eq!(list("search=team-&per_page=2").await, (vec!["team-a".to_string(), "team-b".to_string()], 3));
+    assert_eq!(list("search=team-&per_page=2&page=2").await, (vec!["team-c".to_string()], 3));
+    assert_eq!(list("search=team-&sort=-username&filter=active").await, (vec!["team-b".to_string(), "team-a".to_string()], 2));
+    assert_eq!(list("tier=pro&filter=inactive").await, (vec!["team-c".to_string()], 1));
+    let resp = client.get(format!("{}?sort=password", users_url)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_every_admin_route_rejects_non_localhost() {
+    let upstream = MockUpstream::start().await;
+    // 信任本机代理，用 X-Forwarded-For 模拟外部来源
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions { extra: "[server.trusted_proxies]\ncidrs = [\"127.0.0.1\"]\n", ..ServerOptions::default() },
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    for route in deepseek_proxy::admin::registry() {
+        let url = format!("{}{}", server.base_url, route.path.replace(":username", "alice"));
+        let resp = client
+            .request(route.method.clone(), &url)
+            .header("x-forwarded-for", "203.0.113.7")
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{} {} 未拒绝外部来源", route.method, route.path);
+    }
+
+    let resp = client.get(format!("{}/admin/users", server.base_url)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_internal_listener_admin_with_mtls() {
+    // 公开端口开启 mTLS 时，明文的内部监听仍按 localhost 放行管理接口，不要求客户端证书
+    let certs = std::env::temp_dir().join(format!("deepseek_proxy_e2e_certs_{}", std::process::id()));
+    std::fs::create_dir_all(&certs).unwrap();
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let server_key = rcgen::KeyPair::generate().unwrap();
+    let server_cert = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap().self_signed(&server_key).unwrap();
+    std::fs::write(certs.join("ca.pem"), ca.pem()).unwrap();
+    std::fs::write(certs.join("server.pem"), server_cert.pem()).unwrap();
+    std::fs::write(certs.join("server-key.pem"), server_key.serialize_pem()).unwrap();
+
+    let extra = format!(
+        "[server.tls]\ncert = \"{}\"\nkey = \"{}\"\nclient_ca = \"{}\"\n",
+        certs.join("server.pem").display(),
+        certs.join("server-key.pem").display(),
+        certs.join("ca.pem").display(),
+    );
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(&upstream, ServerOptions { extra: &extra, internal_listen: true, ..ServerOptions::default() }).await;
+    let internal_url = server.internal_url.clone().unwrap();
+    let client = reqwest::Client::new();
+
+    let resp = client.get(format!("{}/admin/users", internal_url)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = client.get(format!("{}/admin/cache-stats", internal_url)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(&certs);
+}
+
+#[tokio::test]
+async fn test_compression_is_charged_only_with_successful_reply() {
+    let upstream = MockUpstream::start().await;
+    let server = TestServer::start(
+        &upstream,
+        ServerOptions {
+            basic_quota: 10,
+            extra: "[compression]\nenabled = true\ntoken_budget = 5\nkeep_recent_messages = 1\nquota_weight = 1\n",
+            ..ServerOptions::default()
+        },
+    )
+    .await;
+    let token = server.token("alice").await;
+    let client = reqwest::Client::new();
+    let chat = || {
+        client
+            .post(format!("{}/chat/completions", server.base_url))
+            .bearer_auth(&token)
+            .json(&serde_json::json!({
+                "model": "deepseek-chat",
+                "stream": true,
+                "messages": [
+                    {"role": "user", "content": "第一个 问题 比较 长"},
+                    {"role": "assistant", "content": "第一个 回答 也 比较 长"},
+                    {"role": "user", "content": "第二个 问题"}
+                ],
+            }))
+            .send()
+    };
+    let used = || async {
+        let me: Value = client.get(format!("{}/me", server.base_url)).bearer_auth(&token).send().await.unwrap().json().await.unwrap();
+        me["used"].as_u64().unwrap()
+    };
+
+    // 压缩 1 次 + 回复 1 次
+    let resp = chat().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    resp.text().await.unwrap();
+    assert_eq!(upstream.chat_requests(), 2);
+    assert_eq!(used().await, 2);
+
+    // 压缩成功但上游回复失败：压缩与回复都不扣费
+    upstream.fail_chat_streams();
+    let resp = chat().await.unwrap();
+    assert!(!resp.status().is_success());
+    assert_eq!(upstream.chat_requests(), 4);
+    assert_eq!(used().await, 2);
+}